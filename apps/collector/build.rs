@@ -0,0 +1,38 @@
+//! Injects the git commit and compiler version reported by the
+//! `collector_build_info` metric.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+
+    // `GIT_SHA` wins for builds from a source tree without `.git`.
+    let git_sha = std::env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| output("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    if let Some(git_dir) = output("git", &["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+        println!("cargo:rerun-if-changed={}/refs", git_dir);
+    }
+
+    // `rustc 1.95.0 (abcdef123 2026-01-01)` -> `1.95.0`
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rust_version = output(&rustc, &["--version"])
+        .and_then(|version| version.split_whitespace().nth(1).map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=COLLECTOR_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=COLLECTOR_RUST_VERSION={}", rust_version);
+}
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
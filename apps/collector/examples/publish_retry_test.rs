@@ -16,6 +16,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         (r#"{"type":"log","message":"Success message 2"}"#, "Success 2"),
         (r#"{"type":"log","fail":"transient","message":"Will retry"}"#, "Transient failure (will retry)"),
         (r#"{"type":"log","message":"Success message 3"}"#, "Success 3"),
+        (r#"{"type":"log","fail":"rate_limited","message":"Retry in 30s"}"#, "Rate limited (retries after 30s)"),
         (r#"{"type":"log","fail":"permanent","message":"No retry"}"#, "Permanent failure (goes to DLQ)"),
         (r#"{"type":"log","message":"Success message 4"}"#, "Success 4"),
     ];
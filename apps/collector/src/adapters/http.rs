@@ -0,0 +1,490 @@
+//! Forwarding of processed events to an HTTP downstream in JSON batches.
+
+use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{oneshot, Notify, Semaphore};
+use tracing::{error, info, warn};
+
+use super::sink::{Event, Sink, WriteError};
+use crate::messaging::consumer::retry_delay;
+use crate::metrics::Metrics;
+
+/// Batches that may be buffered before `submit` waits for room.
+const MAX_BUFFERED_BATCHES: usize = 4;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SEND_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const SEND_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// One event as sent downstream.
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardedEvent {
+    pub routing_key: String,
+    pub version: String,
+    /// The message's correlation id, generated if it carried none.
+    pub correlation_id: String,
+    /// The event itself, or the raw payload as a string if it is not JSON.
+    pub payload: Value,
+    /// Milliseconds since the epoch at which the collector processed it.
+    pub processed_at: u64,
+}
+
+impl ForwardedEvent {
+    pub fn new(routing_key: &str, version: &str, correlation_id: &str, payload: &[u8]) -> Self {
+        Self {
+            routing_key: routing_key.to_string(),
+            version: version.to_string(),
+            correlation_id: correlation_id.to_string(),
+            payload: serde_json::from_slice(payload)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned())),
+            processed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+}
+
+/// A buffered event and where to report what became of it.
+struct Pending {
+    event: ForwardedEvent,
+    /// `Err` holds the downstream's reason for rejecting the batch.
+    delivered: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Serialize)]
+struct Batch<'a> {
+    events: &'a [ForwardedEvent],
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    /// Worth retrying: 429, 5xx or no response. The batch stays buffered.
+    #[error("Downstream unavailable: {reason}")]
+    Transient {
+        reason: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// The downstream rejected the batch itself; retrying would not help.
+    #[error("Downstream rejected batch: {0}")]
+    Rejected(String),
+
+    #[error("Sink is shut down")]
+    Closed,
+}
+
+/// POSTs events to `DOWNSTREAM_URL` as `{"events": [...]}`, once `batch_size`
+/// events are buffered or `interval` has passed since the last flush.
+///
+/// `submit` returns once the event's batch is delivered, so a message is only
+/// acked after the downstream has it. At most `MAX_BUFFERED_BATCHES` batches
+/// are buffered; beyond that `submit` waits for room.
+pub struct HttpSink {
+    client: reqwest::Client,
+    url: String,
+    batch_size: usize,
+    interval: Duration,
+    buffer: Mutex<VecDeque<Pending>>,
+    /// One permit per free buffer slot.
+    capacity: Semaphore,
+    batch_ready: Notify,
+    metrics: Arc<Metrics>,
+}
+
+impl HttpSink {
+    pub fn new(
+        url: String,
+        batch_size: usize,
+        interval: Duration,
+        metrics: Arc<Metrics>,
+    ) -> reqwest::Result<Self> {
+        let batch_size = batch_size.max(1);
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .build()?;
+        Ok(Self {
+            client,
+            url,
+            batch_size,
+            interval,
+            buffer: Mutex::new(VecDeque::new()),
+            capacity: Semaphore::new(batch_size * MAX_BUFFERED_BATCHES),
+            batch_ready: Notify::new(),
+            metrics,
+        })
+    }
+
+    /// Buffers `event`, waiting while the buffer is full, and returns once
+    /// it is delivered. Fails with `SinkError::Rejected` if the downstream
+    /// rejected its batch, and `SinkError::Closed` if the sink shut down first.
+    pub async fn submit(&self, event: ForwardedEvent) -> Result<(), SinkError> {
+        self.capacity
+            .acquire()
+            .await
+            .map_err(|_| SinkError::Closed)?
+            .forget();
+        let (delivered, outcome) = oneshot::channel();
+        let buffered = {
+            let mut buffer = self.buffer.lock().unwrap();
+            // Checked under the lock, so nothing is buffered after `run` has
+            // given up on what is left.
+            if self.capacity.is_closed() {
+                return Err(SinkError::Closed);
+            }
+            buffer.push_back(Pending { event, delivered });
+            buffer.len()
+        };
+        self.metrics.downstream_buffered_events.set(buffered as f64);
+        if buffered >= self.batch_size {
+            self.batch_ready.notify_one();
+        }
+
+        match outcome.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(reason)) => Err(SinkError::Rejected(reason)),
+            Err(_) => Err(SinkError::Closed),
+        }
+    }
+
+    /// Sends batches until `shutdown`, then makes one last attempt to send
+    /// what is left. Whatever that leaves, and any `submit` still waiting,
+    /// fails with `SinkError::Closed`, so its message is retried.
+    pub async fn run(self: Arc<Self>, shutdown: Arc<Notify>) {
+        let mut failures = 0;
+        loop {
+            let wait = match self.flush().await {
+                Ok(()) => {
+                    failures = 0;
+                    None
+                }
+                Err(SinkError::Transient {
+                    reason,
+                    retry_after,
+                }) => {
+                    failures += 1;
+                    let delay = retry_after.unwrap_or_else(|| {
+                        retry_delay(SEND_RETRY_BASE_DELAY, SEND_RETRY_MAX_DELAY, failures)
+                    });
+                    warn!(
+                        reason = %reason,
+                        retry_in_ms = delay.as_millis() as u64,
+                        "Downstream unavailable, keeping batch buffered"
+                    );
+                    Some(delay)
+                }
+                Err(e) => {
+                    error!(error = %e, "Downstream flush failed");
+                    None
+                }
+            };
+
+            let delay = wait.unwrap_or(self.interval);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.batch_ready.notified(), if wait.is_none() => {}
+                _ = shutdown.notified() => break,
+            }
+        }
+
+        self.capacity.close();
+        if let Err(e) = self.flush().await {
+            // Dropping them fails their `submit`, so the messages are retried.
+            let left = std::mem::take(&mut *self.buffer.lock().unwrap());
+            warn!(
+                error = %e,
+                events = left.len(),
+                "Events not forwarded before shutdown, retrying their messages"
+            );
+        }
+        self.metrics.downstream_buffered_events.set(0.0);
+        info!("Downstream sink stopped");
+    }
+
+    /// Sends every buffered event, a batch at a time. A batch leaves the
+    /// buffer once delivered, or once rejected by the downstream, and its
+    /// `submit` calls return.
+    async fn flush(&self) -> Result<(), SinkError> {
+        loop {
+            let batch: Vec<ForwardedEvent> = {
+                let buffer = self.buffer.lock().unwrap();
+                buffer
+                    .iter()
+                    .take(self.batch_size)
+                    .map(|pending| pending.event.clone())
+                    .collect()
+            };
+            if batch.is_empty() {
+                return Ok(());
+            }
+
+            let result = self.deliver(&batch).await;
+            if let Err(SinkError::Transient { .. }) = result {
+                return result;
+            }
+
+            let (sent, buffered) = {
+                let mut buffer = self.buffer.lock().unwrap();
+                let sent: Vec<Pending> = buffer.drain(..batch.len()).collect();
+                (sent, buffer.len())
+            };
+            self.capacity.add_permits(batch.len());
+            self.metrics.downstream_buffered_events.set(buffered as f64);
+            let outcome = result.map_err(|e| match e {
+                SinkError::Rejected(reason) => reason,
+                other => other.to_string(),
+            });
+            // A submitter that gave up waiting has nothing left to tell.
+            for pending in sent {
+                let _ = pending.delivered.send(outcome.clone());
+            }
+        }
+    }
+
+    /// Sends `batch` as one request and counts what became of it. Callers
+    /// that buffer events themselves, like the WAL, use it to bypass `submit`.
+    pub async fn deliver(&self, batch: &[ForwardedEvent]) -> Result<(), SinkError> {
+        let result = self.send(batch).await;
+        match &result {
+            Ok(()) => self
+                .metrics
+                .downstream_events_forwarded_total
+                .inc_by(batch.len() as f64),
+            Err(e @ SinkError::Rejected(_)) => {
+                self.metrics
+                    .downstream_events_rejected_total
+                    .inc_by(batch.len() as f64);
+                error!(error = %e, events = batch.len(), "Batch rejected by downstream");
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn send(&self, batch: &[ForwardedEvent]) -> Result<(), SinkError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&Batch { events: batch })
+            .send()
+            .await
+            .map_err(|e| SinkError::Transient {
+                reason: e.to_string(),
+                retry_after: None,
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err(SinkError::Transient {
+                reason: status.to_string(),
+                retry_after,
+            });
+        }
+        Err(SinkError::Rejected(status.to_string()))
+    }
+}
+
+/// Waits until the event is delivered. A batch the downstream rejected fails
+/// permanently, sending the message to the DLQ; a sink that shut down first
+/// fails transiently, leaving the message to be retried.
+#[async_trait]
+impl Sink for HttpSink {
+    async fn write(&self, event: &Event<'_>) -> Result<(), WriteError> {
+        let event = ForwardedEvent::new(
+            event.routing_key,
+            event.version,
+            event.correlation_id,
+            event.payload,
+        );
+        self.submit(event).await.map_err(|e| match e {
+            SinkError::Rejected(_) => WriteError::Permanent(format!("Failed to forward event: {}", e)),
+            _ => WriteError::Transient(format!("Failed to forward event: {}", e)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task::JoinHandle;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn event(n: u64) -> ForwardedEvent {
+        ForwardedEvent::new(
+            "telemetry",
+            "v1",
+            "req-1",
+            format!(r#"{{"eventType":"log","payload":{{"n":{}}}}}"#, n).as_bytes(),
+        )
+    }
+
+    fn buffered(sink: &HttpSink) -> usize {
+        sink.buffer.lock().unwrap().len()
+    }
+
+    /// Submits `event(n)` in the background once the buffer holds
+    /// everything submitted before it, so events are buffered in order.
+    async fn submit(sink: &Arc<HttpSink>, n: u64) -> JoinHandle<Result<(), SinkError>> {
+        let before = buffered(sink);
+        let submitting = sink.clone();
+        let handle = tokio::spawn(async move { submitting.submit(event(n)).await });
+        while buffered(sink) == before {
+            tokio::task::yield_now().await;
+        }
+        handle
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_posted_and_kept_until_delivered() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/ingest"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/ingest"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let metrics = Metrics::new().unwrap();
+        let sink = Arc::new(
+            HttpSink::new(
+                format!("{}/ingest", server.uri()),
+                2,
+                Duration::from_secs(60),
+                metrics.clone(),
+            )
+            .unwrap(),
+        );
+        let mut submitted = Vec::new();
+        for n in 0..3 {
+            submitted.push(submit(&sink, n).await);
+        }
+
+        // The first attempt fails and keeps everything buffered, unacknowledged.
+        assert!(matches!(
+            sink.flush().await,
+            Err(SinkError::Transient { .. })
+        ));
+        assert_eq!(buffered(&sink), 3);
+        assert!(submitted.iter().all(|handle| !handle.is_finished()));
+
+        sink.flush().await.unwrap();
+        assert_eq!(buffered(&sink), 0);
+        assert_eq!(metrics.downstream_events_forwarded_total.get(), 3.0);
+        for handle in submitted {
+            handle.await.unwrap().unwrap();
+        }
+
+        let requests = server.received_requests().await.unwrap();
+        let batches: Vec<Value> = requests
+            .iter()
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect();
+        assert_eq!(batches.len(), 3);
+        // The failed batch is sent again unchanged, then the remainder.
+        assert_eq!(batches[0], batches[1]);
+        let events = batches[1]["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["routing_key"], "telemetry");
+        assert_eq!(events[0]["version"], "v1");
+        assert_eq!(events[0]["payload"]["payload"]["n"], 0);
+        assert_eq!(batches[2]["events"][0]["payload"]["payload"]["n"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_batch_fails_its_writes_permanently() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        let metrics = Metrics::new().unwrap();
+        let sink = Arc::new(HttpSink::new(server.uri(), 10, Duration::from_millis(10), metrics.clone()).unwrap());
+        tokio::spawn(sink.clone().run(Arc::new(Notify::new())));
+
+        let result = sink
+            .write(&Event {
+                routing_key: "telemetry",
+                version: "v1",
+                payload: br#"{"eventType":"log"}"#,
+                delivery_tag: 1,
+                correlation_id: "req-1",
+            })
+            .await;
+
+        assert!(matches!(result, Err(WriteError::Permanent(_))));
+        assert_eq!(metrics.downstream_events_rejected_total.get(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_fails_undelivered_submits() {
+        let metrics = Metrics::new().unwrap();
+        let sink = Arc::new(
+            HttpSink::new(
+                "http://127.0.0.1:9/unused".to_string(),
+                1,
+                Duration::from_secs(60),
+                metrics,
+            )
+            .unwrap(),
+        );
+        let submitted = submit(&sink, 1).await;
+
+        let shutdown = Arc::new(Notify::new());
+        shutdown.notify_one();
+        sink.clone().run(shutdown).await;
+
+        assert!(matches!(submitted.await.unwrap(), Err(SinkError::Closed)));
+        assert!(matches!(sink.submit(event(2)).await, Err(SinkError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_submit_waits_while_buffer_is_full() {
+        let metrics = Metrics::new().unwrap();
+        let sink = Arc::new(
+            HttpSink::new(
+                "http://127.0.0.1:9/unused".to_string(),
+                1,
+                Duration::from_secs(60),
+                metrics,
+            )
+            .unwrap(),
+        );
+        for n in 0..MAX_BUFFERED_BATCHES as u64 {
+            submit(&sink, n).await;
+        }
+
+        let blocked = tokio::time::timeout(Duration::from_millis(50), sink.submit(event(99))).await;
+        assert!(blocked.is_err());
+        assert_eq!(buffered(&sink), MAX_BUFFERED_BATCHES);
+    }
+}
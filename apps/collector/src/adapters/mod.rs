@@ -1,2 +1,68 @@
-
+pub mod http;
 pub mod loki;
+pub mod parquet_export;
+pub mod sink;
+pub mod sqlite;
+pub mod wal;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use http::HttpSink;
+use sink::{Event, Sink, WriteError};
+use sqlite::SqliteSink;
+use wal::WalBuffer;
+
+/// Where the consumers put successfully processed events before acking them.
+#[derive(Clone)]
+pub enum LocalStore {
+    /// Written straight to SQLite.
+    Direct(Arc<SqliteSink>),
+    /// Appended to the WAL and forwarded to SQLite in the background.
+    Buffered(Arc<WalBuffer>),
+}
+
+/// Returns once the event is durable. A failed write is transient: the
+/// message is retried rather than acked without being stored.
+#[async_trait]
+impl Sink for LocalStore {
+    async fn write(&self, event: &Event<'_>) -> Result<(), WriteError> {
+        let result = match self {
+            Self::Direct(sink) => sink
+                .insert(
+                    event.routing_key,
+                    event.version,
+                    event.payload,
+                    event.delivery_tag,
+                )
+                .map_err(|e| e.to_string()),
+            Self::Buffered(wal) => wal
+                .append_event(event)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        };
+        result.map_err(|e| WriteError::Transient(format!("Failed to store event locally: {}", e)))
+    }
+}
+
+/// Where the consumers send processed events bound for the HTTP downstream.
+#[derive(Clone)]
+pub enum Downstream {
+    /// Handed to the sink; the message is acked once its batch is delivered.
+    Direct(Arc<HttpSink>),
+    /// Appended to a WAL, acked, and forwarded to the sink in the background.
+    Buffered(Arc<WalBuffer>),
+}
+
+#[async_trait]
+impl Sink for Downstream {
+    async fn write(&self, event: &Event<'_>) -> Result<(), WriteError> {
+        match self {
+            Self::Direct(sink) => sink.write(event).await,
+            Self::Buffered(wal) => wal.append_event(event).await.map(|_| ()).map_err(|e| {
+                WriteError::Transient(format!("Failed to buffer event for downstream: {}", e))
+            }),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Export of the local event store to a Parquet file, for columnar queries
+//! offline.
+
+use arrow::array::{ArrayRef, StringArray, TimestampMillisecondArray};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::io::Write;
+use std::sync::Arc;
+
+use super::sqlite::{SqliteSink, StoredEvent};
+
+/// Rows per row group unless `ExportOptions::row_group_size` says otherwise.
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 65_536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// Only events stored at or after this many milliseconds since the epoch.
+    pub from_ms: Option<u64>,
+    /// Only events stored before this many milliseconds since the epoch.
+    pub to_ms: Option<u64>,
+    /// Rows per row group. Events are read from the store a row group at a
+    /// time, so this also bounds memory use.
+    pub row_group_size: usize,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            from_ms: None,
+            to_ms: None,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Failed to read the local store: {0}")]
+    Store(#[from] rusqlite::Error),
+
+    #[error("Failed to build a record batch: {0}")]
+    Arrow(#[from] arrow::error::ArrowError),
+
+    #[error("Failed to write Parquet: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
+
+/// The columns of an export. Only ever extended at the end, so queries
+/// written against an older export keep working.
+///
+/// `timestamp` is when the event was stored, `event_type` the payload's
+/// `eventType` (v1) or `type` (v2) when it is JSON and has one, and
+/// `payload` the payload as stored.
+pub fn event_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("event_type", DataType::Utf8, true),
+        Field::new("routing_key", DataType::Utf8, false),
+        Field::new("version", DataType::Utf8, false),
+        Field::new("payload", DataType::Utf8, false),
+    ]))
+}
+
+/// Writes the stored events in the options' time range to `writer` as
+/// Snappy-compressed Parquet, oldest first, returning how many were written.
+pub fn export_parquet<W: Write + Send>(
+    store: &SqliteSink,
+    writer: W,
+    options: &ExportOptions,
+) -> Result<u64, ExportError> {
+    let schema = event_schema();
+    let row_group_size = options.row_group_size.max(1);
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(row_group_size)
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+
+    let mut after_id = 0;
+    let mut written = 0;
+    loop {
+        let page = store.page(options.from_ms, options.to_ms, after_id, row_group_size)?;
+        let Some(&(last_id, _)) = page.last() else {
+            break;
+        };
+        after_id = last_id;
+        let events: Vec<StoredEvent> = page.into_iter().map(|(_, event)| event).collect();
+        writer.write(&record_batch(&schema, &events)?)?;
+        written += events.len() as u64;
+    }
+    writer.close()?;
+    Ok(written)
+}
+
+fn record_batch(schema: &SchemaRef, events: &[StoredEvent]) -> Result<RecordBatch, ExportError> {
+    let timestamps =
+        TimestampMillisecondArray::from_iter_values(events.iter().map(|e| e.timestamp_ms as i64))
+            .with_timezone("UTC");
+    let event_types: StringArray = events.iter().map(StoredEvent::event_type).collect();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(timestamps),
+        Arc::new(event_types),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|e| e.routing_key.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|e| e.version.as_str()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|e| e.payload.as_str()),
+        )),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fs::File;
+
+    #[test]
+    fn test_export_reads_back_with_the_schema_and_every_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteSink::open(&dir.path().join("events.db")).unwrap();
+        for n in 0..5 {
+            let payload = format!(r#"{{"eventType":"log","payload":{{"n":{}}}}}"#, n);
+            store
+                .insert("telemetry", "v1", payload.as_bytes(), n)
+                .unwrap();
+        }
+        store.insert("telemetry", "v2", b"not json", 5).unwrap();
+
+        let path = dir.path().join("events.parquet");
+        let options = ExportOptions {
+            row_group_size: 4,
+            ..ExportOptions::default()
+        };
+        let written = export_parquet(&store, File::create(&path).unwrap(), &options).unwrap();
+        assert_eq!(written, 6);
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(builder.schema(), &event_schema());
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        let batches: Vec<RecordBatch> = builder.build().unwrap().map(Result::unwrap).collect();
+        let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+        assert_eq!(rows, 6);
+
+        let last = batches.last().unwrap();
+        let column = |name: &str| {
+            last.column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone()
+        };
+        let n = last.num_rows();
+        assert_eq!(column("event_type").value(n - 2), "log");
+        assert!(column("event_type").is_null(n - 1));
+        assert_eq!(column("version").value(n - 1), "v2");
+        assert_eq!(column("payload").value(n - 1), "not json");
+    }
+
+    #[test]
+    fn test_export_keeps_to_the_time_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteSink::open(&dir.path().join("events.db")).unwrap();
+        store.insert("telemetry", "v1", b"{}", 1).unwrap();
+        let stored_at = store.recent(1).unwrap()[0].timestamp_ms;
+
+        let export = |from_ms, to_ms| {
+            let options = ExportOptions {
+                from_ms,
+                to_ms,
+                ..ExportOptions::default()
+            };
+            export_parquet(&store, Vec::new(), &options).unwrap()
+        };
+
+        assert_eq!(export(Some(stored_at), Some(stored_at + 1)), 1);
+        assert_eq!(export(Some(stored_at + 1), None), 0);
+        assert_eq!(export(None, Some(stored_at)), 0);
+    }
+}
@@ -0,0 +1,160 @@
+//! Destinations for successfully handled events, and fan-out to several.
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use std::sync::Arc;
+
+use crate::messaging::HandlerError;
+
+/// A handled event on its way to the sinks, before the message is acked.
+#[derive(Debug, Clone, Copy)]
+pub struct Event<'a> {
+    pub routing_key: &'a str,
+    pub version: &'a str,
+    pub payload: &'a [u8],
+    pub delivery_tag: u64,
+    pub correlation_id: &'a str,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WriteError {
+    /// The message is retried like a transient handler failure.
+    #[error("{0}")]
+    Transient(String),
+
+    /// Retrying would not help; the message goes to the DLQ.
+    #[error("{0}")]
+    Permanent(String),
+}
+
+impl From<WriteError> for HandlerError {
+    fn from(error: WriteError) -> Self {
+        match error {
+            WriteError::Transient(reason) => HandlerError::transient(reason),
+            WriteError::Permanent(reason) => HandlerError::Permanent(reason),
+        }
+    }
+}
+
+/// Somewhere the consumer writes events once the handler has succeeded.
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Returns once the sink has taken the event.
+    async fn write(&self, event: &Event<'_>) -> Result<(), WriteError>;
+}
+
+/// Writes each event to every sink concurrently.
+///
+/// Every sink is written even if another fails, and all failures are
+/// reported together. A permanent failure from any sink makes the whole
+/// write permanent, since a retry could never satisfy that sink; otherwise
+/// any failure is transient. A retried message is written to every sink
+/// again, including those that took it the first time.
+#[derive(Clone, Default)]
+pub struct MultiSink {
+    sinks: Vec<Arc<dyn Sink>>,
+}
+
+impl MultiSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, sink: Arc<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+}
+
+#[async_trait]
+impl Sink for MultiSink {
+    async fn write(&self, event: &Event<'_>) -> Result<(), WriteError> {
+        let results = join_all(self.sinks.iter().map(|sink| sink.write(event))).await;
+
+        let mut permanent = false;
+        let mut reasons = Vec::new();
+        for error in results.into_iter().filter_map(Result::err) {
+            permanent |= matches!(error, WriteError::Permanent(_));
+            reasons.push(error.to_string());
+        }
+
+        match (reasons.is_empty(), permanent) {
+            (true, _) => Ok(()),
+            (false, true) => Err(WriteError::Permanent(reasons.join("; "))),
+            (false, false) => Err(WriteError::Transient(reasons.join("; "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::consumer::dead_letter_type;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        written: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait]
+    impl Sink for RecordingSink {
+        async fn write(&self, event: &Event<'_>) -> Result<(), WriteError> {
+            self.written.lock().unwrap().push(event.delivery_tag);
+            Ok(())
+        }
+    }
+
+    struct FailingSink(fn(String) -> WriteError);
+
+    #[async_trait]
+    impl Sink for FailingSink {
+        async fn write(&self, _event: &Event<'_>) -> Result<(), WriteError> {
+            Err((self.0)("sink unavailable".to_string()))
+        }
+    }
+
+    const EVENT: Event<'static> = Event {
+        routing_key: "telemetry",
+        version: "v1",
+        payload: b"{}",
+        delivery_tag: 7,
+        correlation_id: "req-7",
+    };
+
+    fn fan_out(failing: FailingSink) -> (MultiSink, Arc<RecordingSink>) {
+        let recording = Arc::new(RecordingSink::default());
+        let mut sinks = MultiSink::new();
+        sinks.push(Arc::new(failing));
+        sinks.push(recording.clone());
+        (sinks, recording)
+    }
+
+    #[tokio::test]
+    async fn test_transient_sink_failure_retries_message() {
+        let (sinks, recording) = fan_out(FailingSink(WriteError::Transient));
+
+        let error = HandlerError::from(sinks.write(&EVENT).await.unwrap_err());
+
+        // The healthy sink still got the event.
+        assert_eq!(*recording.written.lock().unwrap(), vec![7]);
+        assert!(
+            matches!(error, HandlerError::Transient { ref reason, .. } if reason == "sink unavailable")
+        );
+        assert_eq!(dead_letter_type(&error, 0, 3), None);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_sink_failure_dead_letters_message() {
+        let (sinks, recording) = fan_out(FailingSink(WriteError::Permanent));
+
+        let error = HandlerError::from(sinks.write(&EVENT).await.unwrap_err());
+
+        assert_eq!(*recording.written.lock().unwrap(), vec![7]);
+        assert_eq!(dead_letter_type(&error, 0, 3), Some("permanent"));
+    }
+
+    #[tokio::test]
+    async fn test_empty_fan_out_succeeds() {
+        assert!(MultiSink::new().write(&EVENT).await.is_ok());
+    }
+}
@@ -0,0 +1,246 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::wal::WalEntry;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id           INTEGER PRIMARY KEY AUTOINCREMENT,
+        routing_key  TEXT    NOT NULL,
+        version      TEXT    NOT NULL,
+        payload      TEXT    NOT NULL,
+        timestamp_ms INTEGER NOT NULL,
+        delivery_tag INTEGER NOT NULL
+    );
+";
+
+const INSERT_SQL: &str = "INSERT INTO events (routing_key, version, payload, timestamp_ms, delivery_tag)
+     VALUES (?1, ?2, ?3, ?4, ?5)";
+
+/// One successfully processed event, as stored locally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredEvent {
+    pub routing_key: String,
+    pub version: String,
+    pub payload: String,
+    /// Milliseconds since the epoch at which the event was stored.
+    pub timestamp_ms: u64,
+    pub delivery_tag: u64,
+}
+
+impl StoredEvent {
+    /// The payload's `eventType` (v1) or `type` (v2), when it is JSON and
+    /// has one.
+    pub fn event_type(&self) -> Option<String> {
+        let json: Value = serde_json::from_str(&self.payload).ok()?;
+        ["eventType", "type"]
+            .into_iter()
+            .find_map(|field| json.get(field)?.as_str().map(str::to_string))
+    }
+}
+
+/// Filter for `SqliteSink::query`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventQuery {
+    /// Only events stored at or after this many milliseconds since the epoch.
+    pub since_ms: Option<u64>,
+    /// Only events whose `StoredEvent::event_type` is this.
+    pub event_type: Option<String>,
+    pub limit: usize,
+}
+
+/// The `eventType` or `type` of the `payload` column, in SQL; null like
+/// `StoredEvent::event_type` when the payload is not JSON.
+const EVENT_TYPE_SQL: &str = "CASE WHEN json_valid(payload) THEN
+         coalesce(json_extract(payload, '$.eventType'), json_extract(payload, '$.type'))
+     END";
+
+/// Local SQLite store of every successfully processed event.
+///
+/// Writes are synchronous and complete before the message is acked, so an
+/// event the broker has forgotten is always on disk.
+pub struct SqliteSink {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteSink {
+    /// Opens the database at `path`, creating it and the `events` table if needed.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn insert(
+        &self,
+        routing_key: &str,
+        version: &str,
+        payload: &[u8],
+        delivery_tag: u64,
+    ) -> rusqlite::Result<()> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.insert_at(routing_key, version, payload, delivery_tag, timestamp_ms)
+    }
+
+    /// Inserts every entry in one transaction, so either all of them are
+    /// stored or none are.
+    pub fn insert_entries(&self, entries: &[WalEntry]) -> rusqlite::Result<()> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(INSERT_SQL)?;
+            for entry in entries {
+                statement.execute(params![
+                    entry.routing_key,
+                    entry.version,
+                    String::from_utf8_lossy(&entry.payload),
+                    timestamp_ms as i64,
+                    entry.delivery_tag as i64,
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Like `insert`, stamping the row with `timestamp_ms` instead of now.
+    pub fn insert_at(
+        &self,
+        routing_key: &str,
+        version: &str,
+        payload: &[u8],
+        delivery_tag: u64,
+        timestamp_ms: u64,
+    ) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            INSERT_SQL,
+            params![
+                routing_key,
+                version,
+                String::from_utf8_lossy(payload),
+                timestamp_ms as i64,
+                delivery_tag as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The `limit` most recently stored events, newest first.
+    pub fn recent(&self, limit: usize) -> rusqlite::Result<Vec<StoredEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT routing_key, version, payload, timestamp_ms, delivery_tag
+             FROM events ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = statement.query_map([limit as i64], |row| stored_event(row, 0))?;
+        rows.collect()
+    }
+
+    /// The newest `query.limit` events matching `query`, newest first, each
+    /// with its row id.
+    pub fn query(&self, query: &EventQuery) -> rusqlite::Result<Vec<(i64, StoredEvent)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&format!(
+            "SELECT id, routing_key, version, payload, timestamp_ms, delivery_tag
+             FROM events
+             WHERE timestamp_ms >= ?1 AND (?2 IS NULL OR {} = ?2)
+             ORDER BY id DESC LIMIT ?3",
+            EVENT_TYPE_SQL
+        ))?;
+        let since_ms = query.since_ms.map_or(i64::MIN, |ms| ms.min(i64::MAX as u64) as i64);
+        let rows = statement.query_map(
+            params![since_ms, query.event_type, query.limit as i64],
+            |row| Ok((row.get(0)?, stored_event(row, 1)?)),
+        )?;
+        rows.collect()
+    }
+
+    /// The event stored in row `id`, if there is one.
+    pub fn get(&self, id: i64) -> rusqlite::Result<Option<StoredEvent>> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT routing_key, version, payload, timestamp_ms, delivery_tag
+                 FROM events WHERE id = ?1",
+                [id],
+                |row| stored_event(row, 0),
+            )
+            .optional()
+    }
+
+    /// Up to `limit` events stored at or after `from_ms` and before `to_ms`,
+    /// oldest first, starting after row `after_id`. Each comes with its row
+    /// id, to pass as `after_id` for the next page.
+    pub fn page(
+        &self,
+        from_ms: Option<u64>,
+        to_ms: Option<u64>,
+        after_id: i64,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<(i64, StoredEvent)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT id, routing_key, version, payload, timestamp_ms, delivery_tag
+             FROM events
+             WHERE id > ?1 AND timestamp_ms >= ?2 AND timestamp_ms < ?3
+             ORDER BY id LIMIT ?4",
+        )?;
+        let from_ms = from_ms.map_or(i64::MIN, |ms| ms.min(i64::MAX as u64) as i64);
+        let to_ms = to_ms.map_or(i64::MAX, |ms| ms.min(i64::MAX as u64) as i64);
+        let rows = statement.query_map(params![after_id, from_ms, to_ms, limit as i64], |row| {
+            Ok((row.get(0)?, stored_event(row, 1)?))
+        })?;
+        rows.collect()
+    }
+}
+
+/// Reads the columns `routing_key, version, payload, timestamp_ms,
+/// delivery_tag` starting at column `first`.
+fn stored_event(row: &rusqlite::Row<'_>, first: usize) -> rusqlite::Result<StoredEvent> {
+    Ok(StoredEvent {
+        routing_key: row.get(first)?,
+        version: row.get(first + 1)?,
+        payload: row.get(first + 2)?,
+        timestamp_ms: row.get::<_, i64>(first + 3)? as u64,
+        delivery_tag: row.get::<_, i64>(first + 4)? as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+        let sink = SqliteSink::open(&path).unwrap();
+
+        sink.insert("telemetry", "v1", br#"{"eventType":"log"}"#, 7)
+            .unwrap();
+        drop(sink);
+
+        // Reopening must keep the schema and the row.
+        let events = SqliteSink::open(&path).unwrap().recent(10).unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.routing_key, "telemetry");
+        assert_eq!(event.version, "v1");
+        assert_eq!(event.payload, r#"{"eventType":"log"}"#);
+        assert_eq!(event.delivery_tag, 7);
+        assert!(event.timestamp_ms > 0);
+    }
+}
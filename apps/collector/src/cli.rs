@@ -0,0 +1,232 @@
+//! Command line of the `collector` binary.
+//!
+//! Every mode reads the same environment (and `CONFIG_PATH` file) as the
+//! consumer; the arguments only pick the mode and its options.
+
+use clap::builder::RangedU64ValueParser;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::adapters::parquet_export::{ExportOptions, DEFAULT_ROW_GROUP_SIZE};
+use crate::messaging::ReplayOptions;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "collector",
+    version,
+    about = "Telemetry collector for the local observability runtime"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The mode to run; `run` when no subcommand was given.
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Run)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Consume the configured queues until shutdown (the default).
+    Run,
+    /// Republish messages from a queue's DLQ back onto the queue.
+    DlqReplay(DlqReplayArgs),
+    /// Load the configuration, print it with secrets masked, and exit.
+    ValidateConfig,
+    /// Publish sample log events to a queue.
+    PublishTest(PublishTestArgs),
+    /// Write the events in the local store to a Parquet file.
+    ParquetExport(ParquetExportArgs),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct DlqReplayArgs {
+    /// Queue whose `<queue>.dlq` is replayed.
+    #[arg(long, default_value = "telemetry")]
+    pub queue: String,
+    /// Stop after replaying this many messages.
+    #[arg(long)]
+    pub max: Option<u64>,
+    /// Count the messages that would be replayed without moving them.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl DlqReplayArgs {
+    pub fn options(&self) -> ReplayOptions {
+        ReplayOptions {
+            max: self.max,
+            dry_run: self.dry_run,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct PublishTestArgs {
+    /// Queue the events are published to, through the default exchange.
+    #[arg(long, default_value = "telemetry")]
+    pub queue: String,
+    /// Number of events to publish.
+    #[arg(long, default_value_t = 5)]
+    pub count: u32,
+    /// Milliseconds to wait between events.
+    #[arg(long, default_value_t = 500)]
+    pub interval_ms: u64,
+}
+
+impl PublishTestArgs {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ParquetExportArgs {
+    /// File the events are written to, replaced if it exists.
+    #[arg(long)]
+    pub output: PathBuf,
+    /// Only events stored at or after this time, in milliseconds since the epoch.
+    #[arg(long)]
+    pub from_ms: Option<u64>,
+    /// Only events stored before this time, in milliseconds since the epoch.
+    #[arg(long)]
+    pub to_ms: Option<u64>,
+    /// Rows per Parquet row group.
+    #[arg(
+        long,
+        default_value_t = DEFAULT_ROW_GROUP_SIZE,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub row_group_size: usize,
+}
+
+impl ParquetExportArgs {
+    pub fn options(&self) -> ExportOptions {
+        ExportOptions {
+            from_ms: self.from_ms,
+            to_ms: self.to_ms,
+            row_group_size: self.row_group_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, clap::Error> {
+        Cli::try_parse_from(std::iter::once("collector").chain(args.iter().copied()))
+            .map(Cli::command)
+    }
+
+    #[test]
+    fn test_no_subcommand_runs_the_consumer() {
+        assert_eq!(parse(&[]).unwrap(), Command::Run);
+        assert_eq!(parse(&["run"]).unwrap(), Command::Run);
+    }
+
+    #[test]
+    fn test_dlq_replay_arguments() {
+        assert_eq!(
+            parse(&["dlq-replay"]).unwrap(),
+            Command::DlqReplay(DlqReplayArgs {
+                queue: "telemetry".into(),
+                max: None,
+                dry_run: false,
+            })
+        );
+
+        let Command::DlqReplay(args) = parse(&[
+            "dlq-replay",
+            "--queue",
+            "orders",
+            "--max",
+            "100",
+            "--dry-run",
+        ])
+        .unwrap() else {
+            panic!("expected dlq-replay");
+        };
+        assert_eq!(args.queue, "orders");
+        assert_eq!(args.options().max, Some(100));
+        assert!(args.options().dry_run);
+
+        assert!(parse(&["dlq-replay", "--max", "lots"]).is_err());
+    }
+
+    #[test]
+    fn test_validate_config_takes_no_arguments() {
+        assert_eq!(
+            parse(&["validate-config"]).unwrap(),
+            Command::ValidateConfig
+        );
+        assert!(parse(&["validate-config", "--queue", "orders"]).is_err());
+    }
+
+    #[test]
+    fn test_publish_test_arguments() {
+        assert_eq!(
+            parse(&["publish-test"]).unwrap(),
+            Command::PublishTest(PublishTestArgs {
+                queue: "telemetry".into(),
+                count: 5,
+                interval_ms: 500,
+            })
+        );
+
+        let Command::PublishTest(args) =
+            parse(&["publish-test", "--count", "2", "--interval-ms", "0"]).unwrap()
+        else {
+            panic!("expected publish-test");
+        };
+        assert_eq!(args.count, 2);
+        assert_eq!(args.interval(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_parquet_export_arguments() {
+        assert!(parse(&["parquet-export"]).is_err());
+
+        let Command::ParquetExport(args) =
+            parse(&["parquet-export", "--output", "events.parquet"]).unwrap()
+        else {
+            panic!("expected parquet-export");
+        };
+        assert_eq!(args.options(), ExportOptions::default());
+
+        let Command::ParquetExport(args) = parse(&[
+            "parquet-export",
+            "--output",
+            "events.parquet",
+            "--from-ms",
+            "1700000000000",
+            "--to-ms",
+            "1700003600000",
+            "--row-group-size",
+            "1000",
+        ])
+        .unwrap() else {
+            panic!("expected parquet-export");
+        };
+        assert_eq!(args.output, PathBuf::from("events.parquet"));
+        assert_eq!(
+            args.options(),
+            ExportOptions {
+                from_ms: Some(1_700_000_000_000),
+                to_ms: Some(1_700_003_600_000),
+                row_group_size: 1000,
+            }
+        );
+
+        assert!(parse(&["parquet-export", "--output", "x", "--row-group-size", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_subcommand_is_rejected() {
+        assert!(parse(&["replay"]).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use prometheus::Counter;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(5);
+
+/// Source of the current wall-clock time, injected where timestamps are
+/// written so tests can pin them.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's real-time clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock stopped at one moment.
+#[cfg(test)]
+pub(crate) struct FixedClock(pub SystemTime);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+/// Compares producer timestamps against the local clock without letting
+/// clock skew corrupt latency observations.
+///
+/// Every feature that measures time since a producer timestamp goes through
+/// `safe_elapsed`, so a skewed producer is handled the same way everywhere: a
+/// timestamp slightly in the future is clamped to zero elapsed, and one further
+/// ahead than `max_skew` is reported and skipped.
+#[derive(Clone)]
+pub struct ClockGuard {
+    max_skew: Duration,
+    skew_detected: Counter,
+}
+
+impl ClockGuard {
+    pub fn new(max_skew: Duration, metrics: &Metrics) -> Self {
+        Self {
+            max_skew,
+            skew_detected: metrics.clock_skew_detected_total.clone(),
+        }
+    }
+
+    /// Time elapsed since `producer_ts` on the local clock.
+    pub fn safe_elapsed(&self, producer_ts: SystemTime) -> Option<Duration> {
+        self.safe_elapsed_at(producer_ts, SystemTime::now())
+    }
+
+    pub fn safe_elapsed_at(&self, producer_ts: SystemTime, now: SystemTime) -> Option<Duration> {
+        match now.duration_since(producer_ts) {
+            Ok(elapsed) => Some(elapsed),
+            Err(e) if e.duration() <= self.max_skew => Some(Duration::ZERO),
+            Err(e) => {
+                self.skew_detected.inc();
+                warn!(
+                    ahead_ms = e.duration().as_millis() as u64,
+                    max_skew_ms = self.max_skew.as_millis() as u64,
+                    "Producer timestamp is in the future, skipping latency observation"
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_past_timestamps_report_elapsed_time() {
+        let metrics = Metrics::new().unwrap();
+        let clock = ClockGuard::new(Duration::from_secs(5), &metrics);
+
+        assert_eq!(
+            clock.safe_elapsed_at(at(1_700_000_000), at(1_700_000_042)),
+            Some(Duration::from_secs(42))
+        );
+        assert_eq!(metrics.clock_skew_detected_total.get(), 0.0);
+    }
+
+    #[test]
+    fn test_future_timestamps_are_clamped_or_skipped() {
+        let metrics = Metrics::new().unwrap();
+        let clock = ClockGuard::new(Duration::from_secs(5), &metrics);
+        let now = at(1_700_000_000);
+
+        assert_eq!(
+            clock.safe_elapsed_at(at(1_700_000_003), now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(metrics.clock_skew_detected_total.get(), 0.0);
+
+        assert_eq!(clock.safe_elapsed_at(at(1_700_000_060), now), None);
+        assert_eq!(metrics.clock_skew_detected_total.get(), 1.0);
+    }
+}
@@ -0,0 +1,139 @@
+//! The subset of TOML accepted in a config file: one `key = value` per line,
+//! where a value is a string, number, boolean or single-line array. Tables
+//! are rejected, since every setting is top-level.
+
+use std::collections::HashMap;
+
+/// Parses `text` into raw values keyed by environment variable name, so they
+/// go through the same parsing and validation as the environment.
+///
+/// Arrays become comma-separated lists, the format list settings use.
+pub(super) fn parse(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut values = HashMap::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(format!("line {}: tables are not supported", line_no));
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", line_no))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("line {}: invalid key `{}`", line_no, key));
+        }
+        let value =
+            parse_value(value.trim()).map_err(|reason| format!("line {}: {}", line_no, reason))?;
+
+        if values.insert(key.to_ascii_uppercase(), value).is_some() {
+            return Err(format!("line {}: duplicate key `{}`", line_no, key));
+        }
+    }
+
+    Ok(values)
+}
+
+/// Drops a trailing `#` comment that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn parse_value(raw: &str) -> Result<String, String> {
+    if let Some(items) = raw.strip_prefix('[') {
+        let items = items
+            .strip_suffix(']')
+            .ok_or("unterminated array; arrays must fit on one line")?;
+        return split_items(items)?
+            .into_iter()
+            .map(|item| parse_scalar(item.trim()))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|items| items.join(","));
+    }
+    parse_scalar(raw)
+}
+
+fn parse_scalar(raw: &str) -> Result<String, String> {
+    if let Some(literal) = raw.strip_prefix('\'') {
+        return literal
+            .strip_suffix('\'')
+            .map(str::to_string)
+            .ok_or_else(|| "unterminated string".to_string());
+    }
+    if let Some(basic) = raw.strip_prefix('"') {
+        let basic = basic.strip_suffix('"').ok_or("unterminated string")?;
+        return unescape(basic);
+    }
+    if raw.is_empty() {
+        return Err("missing value".to_string());
+    }
+    // Numbers and booleans are kept as written, minus TOML digit separators.
+    Ok(raw.replace('_', ""))
+}
+
+fn unescape(raw: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            other => return Err(format!("unsupported escape `\\{}`", other.unwrap_or(' '))),
+        }
+    }
+    Ok(out)
+}
+
+/// Splits array items on commas outside strings, allowing a trailing comma.
+fn split_items(raw: &str) -> Result<Vec<&str>, String> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in raw.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ',' => {
+                items.push(&raw[start..i]);
+                start = i + 1;
+            }
+            None => {}
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated string".to_string());
+    }
+    items.push(&raw[start..]);
+    Ok(items
+        .into_iter()
+        .filter(|item| !item.trim().is_empty())
+        .collect())
+}
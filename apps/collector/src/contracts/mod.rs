@@ -1,3 +1,7 @@
 pub mod processing_error;
+pub mod telemetry_event;
+pub mod v1_event;
 
 pub use processing_error::ProcessingError;
+pub use telemetry_event::TelemetryEvent;
+pub use v1_event::V1Event;
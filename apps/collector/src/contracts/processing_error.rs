@@ -66,6 +66,7 @@ impl std::error::Error for ProcessingError {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messaging::HandlerError;
 
     #[test]
     fn test_transient_error() {
@@ -84,4 +85,33 @@ mod tests {
         assert_eq!(err.reason(), "Invalid schema");
         assert_eq!(err.error_type(), "permanent");
     }
+
+    fn validate(payload: &str) -> Result<(), ProcessingError> {
+        match payload {
+            "" => Err(ProcessingError::permanent("Empty payload")),
+            "busy" => Err(ProcessingError::transient("Downstream busy")),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle(payload: &str) -> Result<(), HandlerError> {
+        validate(payload)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_transient_converts_to_unclassified_transient() {
+        let err = handle("busy").unwrap_err();
+        assert!(
+            matches!(&err, HandlerError::Transient { reason, class: None } if reason == "Downstream busy")
+        );
+        assert_eq!(err.class(), None);
+    }
+
+    #[test]
+    fn test_permanent_converts_to_permanent() {
+        let err = handle("").unwrap_err();
+        assert!(matches!(err, HandlerError::Permanent(reason) if reason == "Empty payload"));
+        assert!(handle("{}").is_ok());
+    }
 }
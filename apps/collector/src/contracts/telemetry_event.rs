@@ -0,0 +1,78 @@
+//! The protobuf form of a v1 telemetry event, defined in
+//! `proto/telemetry_event.proto`.
+//!
+//! The message is derived by hand rather than generated at build time, so
+//! building the collector does not need `protoc`. Keep the tags in sync with
+//! the `.proto`.
+
+use prost_types::value::Kind;
+use serde_json::{Map, Number, Value};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TelemetryEvent {
+    #[prost(string, optional, tag = "1")]
+    pub event_id: Option<String>,
+    #[prost(string, tag = "2")]
+    pub event_type: String,
+    #[prost(int32, optional, tag = "3")]
+    pub event_version: Option<i32>,
+    #[prost(string, optional, tag = "4")]
+    pub timestamp: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub correlation_id: Option<String>,
+    #[prost(message, optional, tag = "6")]
+    pub payload: Option<prost_types::Struct>,
+}
+
+impl TelemetryEvent {
+    /// The event as the JSON path would have parsed it: camelCase keys, with
+    /// unset optional fields left out.
+    pub fn to_json(&self) -> Value {
+        let mut event = Map::new();
+        if let Some(id) = &self.event_id {
+            event.insert("eventId".into(), id.clone().into());
+        }
+        event.insert("eventType".into(), self.event_type.clone().into());
+        if let Some(version) = self.event_version {
+            event.insert("eventVersion".into(), version.into());
+        }
+        if let Some(timestamp) = &self.timestamp {
+            event.insert("timestamp".into(), timestamp.clone().into());
+        }
+        if let Some(correlation_id) = &self.correlation_id {
+            event.insert("correlationId".into(), correlation_id.clone().into());
+        }
+        if let Some(payload) = &self.payload {
+            event.insert("payload".into(), struct_to_json(payload));
+        }
+        Value::Object(event)
+    }
+}
+
+fn struct_to_json(fields: &prost_types::Struct) -> Value {
+    Value::Object(
+        fields
+            .fields
+            .iter()
+            .map(|(key, value)| (key.clone(), value_to_json(value)))
+            .collect(),
+    )
+}
+
+fn value_to_json(value: &prost_types::Value) -> Value {
+    match &value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(b)) => Value::Bool(*b),
+        // `Struct` numbers are doubles; whole ones become JSON integers so
+        // they validate as `"type": "integer"` like their JSON counterparts.
+        Some(Kind::NumberValue(n)) if n.fract() == 0.0 && n.abs() < 2f64.powi(53) => {
+            Value::from(*n as i64)
+        }
+        Some(Kind::NumberValue(n)) => Number::from_f64(*n).map_or(Value::Null, Value::Number),
+        Some(Kind::StringValue(s)) => Value::String(s.clone()),
+        Some(Kind::StructValue(s)) => struct_to_json(s),
+        Some(Kind::ListValue(list)) => {
+            Value::Array(list.values.iter().map(value_to_json).collect())
+        }
+    }
+}
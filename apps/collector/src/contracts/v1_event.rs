@@ -0,0 +1,84 @@
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+/// A v1 telemetry event, as described by `schemas/event.v1.json` and the
+/// TypeScript `EventEnvelope`.
+///
+/// Parsed from the JSON once it passed the schema, so the types here only
+/// fail on what the schema leaves open: a missing `eventType`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct V1Event {
+    pub event_type: String,
+    /// Empty when missing, which only lenient validation lets through.
+    #[serde(default)]
+    pub payload: Map<String, Value>,
+    pub event_id: Option<String>,
+    pub event_version: Option<i64>,
+    pub timestamp: Option<String>,
+    pub correlation_id: Option<String>,
+    /// The producing system. Not part of the schema, so a value that is not
+    /// a string is ignored rather than rejected.
+    #[serde(default, deserialize_with = "string_or_none")]
+    pub source: Option<String>,
+}
+
+impl V1Event {
+    pub fn from_json(json: &Value) -> Result<Self, serde_json::Error> {
+        Self::deserialize(json)
+    }
+}
+
+fn string_or_none<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(s) => Some(s),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_well_formed_event_is_typed() {
+        let event = V1Event::from_json(&json!({
+            "eventId": "evt-1",
+            "eventType": "telemetry.log.captured",
+            "eventVersion": 1,
+            "timestamp": "2024-01-01T00:00:00Z",
+            "correlationId": "req-42",
+            "source": "checkout",
+            "payload": {"level": "info", "message": "ok", "serviceName": "api"},
+        }))
+        .unwrap();
+
+        assert_eq!(event.event_type, "telemetry.log.captured");
+        assert_eq!(event.event_id.as_deref(), Some("evt-1"));
+        assert_eq!(event.event_version, Some(1));
+        assert_eq!(event.timestamp.as_deref(), Some("2024-01-01T00:00:00Z"));
+        assert_eq!(event.correlation_id.as_deref(), Some("req-42"));
+        assert_eq!(event.source.as_deref(), Some("checkout"));
+        assert_eq!(event.payload["serviceName"], "api");
+    }
+
+    #[test]
+    fn test_missing_event_type_fails_to_deserialize() {
+        let err = V1Event::from_json(&json!({"payload": {}})).unwrap_err();
+
+        assert!(
+            err.to_string().contains("missing field `eventType`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_optional_fields_may_be_absent() {
+        let event = V1Event::from_json(&json!({"eventType": "log", "source": 7})).unwrap();
+
+        assert!(event.payload.is_empty());
+        assert_eq!(event.timestamp, None);
+        assert_eq!(event.source, None);
+    }
+}
@@ -1,7 +1,10 @@
 
 pub mod adapters;
+pub mod cli;
+pub mod clock;
 pub mod config;
 pub mod contracts;
+pub mod logging;
 pub mod messaging;
 pub mod metrics;
 pub mod processors;
@@ -1,120 +1,467 @@
-use std::sync::Arc;
-use async_trait::async_trait;
-use lapin::message::Delivery;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use lapin::options::QueueDeclareOptions;
+use lapin::types::FieldTable;
+use clap::Parser;
+use lapin::{BasicProperties, Channel};
+use futures::future::join_all;
 use tokio::sync::Notify;
-use tracing::{info, warn, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing::{error, info, warn, Level};
+use tracing_subscriber::filter::{filter_fn, FilterExt, LevelFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
 
-mod config;
-
-use config::Config;
+use observability_collector::adapters::parquet_export::export_parquet;
+use observability_collector::adapters::sqlite::SqliteSink;
+use observability_collector::adapters::wal::{WalBuffer, WalTarget};
+use observability_collector::adapters::http::HttpSink;
+use observability_collector::adapters::sink::MultiSink;
+use observability_collector::adapters::{Downstream, LocalStore};
+use observability_collector::cli::{Cli, Command, DlqReplayArgs, ParquetExportArgs, PublishTestArgs};
+use observability_collector::config::Config;
+use observability_collector::logging::{fmt_layer, LogFormat};
 use observability_collector::messaging::{
-    ChannelProvider, Consumer, HandlerError, MessageHandler, RabbitMqConnection,
+    default_connection_name, process_spool_pass, publish_confirmed, reconnect_delay, replay_dlq, verify_topology, AckWindow, CachingHandler, ChannelProvider, CircuitBreaker, ConnectionBroker,
+    ConnectionError, Consumer, ConsumerError, DedupCache, DlqReanimator, ExchangeDeclaration, LocalFileSource, MessageHandler,
+    PrefetchSettings, PrefetchTuner, Quarantine, QueueRole, QueueTopology, RabbitMqConnection, RateLimiter, ReanimatorSettings, SignatureFailureMode, SignatureVerifier, TlsConfig,
+    TopologySpec,
 };
-use observability_collector::metrics::{server::start_metrics_server, Metrics};
+use observability_collector::metrics::health::{set_connected, ConnectionMonitor};
+use observability_collector::metrics::heartbeat::Heartbeat;
+use observability_collector::metrics::queue_depth::{ConnectionDepthSource, QueueDepthPoller};
+use observability_collector::metrics::recent::RecentEvents;
+use observability_collector::metrics::resource::ResourceAttributes;
+use observability_collector::metrics::server::{start_metrics_server, ServerState};
+use observability_collector::metrics::statsd::StatsdPusher;
+use observability_collector::metrics::Metrics;
+use observability_collector::processors::log_processor::{process_log_captured, LOG_CAPTURED};
+use observability_collector::processors::registry::HandlerRegistry;
+use observability_collector::processors::sampler::SuccessSampler;
+use observability_collector::processors::schema::EventSchema;
+use observability_collector::processors::telemetry::TelemetryHandler;
 
-struct TelemetryHandler;
+const DLQ_REANIMATE_SCAN_LIMIT: usize = 1000;
+/// Messages whose failures `QUARANTINE_THRESHOLD` keeps count of.
+const QUARANTINE_CAPACITY: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+/// How long consumers get, past `SHUTDOWN_TIMEOUT_SECS`, for the handlers
+/// cancelled at the end of the grace period to return.
+const CANCELLED_HANDLER_WAIT: Duration = Duration::from_secs(2);
+/// Subdirectory of `WAL_DIR` holding the downstream's WAL; the local
+/// store's lives in `WAL_DIR` itself.
+const DOWNSTREAM_WAL_DIR: &str = "downstream";
 
-const EVENT_VERSION_HEADER: &str = "x-event-version";
+/// Subscriber layer exporting spans, present when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+type SpanLayer = Box<dyn Layer<Registry> + Send + Sync>;
+/// Final export run at shutdown.
+type Flush = Box<dyn FnOnce() + Send>;
 
-#[async_trait]
-impl MessageHandler for TelemetryHandler {
-    async fn handle(&self, delivery: Delivery) -> Result<(), HandlerError> {
-        let payload = String::from_utf8_lossy(&delivery.data);
-        
-        // Extract version from headers
-        let version = delivery
-            .properties
-            .headers()
-            .as_ref()
-            .and_then(|headers| headers.inner().get(EVENT_VERSION_HEADER))
-            .and_then(|value| match value {
-                lapin::types::AMQPValue::LongString(s) => Some(s.to_string()),
-                _ => None,
-            })
-            .unwrap_or_else(|| "v1".to_string());
+#[tokio::main]
+async fn main() {
+    match Cli::parse().command() {
+        Command::Run => run().await,
+        Command::DlqReplay(args) => dlq_replay(args).await,
+        Command::ValidateConfig => validate_config(),
+        Command::PublishTest(args) => publish_test(args).await,
+        Command::ParquetExport(args) => parquet_export(args),
+    }
+}
 
-        info!(
-            routing_key = delivery.routing_key.as_str(),
-            version = %version,
-            payload_preview = %payload.chars().take(100).collect::<String>(),
-            "Handling telemetry message"
-        );
+/// Loads the configuration, exiting with its error when it is invalid.
+fn load_config() -> Config {
+    match Config::load() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-        // Version-based routing
-        match version.as_str() {
-            "v1" => self.handle_v1(&payload),
-            _ => {
-                return Err(HandlerError::Permanent(format!(
-                    "Unsupported event version: {}. Only v1 is supported.",
-                    version
-                )));
-            }
+/// Prints the effective configuration, after the file and environment are
+/// merged, with secrets masked. Exits 1 when it does not load.
+fn validate_config() {
+    let config = load_config();
+    if let Err(e) = tls_config(&config) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    println!("{:#?}", config.redacted());
+    println!("Configuration is valid");
+}
+
+/// Connection and confirm-mode channel for a one-off command, named
+/// `<SERVICE_NAME>-<command>` in the management UI unless `CONNECTION_NAME`
+/// is set.
+async fn command_channel(config: &Config, command: &str) -> (RabbitMqConnection, Channel) {
+    let connection_name = config.connection_name.clone().unwrap_or_else(|| {
+        default_connection_name(&format!("{}-{}", config.service_name, command))
+    });
+    let tls = match tls_config(config) {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let rabbitmq =
+        match RabbitMqConnection::connect(config.rabbitmq_url.clone(), &tls, &connection_name).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to connect to RabbitMQ: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let channel = ChannelProvider::create_channel(
+        rabbitmq.get_connection(),
+        config.prefetch_count,
+        config.qos_global,
+    )
+    .await;
+    match channel {
+        Ok(channel) => (rabbitmq, channel),
+        Err(e) => {
+            eprintln!("Failed to create RabbitMQ channel: {}", e);
+            std::process::exit(1);
         }
     }
 }
 
-impl TelemetryHandler {
-    fn handle_v1(&self, payload: &str) -> Result<(), HandlerError> {
-        // Test error simulation
-        if payload.contains("\"fail\":\"transient\"") {
-            return Err(HandlerError::Transient("Simulated transient failure".to_string()));
+/// Writes the events in `LOCAL_STORE_PATH` to a Parquet file. The store
+/// may be in use by a running collector; rows it adds meanwhile may or may
+/// not make it into the file.
+fn parquet_export(args: ParquetExportArgs) {
+    let config = load_config();
+    let Some(store_path) = &config.local_store_path else {
+        eprintln!("parquet-export needs LOCAL_STORE_PATH, the store to export");
+        std::process::exit(1);
+    };
+    let store = match SqliteSink::open(store_path) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Failed to open local store {}: {}", store_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let output = match std::fs::File::create(&args.output) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to create {}: {}", args.output.display(), e);
+            std::process::exit(1);
+        }
+    };
+    match export_parquet(&store, output, &args.options()) {
+        Ok(rows) => println!("Exported {} event(s) to {}", rows, args.output.display()),
+        Err(e) => {
+            eprintln!("Export failed: {}", e);
+            std::process::exit(1);
         }
+    }
+}
+
+/// Republishes messages from a queue's DLQ back onto the queue, exiting
+/// once the DLQ is empty or `--max` messages have been replayed.
+async fn dlq_replay(args: DlqReplayArgs) {
+    let config = load_config();
+    tracing_subscriber::fmt().with_target(false).init();
+    let (rabbitmq, channel) = command_channel(&config, "dlq-replay").await;
 
-        if payload.contains("\"fail\":\"permanent\"") {
-            return Err(HandlerError::Permanent("Simulated permanent failure".to_string()));
+    let options = args.options();
+    let result = replay_dlq(&channel, &args.queue, &options).await;
+    let _ = rabbitmq.shutdown().await;
+    match result {
+        Ok(stats) => {
+            let verb = if options.dry_run { "Would replay" } else { "Replayed" };
+            println!(
+                "{} {} message(s) from {}.dlq; {} remaining",
+                verb, stats.replayed, args.queue, stats.remaining
+            );
+        }
+        Err(e) => {
+            eprintln!("Replay failed: {}", e);
+            std::process::exit(1);
         }
+    }
+}
 
-        // Parse and validate v1 schema
-        match serde_json::from_str::<serde_json::Value>(payload) {
-            Ok(json) => {
-                // Basic v1 validation
-                if !json.get("eventType").is_some() {
-                    return Err(HandlerError::Permanent(
-                        "Missing required field: eventType".to_string(),
-                    ));
-                }
-                if !json.get("payload").is_some() {
-                    return Err(HandlerError::Permanent(
-                        "Missing required field: payload".to_string(),
-                    ));
-                }
-                
-                info!("Successfully processed v1 event");
-                Ok(())
-            }
-            Err(e) => {
-                Err(HandlerError::Permanent(format!(
-                    "Invalid JSON payload: {}",
-                    e
-                )))
-            }
+/// Publishes `--count` sample log events to a queue, waiting for the
+/// broker's confirmation of each, to try a deployment end to end.
+async fn publish_test(args: PublishTestArgs) {
+    let config = load_config();
+    tracing_subscriber::fmt().with_target(false).init();
+    let (rabbitmq, channel) = command_channel(&config, "publish-test").await;
+
+    for i in 1..=args.count {
+        let message = format!(
+            r#"{{"type":"log","service":"test-app","message":"Test message {}"}}"#,
+            i
+        );
+        let properties = BasicProperties::default()
+            .with_message_id(format!("test-msg-{}", uuid::Uuid::new_v4()).into());
+        let published = publish_confirmed(&channel, &args.queue, message.as_bytes(), properties).await;
+        if let Err(e) = published {
+            eprintln!("Failed to publish message {}: {}", i, e);
+            std::process::exit(1);
+        }
+        println!("Published message {} to {}", i, args.queue);
+        if i < args.count {
+            tokio::time::sleep(args.interval()).await;
         }
     }
+    let _ = rabbitmq.shutdown().await;
 }
 
-#[tokio::main]
-async fn main() {
+/// Consumes the configured queues until shutdown.
+async fn run() {
     setup_panic_handler();
-    let config = match Config::from_env() {
-        Ok(cfg) => cfg,
-        Err(e) => {
-            eprintln!("Configuration error: {}", e);
-            std::process::exit(1);
+    let mut config = load_config();
+    let resource = ResourceAttributes::new(&config.service_name, &config.resource_attributes);
+    config.metrics.const_labels = resource.metric_labels();
+
+    let (span_layer, span_flush) = match &config.otel_exporter_otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, flush) = start_span_export(endpoint, &resource);
+            (Some(layer), Some(flush))
         }
+        None => (None, None),
     };
+    setup_logging(&config.rust_log, config.log_format, span_layer);
+    if let Some(endpoint) = &config.otel_exporter_otlp_endpoint {
+        info!(endpoint = %endpoint, "Exporting message processing spans over OTLP");
+    }
 
-    setup_logging(&config.rust_log);
+    if let Err(e) = tls_config(&config) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
     info!(
         version = env!("CARGO_PKG_VERSION"),
         service_name = %config.service_name,
+        delivery_mode = config.delivery_mode.as_str(),
         "Observability Collector starting"
     );
 
-    let rabbitmq = match RabbitMqConnection::connect(config.rabbitmq_url.clone()).await {
+    let metrics = Metrics::with_config(&config.metrics).expect("Failed to create metrics");
+
+    let recent = Arc::new(RecentEvents::new(config.recent_buffer_size));
+    let mut server_state = ServerState::new(metrics.clone())
+        .with_prometheus_endpoint(config.prometheus_metrics_enabled)
+        .with_metrics_path(config.metrics_path.clone())
+        .with_recent_events(recent.clone());
+    if let Some(token) = &config.metrics_auth_token {
+        server_state = server_state.with_metrics_auth_token(token.clone());
+    }
+    let wal_shutdown = Arc::new(Notify::new());
+    let mut wal_handle = None;
+    if let Some(path) = &config.local_store_path {
+        let sink = match SqliteSink::open(path) {
+            Ok(sink) => Arc::new(sink),
+            Err(e) => {
+                eprintln!("Failed to open local store {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        info!(path = %path.display(), "Storing processed events locally");
+        server_state = server_state.with_event_store(sink.clone());
+        let store = match &config.wal_dir {
+            Some(dir) => {
+                let wal = open_wal(dir);
+                info!(dir = %dir.display(), "Buffering processed events in a write-ahead log");
+                wal_handle = Some(tokio::spawn(wal.clone().run_flusher(
+                    WalTarget::Store(sink),
+                    metrics.clone(),
+                    wal_shutdown.clone(),
+                )));
+                LocalStore::Buffered(wal)
+            }
+            None => LocalStore::Direct(sink),
+        };
+        server_state = server_state.with_local_store(store);
+    }
+    if let Some(capacity) = NonZeroUsize::new(config.dedup_cache_size) {
+        let ttl = Duration::from_secs(config.dedup_ttl_secs);
+        info!(capacity, ttl_secs = config.dedup_ttl_secs, "Deduplicating messages by message_id");
+        server_state = server_state.with_dedup(Arc::new(DedupCache::new(capacity, ttl)));
+    }
+    if config.quarantine_threshold > 0 {
+        info!(threshold = config.quarantine_threshold, "Quarantining messages that keep failing");
+        let quarantine = Quarantine::new(QUARANTINE_CAPACITY, config.quarantine_threshold);
+        server_state = server_state.with_quarantine(Arc::new(quarantine));
+    }
+    if config.max_messages_per_sec > 0 {
+        info!(per_sec = config.max_messages_per_sec, "Rate limiting message processing");
+        server_state =
+            server_state.with_rate_limiter(Arc::new(RateLimiter::new(config.max_messages_per_sec)));
+    }
+    if config.adaptive_prefetch {
+        info!(
+            min = config.prefetch_min,
+            max = config.prefetch_max,
+            target_ms = config.adaptive_prefetch_target_ms,
+            "Adapting prefetch to processing latency"
+        );
+    }
+    if config.circuit_breaker_threshold > 0 {
+        info!(
+            threshold = config.circuit_breaker_threshold,
+            cooldown_secs = config.circuit_breaker_cooldown_secs,
+            "Circuit breaker enabled"
+        );
+        let breaker = CircuitBreaker::new(
+            config.circuit_breaker_threshold,
+            Duration::from_secs(config.circuit_breaker_cooldown_secs),
+            &metrics,
+        );
+        server_state = server_state.with_circuit_breaker(Arc::new(breaker));
+    }
+    let downstream_shutdown = Arc::new(Notify::new());
+    let mut downstream_handle = None;
+    if let Some(url) = &config.downstream_url {
+        let sink = match HttpSink::new(
+            url.clone(),
+            config.batch_size,
+            Duration::from_millis(config.batch_interval_ms),
+            metrics.clone(),
+        ) {
+            Ok(sink) => Arc::new(sink),
+            Err(e) => {
+                eprintln!("Failed to create downstream client: {}", e);
+                std::process::exit(1);
+            }
+        };
+        info!(url = %url, batch_size = config.batch_size, "Forwarding processed events downstream");
+        let downstream = match &config.wal_dir {
+            Some(dir) => {
+                let dir = dir.join(DOWNSTREAM_WAL_DIR);
+                let wal = open_wal(&dir);
+                info!(dir = %dir.display(), "Buffering downstream events in a write-ahead log");
+                downstream_handle = Some(tokio::spawn(wal.clone().run_flusher(
+                    WalTarget::Downstream(sink),
+                    metrics.clone(),
+                    downstream_shutdown.clone(),
+                )));
+                Downstream::Buffered(wal)
+            }
+            None => {
+                downstream_handle = Some(tokio::spawn(sink.clone().run(downstream_shutdown.clone())));
+                Downstream::Direct(sink)
+            }
+        };
+        server_state = server_state.with_downstream(downstream);
+    }
+    let otlp_shutdown = Arc::new(Notify::new());
+    let otlp_flush = config
+        .otlp_metrics_endpoint
+        .as_deref()
+        .map(|endpoint| {
+            start_otlp_export(endpoint, &config, &resource, &server_state, otlp_shutdown.clone())
+        });
+    let statsd_shutdown = Arc::new(Notify::new());
+    let mut statsd_handle = None;
+    if let Some(addr) = &config.statsd_addr {
+        match StatsdPusher::connect(addr, server_state.clone()).await {
+            Ok(pusher) => {
+                info!(addr = %addr, interval_secs = config.statsd_interval_secs, "Pushing metrics to StatsD");
+                let interval = Duration::from_secs(config.statsd_interval_secs);
+                statsd_handle = Some(tokio::spawn(pusher.run(interval, statsd_shutdown.clone())));
+            }
+            Err(e) => {
+                eprintln!("Failed to start StatsD push to {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let server_state_clone = server_state.clone();
+    let metrics_bind_addr = config.metrics_bind_addr;
+    let metrics_shutdown = Arc::new(Notify::new());
+    let metrics_shutdown_clone = metrics_shutdown.clone();
+    let metrics_handle = tokio::spawn(async move {
+        if let Err(e) =
+            start_metrics_server(server_state_clone, metrics_bind_addr, metrics_shutdown_clone)
+                .await
+        {
+            eprintln!("Metrics server error: {}", e);
+        }
+    });
+
+    if !config.lenient_fields.is_empty() {
+        warn!(
+            fields = ?config.lenient_fields,
+            "Lenient validation enabled; events missing these fields will be processed anyway"
+        );
+    }
+    let registry = handler_registry();
+    info!(
+        event_types = ?registry.event_types().collect::<Vec<_>>(),
+        "Event processors registered"
+    );
+    let mut telemetry = TelemetryHandler::new(metrics.clone())
+        .with_lenient_fields(config.lenient_fields.clone())
+        .with_redacted_fields(config.redact_fields.clone())
+        .with_preview_len(config.payload_preview_len)
+        .with_registry(registry);
+    if let Some(path) = &config.v1_schema_path {
+        match EventSchema::from_path(path) {
+            Ok(schema) => {
+                info!(path = %path.display(), "Validating v1 events against custom schema");
+                telemetry = telemetry.with_v1_schema(schema);
+            }
+            Err(e) => {
+                eprintln!("Failed to load v1 schema {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &config.success_sample_path
+        && config.success_sample_rate > 0.0
+    {
+        match SuccessSampler::to_file(path, config.success_sample_rate, metrics.clone()) {
+            Ok(sampler) => {
+                info!(
+                    path = %path.display(),
+                    rate = config.success_sample_rate,
+                    "Sampling successfully processed events"
+                );
+                telemetry = telemetry.with_success_sampler(sampler);
+            }
+            Err(e) => {
+                eprintln!("Failed to open success sample file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let telemetry_handler: Arc<dyn MessageHandler> = Arc::new(telemetry);
+    let telemetry_handler = match &config.wasm_transform_path {
+        Some(path) => with_wasm_transform(telemetry_handler, path, &config),
+        None => telemetry_handler,
+    };
+
+    let handler: Arc<dyn MessageHandler> = match NonZeroUsize::new(config.handler_cache_size) {
+        Some(capacity) => {
+            warn!(
+                capacity,
+                "Handler result cache enabled; duplicate payloads will skip the handler"
+            );
+            Arc::new(CachingHandler::new(telemetry_handler, capacity, metrics.clone()))
+        }
+        None => telemetry_handler,
+    };
+    let handler = if config.signature_verification {
+        with_signature_verification(handler, &config, &metrics)
+    } else {
+        handler
+    };
+
+    let sinks = event_sinks(&server_state);
+    let rabbitmq = match connect_with_local_fallback(&config, handler.as_ref(), &sinks, &metrics)
+        .await
+    {
         Ok(conn) => {
             info!("RabbitMQ connection established");
+            server_state.readiness.set_connection(conn.status());
+            set_connected(&server_state.metrics, true);
             conn
         }
         Err(e) => {
@@ -123,7 +470,13 @@ async fn main() {
         }
     };
 
-    let channel = match ChannelProvider::create_channel(rabbitmq.get_connection()).await {
+    let channel = match ChannelProvider::create_channel(
+        rabbitmq.get_connection(),
+        config.prefetch_count,
+        config.qos_global,
+    )
+    .await
+    {
         Ok(ch) => {
             info!("RabbitMQ channel created and configured");
             ch
@@ -134,66 +487,932 @@ async fn main() {
         }
     };
 
-    let shutdown = Arc::new(Notify::new());
-    let shutdown_clone = shutdown.clone();
+    let spec = match &config.topology_spec_path {
+        Some(path) => match load_topology_spec(path) {
+            Ok(spec) => spec,
+            Err(e) => {
+                eprintln!("Failed to load topology spec {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => TopologySpec::default(),
+    };
 
-    let metrics = Metrics::new().expect("Failed to create metrics");
+    // One consumer per queue, sharing the channel and handler. Each has its
+    // own shutdown signal, since a `Notify` wakes a single waiter. Delivery
+    // tags are numbered per channel, so batched acks share one window too;
+    // after a reconnect each consumer has a channel of its own.
+    let ack_window = (config.ack_batch_size > 1)
+        .then(|| Arc::new(Mutex::new(AckWindow::new(config.ack_batch_size))));
+    let mut consumers = Vec::new();
+    for queue_name in &config.queues {
+        let shutdown = Arc::new(Notify::new());
+        let queue_metrics = metrics_for_queue(&config, &server_state, queue_name);
+        let consumer = main_consumer(
+            channel.clone(),
+            queue_name,
+            &config,
+            handler.clone(),
+            shutdown.clone(),
+            queue_metrics.clone(),
+            &server_state,
+        );
+        let consumer = match &ack_window {
+            Some(window) => consumer.with_shared_ack_window(window.clone()),
+            None => consumer,
+        };
 
-    let metrics_clone = metrics.clone();
-    tokio::spawn(async move {
-        if let Err(e) = start_metrics_server(metrics_clone, 9090).await {
-            eprintln!("Metrics server error: {}", e);
+        if consumers.is_empty()
+            && let Err(e) = consumer.declare_topology(&spec).await
+        {
+            eprintln!("Failed to setup exchange topology: {}", e);
+            std::process::exit(1);
         }
-    });
 
-    let handler = Arc::new(TelemetryHandler);
+        match consumer.setup_queues().await {
+            Ok(mut topology) => {
+                topology.exchanges.splice(0..0, spec.exchanges.iter().cloned());
+                topology.bindings = spec.bindings.clone();
+                server_state.topology.write().unwrap().push(topology);
+            }
+            Err(e) => {
+                eprintln!("Failed to setup queue topology for {}: {}", queue_name, e);
+                std::process::exit(1);
+            }
+        }
+        consumers.push((consumer, shutdown, queue_metrics));
+    }
+
+    // Every consumer runs on this connection; after it drops, one reconnect
+    // serves them all.
+    let rabbitmq = Arc::new(SharedConnection::new(rabbitmq, config.clone(), server_state.clone()));
+    let mut consumer_tasks = Vec::new();
+    for (consumer, shutdown, queue_metrics) in consumers {
+        let setup = QueueSetup {
+            queue_name: consumer.topology().queue,
+            migration: false,
+            spec: spec.clone(),
+            config: config.clone(),
+            handler: handler.clone(),
+            shutdown: shutdown.clone(),
+            metrics: queue_metrics,
+            state: server_state.clone(),
+        };
+        let handle = tokio::spawn(consume_with_recovery(setup, consumer, rabbitmq.clone()));
+        consumer_tasks.push((shutdown, handle));
+    }
+
+    let migration_shutdown = Arc::new(Notify::new());
+    let migration_handle = match &config.migrate_from_queue {
+        Some(old_queue) => {
+            let setup = QueueSetup {
+                queue_name: old_queue.clone(),
+                migration: true,
+                spec: spec.clone(),
+                config: config.clone(),
+                handler: handler.clone(),
+                shutdown: migration_shutdown.clone(),
+                metrics: metrics_for_queue(&config, &server_state, old_queue),
+                state: server_state.clone(),
+            };
+            spawn_migration_consumer(setup, &rabbitmq).await
+        }
+        None => None,
+    };
+
+    let connection_shutdown = Arc::new(Notify::new());
+    tokio::spawn(
+        ConnectionMonitor::new(
+            server_state.readiness.clone(),
+            server_state.metrics.clone(),
+            Duration::from_millis(config.queue_poll_interval_ms),
+            connection_shutdown.clone(),
+        )
+        .run(),
+    );
+
+    let heartbeat_shutdown = Arc::new(Notify::new());
+    if config.liveness_log_interval_secs > 0 {
+        let heartbeat = Heartbeat::new(
+            Duration::from_secs(config.liveness_log_interval_secs),
+            server_state.clone(),
+            heartbeat_shutdown.clone(),
+        );
+        tokio::spawn(heartbeat.run());
+    }
+
+    info!("Ready to process telemetry events");
+
+    let signal = shutdown_signal().await;
+
+    warn!(signal, "Shutdown signal received, cleaning up...");
+
+    for (shutdown, _) in &consumer_tasks {
+        shutdown.notify_one();
+    }
+    heartbeat_shutdown.notify_one();
+    connection_shutdown.notify_one();
+    migration_shutdown.notify_one();
+    otlp_shutdown.notify_one();
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    if let Some(handle) = migration_handle {
+        let _ = tokio::time::timeout(shutdown_timeout, handle).await;
+    }
+
+    // Bounds the consumers' drain: messages still in flight after this are
+    // left unacked and redelivered by the broker.
+    let consumers_stopped = join_all(consumer_tasks.into_iter().map(|(_, handle)| handle));
+    let consumers_timeout = shutdown_timeout + CANCELLED_HANDLER_WAIT;
+    if tokio::time::timeout(consumers_timeout, consumers_stopped).await.is_err() {
+        warn!(
+            timeout_secs = config.shutdown_timeout_secs,
+            "Consumer shutdown timed out with messages still in flight; they will be redelivered"
+        );
+    }
+
+    // Anything not yet forwarded stays in the WAL and is replayed on the next start.
+    wal_shutdown.notify_one();
+    if let Some(handle) = wal_handle {
+        let _ = handle.await;
+    }
+    downstream_shutdown.notify_one();
+    if let Some(handle) = downstream_handle {
+        let _ = handle.await;
+    }
+
+    if let Err(e) = rabbitmq.shutdown().await {
+        eprintln!("Error during shutdown: {}", e);
+    }
+
+    // Stopped last so the drain above stays observable; in-flight scrapes
+    // finish before the listener goes away.
+    metrics_shutdown.notify_one();
+    let _ = tokio::time::timeout(shutdown_timeout, metrics_handle).await;
+    statsd_shutdown.notify_one();
+    if let Some(handle) = statsd_handle {
+        let _ = handle.await;
+    }
+
+    if let Some(flush) = otlp_flush {
+        let _ = tokio::task::spawn_blocking(flush).await;
+    }
+    if let Some(flush) = span_flush {
+        let _ = tokio::task::spawn_blocking(flush).await;
+    }
+
+    info!("Observability Collector stopped");
+}
+
+fn main_consumer(
+    channel: Channel,
+    queue_name: &str,
+    config: &Config,
+    handler: Arc<dyn MessageHandler>,
+    shutdown: Arc<Notify>,
+    metrics: Arc<Metrics>,
+    state: &ServerState,
+) -> Consumer {
     let consumer = Consumer::new(
         channel,
-        "telemetry".to_string(),
-        format!("{}-consumer", config.service_name),
+        queue_name.to_string(),
+        format!("{}-{}-consumer", config.service_name, queue_name),
         handler,
-        shutdown_clone,
-        metrics.clone(),
-    );
+        shutdown,
+        metrics,
+        config.max_retries,
+    )
+    .with_retry_backoff(
+        Duration::from_millis(config.retry_base_delay_ms),
+        Duration::from_millis(config.retry_max_delay_ms),
+    )
+    .with_retry_policies(config.retry_policies.clone())
+    .with_retry_tiers(
+        config
+            .retry_tiers_ms
+            .iter()
+            .map(|&ttl| Duration::from_millis(ttl as u64))
+            .collect(),
+    )
+    .with_retry_strategy(config.retry_strategy)
+    .with_queue_options(config.queue_type, config.queue_mode)
+    .with_dlq_envelope(config.dlq_envelope)
+    .with_delivery_mode(config.delivery_mode)
+    .with_ack_batching(config.ack_batch_size)
+    .with_concurrency(config.concurrency)
+    .with_handler_timeout(Duration::from_millis(config.handler_timeout_ms))
+    .with_shutdown_grace(Duration::from_secs(config.shutdown_timeout_secs))
+    .with_max_clock_skew(Duration::from_millis(config.max_clock_skew_ms))
+    .with_stream_restarts(
+        config.consumer_restart_max_attempts,
+        Duration::from_millis(config.consumer_restart_delay_ms),
+    )
+    .with_recent_events(state.recent.clone())
+    .with_readiness(state.readiness.clone());
+    let consumer = if config.adaptive_prefetch {
+        consumer.with_adaptive_prefetch(PrefetchTuner::new(PrefetchSettings {
+            base: config.prefetch_count,
+            min: config.prefetch_min,
+            max: config.prefetch_max,
+            target_latency: Duration::from_millis(config.adaptive_prefetch_target_ms),
+            interval: Duration::from_secs(config.adaptive_prefetch_interval_secs),
+        }))
+    } else {
+        consumer
+    };
+    let consumer = match config.retry_max_length {
+        0 => consumer,
+        max_length => consumer.with_max_length(QueueRole::Retry, max_length, config.retry_overflow),
+    };
+    let consumer = match config.dlq_max_length {
+        0 => consumer,
+        max_length => consumer.with_max_length(QueueRole::DeadLetter, max_length, config.dlq_overflow),
+    };
+    let consumer = match &config.exchange_name {
+        Some(name) => consumer.with_exchange(
+            ExchangeDeclaration {
+                name: name.clone(),
+                kind: config.exchange_type.clone(),
+                durable: true,
+            },
+            config.binding_keys.clone(),
+        ),
+        None => consumer,
+    };
+    let consumer = match &state.local_store {
+        Some(store) => consumer.with_local_store(store.clone()),
+        None => consumer,
+    };
+    let consumer = match &state.downstream {
+        Some(sink) => consumer.with_downstream(sink.clone()),
+        None => consumer,
+    };
+    let consumer = match &state.dedup {
+        Some(cache) => consumer.with_deduplication(cache.clone()),
+        None => consumer,
+    };
+    let consumer = match &state.quarantine {
+        Some(quarantine) => consumer.with_quarantine(quarantine.clone()),
+        None => consumer,
+    };
+    let consumer = match &state.rate_limiter {
+        Some(limiter) => consumer.with_rate_limiter(limiter.clone()),
+        None => consumer,
+    };
+    match &state.circuit_breaker {
+        Some(breaker) => consumer.with_circuit_breaker(breaker.clone()),
+        None => consumer,
+    }
+}
 
-    if let Err(e) = consumer.setup_queues().await {
-        eprintln!("Failed to setup queue topology: {}", e);
-        std::process::exit(1);
+/// The broker connection every consumer and the tasks next to it run on.
+///
+/// Whichever consumer first sees it drop reconnects, while the others wait
+/// for that and then pick up the same new connection.
+struct SharedConnection {
+    /// The connection and its generation, one higher after each reconnect.
+    current: tokio::sync::Mutex<(u64, Arc<RabbitMqConnection>)>,
+    config: Config,
+    state: ServerState,
+}
+
+impl SharedConnection {
+    fn new(connection: RabbitMqConnection, config: Config, state: ServerState) -> Self {
+        Self {
+            current: tokio::sync::Mutex::new((0, Arc::new(connection))),
+            config,
+            state,
+        }
+    }
+
+    async fn current(&self) -> (u64, Arc<RabbitMqConnection>) {
+        self.current.lock().await.clone()
     }
 
-    let consumer_handle = tokio::spawn(async move {
-        if let Err(e) = consumer.start().await {
-            eprintln!("Consumer error: {}", e);
+    /// Replaces the connection of `generation` with a new one, or returns the
+    /// one another consumer already reconnected with.
+    async fn recover(&self, generation: u64) -> (u64, Arc<RabbitMqConnection>) {
+        let mut current = self.current.lock().await;
+        if current.0 == generation {
+            warn!("RabbitMQ connection lost, reconnecting");
+            set_connected(&self.state.metrics, false);
+            let connection = reconnect(&self.config, &self.state.metrics).await;
+            self.state.metrics.connection_recoveries_total.inc();
+            self.state.readiness.set_connection(connection.status());
+            set_connected(&self.state.metrics, true);
+
+            let (_, previous) =
+                std::mem::replace(&mut *current, (generation + 1, Arc::new(connection)));
+            let _ = previous.shutdown().await;
         }
-    });
+        current.clone()
+    }
 
-    info!("Ready to process telemetry events");
+    /// Closes the connection, unless a reconnect is still under way.
+    async fn shutdown(&self) -> Result<(), ConnectionError> {
+        match self.current.try_lock() {
+            Ok(current) => current.1.shutdown().await,
+            Err(_) => Ok(()),
+        }
+    }
+}
 
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to listen for shutdown signal");
+/// One queue's consumer, built again on every new connection together with
+/// the tasks that keep channels of their own next to it.
+struct QueueSetup {
+    queue_name: String,
+    /// Draining a queue being migrated from, see `spawn_migration_consumer`.
+    migration: bool,
+    /// Exchanges and bindings from `TOPOLOGY_SPEC_PATH`, verified along with the queue's.
+    spec: TopologySpec,
+    config: Config,
+    handler: Arc<dyn MessageHandler>,
+    shutdown: Arc<Notify>,
+    metrics: Arc<Metrics>,
+    state: ServerState,
+}
+
+impl QueueSetup {
+    fn consumer(&self, channel: Channel) -> Consumer {
+        let consumer = main_consumer(
+            channel,
+            &self.queue_name,
+            &self.config,
+            self.handler.clone(),
+            self.shutdown.clone(),
+            self.metrics.clone(),
+            &self.state,
+        );
+        if self.migration {
+            consumer.with_idle_shutdown(Duration::from_secs(self.config.migration_idle_secs))
+        } else {
+            consumer
+        }
+    }
+
+    /// What to verify on a new connection. A queue being migrated from keeps
+    /// its own retry and dead-letter queues, but is itself left as it is and
+    /// not bound to the exchange, so it only drains.
+    fn topology(&self, consumer: &Consumer) -> QueueTopology {
+        let mut topology = consumer.topology();
+        topology.exchanges.splice(0..0, self.spec.exchanges.iter().cloned());
+        topology.bindings = self.spec.bindings.clone();
+        if self.migration {
+            topology.queues.retain(|queue| queue.role != QueueRole::Main);
+            topology.queue_bindings.clear();
+        }
+        topology
+    }
+
+    /// Starts the DLQ reanimator, when enabled, and the queue depth poller
+    /// on `connection`. A queue being migrated from has neither.
+    async fn spawn_tasks(
+        &self,
+        connection: &Arc<RabbitMqConnection>,
+        topology: &QueueTopology,
+    ) -> Vec<(Arc<Notify>, tokio::task::JoinHandle<()>)> {
+        let config = &self.config;
+        let mut tasks = Vec::new();
+        if self.migration {
+            return tasks;
+        }
+
+        if config.dlq_reanimate_cooldown_secs > 0 {
+            match ChannelProvider::create_channel(
+                connection.get_connection(),
+                config.prefetch_count,
+                config.qos_global,
+            )
+            .await
+            {
+                Ok(reanimator_channel) => {
+                    let reanimator_shutdown = Arc::new(Notify::new());
+                    let reanimator = DlqReanimator::new(
+                        reanimator_channel,
+                        self.queue_name.clone(),
+                        ReanimatorSettings {
+                            cooldown: Duration::from_secs(config.dlq_reanimate_cooldown_secs),
+                            interval: Duration::from_secs(config.dlq_reanimate_interval_secs),
+                            rate_per_sec: config.dlq_reanimate_rate_per_sec,
+                            max_reanimations: config.dlq_reanimate_max,
+                            scan_limit: DLQ_REANIMATE_SCAN_LIMIT,
+                        },
+                        self.metrics.clone(),
+                        reanimator_shutdown.clone(),
+                    );
+                    tasks.push((reanimator_shutdown, tokio::spawn(reanimator.run())));
+                }
+                Err(e) => {
+                    error!(error = %e, queue = %self.queue_name, "Failed to create DLQ reanimator channel, reanimation disabled");
+                }
+            }
+        }
 
-    warn!("Shutdown signal received, cleaning up...");
+        let depth_shutdown = Arc::new(Notify::new());
+        let poller = QueueDepthPoller::new(
+            ConnectionDepthSource::new(connection.clone()),
+            topology.queues.iter().map(|queue| queue.name.clone()).collect(),
+            Duration::from_millis(config.queue_poll_interval_ms),
+            self.metrics.clone(),
+            depth_shutdown.clone(),
+        );
+        tasks.push((depth_shutdown, tokio::spawn(poller.run())));
+        tasks
+    }
+}
 
-    shutdown.notify_one();
+/// Stops the tasks started by `QueueSetup::spawn_tasks` and waits for them.
+async fn stop_tasks(tasks: Vec<(Arc<Notify>, tokio::task::JoinHandle<()>)>) {
+    for (shutdown, _) in &tasks {
+        shutdown.notify_one();
+    }
+    join_all(tasks.into_iter().map(|(_, handle)| handle)).await;
+}
+
+/// Runs a queue's consumer, reconnecting whenever the broker connection drops.
+///
+/// After each reconnect the consumer, its DLQ reanimator and its queue depth
+/// poller start again on the new connection, once the queue topology has
+/// been declared again and checked for changes made during the outage, which
+/// `TOPOLOGY_DRIFT_POLICY` either rejects or repairs. A consumer that stops
+/// while its connection is still up was shut down on purpose or drained its
+/// queue and is not restarted, unless its stream ended and resubscribing
+/// failed, which exits the process.
+async fn consume_with_recovery(
+    setup: QueueSetup,
+    mut consumer: Consumer,
+    shared: Arc<SharedConnection>,
+) {
+    let queue_name = setup.queue_name.clone();
+    let (mut generation, connection) = shared.current().await;
+    let mut status = connection.status();
+    let mut tasks = setup.spawn_tasks(&connection, &consumer.topology()).await;
+
+    loop {
+        match consumer.start().await {
+            Err(e @ ConsumerError::StreamEnded(_)) if status.connected() => {
+                error!(error = %e, "Consumer stream lost, exiting");
+                std::process::exit(1);
+            }
+            Err(e) => error!(error = %e, "Consumer error"),
+            Ok(()) => {}
+        }
+        if status.connected() {
+            break;
+        }
+
+        warn!(queue = %queue_name, "RabbitMQ connection lost, reconnecting");
+        stop_tasks(std::mem::take(&mut tasks)).await;
+        let (connection, channel) = loop {
+            let connection;
+            (generation, connection) = shared.recover(generation).await;
+            match ChannelProvider::create_channel(
+                connection.get_connection(),
+                setup.config.prefetch_count,
+                setup.config.qos_global,
+            )
+            .await
+            {
+                Ok(channel) => break (connection, channel),
+                Err(e) if !connection.is_connected() => {
+                    warn!(error = %e, queue = %queue_name, "Connection lost again while opening a channel");
+                }
+                Err(e) => {
+                    error!(error = %e, "Failed to create RabbitMQ channel after reconnect, stopping");
+                    std::process::exit(1);
+                }
+            }
+        };
+
+        consumer = setup.consumer(channel);
+        let broker = ConnectionBroker::new(connection.get_connection());
+        let topology = setup.topology(&consumer);
+        let policy = setup.config.topology_drift_policy;
+        match verify_topology(&broker, &topology, policy, &setup.metrics).await {
+            Ok(redeclared) => info!(
+                queue = %queue_name,
+                redeclared = ?redeclared,
+                policy = policy.as_str(),
+                "Reconnected to RabbitMQ, topology verified"
+            ),
+            Err(e) => {
+                error!(error = %e, "Topology verification failed after reconnect, stopping");
+                std::process::exit(1);
+            }
+        }
+
+        status = connection.status();
+        tasks = setup.spawn_tasks(&connection, &consumer.topology()).await;
+    }
+
+    stop_tasks(tasks).await;
+    if setup.migration {
+        info!(queue = %queue_name, "Migration consumer finished");
+    }
+}
+
+/// Connects, backing off exponentially from `RECONNECT_BASE_DELAY_MS` between
+/// attempts. Exits the process once `RECONNECT_MAX_ATTEMPTS` is used up.
+async fn reconnect(config: &Config, metrics: &Metrics) -> RabbitMqConnection {
+    let base = Duration::from_millis(config.reconnect_base_delay_ms);
+    let mut attempt: u32 = 0;
+
+    loop {
+        attempt += 1;
+        metrics.reconnect_attempts_total.inc();
+        let error = match connect(config).await {
+            Ok(connection) => return connection,
+            Err(e) => e.to_string(),
+        };
+
+        if config.reconnect_max_attempts > 0 && attempt >= config.reconnect_max_attempts {
+            error!(attempt, error = %error, "Could not reconnect to RabbitMQ, giving up");
+            std::process::exit(1);
+        }
+        let delay = reconnect_delay(base, attempt);
+        warn!(
+            attempt,
+            error = %error,
+            delay_ms = delay.as_millis() as u64,
+            "Reconnect attempt failed, retrying"
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Opens the WAL in `dir`, exiting if it cannot be.
+fn open_wal(dir: &Path) -> Arc<WalBuffer> {
+    match WalBuffer::open(dir) {
+        Ok(wal) => Arc::new(wal),
+        Err(e) => {
+            eprintln!("Failed to open WAL in {}: {}", dir.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The local store and HTTP downstream the consumers write handled events
+/// to, for messages handled outside a consumer.
+fn event_sinks(state: &ServerState) -> MultiSink {
+    let mut sinks = MultiSink::new();
+    if let Some(store) = &state.local_store {
+        sinks.push(Arc::new(store.clone()));
+    }
+    if let Some(sink) = &state.downstream {
+        sinks.push(Arc::new(sink.clone()));
+    }
+    sinks
+}
+
+/// The metrics a queue's consumer records into: its own registry when
+/// `PER_QUEUE_METRICS` is on, otherwise the shared one.
+fn metrics_for_queue(config: &Config, state: &ServerState, queue_name: &str) -> Arc<Metrics> {
+    if !config.per_queue_metrics {
+        return state.metrics.clone();
+    }
+
+    let metrics = Metrics::for_queue(queue_name, &config.metrics)
+        .expect("Failed to create queue metrics");
+    if let Some(mirror) = state.metrics.histogram_mirror() {
+        metrics.set_histogram_mirror(mirror);
+    }
+    state.register_queue_metrics(queue_name, metrics.clone());
+    metrics
+}
+
+/// Processors for every event type the collector understands.
+fn handler_registry() -> HandlerRegistry {
+    HandlerRegistry::new().register(LOG_CAPTURED, process_log_captured)
+}
 
-    if let Err(e) = tokio::time::timeout(
-        std::time::Duration::from_secs(5),
-        consumer_handle,
+/// Wraps `handler` so that only correctly signed messages reach it.
+///
+/// A secret that cannot be resolved does not stop startup: it is reported
+/// here and then applied per message according to `SIGNATURE_FAILURE_MODE`.
+fn with_signature_verification(
+    handler: Arc<dyn MessageHandler>,
+    config: &Config,
+    metrics: &Arc<Metrics>,
+) -> Arc<dyn MessageHandler> {
+    let mode = config.signature_failure_mode;
+    let key = match (&config.signature_secret_file, &config.signature_secret) {
+        (Some(path), _) => std::fs::read(path)
+            .map(|secret| secret.trim_ascii().to_vec())
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e)),
+        (None, Some(secret)) => Ok(secret.as_bytes().to_vec()),
+        (None, None) => Err("no SIGNATURE_SECRET or SIGNATURE_SECRET_FILE configured".to_string()),
+    };
+
+    match &key {
+        Ok(_) => info!(mode = mode.as_str(), "Message signature verification enabled"),
+        Err(reason) if mode == SignatureFailureMode::FailOpen => error!(
+            reason = %reason,
+            mode = mode.as_str(),
+            "Signature verification enabled but no usable secret; messages will be processed UNVERIFIED"
+        ),
+        Err(reason) => error!(
+            reason = %reason,
+            mode = mode.as_str(),
+            "Signature verification enabled but no usable secret; every message will be rejected"
+        ),
+    }
+
+    Arc::new(SignatureVerifier::new(handler, key, mode, metrics.clone()))
+}
+
+/// Starts the OTLP metrics export and returns the final flush, to run at shutdown.
+#[cfg(feature = "otlp")]
+fn start_otlp_export(
+    endpoint: &str,
+    config: &Config,
+    resource: &ResourceAttributes,
+    state: &ServerState,
+    shutdown: Arc<Notify>,
+) -> Flush {
+    use observability_collector::metrics::otlp;
+
+    let interval = Duration::from_secs(config.otlp_export_interval_secs);
+    match otlp::install(endpoint, interval, resource, state.clone()) {
+        Ok((provider, bridge)) => {
+            tokio::spawn(bridge.run(interval, shutdown));
+            Box::new(move || otlp::shutdown(&provider))
+        }
+        Err(e) => {
+            eprintln!("Failed to start OTLP metrics export to {}: {}", endpoint, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+fn start_otlp_export(
+    endpoint: &str,
+    _config: &Config,
+    _resource: &ResourceAttributes,
+    _state: &ServerState,
+    _shutdown: Arc<Notify>,
+) -> Flush {
+    eprintln!(
+        "OTLP_METRICS_ENDPOINT={} is set, but the collector was built without the `otlp` feature",
+        endpoint
+    );
+    std::process::exit(1);
+}
+
+/// Starts the OTLP span export and returns the layer feeding it and the
+/// final flush, to run at shutdown.
+#[cfg(feature = "otlp")]
+fn start_span_export(endpoint: &str, resource: &ResourceAttributes) -> (SpanLayer, Flush) {
+    use observability_collector::metrics::spans;
+
+    match spans::install(endpoint, resource) {
+        Ok((provider, layer)) => (Box::new(layer), Box::new(move || spans::shutdown(&provider))),
+        Err(e) => {
+            eprintln!("Failed to start OTLP span export to {}: {}", endpoint, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+fn start_span_export(endpoint: &str, _resource: &ResourceAttributes) -> (SpanLayer, Flush) {
+    eprintln!(
+        "OTEL_EXPORTER_OTLP_ENDPOINT={} is set, but the collector was built without the `otlp` feature",
+        endpoint
+    );
+    std::process::exit(1);
+}
+
+#[cfg(feature = "wasm")]
+fn with_wasm_transform(
+    handler: Arc<dyn MessageHandler>,
+    path: &Path,
+    config: &Config,
+) -> Arc<dyn MessageHandler> {
+    use observability_collector::processors::wasm::{
+        TransformingHandler, WasmLimits, WasmTransform,
+    };
+
+    let limits = WasmLimits {
+        timeout: Duration::from_millis(config.wasm_transform_timeout_ms),
+        max_memory_bytes: config.wasm_transform_max_memory_mb * 1024 * 1024,
+    };
+    match WasmTransform::load(path, limits) {
+        Ok(transform) => {
+            info!(module = %path.display(), "WASM payload transform loaded");
+            Arc::new(TransformingHandler::new(handler, transform))
+        }
+        Err(e) => {
+            eprintln!("Failed to load WASM transform {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+fn with_wasm_transform(
+    _handler: Arc<dyn MessageHandler>,
+    path: &Path,
+    _config: &Config,
+) -> Arc<dyn MessageHandler> {
+    eprintln!(
+        "WASM_TRANSFORM_PATH={} is set, but the collector was built without the `wasm` feature",
+        path.display()
+    );
+    std::process::exit(1);
+}
+
+fn load_topology_spec(path: &Path) -> Result<TopologySpec, Box<dyn std::error::Error>> {
+    let raw = std::fs::read_to_string(path)?;
+    Ok(TopologySpec::from_json(&raw)?)
+}
+
+/// Starts a temporary consumer on a queue being renamed to the first of `QUEUES`.
+///
+/// It runs alongside the main consumer and stops by itself once the old queue
+/// has been idle and empty for `MIGRATION_IDLE_SECS`. The old queue is only
+/// checked for, never declared, so a typo cannot create a stray queue; its
+/// retry and dead-letter queues are declared like those of any queue.
+async fn spawn_migration_consumer(
+    setup: QueueSetup,
+    shared: &Arc<SharedConnection>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let config = &setup.config;
+    let old_queue = setup.queue_name.as_str();
+    let (_, connection) = shared.current().await;
+    let channel = match ChannelProvider::create_channel(
+        connection.get_connection(),
+        config.prefetch_count,
+        config.qos_global,
     )
     .await
     {
-        warn!(error = ?e, "Consumer shutdown timeout");
+        Ok(channel) => channel,
+        Err(e) => {
+            error!(error = %e, "Failed to create migration channel, old queue will not be drained");
+            return None;
+        }
+    };
+
+    if let Err(e) = channel
+        .queue_declare(
+            old_queue,
+            QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+    {
+        warn!(error = %e, queue = old_queue, "Queue to migrate from does not exist, nothing to drain");
+        return None;
     }
 
-    if let Err(e) = rabbitmq.shutdown().await {
-        eprintln!("Error during shutdown: {}", e);
+    let consumer = setup.consumer(channel);
+    let broker = ConnectionBroker::new(connection.get_connection());
+    let topology = setup.topology(&consumer);
+    let policy = config.topology_drift_policy;
+    if let Err(e) = verify_topology(&broker, &topology, policy, &setup.metrics).await {
+        error!(error = %e, queue = old_queue, "Failed to verify migration topology, old queue will not be drained");
+        return None;
     }
 
-    info!("Observability Collector stopped");
+    info!(
+        from = old_queue,
+        to = %config.queues[0],
+        idle_secs = config.migration_idle_secs,
+        "Queue migration started, draining old queue"
+    );
+    Some(tokio::spawn(consume_with_recovery(setup, consumer, shared.clone())))
+}
+
+/// Waits for SIGINT or SIGTERM, the signal orchestrators send on pod
+/// termination, and returns its name.
+#[cfg(unix)]
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Failed to listen for SIGINT");
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> &'static str {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for shutdown signal");
+    "ctrl_c"
+}
+
+/// Connects with the configured TLS certificates, read again on every
+/// attempt so a reconnect picks up rotated files.
+async fn connect(config: &Config) -> Result<RabbitMqConnection, ConnectionError> {
+    let connection_name = config
+        .connection_name
+        .clone()
+        .unwrap_or_else(|| default_connection_name(&config.service_name));
+    RabbitMqConnection::connect(config.rabbitmq_url.clone(), &tls_config(config)?, &connection_name)
+        .await
+}
+
+fn tls_config(config: &Config) -> Result<TlsConfig, ConnectionError> {
+    TlsConfig::load(
+        config.tls_ca_cert_path.as_deref(),
+        config.tls_client_cert_path.as_deref(),
+        config.tls_client_key_path.as_deref(),
+    )
+}
+
+/// Connects to RabbitMQ, draining the local spool while the broker is unreachable.
+///
+/// Without `LOCAL_SPOOL_DIR` the connect is retried with the reconnect backoff.
+/// With it, spooled messages are processed between connection attempts until
+/// the broker comes back or `LOCAL_FALLBACK_MAX_SECS` elapses, and written to
+/// `sinks` like those the consumers handle.
+async fn connect_with_local_fallback(
+    config: &Config,
+    handler: &dyn MessageHandler,
+    sinks: &MultiSink,
+    metrics: &Metrics,
+) -> Result<RabbitMqConnection, ConnectionError> {
+    let mut last_error = match connect(config).await {
+        Ok(conn) => return Ok(conn),
+        Err(e) => e,
+    };
+    let Some(spool_dir) = &config.local_spool_dir else {
+        return retry_connect(config, last_error).await;
+    };
+
+    let mut source = match LocalFileSource::open(spool_dir, &config.queues[0]) {
+        Ok(source) => source,
+        Err(e) => {
+            error!(error = %e, "Local spool unavailable, cannot fall back");
+            return Err(last_error);
+        }
+    };
+
+    warn!(
+        spool = %spool_dir.display(),
+        max_secs = config.local_fallback_max_secs,
+        "RabbitMQ unreachable, falling back to local spool"
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(config.local_fallback_max_secs);
+    loop {
+        process_spool_pass(&mut source, handler, sinks, metrics).await;
+
+        if Instant::now() >= deadline {
+            error!("Local fallback window elapsed without reaching RabbitMQ");
+            return Err(last_error);
+        }
+
+        tokio::time::sleep(Duration::from_millis(config.local_fallback_retry_ms)).await;
+
+        match connect(config).await {
+            Ok(conn) => {
+                info!("RabbitMQ reachable again, leaving local fallback");
+                return Ok(conn);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+}
+
+/// Retries the startup connect with the reconnect backoff, returning the last
+/// error once `RECONNECT_MAX_ATTEMPTS` is used up.
+async fn retry_connect(
+    config: &Config,
+    mut last_error: ConnectionError,
+) -> Result<RabbitMqConnection, ConnectionError> {
+    let base = Duration::from_millis(config.reconnect_base_delay_ms);
+    let mut attempt: u32 = 1;
+
+    loop {
+        if config.reconnect_max_attempts > 0 && attempt >= config.reconnect_max_attempts {
+            return Err(last_error);
+        }
+        let delay = reconnect_delay(base, attempt);
+        warn!(
+            attempt,
+            error = %last_error,
+            delay_ms = delay.as_millis() as u64,
+            "RabbitMQ unreachable at startup, retrying"
+        );
+        tokio::time::sleep(delay).await;
+
+        attempt += 1;
+        match connect(config).await {
+            Ok(conn) => return Ok(conn),
+            Err(e) => last_error = e,
+        }
+    }
 }
 
-fn setup_logging(rust_log: &str) {
+fn setup_logging(rust_log: &str, format: LogFormat, spans: Option<SpanLayer>) {
     let log_level = match rust_log.to_lowercase().as_str() {
         "trace" => Level::TRACE,
         "debug" => Level::DEBUG,
@@ -203,13 +1422,12 @@ fn setup_logging(rust_log: &str) {
         _ => Level::INFO,
     };
 
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(log_level)
-        .with_target(false)
-        .with_thread_ids(true)
-        .with_file(true)
-        .with_line_number(true)
-        .finish();
+    // Spans are only for export: log lines are the same with or without them.
+    let fmt = fmt_layer(format, std::io::stdout)
+        .with_filter(LevelFilter::from_level(log_level).and(filter_fn(|meta| !meta.is_span())));
+    let subscriber = tracing_subscriber::registry()
+        .with(spans.map(|layer| layer.with_filter(LevelFilter::INFO)))
+        .with(fmt);
 
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to set tracing subscriber");
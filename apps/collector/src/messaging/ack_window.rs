@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagState {
+    /// Handed to the handler, outcome unknown.
+    InFlight,
+    /// Republished to the retry queue or DLQ, waiting for the broker to confirm.
+    AwaitingConfirm,
+    /// Safe to acknowledge.
+    Settled,
+}
+
+/// Tracks outstanding delivery tags so acks can be batched with `multiple: true`.
+///
+/// A multiple-ack acknowledges every unacked tag up to and including the one
+/// given, so it is only safe up to the highest tag whose predecessors are all
+/// settled. A message republished to the retry queue or DLQ only settles once
+/// that publish is confirmed; until then it holds back the whole window.
+#[derive(Debug)]
+pub struct AckWindow {
+    batch_size: usize,
+    tags: BTreeMap<u64, TagState>,
+}
+
+impl AckWindow {
+    pub fn new(batch_size: usize) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a delivery as soon as it is received.
+    pub fn track(&mut self, delivery_tag: u64) {
+        self.tags.insert(delivery_tag, TagState::InFlight);
+    }
+
+    /// Marks a delivery whose failure is being republished and not yet confirmed.
+    pub fn awaiting_confirm(&mut self, delivery_tag: u64) {
+        if let Some(state) = self.tags.get_mut(&delivery_tag) {
+            *state = TagState::AwaitingConfirm;
+        }
+    }
+
+    /// Marks a delivery as safe to ack: handled successfully, or its republish confirmed.
+    pub fn settle(&mut self, delivery_tag: u64) {
+        if let Some(state) = self.tags.get_mut(&delivery_tag) {
+            *state = TagState::Settled;
+        }
+    }
+
+    /// Drops a delivery that was nacked individually and is no longer outstanding.
+    pub fn forget(&mut self, delivery_tag: u64) {
+        self.tags.remove(&delivery_tag);
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.tags.len()
+    }
+
+    /// Number of leading tags that are settled and could be acked together.
+    fn settled_prefix(&self) -> usize {
+        self.tags
+            .values()
+            .take_while(|state| **state == TagState::Settled)
+            .count()
+    }
+
+    /// Returns the tag to multiple-ack once a full batch is settled, or any
+    /// settled prefix when `force` is set. Returned tags are removed from the window.
+    pub fn take_flush(&mut self, force: bool) -> Option<u64> {
+        let ready = self.settled_prefix();
+        if ready == 0 || (!force && ready < self.batch_size) {
+            return None;
+        }
+
+        let mut highest = None;
+        for _ in 0..ready {
+            highest = self.tags.pop_first().map(|(tag, _)| tag);
+        }
+        highest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_flushes_only_full_settled_batches() {
+        let mut window = AckWindow::new(3);
+        for tag in 1..=4 {
+            window.track(tag);
+        }
+
+        window.settle(1);
+        window.settle(2);
+        assert_eq!(window.take_flush(false), None);
+
+        window.settle(3);
+        assert_eq!(window.take_flush(false), Some(3));
+        assert_eq!(window.outstanding(), 1);
+    }
+
+    #[test]
+    fn test_unconfirmed_retry_holds_back_later_acks() {
+        let mut window = AckWindow::new(1);
+        for tag in 1..=3 {
+            window.track(tag);
+        }
+
+        window.awaiting_confirm(1);
+        window.settle(2);
+        window.settle(3);
+        assert_eq!(window.take_flush(true), None);
+
+        window.settle(1);
+        assert_eq!(window.take_flush(false), Some(3));
+    }
+
+    #[test]
+    fn test_forgotten_tags_do_not_block() {
+        let mut window = AckWindow::new(2);
+        for tag in 1..=3 {
+            window.track(tag);
+        }
+
+        window.awaiting_confirm(1);
+        window.forget(1);
+        window.settle(2);
+        window.settle(3);
+        assert_eq!(window.take_flush(false), Some(3));
+        assert_eq!(window.outstanding(), 0);
+    }
+
+    /// Deterministic xorshift so the interleaving is reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    #[test]
+    fn test_interleaved_successes_and_retries_never_ack_unsafely() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut window = AckWindow::new(8);
+        let mut settled: HashMap<u64, bool> = HashMap::new();
+        let mut unconfirmed: Vec<u64> = Vec::new();
+        let mut acked_up_to = 0;
+        let mut next_tag = 1;
+
+        for _ in 0..20_000 {
+            match rng.next(4) {
+                0 | 1 if next_tag <= 5_000 => {
+                    let tag = next_tag;
+                    next_tag += 1;
+                    window.track(tag);
+                    if rng.next(3) == 0 {
+                        window.awaiting_confirm(tag);
+                        unconfirmed.push(tag);
+                        settled.insert(tag, false);
+                    } else {
+                        window.settle(tag);
+                        settled.insert(tag, true);
+                    }
+                }
+                2 if !unconfirmed.is_empty() => {
+                    let tag = unconfirmed.swap_remove(rng.next(unconfirmed.len()));
+                    window.settle(tag);
+                    settled.insert(tag, true);
+                }
+                _ => {}
+            }
+
+            if let Some(flushed) = window.take_flush(rng.next(10) == 0) {
+                assert!(flushed > acked_up_to);
+                for tag in acked_up_to + 1..=flushed {
+                    assert!(settled[&tag], "tag {} acked before it was safe", tag);
+                }
+                acked_up_to = flushed;
+            }
+        }
+
+        for tag in unconfirmed.drain(..) {
+            window.settle(tag);
+        }
+        if let Some(flushed) = window.take_flush(true) {
+            acked_up_to = flushed;
+        }
+        assert_eq!(acked_up_to, next_tag - 1);
+        assert_eq!(window.outstanding(), 0);
+    }
+}
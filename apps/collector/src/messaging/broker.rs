@@ -0,0 +1,92 @@
+use async_trait::async_trait;
+use lapin::options::{BasicAckOptions, BasicCancelOptions, BasicNackOptions};
+use lapin::{BasicProperties, Channel};
+
+use super::channel::{publish_confirmed, qos_options, PublishError};
+use super::recovery::TopologyBroker;
+use super::source::{AmqpSource, Source, SourceError};
+use crate::metrics::queue_depth::QueueDepthSource;
+
+/// The broker calls a `Consumer` makes: declaring its topology (through
+/// `TopologyBroker`), subscribing to its queue, settling deliveries by tag and publishing retry and DLQ
+/// copies.
+///
+/// Implemented for `Channel`. Tests run a consumer end to end against an
+/// in-memory `MockBroker` instead.
+#[async_trait]
+pub trait ConsumerBroker: TopologyBroker + QueueDepthSource {
+    /// Starts consuming `queue`.
+    async fn subscribe(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+    ) -> Result<Box<dyn Source>, SourceError>;
+
+    /// Stops the consumer started as `consumer_tag`.
+    async fn cancel(&self, consumer_tag: &str) -> Result<(), String>;
+
+    /// Acks `delivery_tag`, and with `multiple` every earlier delivery too.
+    async fn ack(&self, delivery_tag: u64, multiple: bool) -> Result<(), lapin::Error>;
+
+    /// Rejects `delivery_tag`; with `requeue` the broker delivers it again.
+    async fn nack(&self, delivery_tag: u64, requeue: bool) -> Result<(), lapin::Error>;
+
+    /// Publishes to `queue` through the default exchange, returning once the
+    /// broker confirmed it.
+    async fn publish(
+        &self,
+        queue: &str,
+        data: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), PublishError>;
+
+    /// Changes the prefetch limit, `global` for the whole channel.
+    async fn set_prefetch(&self, prefetch_count: u16, global: bool) -> Result<(), lapin::Error>;
+}
+
+#[async_trait]
+impl ConsumerBroker for Channel {
+    async fn subscribe(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+    ) -> Result<Box<dyn Source>, SourceError> {
+        let source = AmqpSource::subscribe(self, queue, consumer_tag).await?;
+        Ok(Box::new(source))
+    }
+
+    async fn cancel(&self, consumer_tag: &str) -> Result<(), String> {
+        self.basic_cancel(consumer_tag, BasicCancelOptions::default())
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn ack(&self, delivery_tag: u64, multiple: bool) -> Result<(), lapin::Error> {
+        self.basic_ack(delivery_tag, BasicAckOptions { multiple })
+            .await
+    }
+
+    async fn nack(&self, delivery_tag: u64, requeue: bool) -> Result<(), lapin::Error> {
+        self.basic_nack(
+            delivery_tag,
+            BasicNackOptions {
+                multiple: false,
+                requeue,
+            },
+        )
+        .await
+    }
+
+    async fn publish(
+        &self,
+        queue: &str,
+        data: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), PublishError> {
+        publish_confirmed(self, queue, data, properties).await
+    }
+
+    async fn set_prefetch(&self, prefetch_count: u16, global: bool) -> Result<(), lapin::Error> {
+        self.basic_qos(prefetch_count, qos_options(global)).await
+    }
+}
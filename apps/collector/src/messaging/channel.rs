@@ -1,11 +1,17 @@
-use lapin::{Channel, Connection};
+use lapin::options::{BasicPublishOptions, BasicQosOptions, ConfirmSelectOptions};
+use lapin::publisher_confirm::Confirmation;
+use lapin::{BasicProperties, Channel, Connection};
 use tracing::{error, info};
 
 pub struct ChannelProvider;
 
 impl ChannelProvider {
 
-    pub async fn create_channel(connection: &Connection) -> Result<Channel, ChannelError> {
+    pub async fn create_channel(
+        connection: &Connection,
+        prefetch_count: u16,
+        qos_global: bool,
+    ) -> Result<Channel, ChannelError> {
         info!("Creating RabbitMQ channel");
 
         let channel = connection
@@ -18,10 +24,10 @@ impl ChannelProvider {
 
         info!(channel_id = channel.id(), "Channel created successfully");
 
-        info!(prefetch_count = 10, "Configuring channel QoS");
-        
+        info!(prefetch_count, qos_global, "Configuring channel QoS");
+
         channel
-            .basic_qos(10, Default::default())
+            .basic_qos(prefetch_count, qos_options(qos_global))
             .await
             .map_err(|e| {
                 error!(error = %e, "Failed to configure channel QoS");
@@ -30,10 +36,20 @@ impl ChannelProvider {
 
         info!(
             channel_id = channel.id(),
-            prefetch_count = 10,
+            prefetch_count,
             "Channel QoS configured successfully"
         );
 
+        // Retries, DLQ moves and reanimation ack the original delivery only
+        // once the broker has confirmed the republished copy.
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .map_err(|e| {
+                error!(error = %e, "Failed to enable publisher confirms");
+                ChannelError::ConfirmSelectFailed(e.to_string())
+            })?;
+
         Ok(channel)
     }
 
@@ -54,6 +70,73 @@ impl ChannelProvider {
     }
 }
 
+/// QoS options for `basic_qos`. RabbitMQ reads the `global` flag differently
+/// from the AMQP spec: unset, the prefetch limit applies to each consumer
+/// started on the channel afterwards, so every consumer gets its own
+/// `prefetch_count`; set, one limit is shared by all consumers on the channel.
+pub(crate) fn qos_options(global: bool) -> BasicQosOptions {
+    BasicQosOptions { global }
+}
+
+/// Publishes to `queue` through the default exchange and waits for the
+/// broker's confirmation.
+///
+/// Only an `Ack` means the broker has taken the message; anything else is an
+/// error, so the caller keeps the original delivery for redelivery. The
+/// publish is mandatory, since the broker acks a message it could not route,
+/// e.g. to a queue that does not exist, after returning it.
+pub async fn publish_confirmed(
+    channel: &Channel,
+    queue: &str,
+    data: &[u8],
+    properties: BasicProperties,
+) -> Result<(), PublishError> {
+    let confirmation = channel
+        .basic_publish(
+            "",
+            queue,
+            BasicPublishOptions {
+                mandatory: true,
+                ..BasicPublishOptions::default()
+            },
+            data,
+            properties,
+        )
+        .await?
+        .await?;
+    check_confirmation(confirmation, queue)
+}
+
+pub(crate) fn check_confirmation(
+    confirmation: Confirmation,
+    queue: &str,
+) -> Result<(), PublishError> {
+    match confirmation {
+        Confirmation::Ack(None) => Ok(()),
+        Confirmation::Ack(Some(returned)) => Err(PublishError::Returned {
+            queue: queue.to_string(),
+            reason: returned.reply_text.to_string(),
+        }),
+        Confirmation::Nack(_) => Err(PublishError::Nacked(queue.to_string())),
+        Confirmation::NotRequested => Err(PublishError::NotConfirmed),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PublishError {
+    #[error("Failed to publish: {0}")]
+    Failed(#[from] lapin::Error),
+
+    #[error("Broker nacked the publish to {0}")]
+    Nacked(String),
+
+    #[error("Broker returned the publish to {queue} as unroutable: {reason}")]
+    Returned { queue: String, reason: String },
+
+    #[error("Channel is not in confirm mode, publish cannot be verified")]
+    NotConfirmed,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ChannelError {
     #[error("Failed to create channel: {0}")]
@@ -62,6 +145,56 @@ pub enum ChannelError {
     #[error("Failed to configure channel QoS: {0}")]
     QoSConfigurationFailed(String),
 
+    #[error("Failed to enable publisher confirms: {0}")]
+    ConfirmSelectFailed(String),
+
     #[error("Failed to close channel: {0}")]
     CloseFailed(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_an_ack_confirms_a_publish() {
+        assert!(check_confirmation(Confirmation::Ack(None), "telemetry.retry").is_ok());
+        assert!(matches!(
+            check_confirmation(Confirmation::Nack(None), "telemetry.retry"),
+            Err(PublishError::Nacked(queue)) if queue == "telemetry.retry"
+        ));
+        assert!(matches!(
+            check_confirmation(Confirmation::NotRequested, "telemetry.retry"),
+            Err(PublishError::NotConfirmed)
+        ));
+    }
+
+    #[test]
+    fn test_returned_publish_is_not_confirmed() {
+        let returned = lapin::message::BasicReturnMessage {
+            delivery: lapin::message::Delivery {
+                delivery_tag: 0,
+                exchange: "".into(),
+                routing_key: "telemetry.retry".into(),
+                redelivered: false,
+                properties: BasicProperties::default(),
+                data: b"{}".to_vec(),
+                acker: Default::default(),
+            },
+            reply_code: 312,
+            reply_text: "NO_ROUTE".into(),
+        };
+
+        assert!(matches!(
+            check_confirmation(Confirmation::Ack(Some(Box::new(returned))), "telemetry.retry"),
+            Err(PublishError::Returned { queue, reason })
+                if queue == "telemetry.retry" && reason == "NO_ROUTE"
+        ));
+    }
+
+    #[test]
+    fn test_qos_global_flag_is_passed_through() {
+        assert!(qos_options(true).global);
+        assert!(!qos_options(false).global);
+    }
+}
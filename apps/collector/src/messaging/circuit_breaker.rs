@@ -0,0 +1,200 @@
+use prometheus::Gauge;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::metrics::Metrics;
+
+/// Where a `CircuitBreaker` is, exported as `collector_circuit_breaker_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Every message reaches the handler.
+    Closed,
+    /// Messages go straight to retry until the cooldown is over.
+    Open,
+    /// One message is let through to test whether the handler recovered.
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// The gauge value: 0 closed, 1 open, 2 half-open.
+    pub fn as_gauge(&self) -> f64 {
+        match self {
+            BreakerState::Closed => 0.0,
+            BreakerState::Open => 1.0,
+            BreakerState::HalfOpen => 2.0,
+        }
+    }
+}
+
+/// Stops calling a handler, and the sinks behind it, that keeps failing
+/// transiently, shared by every consumer it is given to.
+///
+/// After `threshold` consecutive transient failures the breaker opens and
+/// turns messages away for `cooldown`. It then half-opens, letting a single
+/// message through: success closes it again, another transient failure
+/// reopens it for a further `cooldown`. Any other outcome, including a
+/// permanent failure, shows the handler is reachable and counts as success.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<Inner>,
+    gauge: Gauge,
+}
+
+struct Inner {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration, metrics: &Metrics) -> Self {
+        let gauge = metrics.circuit_breaker_state.clone();
+        gauge.set(BreakerState::Closed.as_gauge());
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            inner: Mutex::new(Inner {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+            }),
+            gauge,
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Whether a message may be handled. When it may not, returns how long
+    /// until the breaker is due to let one through again.
+    ///
+    /// A message admitted here must have its outcome reported through
+    /// `record_success` or `record_failure`.
+    pub fn admit(&self) -> Result<(), Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open => {
+                let open_for = inner.opened_at.elapsed();
+                if open_for < self.cooldown {
+                    return Err(self.cooldown - open_for);
+                }
+                info!("Circuit breaker half-open, letting a test message through");
+                self.transition(&mut inner, BreakerState::HalfOpen);
+                Ok(())
+            }
+            // The test message is still running; if it fails the breaker
+            // stays open for another cooldown.
+            BreakerState::HalfOpen => Err(self.cooldown),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures = 0;
+        if inner.state != BreakerState::Closed {
+            info!("Circuit breaker closed, handler recovered");
+            self.transition(&mut inner, BreakerState::Closed);
+        }
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+        let reopen = match inner.state {
+            BreakerState::Closed => inner.consecutive_failures >= self.threshold,
+            BreakerState::HalfOpen => true,
+            // A message admitted before the breaker opened.
+            BreakerState::Open => false,
+        };
+        if reopen {
+            warn!(
+                consecutive_failures = inner.consecutive_failures,
+                cooldown_ms = self.cooldown.as_millis() as u64,
+                "Circuit breaker opened, sending messages straight to retry"
+            );
+            inner.opened_at = Instant::now();
+            self.transition(&mut inner, BreakerState::Open);
+        }
+    }
+
+    fn transition(&self, inner: &mut Inner, state: BreakerState) {
+        inner.state = state;
+        self.gauge.set(state.as_gauge());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    fn breaker(metrics: &Metrics) -> CircuitBreaker {
+        CircuitBreaker::new(3, COOLDOWN, metrics)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_consecutive_failures_open_the_breaker() {
+        let metrics = Metrics::new().unwrap();
+        let breaker = breaker(&metrics);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.admit(), Ok(()));
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(metrics.circuit_breaker_state.get(), 1.0);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(breaker.admit(), Err(Duration::from_secs(20)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_half_open_success_closes_the_breaker() {
+        let metrics = Metrics::new().unwrap();
+        let breaker = breaker(&metrics);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+
+        tokio::time::advance(COOLDOWN).await;
+        assert_eq!(breaker.admit(), Ok(()));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert_eq!(metrics.circuit_breaker_state.get(), 2.0);
+        // Only the one test message gets through while it runs.
+        assert!(breaker.admit().is_err());
+
+        breaker.record_success();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(metrics.circuit_breaker_state.get(), 0.0);
+        assert_eq!(breaker.admit(), Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_half_open_failure_reopens_for_another_cooldown() {
+        let metrics = Metrics::new().unwrap();
+        let breaker = breaker(&metrics);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        tokio::time::advance(COOLDOWN).await;
+        assert_eq!(breaker.admit(), Ok(()));
+
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(metrics.circuit_breaker_state.get(), 1.0);
+        assert_eq!(breaker.admit(), Err(COOLDOWN));
+        tokio::time::advance(COOLDOWN).await;
+        assert_eq!(breaker.admit(), Ok(()));
+    }
+}
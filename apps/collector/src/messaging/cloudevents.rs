@@ -0,0 +1,342 @@
+//! Turning CloudEvents into the v1 JSON event the handler validates.
+//!
+//! Both AMQP content modes are recognized. A binary-mode event carries its
+//! attributes in `ce-` headers, with `ce-specversion` marking it as a
+//! CloudEvent, and its data as the body. A structured-mode event is a JSON
+//! document with `content_type: application/cloudevents+json`.
+//!
+//! `id`, `type` and `source` become `eventId`, `eventType` and `source`,
+//! `time` becomes `timestamp` and the data becomes `payload`; validation then
+//! runs on the result as for any v1 event.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use lapin::types::{AMQPValue, FieldTable, ShortString};
+use serde_json::{Map, Value};
+
+use super::consumer::EVENT_VERSION_HEADER;
+use super::dlq::header_string;
+use super::encoding::{convert, JSON_CONTENT_TYPE};
+use super::handler::HandlerError;
+use super::source::IncomingMessage;
+
+pub const CLOUDEVENTS_CONTENT_TYPE: &str = "application/cloudevents+json";
+/// Prefix of the headers carrying a binary-mode event's attributes.
+pub const CE_HEADER_PREFIX: &str = "ce-";
+const SPECVERSION_HEADER: &str = "ce-specversion";
+const SUPPORTED_SPECVERSION: &str = "1.0";
+
+/// Rewrites a CloudEvent into a v1 JSON event, returning whether the message
+/// was one. Other messages are left as they are.
+///
+/// The `ce-` headers are removed and `content_type` set to JSON; the event as
+/// published is kept as the message's `original`. Failures are permanent.
+pub(crate) fn decode_cloud_event(
+    message: &mut IncomingMessage,
+    media_type: Option<&str>,
+) -> Result<bool, HandlerError> {
+    let headers = message.properties.headers().clone().unwrap_or_default();
+    let event = if headers.inner().contains_key(SPECVERSION_HEADER) {
+        from_binary(&headers, &message.data, media_type)?
+    } else if media_type == Some(CLOUDEVENTS_CONTENT_TYPE) {
+        from_structured(&message.data)?
+    } else {
+        return Ok(false);
+    };
+
+    let mut remaining = FieldTable::default();
+    for (key, value) in headers.inner() {
+        if !key.as_str().starts_with(CE_HEADER_PREFIX) {
+            remaining.insert(key.clone(), value.clone());
+        }
+    }
+    // The event now has the v1 shape, whatever version the producer declared.
+    remaining.insert(
+        EVENT_VERSION_HEADER.into(),
+        AMQPValue::LongString("v1".into()),
+    );
+
+    let data = serde_json::to_vec(&event)
+        .map_err(|e| HandlerError::Permanent(format!("Cannot encode event: {}", e)))?;
+    let properties = message
+        .properties
+        .clone()
+        .with_headers(remaining)
+        .with_content_type(ShortString::from(JSON_CONTENT_TYPE));
+    convert(message, data, properties);
+    Ok(true)
+}
+
+/// A binary-mode event: attributes from the `ce-` headers, data from the
+/// body, which must be JSON.
+fn from_binary(
+    headers: &FieldTable,
+    body: &[u8],
+    media_type: Option<&str>,
+) -> Result<Value, HandlerError> {
+    let attribute = |name: &str| header_string(headers, &format!("{}{}", CE_HEADER_PREFIX, name));
+    check_specversion(attribute("specversion").as_deref())?;
+
+    if let Some(other) = media_type.filter(|t| !t.is_empty() && *t != JSON_CONTENT_TYPE) {
+        return Err(HandlerError::Permanent(format!(
+            "Unsupported CloudEvent data content type `{}`; expected {}",
+            other, JSON_CONTENT_TYPE
+        )));
+    }
+    let data = if body.is_empty() {
+        None
+    } else {
+        Some(
+            serde_json::from_slice(body)
+                .map_err(|e| HandlerError::Permanent(format!("Invalid CloudEvent data: {}", e)))?,
+        )
+    };
+
+    Ok(to_event(
+        required(attribute("id"), "id")?,
+        required(attribute("type"), "type")?,
+        required(attribute("source"), "source")?,
+        attribute("time"),
+        data,
+    ))
+}
+
+/// A structured-mode event: attributes and data in one JSON document.
+fn from_structured(body: &[u8]) -> Result<Value, HandlerError> {
+    let document: Value = serde_json::from_slice(body)
+        .map_err(|e| HandlerError::Permanent(format!("Invalid CloudEvent JSON: {}", e)))?;
+    let attribute = |name: &str| {
+        document
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    check_specversion(attribute("specversion").as_deref())?;
+
+    let data =
+        match (document.get("data"), attribute("data_base64")) {
+            (Some(data), _) => Some(data.clone()),
+            (None, Some(encoded)) => {
+                let bytes = BASE64.decode(encoded).map_err(|e| {
+                    HandlerError::Permanent(format!("Invalid CloudEvent data_base64: {}", e))
+                })?;
+                Some(serde_json::from_slice(&bytes).map_err(|e| {
+                    HandlerError::Permanent(format!("Invalid CloudEvent data: {}", e))
+                })?)
+            }
+            (None, None) => None,
+        };
+
+    Ok(to_event(
+        required(attribute("id"), "id")?,
+        required(attribute("type"), "type")?,
+        required(attribute("source"), "source")?,
+        attribute("time"),
+        data,
+    ))
+}
+
+fn check_specversion(specversion: Option<&str>) -> Result<(), HandlerError> {
+    match specversion {
+        Some(SUPPORTED_SPECVERSION) => Ok(()),
+        Some(other) => Err(HandlerError::Permanent(format!(
+            "Unsupported CloudEvents specversion `{}`; expected {}",
+            other, SUPPORTED_SPECVERSION
+        ))),
+        None => Err(HandlerError::Permanent(
+            "CloudEvent is missing specversion".to_string(),
+        )),
+    }
+}
+
+fn required(value: Option<String>, name: &str) -> Result<String, HandlerError> {
+    value.filter(|v| !v.is_empty()).ok_or_else(|| {
+        HandlerError::Permanent(format!(
+            "CloudEvent is missing required attribute `{}`",
+            name
+        ))
+    })
+}
+
+/// The v1 event standing for a CloudEvent. A missing `data` leaves out
+/// `payload`, for validation to reject.
+fn to_event(
+    id: String,
+    event_type: String,
+    source: String,
+    time: Option<String>,
+    data: Option<Value>,
+) -> Value {
+    let mut event = Map::new();
+    event.insert("eventId".into(), id.into());
+    event.insert("eventType".into(), event_type.into());
+    event.insert("source".into(), source.into());
+    if let Some(time) = time {
+        event.insert("timestamp".into(), time.into());
+    }
+    if let Some(data) = data {
+        event.insert("payload".into(), data);
+    }
+    Value::Object(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::encoding::decode_body;
+    use crate::messaging::handler::MessageHandler;
+    use crate::messaging::test_util::delivery_with_properties;
+    use crate::metrics::Metrics;
+    use crate::processors::telemetry::TelemetryHandler;
+    use lapin::BasicProperties;
+    use serde_json::json;
+    use tokio_util::sync::CancellationToken;
+
+    fn log_data() -> Value {
+        json!({"level": "info", "message": "started", "serviceName": "api"})
+    }
+
+    fn expected_event() -> Value {
+        json!({
+            "eventId": "evt-1",
+            "eventType": "telemetry.log.captured",
+            "source": "/services/api",
+            "timestamp": "2024-01-01T00:00:00Z",
+            "payload": log_data()
+        })
+    }
+
+    fn binary(attributes: &[(&str, &str)], data: &[u8]) -> IncomingMessage {
+        let mut headers = FieldTable::default();
+        for (name, value) in attributes {
+            headers.insert(
+                format!("{}{}", CE_HEADER_PREFIX, name).into(),
+                AMQPValue::LongString((*value).into()),
+            );
+        }
+        headers.insert("x-tenant".into(), AMQPValue::LongString("acme".into()));
+        delivery_with_properties(
+            1,
+            data,
+            BasicProperties::default()
+                .with_headers(headers)
+                .with_content_type(JSON_CONTENT_TYPE.into()),
+        )
+    }
+
+    fn structured(document: &Value) -> IncomingMessage {
+        delivery_with_properties(
+            1,
+            &serde_json::to_vec(document).unwrap(),
+            BasicProperties::default().with_content_type(CLOUDEVENTS_CONTENT_TYPE.into()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_binary_mode_event_is_mapped_and_processed() {
+        let mut message = binary(
+            &[
+                ("specversion", "1.0"),
+                ("id", "evt-1"),
+                ("type", "telemetry.log.captured"),
+                ("source", "/services/api"),
+                ("time", "2024-01-01T00:00:00Z"),
+            ],
+            &serde_json::to_vec(&log_data()).unwrap(),
+        );
+
+        decode_body(&mut message).unwrap();
+
+        let event: Value = serde_json::from_slice(&message.data).unwrap();
+        assert_eq!(event, expected_event());
+        let headers = message.properties.headers().clone().unwrap();
+        assert!(!headers
+            .inner()
+            .keys()
+            .any(|key| key.as_str().starts_with(CE_HEADER_PREFIX)));
+        assert_eq!(header_string(&headers, "x-tenant").as_deref(), Some("acme"));
+
+        // Decoding a retried copy again leaves it as it is.
+        let decoded = message.data.clone();
+        decode_body(&mut message).unwrap();
+        assert_eq!(message.data, decoded);
+
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+        assert!(handler
+            .handle(message, CancellationToken::new())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_structured_mode_event_is_mapped_and_processed() {
+        let mut message = structured(&json!({
+            "specversion": "1.0",
+            "id": "evt-1",
+            "type": "telemetry.log.captured",
+            "source": "/services/api",
+            "time": "2024-01-01T00:00:00Z",
+            "datacontenttype": "application/json",
+            "data": log_data()
+        }));
+
+        decode_body(&mut message).unwrap();
+
+        let event: Value = serde_json::from_slice(&message.data).unwrap();
+        assert_eq!(event, expected_event());
+        assert_eq!(
+            message
+                .properties
+                .content_type()
+                .as_ref()
+                .map(|t| t.as_str()),
+            Some(JSON_CONTENT_TYPE)
+        );
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+        assert!(handler
+            .handle(message, CancellationToken::new())
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_structured_mode_reads_data_base64() {
+        let mut message = structured(&json!({
+            "specversion": "1.0",
+            "id": "evt-1",
+            "type": "telemetry.log.captured",
+            "source": "/services/api",
+            "time": "2024-01-01T00:00:00Z",
+            "data_base64": BASE64.encode(serde_json::to_vec(&log_data()).unwrap())
+        }));
+
+        decode_body(&mut message).unwrap();
+
+        let event: Value = serde_json::from_slice(&message.data).unwrap();
+        assert_eq!(event, expected_event());
+    }
+
+    #[test]
+    fn test_invalid_cloud_events_are_permanent() {
+        let missing_source = binary(
+            &[("specversion", "1.0"), ("id", "evt-1"), ("type", "log")],
+            b"{}",
+        );
+        let wrong_version = structured(&json!({
+            "specversion": "0.3", "id": "evt-1", "type": "log", "source": "/api"
+        }));
+
+        for (mut message, expected) in [
+            (
+                missing_source,
+                "CloudEvent is missing required attribute `source`",
+            ),
+            (wrong_version, "Unsupported CloudEvents specversion `0.3`"),
+        ] {
+            assert!(matches!(
+                decode_body(&mut message),
+                Err(HandlerError::Permanent(reason)) if reason.starts_with(expected)
+            ));
+        }
+    }
+}
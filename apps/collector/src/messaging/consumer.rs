@@ -1,28 +1,208 @@
-use futures::StreamExt;
-use lapin::{options::*, types::FieldTable, BasicProperties, Channel};
-use std::sync::Arc;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use lru::LruCache;
+use lapin::{BasicProperties, Channel};
+use prometheus::Counter;
+use std::fmt::Display;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Notify;
-use tracing::{error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 
-use super::handler::{HandlerError, MessageHandler};
+use super::ack_window::AckWindow;
+use super::broker::ConsumerBroker;
+use super::circuit_breaker::CircuitBreaker;
+use super::correlation::{self, ensure_correlation_id};
+use super::dedup::DedupCache;
+use super::quarantine::Quarantine;
+use super::encoding::decode_body;
+use super::dlq::{
+    header_string, header_u32, header_u64, headers_to_json, normalize_epoch_millis, DlqEnvelope,
+    DLQ_ENVELOPE_CONTENT_TYPE, REANIMATION_COUNT_HEADER,
+};
+use super::handler::{HandlerError, HandlerOutcome, MessageHandler};
+use super::prefetch::PrefetchTuner;
+use super::recovery::declare_queues;
+use super::rate_limit::RateLimiter;
+use super::retry_policy::{policy_for, RetryPolicies, RetryPolicy};
+use super::source::{IncomingMessage, Source};
+use super::topology::{
+    ExchangeDeclaration, Overflow, QueueMode, QueueRole, QueueTopology, QueueType,
+    retry_tier_queue, TopologyOperation, TopologySpec,
+};
+use super::trace_context::{set_parent, TraceParent};
+use super::worker_pool::WorkerPool;
+use crate::adapters::sink::{Event, MultiSink, Sink};
+use crate::adapters::{Downstream, LocalStore};
+use crate::clock::{Clock, ClockGuard, SystemClock, DEFAULT_MAX_CLOCK_SKEW};
+use crate::metrics::health::Readiness;
+use crate::metrics::recent::{Outcome, RecentEvent, RecentEvents};
 use crate::metrics::Metrics;
 
-const MAX_RETRIES: u32 = 3;
+/// Transient failures retried when the builder is not given `max_retries`.
+const DEFAULT_MAX_RETRIES: u32 = 3;
+/// Retry delay used for every attempt unless `with_retry_backoff` is set.
 const RETRY_DELAY_MS: u64 = 5000;
-const RETRY_HEADER: &str = "x-retry-count";
-const ERROR_REASON_HEADER: &str = "x-error-reason";
-const ERROR_TYPE_HEADER: &str = "x-error-type";
+/// Attempts at a `basic_ack` before the delivery is left to be redelivered.
+const ACK_ATTEMPTS: u32 = 3;
+/// Pause between ack attempts.
+const ACK_RETRY_DELAY: Duration = Duration::from_millis(100);
+/// How often a partially filled ack batch is flushed when batching is enabled.
+const ACK_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+/// How often an idle-shutdown consumer checks whether its queue has drained.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Requeued messages whose retry count is remembered; the least recently
+/// requeued are forgotten beyond this.
+const REQUEUE_TRACKED_MESSAGES: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+/// Cap on the wait before a resubscribe attempt.
+const MAX_STREAM_RESTART_DELAY: Duration = Duration::from_secs(60);
+pub const RETRY_HEADER: &str = "x-retry-count";
+/// Deliveries of a message so far, added by quorum queues.
+pub const DELIVERY_COUNT_HEADER: &str = "x-delivery-count";
+pub const ERROR_REASON_HEADER: &str = "x-error-reason";
+pub const ERROR_TYPE_HEADER: &str = "x-error-type";
+pub const ORIGINAL_QUEUE_HEADER: &str = "x-original-queue";
+pub const EVENT_VERSION_HEADER: &str = "x-event-version";
+/// Version label for messages whose version header cannot be read.
+pub const UNKNOWN_VERSION: &str = "unknown";
+/// Enqueue time in milliseconds, set by the broker's `rabbitmq_message_timestamp` plugin.
+pub const BROKER_TIMESTAMP_HEADER: &str = "timestamp_in_ms";
+
+/// When a delivery is acknowledged relative to handling it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// Ack once the message is processed, retried or dead-lettered, so a
+    /// crash mid-processing redelivers it.
+    AtLeastOnce,
+    /// Ack on receipt, before the handler runs, so a crash mid-processing
+    /// loses the message. Failures are dropped instead of retried or
+    /// dead-lettered.
+    AtMostOnce,
+}
+
+impl DeliveryMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AtLeastOnce => "at_least_once",
+            Self::AtMostOnce => "at_most_once",
+        }
+    }
+}
+
+impl FromStr for DeliveryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "at_least_once" => Ok(Self::AtLeastOnce),
+            "at_most_once" => Ok(Self::AtMostOnce),
+            other => Err(format!(
+                "unknown delivery mode `{}`, expected at_least_once or at_most_once",
+                other
+            )),
+        }
+    }
+}
+
+/// How a message that failed transiently is handed back for another attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryStrategy {
+    /// Republish it to the retry queue with an incremented `x-retry-count`,
+    /// from where it returns to the main queue after the backoff delay.
+    Republish,
+    /// `basic_nack` it with `requeue`, so the broker delivers it again at
+    /// once, close to its original position.
+    Requeue,
+}
+
+impl RetryStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Republish => "republish",
+            Self::Requeue => "requeue",
+        }
+    }
+}
+
+impl FromStr for RetryStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "republish" => Ok(Self::Republish),
+            "requeue" => Ok(Self::Requeue),
+            other => Err(format!(
+                "unknown retry strategy `{}`, expected republish or requeue",
+                other
+            )),
+        }
+    }
+}
 
 pub struct Consumer {
-    channel: Channel,
+    broker: Arc<dyn ConsumerBroker>,
     queue_name: String,
     consumer_tag: String,
     handler: Arc<dyn MessageHandler>,
     shutdown: Arc<Notify>,
     metrics: Arc<Metrics>,
+    /// Transient failures retried before a message goes to the DLQ.
+    max_retries: u32,
+    retry_base_delay: Duration,
+    /// Cap on the backoff, and the retry queue's own TTL.
+    retry_max_delay: Duration,
+    /// Overrides of `max_retries` and `retry_base_delay` by error class.
+    retry_policies: RetryPolicies,
+    /// TTLs of the tiered retry queues; empty uses the single retry queue.
+    retry_tiers: Vec<Duration>,
+    retry_strategy: RetryStrategy,
+    /// Retry counts of requeued messages, which come back with their headers
+    /// unchanged, keyed as by `Quarantine::key`.
+    requeued: Mutex<LruCache<String, u32>>,
+    /// Exchange the queue is bound to, with its routing keys; `None` consumes
+    /// through the default exchange only.
+    exchange: Option<(ExchangeDeclaration, Vec<String>)>,
+    /// Type of the main queue, and mode of every classic queue.
+    queue_type: QueueType,
+    queue_mode: QueueMode,
+    /// Length limits of the queues of each role; unlisted queues are unbounded.
+    max_lengths: Vec<(QueueRole, u32, Overflow)>,
+    /// Dead-letter a `DlqEnvelope` instead of the bare body.
+    dlq_envelope: bool,
+    delivery_mode: DeliveryMode,
+    ack_window: Option<Arc<Mutex<AckWindow>>>,
+    idle_shutdown: Option<Duration>,
+    /// Times the subscription is re-established after its stream ends, and the
+    /// delay before the first attempt, doubled for each further one.
+    stream_restarts: u32,
+    stream_restart_delay: Duration,
+    clock: ClockGuard,
+    /// Wall-clock time for timestamps written on messages and recent events.
+    time: Arc<dyn Clock>,
+    recent: Option<Arc<RecentEvents>>,
+    readiness: Option<Arc<Readiness>>,
+    sinks: MultiSink,
+    concurrency: usize,
+    handler_timeout: Option<Duration>,
+    /// Time in-flight handlers get to finish after shutdown before `cancel`
+    /// is cancelled; `None` never cancels them.
+    shutdown_grace: Option<Duration>,
+    /// Parent of the token every handler call is given.
+    cancel: CancellationToken,
+    dedup: Option<Arc<DedupCache>>,
+    quarantine: Option<Arc<Quarantine>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    prefetch_tuner: Option<Mutex<PrefetchTuner>>,
 }
 
 impl Consumer {
+    /// Builds a consumer from positional arguments; see `ConsumerBuilder` for
+    /// the named equivalent.
     pub fn new(
         channel: Channel,
         queue_name: String,
@@ -30,110 +210,385 @@ impl Consumer {
         handler: Arc<dyn MessageHandler>,
         shutdown: Arc<Notify>,
         metrics: Arc<Metrics>,
+        max_retries: u32,
     ) -> Self {
-        Self {
-            channel,
-            queue_name,
-            consumer_tag,
-            metrics,
-            handler,
-            shutdown,
+        ConsumerBuilder::new()
+            .channel(channel)
+            .queue_name(queue_name)
+            .consumer_tag(consumer_tag)
+            .handler(handler)
+            .shutdown(shutdown)
+            .metrics(metrics)
+            .max_retries(max_retries)
+            .build()
+            .expect("every required field is set")
+    }
+
+    /// Acknowledges deliveries in batches of `batch_size` using `multiple: true`.
+    ///
+    /// A batch is only flushed up to the highest tag whose predecessors are all
+    /// settled, so a message whose retry or DLQ publish is still unconfirmed
+    /// holds back every later ack. A batch size of 1 keeps per-message acks.
+    pub fn with_ack_batching(mut self, batch_size: usize) -> Self {
+        self.ack_window =
+            (batch_size > 1).then(|| Arc::new(Mutex::new(AckWindow::new(batch_size))));
+        self
+    }
+
+    /// Batches acks in `window`, shared with the other consumers on the same channel.
+    ///
+    /// Delivery tags are numbered per channel, so a multiple-ack also covers
+    /// the other consumers' earlier deliveries. Tracking them all in one
+    /// window keeps a batch from acking one that is still in flight.
+    pub fn with_shared_ack_window(mut self, window: Arc<Mutex<AckWindow>>) -> Self {
+        self.ack_window = Some(window);
+        self
+    }
+
+    /// Delays retry attempt N by `base * 2^(N-1)`, capped at `max`.
+    ///
+    /// The delay is set as each message's `expiration`; the retry queue's TTL
+    /// becomes `max`, so it only ever acts as the cap.
+    pub fn with_retry_backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.retry_base_delay = base;
+        self.retry_max_delay = max.max(base);
+        self
+    }
+
+    /// Retries transient errors tagged with one of these classes under its
+    /// policy instead of the consumer's `max_retries` and backoff base. The
+    /// backoff is still capped by the retry queue's TTL.
+    pub fn with_retry_policies(mut self, policies: RetryPolicies) -> Self {
+        self.retry_policies = policies;
+        self
+    }
+
+    /// Retries through one queue per delay in `tiers` instead of the single
+    /// retry queue with per-message expirations: retry N goes to tier N, and
+    /// later retries to the last tier. A retry hint picks the first tier
+    /// that waits at least as long.
+    ///
+    /// The broker only expires messages at the head of a queue, so with a
+    /// single queue a short delay can wait behind a long one. Every message
+    /// in a tier has the same TTL, so each waits exactly its tier's delay.
+    /// The backoff set by `with_retry_backoff` and the base delays of retry
+    /// policies no longer apply; retry limits still do.
+    pub fn with_retry_tiers(mut self, tiers: Vec<Duration>) -> Self {
+        self.retry_tiers = tiers;
+        self
+    }
+
+    /// Hands failed messages back for another attempt by `strategy`.
+    ///
+    /// With `RetryStrategy::Requeue` there is no delay, so backoff, retry
+    /// tiers and retry hints do not apply; retry limits still do. A requeued
+    /// message keeps its headers, so its retry count is the larger of the
+    /// count this consumer remembers for it and the `x-delivery-count` quorum
+    /// queues add, on top of any `x-retry-count` it arrived with.
+    pub fn with_retry_strategy(mut self, strategy: RetryStrategy) -> Self {
+        self.retry_strategy = strategy;
+        self
+    }
+
+    /// Binds the queue to `exchange` once per routing key, declaring the
+    /// exchange first, so messages published there are consumed too.
+    pub fn with_exchange(mut self, exchange: ExchangeDeclaration, routing_keys: Vec<String>) -> Self {
+        self.exchange = Some((exchange, routing_keys));
+        self
+    }
+
+    /// Declares the main queue as `queue_type` and every classic queue in
+    /// `queue_mode`; see `QueueTopology::with_queue_options`.
+    pub fn with_queue_options(mut self, queue_type: QueueType, queue_mode: QueueMode) -> Self {
+        self.queue_type = queue_type;
+        self.queue_mode = queue_mode;
+        self
+    }
+
+    /// Caps the queue of `role` at `max_length` messages, with `overflow`
+    /// deciding what the broker does with messages beyond it.
+    pub fn with_max_length(mut self, role: QueueRole, max_length: u32, overflow: Overflow) -> Self {
+        self.max_lengths.push((role, max_length, overflow));
+        self
+    }
+
+    /// Publishes failed messages to the DLQ wrapped in a JSON `DlqEnvelope`
+    /// carrying the original body, routing key and headers, instead of the
+    /// bare body.
+    pub fn with_dlq_envelope(mut self, enabled: bool) -> Self {
+        self.dlq_envelope = enabled;
+        self
+    }
+
+    /// Acks each delivery on receipt in `DeliveryMode::AtMostOnce`, trading
+    /// retries and the DLQ for never handling a message twice.
+    ///
+    /// Those acks bypass ack batching, which would hold them back until
+    /// after the handler ran.
+    pub fn with_delivery_mode(mut self, mode: DeliveryMode) -> Self {
+        self.delivery_mode = mode;
+        self
+    }
+
+    /// Stops consuming once the queue looks drained, for draining a queue
+    /// that is being renamed while another consumer takes over the new name.
+    ///
+    /// The queue counts as drained when no delivery arrived for `idle` and the
+    /// broker reports no ready messages. Messages parked in the retry queue
+    /// are invisible to that check, so `idle` must be longer than the retry
+    /// delay: a retry coming back resets the idle timer. Once stopped this
+    /// way the consumer no longer counts towards readiness.
+    pub fn with_idle_shutdown(mut self, idle: Duration) -> Self {
+        self.idle_shutdown = Some(idle);
+        self
+    }
+
+    /// Subscribes again, up to `max_attempts` times in a row, when the
+    /// delivery stream ends while the consumer is still meant to run, waiting
+    /// `delay * 2^(N-1)`, capped at a minute, before attempt N. A delivery
+    /// resets the count. Once
+    /// the attempts are used up `start` fails with `StreamEnded`; with 0 it
+    /// returns as soon as the stream ends.
+    pub fn with_stream_restarts(mut self, max_attempts: u32, delay: Duration) -> Self {
+        self.stream_restarts = max_attempts;
+        self.stream_restart_delay = delay;
+        self
+    }
+
+    /// How far ahead of the local clock a producer timestamp may be before
+    /// latency observations based on it are skipped.
+    pub fn with_max_clock_skew(mut self, max_skew: Duration) -> Self {
+        self.clock = ClockGuard::new(max_skew, &self.metrics);
+        self
+    }
+
+    /// Reads the current time from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.time = clock;
+        self
+    }
+
+    /// Records the outcome of every handled message in `recent`.
+    pub fn with_recent_events(mut self, recent: Arc<RecentEvents>) -> Self {
+        self.recent = recent.is_enabled().then_some(recent);
+        self
+    }
+
+    /// Reports in `readiness` whether this consumer is subscribed.
+    pub fn with_readiness(mut self, readiness: Arc<Readiness>) -> Self {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    /// Handles up to `concurrency` deliveries at once, each in its own task.
+    ///
+    /// Only deliveries the broker has sent can be in flight, so anything above
+    /// the channel's prefetch count has no effect. Each message is still acked,
+    /// retried or dead-lettered on its own; with ack batching the window only
+    /// advances past messages whose predecessors are settled.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Gives up on a handler call after `timeout`, retrying the message as a
+    /// transient failure so a hung handler cannot hold a prefetch slot forever.
+    /// A zero timeout leaves handler calls unbounded.
+    pub fn with_handler_timeout(mut self, timeout: Duration) -> Self {
+        self.handler_timeout = (!timeout.is_zero()).then_some(timeout);
+        self
+    }
+
+    /// Cancels the tokens of handlers still running `grace` after the consumer
+    /// stopped, so handlers that watch theirs return early instead of
+    /// running past the shutdown timeout. It then keeps waiting for them.
+    pub fn with_shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = Some(grace);
+        self
+    }
+
+    /// Acks a message whose `message_id` is in `cache` without handling it,
+    /// and adds the id of every successfully processed message. Messages
+    /// without a `message_id` are always handled.
+    pub fn with_deduplication(mut self, cache: Arc<DedupCache>) -> Self {
+        self.dedup = Some(cache);
+        self
+    }
+
+    /// Counts panics, timeouts and redeliveries of each message in
+    /// `quarantine`, and dead-letters one that reached its threshold as
+    /// `poison` instead of handling it again.
+    pub fn with_quarantine(mut self, quarantine: Arc<Quarantine>) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Waits for a token from `limiter` before each handler call. The waiting
+    /// message stays unacked, so once prefetch is used up the broker stops
+    /// sending more; share one limiter to cap several consumers together.
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Sends messages straight to retry, without calling the handler, while
+    /// `breaker` is open. Short-circuited messages use up a retry like any
+    /// transient failure, with the rest of the cooldown as their delay.
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Retunes the channel's prefetch every `tuner.interval()` from how long
+    /// this queue's messages took to process, within the tuner's bounds.
+    ///
+    /// The limit is set channel-wide (global), the only kind RabbitMQ changes
+    /// for consumers already running; a per-consumer limit would only reach
+    /// consumers started after it.
+    pub fn with_adaptive_prefetch(mut self, tuner: PrefetchTuner) -> Self {
+        self.prefetch_tuner = Some(Mutex::new(tuner));
+        self
+    }
+
+    /// Stores every successfully handled message in `store` before acking it.
+    pub fn with_local_store(self, store: LocalStore) -> Self {
+        self.with_sink(Arc::new(store))
+    }
+
+    /// Hands every successfully handled message to `downstream` and acks it
+    /// once the downstream, or its WAL, has it.
+    pub fn with_downstream(self, downstream: Downstream) -> Self {
+        self.with_sink(Arc::new(downstream))
+    }
+
+    /// Writes every successfully handled message to `sink` before acking it,
+    /// alongside any sinks added before. See [`MultiSink`] for how failures
+    /// of individual sinks decide between retry and the DLQ.
+    pub fn with_sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// The topology this consumer declares in `setup_queues`.
+    pub fn topology(&self) -> QueueTopology {
+        let mut topology =
+            QueueTopology::for_queue(&self.queue_name, self.retry_max_delay.as_millis() as u32);
+        if !self.retry_tiers.is_empty() {
+            let ttls: Vec<u32> = self.retry_tiers.iter().map(|ttl| ttl.as_millis() as u32).collect();
+            topology = topology.with_retry_tiers(&ttls);
+        }
+        let topology = topology.with_queue_options(self.queue_type, self.queue_mode);
+        let topology = self
+            .max_lengths
+            .iter()
+            .fold(topology, |topology, &(role, max_length, overflow)| {
+                topology.with_max_length(role, max_length, overflow)
+            });
+        match &self.exchange {
+            Some((exchange, routing_keys)) => topology.with_exchange(exchange.clone(), routing_keys),
+            None => topology,
         }
     }
 
-    pub async fn setup_queues(&self) -> Result<(), ConsumerError> {
-        let dlq_name = format!("{}.dlq", self.queue_name);
-        let retry_name = format!("{}.retry", self.queue_name);
-
-        let dlq_args = FieldTable::default();
-        self.channel
-            .queue_declare(
-                &dlq_name,
-                QueueDeclareOptions {
-                    durable: true,
-                    ..Default::default()
-                },
-                dlq_args.clone(),
-            )
-            .await
-            .map_err(|e| ConsumerError::SetupFailed(format!("DLQ setup failed: {}", e)))?;
-
-        let mut retry_args = FieldTable::default();
-        retry_args.insert(
-            "x-message-ttl".into(),
-            lapin::types::AMQPValue::LongInt(RETRY_DELAY_MS as i32),
-        );
-        retry_args.insert(
-            "x-dead-letter-exchange".into(),
-            lapin::types::AMQPValue::LongString("".into()),
-        );
-        retry_args.insert(
-            "x-dead-letter-routing-key".into(),
-            lapin::types::AMQPValue::LongString(self.queue_name.clone().into()),
-        );
-
-        self.channel
-            .queue_declare(
-                &retry_name,
-                QueueDeclareOptions {
-                    durable: true,
-                    ..Default::default()
-                },
-                retry_args,
-            )
-            .await
-            .map_err(|e| ConsumerError::SetupFailed(format!("Retry queue setup failed: {}", e)))?;
-
-        let mut main_args = FieldTable::default();
-        main_args.insert(
-            "x-dead-letter-exchange".into(),
-            lapin::types::AMQPValue::LongString("".into()),
-        );
-        main_args.insert(
-            "x-dead-letter-routing-key".into(),
-            lapin::types::AMQPValue::LongString(dlq_name.clone().into()),
-        );
-
-        self.channel
-            .queue_declare(
-                &self.queue_name,
-                QueueDeclareOptions {
-                    durable: true,
-                    passive: false,
-                    ..Default::default()
-                },
-                main_args,
-            )
-            .await
-            .map_err(|e| ConsumerError::SetupFailed(format!("Main queue setup failed: {}", e)))?;
+    pub async fn setup_queues(&self) -> Result<QueueTopology, ConsumerError> {
+        let topology = self.topology();
+        declare_queues(self.broker.as_ref(), &topology).await?;
+        self.setup_exchange_and_bindings(&topology).await?;
 
+        for queue in topology.queues.iter().filter(|queue| queue.max_length.is_some()) {
+            warn!(
+                queue = %queue.name,
+                max_length = queue.max_length,
+                overflow = queue.overflow.map(|overflow| overflow.as_str()),
+                "Queue length is limited; messages beyond it are dropped or refused by the broker"
+            );
+        }
+
+        let retry_queues: Vec<&str> = topology
+            .queues
+            .iter()
+            .filter(|queue| queue.role == QueueRole::Retry)
+            .map(|queue| queue.name.as_str())
+            .collect();
         info!(
             queue = %self.queue_name,
-            dlq = %dlq_name,
-            retry_queue = %retry_name,
-            max_retries = MAX_RETRIES,
-            retry_delay_ms = RETRY_DELAY_MS,
+            dlq = %format!("{}.dlq", self.queue_name),
+            retry_queue = %retry_queues.join(","),
+            max_retries = self.max_retries,
+            retry_base_delay_ms = self.retry_base_delay.as_millis() as u64,
+            retry_max_delay_ms = self.retry_max_delay.as_millis() as u64,
             "Queue topology configured"
         );
 
+        Ok(topology)
+    }
+
+    /// Declares the exchanges and exchange-to-exchange bindings in `spec`.
+    pub async fn declare_topology(&self, spec: &TopologySpec) -> Result<(), ConsumerError> {
+        for operation in spec.operations() {
+            self.apply(operation).await?;
+        }
+
+        info!(
+            exchanges = spec.exchanges.len(),
+            bindings = spec.bindings.len(),
+            "Exchange topology configured"
+        );
+
+        Ok(())
+    }
+
+    /// Declares the exchange the queue consumes from and binds the queue to
+    /// it with each routing key. Does nothing when the topology has no
+    /// exchange, leaving the queue on the default exchange only.
+    pub async fn setup_exchange_and_bindings(
+        &self,
+        topology: &QueueTopology,
+    ) -> Result<(), ConsumerError> {
+        for operation in topology.exchange_operations() {
+            self.apply(operation).await?;
+        }
+
+        if !topology.queue_bindings.is_empty() {
+            info!(
+                queue = %topology.queue,
+                exchanges = ?topology.exchanges.iter().map(|e| &e.name).collect::<Vec<_>>(),
+                routing_keys = ?topology
+                    .queue_bindings
+                    .iter()
+                    .map(|b| &b.routing_key)
+                    .collect::<Vec<_>>(),
+                "Queue bound to exchange"
+            );
+        }
+
         Ok(())
     }
 
+    async fn apply(&self, operation: TopologyOperation<'_>) -> Result<(), ConsumerError> {
+        self.broker.apply(operation).await.map_err(|e| {
+            ConsumerError::SetupFailed(format!("{} setup failed: {}", operation, e))
+        })
+    }
+
+    /// Consumes until shutdown, the queue drains or the stream ends for good,
+    /// then waits for messages still being processed before returning.
     pub async fn start(self) -> Result<(), ConsumerError> {
+        let this = Arc::new(self);
+        this.run().await
+    }
+
+    async fn run(self: &Arc<Self>) -> Result<(), ConsumerError> {
         info!(
             queue = %self.queue_name,
             consumer_tag = %self.consumer_tag,
+            delivery_mode = self.delivery_mode.as_str(),
             "Starting RabbitMQ consumer"
         );
 
-        let mut consumer = self
-            .channel
-            .basic_consume(
-                &self.queue_name,
-                &self.consumer_tag,
-                BasicConsumeOptions::default(),
-                FieldTable::default(),
-            )
+        let mut source = self
+            .broker
+            .subscribe(&self.queue_name, &self.consumer_tag)
             .await
             .map_err(|e| {
                 error!(error = %e, queue = %self.queue_name, "Failed to start consumer");
@@ -146,9 +601,52 @@ impl Consumer {
         );
 
         self.metrics.active_consumers.inc();
+        if let Some(readiness) = &self.readiness {
+            readiness.set_consuming(&self.consumer_tag, true);
+        }
+        self.metrics
+            .queue_consuming
+            .with_label_values(&[&self.queue_name])
+            .set(1.0);
+
+        let mut ack_flush = tokio::time::interval(ACK_FLUSH_INTERVAL);
+        let mut idle_check = tokio::time::interval(IDLE_CHECK_INTERVAL);
+        let mut prefetch_tune = tokio::time::interval(
+            self.prefetch_tuner
+                .as_ref()
+                .map_or(IDLE_CHECK_INTERVAL, |tuner| tuner.lock().unwrap().interval()),
+        );
+        let mut last_delivery = Instant::now();
+        let mut workers = WorkerPool::new(self.concurrency);
+        let mut restarts: u32 = 0;
+        let mut ended = false;
+        let mut finished = false;
 
         loop {
             tokio::select! {
+                _ = ack_flush.tick(), if self.ack_window.is_some() => {
+                    self.flush_acks(true).await;
+                }
+
+                _ = idle_check.tick(), if self.idle_shutdown.is_some() => {
+                    if self.drained(last_delivery.elapsed()).await {
+                        info!(
+                            queue = %self.queue_name,
+                            consumer_tag = %self.consumer_tag,
+                            "Queue drained, stopping consumer"
+                        );
+                        if let Err(e) = self.broker.cancel(&self.consumer_tag).await {
+                            warn!(error = %e, "Failed to cancel drained consumer");
+                        }
+                        finished = true;
+                        break;
+                    }
+                }
+
+                _ = prefetch_tune.tick(), if self.prefetch_tuner.is_some() => {
+                    self.tune_prefetch().await;
+                }
+
                 _ = self.shutdown.notified() => {
                     info!(
                         consumer_tag = %self.consumer_tag,
@@ -157,16 +655,28 @@ impl Consumer {
                     break;
                 }
 
-                delivery = consumer.next() => {
+                delivery = source.next_message() => {
                     match delivery {
                         Some(Ok(delivery)) => {
-                            self.process_message(delivery).await;
+                            last_delivery = Instant::now();
+                            restarts = 0;
+                            let consumer = self.clone();
+                            workers
+                                .spawn(async move { consumer.process_message(delivery).await })
+                                .await;
                         }
                         Some(Err(e)) => {
                             error!(error = %e, "Error receiving message from RabbitMQ");
                         }
+                        None if restarts < self.stream_restarts => {
+                            restarts += 1;
+                            if !self.resubscribe(&mut source, restarts).await {
+                                break;
+                            }
+                        }
                         None => {
                             warn!("Consumer stream ended");
+                            ended = self.stream_restarts > 0;
                             break;
                         }
                     }
@@ -174,120 +684,592 @@ impl Consumer {
             }
         }
 
+        // No new deliveries are pulled from here on; messages already handed
+        // to the handler are finished and settled so they are not redelivered.
+        let drained = drain_with_grace(&mut workers, self.shutdown_grace, &self.cancel).await;
+        if drained > 0 {
+            info!(
+                consumer_tag = %self.consumer_tag,
+                drained,
+                "Drained in-flight messages"
+            );
+        }
+        self.flush_acks(true).await;
         self.metrics.active_consumers.dec();
+        match &self.readiness {
+            Some(readiness) if finished => readiness.forget_consumer(&self.consumer_tag),
+            Some(readiness) => readiness.set_consuming(&self.consumer_tag, false),
+            None => {}
+        }
+        self.metrics
+            .queue_consuming
+            .with_label_values(&[&self.queue_name])
+            .set(0.0);
         info!(consumer_tag = %self.consumer_tag, "Consumer stopped");
+        if ended {
+            return Err(ConsumerError::StreamEnded(self.stream_restarts));
+        }
         Ok(())
     }
-    async fn process_message(&self, delivery: lapin::message::Delivery) {
+
+    /// Waits out the backoff of restart `attempt` and subscribes again,
+    /// replacing `source` on success. A failed subscribe leaves the ended
+    /// stream in place, so the next read counts as another attempt. Returns
+    /// false if shutdown was signalled while waiting.
+    async fn resubscribe(&self, source: &mut Box<dyn Source>, attempt: u32) -> bool {
+        let delay = retry_delay(self.stream_restart_delay, MAX_STREAM_RESTART_DELAY, attempt);
+        warn!(
+            queue = %self.queue_name,
+            consumer_tag = %self.consumer_tag,
+            attempt,
+            max_attempts = self.stream_restarts,
+            delay_ms = delay.as_millis() as u64,
+            "Consumer stream ended, resubscribing"
+        );
+        self.metrics.consumer_restarts_total.inc();
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = self.shutdown.notified() => {
+                info!(
+                    consumer_tag = %self.consumer_tag,
+                    "Shutdown signal received, stopping consumer"
+                );
+                return false;
+            }
+        }
+
+        match self.broker.subscribe(&self.queue_name, &self.consumer_tag).await {
+            Ok(resubscribed) => {
+                *source = resubscribed;
+                info!(
+                    queue = %self.queue_name,
+                    consumer_tag = %self.consumer_tag,
+                    attempt,
+                    "Consumer resubscribed"
+                );
+            }
+            Err(e) => {
+                error!(error = %e, queue = %self.queue_name, attempt, "Failed to resubscribe");
+            }
+        }
+        true
+    }
+
+    /// Handles `delivery` in a `process_message` span, exported over OTLP when
+    /// configured, that ends once the message is acked, retried or
+    /// dead-lettered. A `traceparent` header makes it a child of the
+    /// producer's span; without one it starts a new trace.
+    ///
+    /// The span and every log line carry the message's correlation id, one
+    /// being generated and added to its headers when it has none.
+    async fn process_message(&self, mut delivery: IncomingMessage) {
+        observe_message_age(
+            &self.metrics,
+            &self.queue_name,
+            &delivery.properties,
+            &self.clock,
+            self.time.now(),
+        );
+        let (properties, correlation_id) = ensure_correlation_id(delivery.properties);
+        delivery.properties = properties;
+        let span = info_span!(
+            "process_message",
+            queue = %self.queue_name,
+            routing_key = %delivery.routing_key,
+            delivery_tag = delivery.delivery_tag,
+            %correlation_id,
+            retry_count = field::Empty,
+            outcome = field::Empty,
+        );
+        if let Some(parent) = delivery
+            .properties
+            .headers()
+            .as_ref()
+            .and_then(TraceParent::from_headers)
+        {
+            set_parent(&span, &parent);
+        }
+
+        self.handle_delivery(delivery, &correlation_id).instrument(span).await
+    }
+
+    async fn handle_delivery(&self, mut delivery: IncomingMessage, correlation_id: &str) {
+        // Decoded up front so sinks, retries and the DLQ all see the decoded body.
+        let decoded = decode_body(&mut delivery);
         let delivery_tag = delivery.delivery_tag;
         let routing_key = delivery.routing_key.clone();
-        let retry_count = self.get_retry_count(&delivery.properties);
+        let retry_count = self.get_retry_count(&delivery);
+        Span::current().record("retry_count", retry_count);
         let data = delivery.data.clone();
         let properties = delivery.properties.clone();
+        // A converted body is republished as it was published, so the copy's
+        // signature still matches when it comes back.
+        let (republish_data, republish_properties) = match &delivery.original {
+            Some(original) => (original.data.clone(), original.properties.clone()),
+            None => (data.clone(), properties.clone()),
+        };
+        let version = event_version(&properties).unwrap_or_else(|| UNKNOWN_VERSION.to_string());
+
+        let acked_on_receipt = self.delivery_mode == DeliveryMode::AtMostOnce;
+        if acked_on_receipt {
+            // Unacked, the broker would redeliver it, so it is not handled at all.
+            if let Err(e) = ack_with_retry(delivery_tag, &self.metrics.ack_failures_total, || {
+                self.broker.ack(delivery_tag, false)
+            })
+            .await
+            {
+                error!(
+                    error = %e,
+                    delivery_tag,
+                    %correlation_id,
+                    "Failed to ack message on receipt, skipping it"
+                );
+                return;
+            }
+        } else if let Some(window) = &self.ack_window {
+            window.lock().unwrap().track(delivery_tag);
+        }
+
+        if let Some(wait) = queue_wait(&properties, &self.clock, self.time.now()) {
+            self.metrics.observe(
+                &self.metrics.queue_wait_seconds,
+                &[&self.queue_name],
+                wait.as_secs_f64(),
+            );
+        }
 
         info!(
             delivery_tag,
+            %correlation_id,
             routing_key = routing_key.as_str(),
             retry_count,
+            redelivered = delivery.redelivered,
             payload_size = data.len(),
             "Processing message"
         );
 
+        if delivery.redelivered {
+            self.metrics.messages_redelivered_total.inc();
+        }
+        let message_id = properties.message_id().as_ref().map(|id| id.to_string());
+        if let (Some(dedup), Some(id)) = (&self.dedup, &message_id)
+            && dedup.is_duplicate(id)
+        {
+            info!(
+                delivery_tag,
+                %correlation_id,
+                message_id = %id,
+                "Duplicate message, acking without handling"
+            );
+            self.metrics.messages_deduplicated_total.inc();
+            Span::current().record("outcome", "deduplicated");
+            if !acked_on_receipt && let Err(e) = self.ack(delivery_tag).await {
+                error!(error = %e, delivery_tag, %correlation_id, "Failed to ack message");
+            }
+            return;
+        }
+        let quarantine = self
+            .quarantine
+            .as_ref()
+            .map(|quarantine| (quarantine, Quarantine::key(message_id.as_deref(), &data)));
+        if let Some((quarantine, key)) = &quarantine
+            && delivery.redelivered
+        {
+            quarantine.record_failure(key);
+        }
+        // An already acked message cannot be dead-lettered, so poison is not checked for.
+        let poison_reason = if acked_on_receipt {
+            None
+        } else if is_poison_candidate(delivery.redelivered, retry_count, self.poison_retries()) {
+            warn!(
+                delivery_tag,
+                %correlation_id,
+                retry_count,
+                "Redelivered message is a poison candidate, sending to DLQ"
+            );
+            Some("Redelivered after using up its retries")
+        } else if let Some((quarantine, key)) = &quarantine
+            && quarantine.is_quarantined(key)
+        {
+            warn!(
+                delivery_tag,
+                %correlation_id,
+                key = %key,
+                "Message keeps failing, quarantining it in the DLQ"
+            );
+            self.metrics.messages_quarantined_total.inc();
+            Some("Quarantined after repeated failures")
+        } else {
+            None
+        };
+        if let Some(reason) = poison_reason {
+            self.metrics
+                .messages_failed_total
+                .with_label_values(&[&self.queue_name, POISON_ERROR_TYPE, &version])
+                .inc();
+            count_dead_letter(&self.metrics, POISON_ERROR_TYPE, routing_key.as_str());
+            self.record_recent(&properties, routing_key.as_str(), Outcome::DeadLettered, Some(reason), 0.0);
+            Span::current().record("outcome", Outcome::DeadLettered.as_str());
+            if let Err(e) = self
+                .reject_to_dlq_with_reason(
+                    delivery_tag,
+                    republish_data,
+                    republish_properties,
+                    routing_key.as_str(),
+                    reason,
+                    POISON_ERROR_TYPE,
+                )
+                .await
+            {
+                error!(
+                    error = %e,
+                    delivery_tag,
+                    %correlation_id,
+                    "Failed to reject to DLQ with metadata"
+                );
+                self.abandon(delivery_tag).await;
+            }
+            return;
+        }
+
+        // Only messages the handler would see are checked, so a body that
+        // failed to decode never takes the half-open test slot.
+        let admission = match (&decoded, &self.circuit_breaker) {
+            (Ok(()), Some(breaker)) => Some(breaker.admit()),
+            _ => None,
+        };
+        let short_circuited = matches!(admission, Some(Err(_)));
+        if !short_circuited && let Some(limiter) = &self.rate_limiter {
+            let waited = limiter.acquire().await;
+            self.metrics.observe(
+                &self.metrics.rate_limit_wait_seconds,
+                &[&self.queue_name],
+                waited.as_secs_f64(),
+            );
+        }
+
         let start = std::time::Instant::now();
-        match self.handler.handle(delivery).await {
-            Ok(()) => {
+        let run = match (decoded, admission) {
+            (Ok(()), Some(Err(after))) => HandlerRun::Completed(Err(HandlerError::Retry {
+                after,
+                reason: CIRCUIT_OPEN_REASON.to_string(),
+            })),
+            (Ok(()), _) => {
+                let cancel = self.cancel.child_token();
+                run_handler(self.handler.clone(), delivery, self.handler_timeout, cancel).await
+            }
+            (Err(e), _) => HandlerRun::Completed(Err(e)),
+        };
+        match &run {
+            HandlerRun::Panicked(message) => {
+                self.metrics.messages_panicked_total.inc();
+                error!(delivery_tag, %correlation_id, panic = %message, "Handler panicked");
+            }
+            HandlerRun::TimedOut => {
+                self.metrics.messages_timed_out_total.inc();
+                warn!(delivery_tag, %correlation_id, "Handler timed out");
+            }
+            HandlerRun::Completed(_) => {}
+        }
+        if let Some((quarantine, key)) = &quarantine
+            && matches!(run, HandlerRun::Panicked(_) | HandlerRun::TimedOut)
+        {
+            quarantine.record_failure(key);
+        }
+        let (result, panicked) = run.into_result();
+        let result = match result {
+            Ok(outcome) => {
+                let event = Event {
+                    routing_key: routing_key.as_str(),
+                    version: &version,
+                    payload: &data,
+                    delivery_tag,
+                    correlation_id,
+                };
+                self.sinks.write(&event).await.map(|()| outcome).map_err(HandlerError::from)
+            }
+            Err(e) => Err(e),
+        };
+        if let (Some(breaker), Some(Ok(()))) = (&self.circuit_breaker, admission) {
+            match &result {
+                Err(HandlerError::Transient { .. } | HandlerError::Retry { .. }) => {
+                    breaker.record_failure()
+                }
+                _ => breaker.record_success(),
+            }
+        }
+        let retry_after = result.as_ref().err().and_then(HandlerError::retry_after);
+        let policy = match &result {
+            Err(e) => policy_for(e, &self.retry_policies, self.default_retry_policy()),
+            Ok(_) => self.default_retry_policy(),
+        };
+        let route = result.as_ref().err().map(|e| {
+            failure_route(self.delivery_mode, e, retry_count, policy.max_retries, panicked)
+        });
+        match result {
+            Ok(outcome) => {
                 let duration = start.elapsed().as_secs_f64();
-                info!(delivery_tag, retry_count, duration_ms = duration * 1000.0, "Message processed successfully");
+                info!(
+                    delivery_tag,
+                    %correlation_id,
+                    retry_count,
+                    duration_ms = duration * 1000.0,
+                    "Message processed successfully"
+                );
 
-                self.metrics
-                    .messages_processed_total
-                    .with_label_values(&[&self.queue_name, routing_key.as_str()])
-                    .inc();
+                self.metrics.record_processed(
+                    &self.queue_name,
+                    routing_key.as_str(),
+                    &version,
+                    &outcome.labels,
+                );
+                self.metrics.mark_processed_at(self.time.now());
+                self.record_recent(&properties, routing_key.as_str(), Outcome::Processed, None, duration);
+                Span::current().record("outcome", Outcome::Processed.as_str());
 
-                self.metrics
-                    .message_processing_duration_seconds
-                    .with_label_values(&[&self.queue_name, "success"])
-                    .observe(duration);
-
-                if let Err(e) = self
-                    .channel
-                    .basic_ack(delivery_tag, BasicAckOptions::default())
-                    .await
-                {
-                    error!(error = %e, delivery_tag, "Failed to ack message");
+                self.metrics.observe(
+                    &self.metrics.message_processing_duration_seconds,
+                    &[&self.queue_name, "success"],
+                    duration,
+                );
+
+                if let Some((quarantine, key)) = &quarantine {
+                    quarantine.forget(key);
+                }
+                if let (Some(dedup), Some(id)) = (&self.dedup, &message_id) {
+                    dedup.remember(id);
+                }
+                if !acked_on_receipt && let Err(e) = self.ack(delivery_tag).await {
+                    error!(error = %e, delivery_tag, %correlation_id, "Failed to ack message");
                 }
             }
-            Err(HandlerError::Transient(err)) => {
+            Err(
+                HandlerError::Transient { reason: err, .. }
+                | HandlerError::Retry { reason: err, .. },
+            ) => {
                 let duration = start.elapsed().as_secs_f64();
                 
                 self.metrics
                     .messages_failed_total
-                    .with_label_values(&[&self.queue_name, "transient"])
+                    .with_label_values(&[&self.queue_name, "transient", &version])
                     .inc();
+                let outcome = match route {
+                    Some(FailureRoute::DeadLetter(_)) => Outcome::DeadLettered,
+                    Some(FailureRoute::Retry) => Outcome::Retried,
+                    _ => Outcome::Discarded,
+                };
+                self.record_recent(&properties, routing_key.as_str(), outcome, Some(&err), duration);
+                Span::current().record("outcome", outcome.as_str());
 
-                self.metrics
-                    .message_processing_duration_seconds
-                    .with_label_values(&[&self.queue_name, "transient_error"])
-                    .observe(duration);
+                self.metrics.observe(
+                    &self.metrics.message_processing_duration_seconds,
+                    &[&self.queue_name, "transient_error"],
+                    duration,
+                );
 
-                if retry_count >= MAX_RETRIES {
+                if let Some(FailureRoute::DeadLetter(error_type)) = route {
                     error!(
                         delivery_tag,
+                        %correlation_id,
                         retry_count,
                         error = %err,
                         "Max retries exceeded, sending to DLQ"
                     );
 
-                    self.metrics.messages_dlq_total.inc();
+                    count_dead_letter(&self.metrics, error_type, routing_key.as_str());
+                    observe_dlq_retry_count(&self.metrics, &self.queue_name, retry_count);
 
                     // Add error metadata to headers before DLQ
-                    if let Err(e) = self.reject_to_dlq_with_reason(delivery_tag, data, properties, &err, "transient").await {
-                        error!(error = %e, delivery_tag, "Failed to reject to DLQ with metadata");
+                    if let Err(e) = self.reject_to_dlq_with_reason(delivery_tag, republish_data, republish_properties, routing_key.as_str(), &err, error_type).await {
+                        error!(
+                            error = %e,
+                            delivery_tag,
+                            %correlation_id,
+                            "Failed to reject to DLQ with metadata"
+                        );
+                        self.abandon(delivery_tag).await;
                     }
-                } else {
+                } else if acked_on_receipt {
                     warn!(
                         delivery_tag,
-                        retry_count,
+                        %correlation_id,
                         error = %err,
-                        "Transient error, scheduling retry"
+                        "Transient error, dropping message acked on receipt"
                     );
+                } else {
+                    // Logged quietly: while the breaker is open every message takes this path.
+                    if short_circuited {
+                        debug!(
+                            delivery_tag,
+                            %correlation_id,
+                            retry_count,
+                            "Circuit breaker open, scheduling retry"
+                        );
+                    } else {
+                        warn!(
+                            delivery_tag,
+                            %correlation_id,
+                            retry_count,
+                            error = %err,
+                            "Transient error, scheduling retry"
+                        );
+                    }
 
                     self.metrics.messages_retried_total.inc();
+                    let delay = match retry_after {
+                        Some(hint) => RetryDelay::Hinted(hint),
+                        None => RetryDelay::Backoff(policy.base_delay),
+                    };
 
-                    if let Err(e) = self.retry_message(delivery_tag, data, properties, retry_count, Some(&err)).await {
-                        error!(error = %e, delivery_tag, "Failed to schedule retry");
+                    if let Err(e) = self
+                        .retry_message(delivery_tag, republish_data, republish_properties, retry_count, Some(&err), delay)
+                        .await
+                    {
+                        error!(
+                            error = %e,
+                            delivery_tag,
+                            %correlation_id,
+                            "Failed to schedule retry"
+                        );
+                        self.abandon(delivery_tag).await;
                     }
                 }
             }
+            Err(HandlerError::Discard { reason }) => {
+                let duration = start.elapsed().as_secs_f64();
+                info!(
+                    delivery_tag,
+                    %correlation_id,
+                    reason = %reason,
+                    "Message discarded by handler"
+                );
+
+                self.metrics.messages_discarded_total.inc();
+                self.record_recent(&properties, routing_key.as_str(), Outcome::Discarded, Some(&reason), duration);
+                Span::current().record("outcome", Outcome::Discarded.as_str());
+
+                self.metrics.observe(
+                    &self.metrics.message_processing_duration_seconds,
+                    &[&self.queue_name, "discarded"],
+                    duration,
+                );
+
+                if !acked_on_receipt && let Err(e) = self.ack(delivery_tag).await {
+                    error!(error = %e, delivery_tag, %correlation_id, "Failed to ack message");
+                }
+            }
             Err(HandlerError::Permanent(err)) => {
                 let duration = start.elapsed().as_secs_f64();
+                let error_type = match route {
+                    Some(FailureRoute::DeadLetter(error_type)) => error_type,
+                    _ if panicked => PANIC_ERROR_TYPE,
+                    _ => "permanent",
+                };
                 
                 self.metrics
                     .messages_failed_total
-                    .with_label_values(&[&self.queue_name, "permanent"])
+                    .with_label_values(&[&self.queue_name, error_type, &version])
                     .inc();
+                let outcome = match route {
+                    Some(FailureRoute::DeadLetter(_)) => Outcome::DeadLettered,
+                    _ => Outcome::Discarded,
+                };
+                self.record_recent(&properties, routing_key.as_str(), outcome, Some(&err), duration);
+                Span::current().record("outcome", outcome.as_str());
 
-                self.metrics
-                    .message_processing_duration_seconds
-                    .with_label_values(&[&self.queue_name, "permanent_error"])
-                    .observe(duration);
+                self.metrics.observe(
+                    &self.metrics.message_processing_duration_seconds,
+                    &[&self.queue_name, "permanent_error"],
+                    duration,
+                );
+
+                if acked_on_receipt {
+                    error!(
+                        delivery_tag,
+                        %correlation_id,
+                        error = %err,
+                        "Permanent error, dropping message acked on receipt"
+                    );
+                    return;
+                }
 
-                self.metrics.messages_dlq_total.inc();
+                count_dead_letter(&self.metrics, error_type, routing_key.as_str());
 
                 error!(
                     delivery_tag,
+                    %correlation_id,
                     error = %err,
                     "Permanent error, rejecting to DLQ"
                 );
 
                 // Add error metadata to headers before DLQ
-                if let Err(e) = self.reject_to_dlq_with_reason(delivery_tag, data, properties, &err, "permanent").await {
-                    error!(error = %e, delivery_tag, "Failed to reject to DLQ with metadata");
+                if let Err(e) = self.reject_to_dlq_with_reason(delivery_tag, republish_data, republish_properties, routing_key.as_str(), &err, error_type).await {
+                    error!(
+                        error = %e,
+                        delivery_tag,
+                        %correlation_id,
+                        "Failed to reject to DLQ with metadata"
+                    );
+                    self.abandon(delivery_tag).await;
                 }
             }
         }
     }
 
+    fn default_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: self.retry_base_delay,
+        }
+    }
+
+    /// The highest retry limit of any policy, past which a redelivery is a
+    /// poison candidate whatever its error class would have been.
+    fn most_retries(&self) -> u32 {
+        self.retry_policies
+            .values()
+            .map(|policy| policy.max_retries)
+            .fold(self.max_retries, u32::max)
+    }
+
+    fn record_recent(
+        &self,
+        properties: &BasicProperties,
+        routing_key: &str,
+        outcome: Outcome,
+        error_reason: Option<&str>,
+        duration_secs: f64,
+    ) {
+        let Some(recent) = &self.recent else {
+            return;
+        };
+        recent.record(RecentEvent {
+            queue: self.queue_name.clone(),
+            correlation_id: correlation::correlation_id(properties),
+            routing_key: routing_key.to_string(),
+            outcome,
+            error_reason: error_reason.map(str::to_string),
+            timestamp_ms: self
+                .time
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            duration_ms: duration_secs * 1000.0,
+        });
+    }
+
+    /// The retry count past which a redelivery is a poison candidate. Every
+    /// requeued retry is a redelivery, so under `RetryStrategy::Requeue` the
+    /// final attempt at `most_retries` is still handled.
+    fn poison_retries(&self) -> u32 {
+        match self.retry_strategy {
+            RetryStrategy::Republish => self.most_retries(),
+            RetryStrategy::Requeue => self.most_retries().saturating_add(1),
+        }
+    }
+
     async fn retry_message(
         &self,
         delivery_tag: u64,
@@ -295,55 +1277,93 @@ impl Consumer {
         properties: BasicProperties,
         retry_count: u32,
         error_reason: Option<&str>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let retry_queue = format!("{}.retry", self.queue_name);
+        delay: RetryDelay,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let new_retry_count = retry_count + 1;
+        let correlation_id = correlation::correlation_id(&properties).unwrap_or_default();
 
-        let mut headers = properties
-            .headers()
-            .clone()
-            .unwrap_or_else(FieldTable::default);
-
-        headers.insert(
-            RETRY_HEADER.into(),
-            lapin::types::AMQPValue::LongUInt(new_retry_count),
-        );
+        if self.retry_strategy == RetryStrategy::Requeue {
+            self.requeued
+                .lock()
+                .unwrap()
+                .put(requeue_key(&properties, &data), new_retry_count);
+            if let Some(window) = &self.ack_window {
+                window.lock().unwrap().forget(delivery_tag);
+            }
+            self.broker.nack(delivery_tag, true).await?;
 
-        // Store error reason for debugging
-        if let Some(reason) = error_reason {
-            headers.insert(
-                ERROR_REASON_HEADER.into(),
-                lapin::types::AMQPValue::LongString(reason.into()),
-            );
-            headers.insert(
-                ERROR_TYPE_HEADER.into(),
-                lapin::types::AMQPValue::LongString("transient".into()),
+            info!(
+                delivery_tag,
+                %correlation_id,
+                retry_count = new_retry_count,
+                "Message requeued for retry"
             );
+            return Ok(());
         }
 
-        let retry_properties = BasicProperties::default()
-            .with_headers(headers)
-            .with_delivery_mode(2);
+        if !self.retry_tiers.is_empty() {
+            let tier = match delay {
+                RetryDelay::Hinted(hint) => hinted_retry_tier(hint, &self.retry_tiers),
+                RetryDelay::Backoff(_) => retry_tier(new_retry_count, self.retry_tiers.len()),
+            };
+            let retry_queue = retry_tier_queue(&self.queue_name, tier);
+            // The tier's TTL is the delay; a per-message expiration would only
+            // bring back the head-of-queue problem the tiers avoid.
+            let retry_properties =
+                build_retry_properties(&properties, new_retry_count, error_reason, None);
 
-        self.channel
-            .basic_publish(
-                "",
-                &retry_queue,
-                BasicPublishOptions::default(),
-                &data,
-                retry_properties,
-            )
-            .await?
-            .await?;
+            self.mark_awaiting_confirm(delivery_tag);
+            self.broker.publish(&retry_queue, &data, retry_properties).await?;
 
-        self.channel
-            .basic_ack(delivery_tag, BasicAckOptions::default())
-            .await?;
+            self.ack(delivery_tag).await?;
 
-        info!(
-            delivery_tag,
-            retry_count = new_retry_count,
-            retry_queue = %retry_queue,
+            info!(
+                delivery_tag,
+                %correlation_id,
+                retry_count = new_retry_count,
+                retry_queue = %retry_queue,
+                tier,
+                delay_ms = self.retry_tiers[tier - 1].as_millis() as u64,
+                "Message scheduled for retry"
+            );
+            return Ok(());
+        }
+
+        let retry_queue = format!("{}.retry", self.queue_name);
+
+        // The broker applies the lower of the queue TTL and the per-message
+        // expiration, so a hint longer than the retry queue's TTL cannot be honored.
+        let (delay, hinted) = match delay {
+            RetryDelay::Hinted(hint) => {
+                if hint > self.retry_max_delay {
+                    warn!(
+                        delivery_tag,
+                        %correlation_id,
+                        hint_ms = hint.as_millis() as u64,
+                        max_ms = self.retry_max_delay.as_millis() as u64,
+                        "Retry hint exceeds retry queue TTL, clamping"
+                    );
+                }
+                (hint.min(self.retry_max_delay), true)
+            }
+            RetryDelay::Backoff(base) => (retry_delay(base, self.retry_max_delay, new_retry_count), false),
+        };
+
+        let retry_properties =
+            build_retry_properties(&properties, new_retry_count, error_reason, Some(delay));
+
+        self.mark_awaiting_confirm(delivery_tag);
+        self.broker.publish(&retry_queue, &data, retry_properties).await?;
+
+        self.ack(delivery_tag).await?;
+
+        info!(
+            delivery_tag,
+            %correlation_id,
+            retry_count = new_retry_count,
+            retry_queue = %retry_queue,
+            delay_ms = delay.as_millis() as u64,
+            hinted,
             "Message scheduled for retry"
         );
 
@@ -355,58 +1375,31 @@ impl Consumer {
         delivery_tag: u64,
         data: Vec<u8>,
         properties: BasicProperties,
+        routing_key: &str,
         error_reason: &str,
         error_type: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let dlq_name = format!("{}.dlq", self.queue_name);
-
-        let mut headers = properties
-            .headers()
-            .clone()
-            .unwrap_or_else(FieldTable::default);
-
-        // Add error metadata for DLQ inspection
-        headers.insert(
-            ERROR_REASON_HEADER.into(),
-            lapin::types::AMQPValue::LongString(error_reason.into()),
-        );
-        headers.insert(
-            ERROR_TYPE_HEADER.into(),
-            lapin::types::AMQPValue::LongString(error_type.into()),
-        );
-        headers.insert(
-            "x-original-queue".into(),
-            lapin::types::AMQPValue::LongString(self.queue_name.clone().into()),
-        );
-
-        let dlq_properties = BasicProperties::default()
-            .with_headers(headers)
-            .with_delivery_mode(2)
-            .with_timestamp(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
-            );
+        let correlation_id = correlation::correlation_id(&properties).unwrap_or_default();
+        let now = self.time.now();
+        let mut dlq_properties =
+            build_dlq_properties(&properties, &self.queue_name, error_reason, error_type, now);
+        let body = if self.dlq_envelope {
+            dlq_properties = dlq_properties.with_content_type(DLQ_ENVELOPE_CONTENT_TYPE.into());
+            build_dlq_envelope(&data, routing_key, &properties, error_reason, error_type, now)
+        } else {
+            data
+        };
 
         // Publish to DLQ instead of reject to preserve headers
-        self.channel
-            .basic_publish(
-                "",
-                &dlq_name,
-                BasicPublishOptions::default(),
-                &data,
-                dlq_properties,
-            )
-            .await?
-            .await?;
+        self.mark_awaiting_confirm(delivery_tag);
+        self.broker.publish(&dlq_name, &body, dlq_properties).await?;
 
-        self.channel
-            .basic_ack(delivery_tag, BasicAckOptions::default())
-            .await?;
+        self.ack(delivery_tag).await?;
 
         info!(
             delivery_tag,
+            %correlation_id,
             error_type,
             error_reason,
             dlq = %dlq_name,
@@ -416,24 +1409,1610 @@ impl Consumer {
         Ok(())
     }
 
-    fn get_retry_count(&self, properties: &BasicProperties) -> u32 {
-        properties
-            .headers()
-            .as_ref()
-            .and_then(|headers| headers.inner().get(RETRY_HEADER))
-            .and_then(|value| match value {
-                lapin::types::AMQPValue::LongUInt(count) => Some(*count),
-                _ => None,
+    /// Moves the prefetch one step towards the tuner's latency target, see
+    /// `with_adaptive_prefetch`. A failed change is only logged: the tuner
+    /// keeps the new value and the channel catches up at the next change.
+    async fn tune_prefetch(&self) {
+        let Some(tuner) = &self.prefetch_tuner else {
+            return;
+        };
+        let (count, sum) = self.metrics.processing_totals(&self.queue_name);
+        let Some(adjustment) = tuner.lock().unwrap().observe(count, sum) else {
+            return;
+        };
+        info!(
+            queue = %self.queue_name,
+            from = adjustment.from,
+            to = adjustment.to,
+            mean_latency_ms = adjustment.mean_latency.as_millis() as u64,
+            "Adjusting prefetch"
+        );
+        if let Err(e) = self.broker.set_prefetch(adjustment.to, true).await {
+            warn!(error = %e, prefetch_count = adjustment.to, "Failed to adjust prefetch");
+        }
+    }
+
+    /// Whether an idle-shutdown consumer's queue has drained, see `with_idle_shutdown`.
+    async fn drained(&self, idle_for: Duration) -> bool {
+        let Some(idle) = self.idle_shutdown else {
+            return false;
+        };
+        if idle_for < idle {
+            return false;
+        }
+
+        match self.broker.message_count(&self.queue_name).await {
+            Ok(ready_messages) => drain_complete(idle_for, idle, ready_messages),
+            Err(e) => {
+                warn!(error = %e, queue = %self.queue_name, "Cannot check queue depth");
+                false
+            }
+        }
+    }
+
+    /// Acks a single delivery, or settles it in the batch window and flushes
+    /// the window once a full batch is safe to acknowledge.
+    async fn ack(&self, delivery_tag: u64) -> Result<(), lapin::Error> {
+        let Some(window) = &self.ack_window else {
+            return ack_with_retry(delivery_tag, &self.metrics.ack_failures_total, || {
+                self.broker.ack(delivery_tag, false)
             })
-            .unwrap_or(0)
+            .await;
+        };
+
+        window.lock().unwrap().settle(delivery_tag);
+        self.flush_acks(false).await;
+        Ok(())
+    }
+
+    async fn flush_acks(&self, force: bool) {
+        let Some(window) = &self.ack_window else {
+            return;
+        };
+
+        let flushed = window.lock().unwrap().take_flush(force);
+        if let Some(delivery_tag) = flushed
+            && let Err(e) = ack_with_retry(delivery_tag, &self.metrics.ack_failures_total, || {
+                self.broker.ack(delivery_tag, true)
+            })
+            .await
+        {
+            error!(error = %e, delivery_tag, "Failed to ack message batch");
+        }
+    }
+
+    fn mark_awaiting_confirm(&self, delivery_tag: u64) {
+        if let Some(window) = &self.ack_window {
+            window.lock().unwrap().awaiting_confirm(delivery_tag);
+        }
+    }
+
+    /// Gives up on a delivery whose retry or DLQ publish failed or was not
+    /// confirmed, requeueing it so the broker redelivers it.
+    ///
+    /// With batching it is also dropped from the window, where it would
+    /// otherwise block every later ack.
+    async fn abandon(&self, delivery_tag: u64) {
+        if let Some(window) = &self.ack_window {
+            window.lock().unwrap().forget(delivery_tag);
+        }
+        if let Err(e) = self.broker.nack(delivery_tag, true).await {
+            error!(error = %e, delivery_tag, "Failed to requeue message");
+        }
+    }
+
+    /// The retry count of `delivery`: its `x-retry-count`, and for a
+    /// redelivery under `RetryStrategy::Requeue` the requeues on top of it.
+    fn get_retry_count(&self, delivery: &IncomingMessage) -> u32 {
+        let headers = delivery.properties.headers().as_ref();
+        let republished = headers
+            .and_then(|headers| header_u32(headers, RETRY_HEADER))
+            .unwrap_or(0);
+        if self.retry_strategy != RetryStrategy::Requeue || !delivery.redelivered {
+            return republished;
+        }
+
+        let key = requeue_key(&delivery.properties, &delivery.data);
+        let remembered = self.requeued.lock().unwrap().pop(&key).unwrap_or(0);
+        // Quorum queues count the first delivery too.
+        let delivered = headers
+            .and_then(|headers| header_u32(headers, DELIVERY_COUNT_HEADER))
+            .map_or(0, |count| republished.saturating_add(count.saturating_sub(1)));
+        remembered.max(delivered).max(republished)
+    }
+}
+
+/// The key a requeued message's retry count is remembered under.
+fn requeue_key(properties: &BasicProperties, data: &[u8]) -> String {
+    let message_id = properties.message_id().as_ref().map(|id| id.to_string());
+    Quarantine::key(message_id.as_deref(), data)
+}
+
+/// Properties for a message republished to the retry queue.
+///
+/// When `retry_after` is set it becomes the per-message `expiration`, so the
+/// message dead-letters back to the main queue after that delay instead of the
+/// queue-level TTL. The consumer always sets it, since a queue-level TTL
+/// cannot vary by attempt. Per-message expiry is only checked at the head of the
+/// queue, so a short hint can still wait behind a message with a longer one.
+///
+/// The `correlation_id`, `message_id` and `timestamp` carry over, so a retry
+/// keeps its dedup and quarantine key and its age since it was first sent.
+pub(crate) fn build_retry_properties(
+    properties: &BasicProperties,
+    retry_count: u32,
+    error_reason: Option<&str>,
+    retry_after: Option<Duration>,
+) -> BasicProperties {
+    let mut headers = properties.headers().clone().unwrap_or_default();
+
+    headers.insert(
+        RETRY_HEADER.into(),
+        lapin::types::AMQPValue::LongUInt(retry_count),
+    );
+
+    // Store error reason for debugging
+    if let Some(reason) = error_reason {
+        headers.insert(
+            ERROR_REASON_HEADER.into(),
+            lapin::types::AMQPValue::LongString(reason.into()),
+        );
+        headers.insert(
+            ERROR_TYPE_HEADER.into(),
+            lapin::types::AMQPValue::LongString("transient".into()),
+        );
+    }
+
+    let mut retry_properties = BasicProperties::default()
+        .with_headers(headers)
+        .with_delivery_mode(2);
+    if let Some(id) = properties.correlation_id() {
+        retry_properties = retry_properties.with_correlation_id(id.clone());
+    }
+    if let Some(id) = properties.message_id() {
+        retry_properties = retry_properties.with_message_id(id.clone());
+    }
+    if let Some(timestamp) = properties.timestamp() {
+        retry_properties = retry_properties.with_timestamp(*timestamp);
+    }
+
+    match retry_after {
+        Some(delay) => retry_properties.with_expiration(delay.as_millis().to_string().into()),
+        None => retry_properties,
+    }
+}
+
+/// Runs `ack` up to `ACK_ATTEMPTS` times, `ACK_RETRY_DELAY` apart, so a
+/// broker hiccup does not leave a handled message to be redelivered and
+/// processed again. Every failed attempt is counted in `failures`; the last
+/// error is returned if none succeeds.
+pub(crate) async fn ack_with_retry<F, Fut, E>(
+    delivery_tag: u64,
+    failures: &Counter,
+    mut ack: F,
+) -> Result<(), E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let mut attempt = 1;
+    loop {
+        match ack().await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                failures.inc();
+                if attempt == ACK_ATTEMPTS {
+                    return Err(e);
+                }
+                warn!(error = %e, delivery_tag, attempt, "Ack failed, retrying");
+                attempt += 1;
+                tokio::time::sleep(ACK_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Properties for a message published to the DLQ: the original headers plus
+/// the failure metadata, timestamped in seconds with the time it failed.
+pub(crate) fn build_dlq_properties(
+    properties: &BasicProperties,
+    queue_name: &str,
+    error_reason: &str,
+    error_type: &str,
+    now: SystemTime,
+) -> BasicProperties {
+    let mut headers = properties.headers().clone().unwrap_or_default();
+
+    // Add error metadata for DLQ inspection
+    headers.insert(
+        ERROR_REASON_HEADER.into(),
+        lapin::types::AMQPValue::LongString(error_reason.into()),
+    );
+    headers.insert(
+        ERROR_TYPE_HEADER.into(),
+        lapin::types::AMQPValue::LongString(error_type.into()),
+    );
+    headers.insert(
+        ORIGINAL_QUEUE_HEADER.into(),
+        lapin::types::AMQPValue::LongString(queue_name.into()),
+    );
+
+    let dlq_properties = BasicProperties::default()
+        .with_headers(headers)
+        .with_delivery_mode(2)
+        .with_timestamp(now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+    match properties.correlation_id() {
+        Some(id) => dlq_properties.with_correlation_id(id.clone()),
+        None => dlq_properties,
+    }
+}
+
+/// The JSON `DlqEnvelope` published to the DLQ in place of the body when
+/// envelope mode is on: everything about the original delivery plus why and
+/// when it failed.
+pub(crate) fn build_dlq_envelope(
+    data: &[u8],
+    routing_key: &str,
+    properties: &BasicProperties,
+    error_reason: &str,
+    error_type: &str,
+    now: SystemTime,
+) -> Vec<u8> {
+    let headers = properties.headers().clone().unwrap_or_default();
+    let envelope = DlqEnvelope {
+        body: BASE64.encode(data),
+        routing_key: routing_key.to_string(),
+        content_type: properties.content_type().as_ref().map(|t| t.to_string()),
+        headers: headers_to_json(&headers),
+        error_reason: error_reason.to_string(),
+        error_type: error_type.to_string(),
+        retry_count: header_u32(&headers, RETRY_HEADER).unwrap_or(0),
+        failed_at: now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+    };
+    serde_json::to_vec(&envelope).expect("a DLQ envelope always serializes")
+}
+
+/// When a retried message comes back.
+pub(crate) enum RetryDelay {
+    /// After the delay the handler asked for.
+    Hinted(Duration),
+    /// After the exponential backoff from this base delay.
+    Backoff(Duration),
+}
+
+/// Backoff before retry `attempt` (1 for the first retry): `base * 2^(attempt-1)`,
+/// capped at `max`.
+pub(crate) fn retry_delay(base: Duration, max: Duration, attempt: u32) -> Duration {
+    let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+    base.saturating_mul(factor).min(max)
+}
+
+/// The retry tier (1 for the first) of retry `attempt` (1 for the first):
+/// attempt N goes to tier N, and attempts past the last tier to the last one.
+pub(crate) fn retry_tier(attempt: u32, tiers: usize) -> usize {
+    (attempt.max(1) as usize).min(tiers)
+}
+
+/// The first tier whose TTL is at least `hint`, or the last tier if none is.
+pub(crate) fn hinted_retry_tier(hint: Duration, tiers: &[Duration]) -> usize {
+    tiers
+        .iter()
+        .position(|&ttl| ttl >= hint)
+        .map_or(tiers.len(), |index| index + 1)
+}
+
+/// DLQ `error_type` of a message whose handler panicked.
+pub const PANIC_ERROR_TYPE: &str = "panic";
+
+/// DLQ `error_type` of a redelivered message sent there without handling.
+pub const POISON_ERROR_TYPE: &str = "poison";
+
+/// Retry reason of a message sent back while the circuit breaker was open.
+pub const CIRCUIT_OPEN_REASON: &str = "circuit breaker open";
+
+/// A delivery the broker redelivered, typically because the consumer
+/// handling it died, that had already used up its retries. It may well be
+/// what killed the consumer, so it is dead-lettered instead of handled again.
+/// The retry header alone cannot tell, since a broker redelivery does not
+/// bump it.
+pub(crate) fn is_poison_candidate(redelivered: bool, retry_count: u32, max_retries: u32) -> bool {
+    redelivered && retry_count >= max_retries
+}
+
+/// How a handler call run by `run_handler` ended.
+pub(crate) enum HandlerRun {
+    Completed(Result<HandlerOutcome, HandlerError>),
+    Panicked(String),
+    /// Did not finish within the handler timeout and was aborted.
+    TimedOut,
+}
+
+impl HandlerRun {
+    /// The handler's result, a panic becoming a permanent failure and a
+    /// timeout a transient one, and whether it panicked.
+    pub(crate) fn into_result(self) -> (Result<HandlerOutcome, HandlerError>, bool) {
+        match self {
+            HandlerRun::Completed(result) => (result, false),
+            HandlerRun::Panicked(message) => (
+                Err(HandlerError::Permanent(format!("Handler panicked: {}", message))),
+                true,
+            ),
+            HandlerRun::TimedOut => (Err(HandlerError::transient("handler timeout")), false),
+        }
+    }
+}
+
+/// Runs the handler in a task of its own, so a panic is caught at the task
+/// boundary instead of unwinding through the consumer. A task still running
+/// after `timeout` is aborted rather than left to run on unobserved, and
+/// `cancel` is cancelled with it so work the handler handed a clone of the
+/// token to stops too.
+pub(crate) async fn run_handler(
+    handler: Arc<dyn MessageHandler>,
+    delivery: IncomingMessage,
+    timeout: Option<Duration>,
+    cancel: CancellationToken,
+) -> HandlerRun {
+    let token = cancel.clone();
+    let mut task =
+        tokio::spawn(async move { handler.handle(delivery, token).await }.in_current_span());
+    let joined = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, &mut task).await {
+            Ok(joined) => joined,
+            Err(_) => {
+                cancel.cancel();
+                task.abort();
+                return HandlerRun::TimedOut;
+            }
+        },
+        None => task.await,
+    };
+    match joined {
+        Ok(result) => HandlerRun::Completed(result),
+        Err(e) if e.is_panic() => {
+            let panic = e.into_panic();
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "non-string panic payload".to_string());
+            HandlerRun::Panicked(message)
+        }
+        Err(e) => HandlerRun::Completed(Err(HandlerError::transient(format!(
+            "Handler task cancelled: {}",
+            e
+        )))),
+    }
+}
+
+/// Waits for every task in `workers`, cancelling `cancel` if they are still
+/// running after `grace`, and returns how many there were.
+pub(crate) async fn drain_with_grace(
+    workers: &mut WorkerPool,
+    grace: Option<Duration>,
+    cancel: &CancellationToken,
+) -> usize {
+    let Some(grace) = grace else {
+        return workers.drain().await;
+    };
+    let drain = workers.drain();
+    tokio::pin!(drain);
+    tokio::select! {
+        drained = &mut drain => drained,
+        _ = tokio::time::sleep(grace) => {
+            warn!(
+                grace_secs = grace.as_secs_f64(),
+                "Handlers still running after the shutdown grace period, cancelling them"
+            );
+            cancel.cancel();
+            drain.await
+        }
+    }
+}
+
+/// The `error_type` a failed attempt is dead-lettered with, or `None` while
+/// it still has retries left.
+pub(crate) fn dead_letter_type(
+    error: &HandlerError,
+    retry_count: u32,
+    max_retries: u32,
+) -> Option<&'static str> {
+    match error {
+        HandlerError::Discard { .. } => None,
+        HandlerError::Permanent(_) => Some("permanent"),
+        _ if retry_count >= max_retries => Some("transient"),
+        _ => None,
+    }
+}
+
+/// Where a failed delivery goes next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FailureRoute {
+    Retry,
+    /// Dead-lettered with this `error_type`.
+    DeadLetter(&'static str),
+    /// Acked, or left alone if it already was; nothing goes back to the broker.
+    Drop,
+}
+
+/// Routes a failed attempt. In `DeliveryMode::AtMostOnce` the delivery was
+/// acked on receipt, so every failure is dropped: retrying or dead-lettering
+/// it could get it handled a second time.
+pub(crate) fn failure_route(
+    mode: DeliveryMode,
+    error: &HandlerError,
+    retry_count: u32,
+    max_retries: u32,
+    panicked: bool,
+) -> FailureRoute {
+    if mode == DeliveryMode::AtMostOnce {
+        return FailureRoute::Drop;
+    }
+    match dead_letter_type(error, retry_count, max_retries) {
+        _ if panicked => FailureRoute::DeadLetter(PANIC_ERROR_TYPE),
+        Some(error_type) => FailureRoute::DeadLetter(error_type),
+        None if matches!(error, HandlerError::Discard { .. }) => FailureRoute::Drop,
+        None => FailureRoute::Retry,
+    }
+}
+
+/// Counts a message sent to the DLQ by why it failed and where it was headed.
+pub(crate) fn count_dead_letter(metrics: &Metrics, error_type: &str, routing_key: &str) {
+    metrics
+        .messages_dlq_total
+        .with_label_values(&[error_type, routing_key])
+        .inc();
+}
+
+/// Records the retries a message used up before its transient failures got
+/// it dead-lettered, telling apart messages that barely failed from ones
+/// that kept failing.
+pub(crate) fn observe_dlq_retry_count(metrics: &Metrics, queue: &str, retry_count: u32) {
+    metrics.observe(&metrics.dlq_retry_count, &[queue], retry_count as f64);
+}
+
+/// The event version a message is handled as: its `x-event-version` header,
+/// or `v1` without one. `None` when the header is present but not a string.
+pub(crate) fn event_version(properties: &BasicProperties) -> Option<String> {
+    match properties.headers() {
+        Some(headers) if headers.inner().contains_key(EVENT_VERSION_HEADER) => {
+            header_string(headers, EVENT_VERSION_HEADER)
+        }
+        _ => Some("v1".to_string()),
+    }
+}
+
+/// The drain-complete heuristic: nothing delivered for `idle` and nothing ready.
+pub(crate) fn drain_complete(idle_for: Duration, idle: Duration, ready_messages: u32) -> bool {
+    idle_for >= idle && ready_messages == 0
+}
+
+/// Time a message spent in the main queue before it was delivered.
+///
+/// The enqueue time is the broker's `timestamp_in_ms` header when present,
+/// otherwise the producer's `timestamp` property, in seconds or milliseconds.
+/// Only first attempts are measured: a retried or reanimated message's
+/// timestamp also covers time spent in the retry queue or DLQ. Returns `None`
+/// without a timestamp, or when `clock` rejects it as skewed.
+pub(crate) fn queue_wait(
+    properties: &BasicProperties,
+    clock: &ClockGuard,
+    now: SystemTime,
+) -> Option<Duration> {
+    let headers = properties.headers().clone().unwrap_or_default();
+    if header_u32(&headers, RETRY_HEADER).unwrap_or(0) > 0
+        || header_u32(&headers, REANIMATION_COUNT_HEADER).unwrap_or(0) > 0
+    {
+        return None;
+    }
+
+    let enqueued_ms = header_u64(&headers, BROKER_TIMESTAMP_HEADER)
+        .or_else(|| properties.timestamp().map(normalize_epoch_millis))?;
+
+    clock.safe_elapsed_at(UNIX_EPOCH + Duration::from_millis(enqueued_ms), now)
+}
+
+/// Records how long ago the producer's `timestamp` property was set, in
+/// seconds or milliseconds. Unlike `queue_wait` this covers retried messages
+/// too, so it is the message's full age. Nothing is recorded without a
+/// timestamp, or when `clock` rejects it as skewed.
+pub(crate) fn observe_message_age(
+    metrics: &Metrics,
+    queue_name: &str,
+    properties: &BasicProperties,
+    clock: &ClockGuard,
+    now: SystemTime,
+) {
+    let Some(sent_ms) = properties.timestamp().map(normalize_epoch_millis) else {
+        return;
+    };
+    if let Some(age) = clock.safe_elapsed_at(UNIX_EPOCH + Duration::from_millis(sent_ms), now) {
+        metrics.observe(&metrics.message_age_seconds, &[queue_name], age.as_secs_f64());
+    }
+}
+
+/// Named construction of a `Consumer`. The channel (or another broker),
+/// queue name, handler, shutdown signal and metrics are required; the consumer tag defaults to
+/// `<queue_name>-consumer` and `max_retries` to 3.
+#[derive(Default)]
+pub struct ConsumerBuilder {
+    broker: Option<Arc<dyn ConsumerBroker>>,
+    queue_name: Option<String>,
+    consumer_tag: Option<String>,
+    handler: Option<Arc<dyn MessageHandler>>,
+    shutdown: Option<Arc<Notify>>,
+    metrics: Option<Arc<Metrics>>,
+    max_retries: Option<u32>,
+}
+
+impl ConsumerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn channel(self, channel: Channel) -> Self {
+        self.broker(Arc::new(channel))
+    }
+
+    /// Consumes through `broker` instead of a RabbitMQ channel.
+    pub fn broker(mut self, broker: Arc<dyn ConsumerBroker>) -> Self {
+        self.broker = Some(broker);
+        self
+    }
+
+    pub fn queue_name(mut self, queue_name: impl Into<String>) -> Self {
+        self.queue_name = Some(queue_name.into());
+        self
+    }
+
+    pub fn consumer_tag(mut self, consumer_tag: impl Into<String>) -> Self {
+        self.consumer_tag = Some(consumer_tag.into());
+        self
+    }
+
+    pub fn handler(mut self, handler: Arc<dyn MessageHandler>) -> Self {
+        self.handler = Some(handler);
+        self
+    }
+
+    pub fn shutdown(mut self, shutdown: Arc<Notify>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// The consumer, or `ConsumerError::MissingFields` naming every required
+    /// field that was not set.
+    pub fn build(self) -> Result<Consumer, ConsumerError> {
+        let missing: Vec<&'static str> = [
+            ("channel", self.broker.is_none()),
+            ("queue_name", self.queue_name.is_none()),
+            ("handler", self.handler.is_none()),
+            ("shutdown", self.shutdown.is_none()),
+            ("metrics", self.metrics.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(field, unset)| unset.then_some(field))
+        .collect();
+        let (Some(broker), Some(queue_name), Some(handler), Some(shutdown), Some(metrics)) =
+            (self.broker, self.queue_name, self.handler, self.shutdown, self.metrics)
+        else {
+            return Err(ConsumerError::MissingFields(missing));
+        };
+
+        Ok(Consumer {
+            consumer_tag: self
+                .consumer_tag
+                .unwrap_or_else(|| format!("{}-consumer", queue_name)),
+            broker,
+            queue_name,
+            max_retries: self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            retry_base_delay: Duration::from_millis(RETRY_DELAY_MS),
+            retry_max_delay: Duration::from_millis(RETRY_DELAY_MS),
+            retry_policies: RetryPolicies::new(),
+            retry_tiers: Vec::new(),
+            retry_strategy: RetryStrategy::Republish,
+            requeued: Mutex::new(LruCache::new(REQUEUE_TRACKED_MESSAGES)),
+            exchange: None,
+            queue_type: QueueType::Classic,
+            queue_mode: QueueMode::Default,
+            max_lengths: Vec::new(),
+            dlq_envelope: false,
+            delivery_mode: DeliveryMode::AtLeastOnce,
+            clock: ClockGuard::new(DEFAULT_MAX_CLOCK_SKEW, &metrics),
+            time: Arc::new(SystemClock),
+            metrics,
+            handler,
+            shutdown,
+            ack_window: None,
+            idle_shutdown: None,
+            stream_restarts: 0,
+            stream_restart_delay: Duration::ZERO,
+            recent: None,
+            readiness: None,
+            sinks: MultiSink::new(),
+            concurrency: 1,
+            handler_timeout: None,
+            shutdown_grace: None,
+            cancel: CancellationToken::new(),
+            dedup: None,
+            quarantine: None,
+            rate_limiter: None,
+            circuit_breaker: None,
+            prefetch_tuner: None,
+        })
     }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConsumerError {
+    #[error("Consumer is missing required fields: {}", .0.join(", "))]
+    MissingFields(Vec<&'static str>),
+
     #[error("Failed to start consumer: {0}")]
     ConsumeFailed(String),
 
     #[error("Failed to setup queue topology: {0}")]
     SetupFailed(String),
+
+    #[error("Consumer stream ended and {0} resubscribe attempts did not restore it")]
+    StreamEnded(u32),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::messaging::dlq::{header_string, header_u32, republish_properties, DlqMessage};
+    use lapin::types::{AMQPValue, FieldTable};
+    use crate::messaging::circuit_breaker::BreakerState;
+    use crate::messaging::correlation::{CORRELATION_ID_HEADER, X_CORRELATION_ID_HEADER};
+    use crate::messaging::prefetch::PrefetchSettings;
+    use crate::messaging::source::Settlement;
+    use crate::messaging::test_util::{delivery, delivery_with_properties, MockBroker};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_builder_without_handler_fails() {
+        let err = ConsumerBuilder::new()
+            .queue_name("telemetry")
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(Metrics::new().unwrap())
+            .build()
+            .err()
+            .unwrap();
+
+        let ConsumerError::MissingFields(missing) = &err else {
+            panic!("expected missing fields, got {err}");
+        };
+        assert!(missing.contains(&"handler"), "{err}");
+        assert!(!missing.contains(&"queue_name"), "{err}");
+    }
+
+    /// Succeeds on `ok`, fails transiently on `flaky` and permanently on
+    /// anything else.
+    struct PayloadHandler;
+
+    #[async_trait::async_trait]
+    impl MessageHandler for PayloadHandler {
+        async fn handle(
+            &self,
+            delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            match delivery.data.as_slice() {
+                b"ok" => Ok(HandlerOutcome::default()),
+                b"flaky" => Err(HandlerError::transient("downstream unavailable")),
+                _ => Err(HandlerError::Permanent("Invalid JSON".to_string())),
+            }
+        }
+    }
+
+    /// Declares the topology and consumes everything `broker` holds.
+    async fn consume_all(broker: &Arc<MockBroker>, metrics: &Arc<Metrics>) {
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(PayloadHandler))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .max_retries(3)
+            .build()
+            .unwrap();
+        consumer.setup_queues().await.unwrap();
+        consumer.start().await.unwrap();
+    }
+
+    fn with_retry_count(retry_count: u32) -> BasicProperties {
+        let mut headers = FieldTable::default();
+        headers.insert(RETRY_HEADER.into(), AMQPValue::LongUInt(retry_count));
+        BasicProperties::default().with_headers(headers)
+    }
+
+    #[tokio::test]
+    async fn test_mock_broker_processed_message_is_acked() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let tag = broker.deliver(b"ok", BasicProperties::default());
+
+        consume_all(&broker, &metrics).await;
+
+        assert_eq!(
+            broker.declared(),
+            vec!["telemetry.dlq", "telemetry.retry", "telemetry"]
+        );
+        assert_eq!(broker.settlements(), vec![(tag, Settlement::Acked)]);
+        assert!(broker.published().is_empty());
+        assert_eq!(metrics.processed_total(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ended_stream_is_resubscribed_with_backoff() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let tag = broker.deliver(b"ok", BasicProperties::default());
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(PayloadHandler))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .build()
+            .unwrap()
+            .with_stream_restarts(2, Duration::from_millis(100));
+        let started = tokio::time::Instant::now();
+
+        let result = consumer.start().await;
+
+        assert!(matches!(result, Err(ConsumerError::StreamEnded(2))));
+        assert_eq!(broker.subscriptions(), 3);
+        assert_eq!(metrics.consumer_restarts_total.get(), 2.0);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert_eq!(broker.settlements(), vec![(tag, Settlement::Acked)]);
+    }
+
+    #[tokio::test]
+    async fn test_mock_broker_transient_failure_is_republished_for_retry() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let tag = broker.deliver(b"flaky", with_retry_count(1));
+
+        consume_all(&broker, &metrics).await;
+
+        let published = broker.published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].queue, "telemetry.retry");
+        assert_eq!(published[0].data, b"flaky");
+        let headers = published[0].properties.headers().clone().unwrap();
+        assert_eq!(header_u32(&headers, RETRY_HEADER), Some(2));
+        // The original is acked only once its copy was published.
+        assert_eq!(broker.settlements(), vec![(tag, Settlement::Acked)]);
+        assert_eq!(metrics.messages_retried_total.get(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_requeue_strategy_nacks_until_retries_run_out() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(PayloadHandler))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .max_retries(2)
+            .build()
+            .unwrap()
+            .with_retry_strategy(RetryStrategy::Requeue);
+
+        // The broker hands a requeued message back with its headers unchanged.
+        for attempt in 0..3 {
+            let mut flaky = delivery(attempt + 1, b"flaky");
+            flaky.redelivered = attempt > 0;
+            consumer.process_message(flaky).await;
+        }
+
+        let requeued = Settlement::Rejected { requeue: true };
+        assert_eq!(
+            broker.settlements(),
+            vec![(1, requeued), (2, requeued), (3, Settlement::Acked)]
+        );
+        let published = broker.published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].queue, "telemetry.dlq");
+        let headers = published[0].properties.headers().clone().unwrap();
+        assert_eq!(
+            header_string(&headers, ERROR_TYPE_HEADER).as_deref(),
+            Some("transient")
+        );
+        assert_eq!(metrics.messages_retried_total.get(), 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_correlation_id_is_carried_to_the_retried_message() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let mut headers = FieldTable::default();
+        headers.insert(
+            X_CORRELATION_ID_HEADER.into(),
+            AMQPValue::LongString("req-42".into()),
+        );
+        broker.deliver(b"flaky", BasicProperties::default().with_headers(headers));
+        broker.deliver(b"flaky", BasicProperties::default());
+
+        consume_all(&broker, &metrics).await;
+
+        let published = broker.published();
+        assert_eq!(published.len(), 2);
+        assert_eq!(
+            correlation::correlation_id(&published[0].properties).as_deref(),
+            Some("req-42")
+        );
+        // Without one, the generated id goes with the retry so it stays the same.
+        let generated = published[1].properties.headers().clone().unwrap();
+        let id = header_string(&generated, CORRELATION_ID_HEADER).unwrap();
+        assert!(uuid::Uuid::parse_str(&id).is_ok(), "{id}");
+    }
+
+    #[tokio::test]
+    async fn test_mock_broker_failures_land_in_the_dlq() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        broker.deliver(b"not json", BasicProperties::default());
+        broker.deliver(b"flaky", with_retry_count(3));
+
+        consume_all(&broker, &metrics).await;
+
+        let published = broker.published();
+        let error_types: Vec<(String, Option<String>)> = published
+            .iter()
+            .map(|message| {
+                let headers = message.properties.headers().clone().unwrap();
+                (message.queue.clone(), header_string(&headers, ERROR_TYPE_HEADER))
+            })
+            .collect();
+        assert_eq!(
+            error_types,
+            vec![
+                ("telemetry.dlq".to_string(), Some("permanent".to_string())),
+                ("telemetry.dlq".to_string(), Some("transient".to_string())),
+            ]
+        );
+        assert_eq!(
+            broker.settlements(),
+            vec![(1, Settlement::Acked), (2, Settlement::Acked)]
+        );
+        assert_eq!(metrics.messages_retried_total.get(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_breaker_sends_messages_straight_to_retry() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        for data in [&b"flaky"[..], b"flaky", b"ok", b"ok"] {
+            broker.deliver(data, BasicProperties::default());
+        }
+        let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_secs(30), &metrics));
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(PayloadHandler))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .build()
+            .unwrap()
+            .with_circuit_breaker(breaker.clone());
+
+        consumer.start().await.unwrap();
+
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert_eq!(metrics.circuit_breaker_state.get(), 1.0);
+        // The last two never reached the handler, which would have succeeded.
+        assert_eq!(metrics.processed_total(), 0);
+        let reasons: Vec<(String, Option<String>)> = broker
+            .published()
+            .iter()
+            .map(|published| {
+                let headers = published.properties.headers().clone().unwrap();
+                (
+                    published.queue.clone(),
+                    header_string(&headers, ERROR_REASON_HEADER),
+                )
+            })
+            .collect();
+        let retried = |reason: &str| ("telemetry.retry".to_string(), Some(reason.to_string()));
+        assert_eq!(
+            reasons,
+            vec![
+                retried("downstream unavailable"),
+                retried("downstream unavailable"),
+                retried(CIRCUIT_OPEN_REASON),
+                retried(CIRCUIT_OPEN_REASON),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_prefetch_shrinks_when_latency_rises() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(PayloadHandler))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .build()
+            .unwrap()
+            .with_adaptive_prefetch(PrefetchTuner::new(PrefetchSettings {
+                base: 20,
+                min: 1,
+                max: 50,
+                target_latency: Duration::from_millis(100),
+                interval: Duration::from_secs(10),
+            }));
+        let observe = |queue: &str, seconds: f64| {
+            metrics.observe(
+                &metrics.message_processing_duration_seconds,
+                &[queue, "success"],
+                seconds,
+            )
+        };
+
+        consumer.tune_prefetch().await;
+        observe("telemetry", 0.08);
+        consumer.tune_prefetch().await;
+        assert!(broker.prefetches().is_empty());
+
+        observe("telemetry", 0.5);
+        observe("telemetry", 0.7);
+        // Another queue's latency does not count.
+        observe("other", 0.001);
+        consumer.tune_prefetch().await;
+
+        assert_eq!(broker.prefetches(), vec![(10, true)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_caps_throughput() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        for _ in 0..11 {
+            broker.deliver(b"ok", BasicProperties::default());
+        }
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(PayloadHandler))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .build()
+            .unwrap()
+            .with_concurrency(4)
+            .with_rate_limiter(Arc::new(RateLimiter::new(5)));
+        let start = tokio::time::Instant::now();
+
+        consumer.start().await.unwrap();
+
+        // Concurrency does not help: 10 messages after the first, 200ms apart.
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert_eq!(metrics.processed_total(), 11);
+        let waits = metrics.rate_limit_wait_seconds.with_label_values(&["telemetry"]);
+        assert_eq!(waits.get_sample_count(), 11);
+        assert!(waits.get_sample_sum() >= 2.0);
+    }
+
+    #[test]
+    fn test_retry_hint_sets_per_message_expiration() {
+        let properties = build_retry_properties(
+            &BasicProperties::default(),
+            1,
+            Some("rate limited"),
+            Some(Duration::from_millis(1500)),
+        );
+
+        assert_eq!(
+            properties.expiration().as_ref().map(|e| e.as_str()),
+            Some("1500")
+        );
+        let headers = properties.headers().as_ref().unwrap();
+        assert_eq!(header_u32(headers, RETRY_HEADER), Some(1));
+    }
+
+    #[test]
+    fn test_no_hint_leaves_delay_to_queue_ttl() {
+        let properties = build_retry_properties(&BasicProperties::default(), 2, None, None);
+
+        assert_eq!(properties.expiration(), &None);
+        assert_eq!(properties.delivery_mode(), &Some(2));
+        let headers = properties.headers().as_ref().unwrap();
+        assert_eq!(header_u32(headers, RETRY_HEADER), Some(2));
+        assert!(!headers.contains_key(ERROR_TYPE_HEADER));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ack_failures_are_counted_and_retried() {
+        let metrics = Metrics::new().unwrap();
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let flaky = || async {
+            match calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err("channel closed".to_string()),
+                _ => Ok(()),
+            }
+        };
+
+        assert!(ack_with_retry(1, &metrics.ack_failures_total, flaky).await.is_ok());
+        assert_eq!(metrics.ack_failures_total.get(), 1.0);
+
+        let down = || async { Err::<(), _>("connection lost".to_string()) };
+        assert!(ack_with_retry(2, &metrics.ack_failures_total, down).await.is_err());
+        assert_eq!(metrics.ack_failures_total.get(), 1.0 + ACK_ATTEMPTS as f64);
+    }
+
+    /// Holds `held` until `release` is notified; succeeds on everything.
+    struct GatedHandler {
+        release: Arc<Notify>,
+    }
+
+    #[async_trait::async_trait]
+    impl MessageHandler for GatedHandler {
+        async fn handle(
+            &self,
+            delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            if delivery.data == b"held" {
+                self.release.notified().await;
+            }
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_ack_window_holds_batches_behind_other_queues() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let release = Arc::new(Notify::new());
+        let window = Arc::new(Mutex::new(AckWindow::new(2)));
+        let consumer = |queue: &str| {
+            ConsumerBuilder::new()
+                .broker(broker.clone())
+                .queue_name(queue)
+                .handler(Arc::new(GatedHandler {
+                    release: release.clone(),
+                }))
+                .shutdown(Arc::new(Notify::new()))
+                .metrics(metrics.clone())
+                .build()
+                .unwrap()
+                .with_shared_ack_window(window.clone())
+        };
+        let (telemetry, audit) = (consumer("telemetry"), consumer("audit"));
+
+        // Tags are numbered per channel, so the two queues' deliveries interleave.
+        tokio::join!(audit.process_message(delivery(2, b"held")), async {
+            telemetry.process_message(delivery(1, b"ok")).await;
+            telemetry.process_message(delivery(3, b"ok")).await;
+            // A multiple-ack of 3 would also ack the audit delivery still in flight.
+            assert!(broker.settlements().is_empty());
+            release.notify_one();
+        });
+        audit.process_message(delivery(4, b"ok")).await;
+
+        assert_eq!(broker.settlements(), vec![(3, Settlement::Acked)]);
+        assert_eq!(window.lock().unwrap().outstanding(), 1);
+    }
+
+    #[test]
+    fn test_dlq_timestamp_comes_from_the_clock() {
+        let clock = FixedClock(UNIX_EPOCH + Duration::from_millis(1_700_000_042_900));
+
+        let properties = build_dlq_properties(
+            &BasicProperties::default(),
+            "telemetry",
+            "Invalid JSON",
+            "permanent",
+            clock.now(),
+        );
+
+        assert_eq!(properties.timestamp(), &Some(1_700_000_042));
+        let headers = properties.headers().as_ref().unwrap();
+        assert_eq!(header_string(headers, ORIGINAL_QUEUE_HEADER).as_deref(), Some("telemetry"));
+        assert_eq!(header_string(headers, ERROR_TYPE_HEADER).as_deref(), Some("permanent"));
+    }
+
+    #[test]
+    fn test_dlq_envelope_carries_the_original_message() {
+        let mut headers = FieldTable::default();
+        headers.insert(RETRY_HEADER.into(), AMQPValue::LongUInt(3));
+        headers.insert("tenant".into(), AMQPValue::LongString("acme".into()));
+        let original = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_headers(headers);
+        let data = [0xff, b'{', b'}'];
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_042);
+
+        let body = build_dlq_envelope(&data, "telemetry.login", &original, "boom", "transient", now);
+
+        let envelope: DlqEnvelope = serde_json::from_slice(&body).unwrap();
+        assert_eq!(envelope.original_body().unwrap(), data);
+        assert_eq!(envelope.routing_key, "telemetry.login");
+        assert_eq!(envelope.content_type.as_deref(), Some("application/json"));
+        assert_eq!(envelope.headers["tenant"], "acme");
+        assert_eq!(envelope.headers[RETRY_HEADER], 3);
+        assert_eq!(envelope.error_reason, "boom");
+        assert_eq!(envelope.error_type, "transient");
+        assert_eq!(envelope.retry_count, 3);
+        assert_eq!(envelope.failed_at, 1_700_000_042);
+
+        // Inspection and replay see the original body, not the envelope.
+        let properties = build_dlq_properties(&original, "telemetry", "boom", "transient", now)
+            .with_content_type(DLQ_ENVELOPE_CONTENT_TYPE.into());
+        let message = DlqMessage::from_parts("telemetry.dlq", &properties, &body);
+        assert!(message.enveloped);
+        assert_eq!(message.body, data);
+        assert_eq!(message.error_type, "transient");
+        let republished = republish_properties(&message, &properties);
+        assert_eq!(
+            republished.content_type().as_ref().map(|t| t.as_str()),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_the_cap() {
+        let base = Duration::from_millis(1000);
+        let max = Duration::from_millis(10_000);
+
+        let delays: Vec<u128> = (1..=6)
+            .map(|attempt| retry_delay(base, max, attempt).as_millis())
+            .collect();
+
+        assert_eq!(delays, vec![1000, 2000, 4000, 8000, 10_000, 10_000]);
+        assert_eq!(retry_delay(base, max, 64), max);
+    }
+
+    #[test]
+    fn test_retry_tier_follows_retry_count() {
+        let tiers: Vec<usize> = (1..=5).map(|attempt| retry_tier(attempt, 3)).collect();
+        assert_eq!(tiers, vec![1, 2, 3, 3, 3]);
+
+        let ttls = [
+            Duration::from_secs(5),
+            Duration::from_secs(30),
+            Duration::from_secs(120),
+        ];
+        assert_eq!(hinted_retry_tier(Duration::from_secs(1), &ttls), 1);
+        assert_eq!(hinted_retry_tier(Duration::from_secs(30), &ttls), 2);
+        assert_eq!(hinted_retry_tier(Duration::from_secs(31), &ttls), 3);
+        assert_eq!(hinted_retry_tier(Duration::from_secs(600), &ttls), 3);
+    }
+
+    #[test]
+    fn test_queue_wait_from_enqueue_timestamp() {
+        let now = UNIX_EPOCH + Duration::from_millis(1_700_000_042_500);
+        let metrics = Metrics::new().unwrap();
+        let clock = ClockGuard::new(DEFAULT_MAX_CLOCK_SKEW, &metrics);
+
+        let producer_secs = BasicProperties::default().with_timestamp(1_700_000_000);
+        assert_eq!(
+            queue_wait(&producer_secs, &clock, now),
+            Some(Duration::from_millis(42_500))
+        );
+
+        let mut headers = lapin::types::FieldTable::default();
+        headers.insert(
+            BROKER_TIMESTAMP_HEADER.into(),
+            lapin::types::AMQPValue::LongLongInt(1_700_000_040_000),
+        );
+        let broker_ms = BasicProperties::default()
+            .with_timestamp(1_700_000_000)
+            .with_headers(headers);
+        assert_eq!(
+            queue_wait(&broker_ms, &clock, now),
+            Some(Duration::from_millis(2_500))
+        );
+
+        let wait = queue_wait(&broker_ms, &clock, now).unwrap();
+        let histogram = metrics.queue_wait_seconds.with_label_values(&["telemetry"]);
+        histogram.observe(wait.as_secs_f64());
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert_eq!(histogram.get_sample_sum(), 2.5);
+    }
+
+    #[test]
+    fn test_queue_wait_skips_retries_and_missing_timestamps() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_060);
+        let metrics = Metrics::new().unwrap();
+        let clock = ClockGuard::new(DEFAULT_MAX_CLOCK_SKEW, &metrics);
+
+        assert_eq!(queue_wait(&BasicProperties::default(), &clock, now), None);
+
+        let sent = BasicProperties::default().with_timestamp(1_700_000_000);
+        let retried = build_retry_properties(&sent, 1, None, None);
+        assert_eq!(queue_wait(&retried, &clock, now), None);
+
+        let future = BasicProperties::default().with_timestamp(1_700_000_100);
+        assert_eq!(queue_wait(&future, &clock, now), None);
+        assert_eq!(metrics.clock_skew_detected_total.get(), 1.0);
+    }
+
+    #[test]
+    fn test_message_age_observed_from_timestamp() {
+        let now = UNIX_EPOCH + Duration::from_millis(1_700_000_003_000);
+        let metrics = Metrics::new().unwrap();
+        let clock = ClockGuard::new(DEFAULT_MAX_CLOCK_SKEW, &metrics);
+        let histogram = metrics.message_age_seconds.with_label_values(&["telemetry"]);
+
+        observe_message_age(&metrics, "telemetry", &BasicProperties::default(), &clock, now);
+        assert_eq!(histogram.get_sample_count(), 0);
+
+        // Retried messages count too, with their age since first being sent.
+        let sent = BasicProperties::default()
+            .with_timestamp(1_700_000_000)
+            .with_message_id("event-7".into());
+        let retried = build_retry_properties(&sent, 2, None, None);
+        assert_eq!(retried.timestamp(), &Some(1_700_000_000));
+        assert_eq!(retried.message_id(), sent.message_id());
+        observe_message_age(&metrics, "telemetry", &retried, &clock, now);
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert_eq!(histogram.get_sample_sum(), 3.0);
+    }
+
+    #[test]
+    fn test_event_version_defaults_to_v1_and_flags_unreadable_headers() {
+        assert_eq!(event_version(&BasicProperties::default()).as_deref(), Some("v1"));
+
+        let with_version = |value| {
+            let mut headers = FieldTable::default();
+            headers.insert(EVENT_VERSION_HEADER.into(), value);
+            BasicProperties::default().with_headers(headers)
+        };
+        assert_eq!(
+            event_version(&with_version(lapin::types::AMQPValue::LongString("v2".into())))
+                .as_deref(),
+            Some("v2")
+        );
+        assert_eq!(event_version(&with_version(lapin::types::AMQPValue::LongInt(2))), None);
+    }
+
+    #[test]
+    fn test_dead_letters_counted_by_error_type_and_routing_key() {
+        let metrics = Metrics::new().unwrap();
+        let permanent = HandlerError::Permanent("Invalid JSON".to_string());
+        let transient = HandlerError::transient("downstream unavailable");
+
+        // A transient failure with retries left is not dead-lettered.
+        assert_eq!(dead_letter_type(&transient, 2, 3), None);
+
+        for (error, retry_count, routing_key) in [
+            (&permanent, 0, "telemetry.log"),
+            (&transient, 3, "telemetry.log"),
+            (&transient, 3, "telemetry.metric"),
+        ] {
+            let error_type = dead_letter_type(error, retry_count, 3).unwrap();
+            count_dead_letter(&metrics, error_type, routing_key);
+        }
+
+        let count = |labels: &[&str]| metrics.messages_dlq_total.with_label_values(labels).get();
+        assert_eq!(count(&["permanent", "telemetry.log"]), 1.0);
+        assert_eq!(count(&["transient", "telemetry.log"]), 1.0);
+        assert_eq!(count(&["transient", "telemetry.metric"]), 1.0);
+        assert_eq!(count(&["permanent", "telemetry.metric"]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_retry_count_is_observed_when_retries_run_out() {
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(PayloadHandler))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .max_retries(3)
+            .build()
+            .unwrap();
+
+        // Each retry comes back from the retry queue with the properties it
+        // was republished with, until the fourth attempt is dead-lettered.
+        let mut properties = BasicProperties::default();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            consumer
+                .process_message(delivery_with_properties(attempts, b"flaky", properties))
+                .await;
+            let published = broker.published().pop().unwrap();
+            if published.queue == "telemetry.dlq" {
+                break;
+            }
+            assert_eq!(published.queue, "telemetry.retry");
+            properties = published.properties;
+        }
+        assert_eq!(attempts, 4);
+
+        let histogram = metrics.dlq_retry_count.with_label_values(&["telemetry"]);
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert_eq!(histogram.get_sample_sum(), 3.0);
+    }
+
+    /// Fails transiently the first time, then succeeds.
+    struct FailsOnce(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl MessageHandler for FailsOnce {
+        async fn handle(
+            &self,
+            _delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(HandlerError::transient("downstream unavailable"))
+            } else {
+                Ok(HandlerOutcome::default())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signed_protobuf_verifies_before_and_after_a_retry() {
+        use crate::contracts::TelemetryEvent;
+        use crate::messaging::encoding::PROTOBUF_CONTENT_TYPE;
+        use crate::messaging::signature::{SignatureFailureMode, SignatureVerifier, SIGNATURE_HEADER};
+        use hmac::{Hmac, Mac};
+        use prost::Message;
+        use sha2::Sha256;
+
+        let body = TelemetryEvent {
+            event_id: Some("evt-1".into()),
+            event_type: "telemetry.log.captured".into(),
+            event_version: Some(1),
+            timestamp: None,
+            correlation_id: None,
+            payload: Some(prost_types::Struct::default()),
+        }
+        .encode_to_vec();
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(&body);
+        let mut headers = FieldTable::default();
+        headers.insert(
+            SIGNATURE_HEADER.into(),
+            AMQPValue::LongString(hex::encode(mac.finalize().into_bytes()).into()),
+        );
+        let properties = BasicProperties::default()
+            .with_content_type(PROTOBUF_CONTENT_TYPE.into())
+            .with_headers(headers);
+
+        let broker = Arc::new(MockBroker::default());
+        let metrics = Metrics::new().unwrap();
+        let inner = Arc::new(FailsOnce(AtomicUsize::new(0)));
+        let verifier = SignatureVerifier::new(
+            inner.clone(),
+            Ok(b"secret".to_vec()),
+            SignatureFailureMode::FailClosed,
+            metrics.clone(),
+        );
+        let consumer = ConsumerBuilder::new()
+            .broker(broker.clone())
+            .queue_name("telemetry")
+            .handler(Arc::new(verifier))
+            .shutdown(Arc::new(Notify::new()))
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+
+        consumer
+            .process_message(delivery_with_properties(1, &body, properties))
+            .await;
+        let retry = broker.published().pop().unwrap();
+        assert_eq!(retry.queue, "telemetry.retry");
+        assert_eq!(retry.data, body);
+
+        // The retried copy comes back as published and verifies again.
+        consumer
+            .process_message(delivery_with_properties(2, &retry.data, retry.properties))
+            .await;
+
+        assert_eq!(inner.0.load(Ordering::SeqCst), 2);
+        assert_eq!(broker.published().len(), 1);
+        assert_eq!(broker.settlements().last(), Some(&(2, Settlement::Acked)));
+    }
+
+    #[test]
+    fn test_discard_is_never_dead_lettered() {
+        let discard = HandlerError::Discard {
+            reason: "health ping".to_string(),
+        };
+
+        assert_eq!(dead_letter_type(&discard, 0, 3), None);
+        assert_eq!(dead_letter_type(&discard, 10, 3), None);
+    }
+
+    #[test]
+    fn test_at_most_once_never_routes_back_to_the_broker() {
+        let errors = [
+            HandlerError::transient("downstream unavailable"),
+            HandlerError::Retry {
+                after: Duration::from_secs(1),
+                reason: "rate limited".to_string(),
+            },
+            HandlerError::Permanent("Invalid JSON".to_string()),
+            HandlerError::Discard {
+                reason: "health ping".to_string(),
+            },
+        ];
+
+        for error in &errors {
+            for (retry_count, panicked) in [(0, false), (3, false), (0, true)] {
+                assert_eq!(
+                    failure_route(DeliveryMode::AtMostOnce, error, retry_count, 3, panicked),
+                    FailureRoute::Drop
+                );
+            }
+        }
+
+        // The same failures are retried or dead-lettered at least once.
+        let route = |error, retry_count, panicked| {
+            failure_route(DeliveryMode::AtLeastOnce, error, retry_count, 3, panicked)
+        };
+        assert_eq!(route(&errors[0], 0, false), FailureRoute::Retry);
+        assert_eq!(route(&errors[1], 3, false), FailureRoute::DeadLetter("transient"));
+        assert_eq!(route(&errors[2], 0, false), FailureRoute::DeadLetter("permanent"));
+        assert_eq!(route(&errors[2], 0, true), FailureRoute::DeadLetter(PANIC_ERROR_TYPE));
+        assert_eq!(route(&errors[3], 0, false), FailureRoute::Drop);
+    }
+
+    struct PanickingHandler;
+
+    #[async_trait::async_trait]
+    impl MessageHandler for PanickingHandler {
+        async fn handle(
+            &self,
+            delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            if delivery.data == b"boom" {
+                panic!("handler exploded");
+            }
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_panic_is_caught_and_dead_lettered() {
+        let handler: Arc<dyn MessageHandler> = Arc::new(PanickingHandler);
+
+        let (result, panicked) = run_handler(handler.clone(), delivery(1, b"boom"), None, CancellationToken::new())
+            .await
+            .into_result();
+        assert!(panicked);
+        // Permanent, so it goes straight to the DLQ rather than being retried.
+        let Err(HandlerError::Permanent(reason)) = result else {
+            panic!("expected a permanent failure, got {:?}", result);
+        };
+        assert_eq!(reason, "Handler panicked: handler exploded");
+
+        // The next message is handled as usual.
+        assert!(matches!(
+            run_handler(handler, delivery(2, b"{}"), None, CancellationToken::new()).await,
+            HandlerRun::Completed(Ok(_))
+        ));
+    }
+
+    struct HangingHandler {
+        finished: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    #[async_trait::async_trait]
+    impl MessageHandler for HangingHandler {
+        async fn handle(
+            &self,
+            _delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            self.finished.store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hung_handler_times_out_as_transient_and_is_aborted() {
+        let finished = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let handler: Arc<dyn MessageHandler> = Arc::new(HangingHandler {
+            finished: finished.clone(),
+        });
+
+        let cancel = CancellationToken::new();
+        let run =
+            run_handler(handler, delivery(1, b"{}"), Some(Duration::from_secs(5)), cancel.clone())
+                .await;
+        assert!(matches!(run, HandlerRun::TimedOut));
+        let (result, _) = run.into_result();
+        assert!(matches!(
+            result,
+            Err(HandlerError::Transient { ref reason, .. }) if reason == "handler timeout"
+        ));
+
+        // The aborted handler never gets to finish in the background.
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert!(!finished.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(cancel.is_cancelled());
+    }
+
+    struct CooperativeHandler;
+
+    #[async_trait::async_trait]
+    impl MessageHandler for CooperativeHandler {
+        async fn handle(
+            &self,
+            _delivery: IncomingMessage,
+            cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(600)) => Ok(HandlerOutcome::default()),
+                _ = cancel.cancelled() => Err(HandlerError::transient("cancelled")),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_grace_cancels_in_flight_handlers() {
+        let handler: Arc<dyn MessageHandler> = Arc::new(CooperativeHandler);
+        let cancel = CancellationToken::new();
+        let result = Arc::new(Mutex::new(None));
+        let mut workers = WorkerPool::new(1);
+        let (token, recorded) = (cancel.child_token(), result.clone());
+        workers
+            .spawn(async move {
+                let run = run_handler(handler, delivery(1, b"{}"), None, token).await;
+                *recorded.lock().unwrap() = Some(run.into_result().0);
+            })
+            .await;
+
+        let start = tokio::time::Instant::now();
+        let drained = drain_with_grace(&mut workers, Some(Duration::from_secs(5)), &cancel).await;
+
+        assert_eq!(drained, 1);
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        assert!(matches!(
+            result.lock().unwrap().take(),
+            Some(Err(HandlerError::Transient { ref reason, .. })) if reason == "cancelled"
+        ));
+    }
+
+    #[test]
+    fn test_redelivered_message_at_max_retries_is_poison() {
+        let mut redelivered = delivery(1, b"{}");
+        redelivered.redelivered = true;
+        redelivered.properties = build_retry_properties(&BasicProperties::default(), 3, None, None);
+        let retry_count = header_u32(redelivered.properties.headers().as_ref().unwrap(), RETRY_HEADER)
+            .unwrap();
+
+        assert!(is_poison_candidate(redelivered.redelivered, retry_count, 3));
+        // Retries left, or a first delivery at the limit, are handled as usual.
+        assert!(!is_poison_candidate(true, 2, 3));
+        assert!(!is_poison_candidate(false, 3, 3));
+    }
+
+    #[test]
+    fn test_drain_requires_idle_window_and_empty_queue() {
+        let idle = Duration::from_secs(300);
+
+        assert!(!drain_complete(Duration::from_secs(299), idle, 0));
+        assert!(!drain_complete(Duration::from_secs(600), idle, 3));
+        assert!(drain_complete(Duration::from_secs(300), idle, 0));
+    }
 }
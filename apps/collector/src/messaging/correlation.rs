@@ -0,0 +1,112 @@
+//! The correlation id tying a message to the request that produced it, so
+//! its log lines can be found alongside those of other services.
+
+use lapin::types::AMQPValue;
+use lapin::BasicProperties;
+
+use super::dlq::header_string;
+
+pub const CORRELATION_ID_HEADER: &str = "correlation_id";
+/// Read as well, for producers using the HTTP-style name.
+pub const X_CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// The message's correlation id: the `correlation_id` header, else the
+/// `x-correlation-id` header, else the AMQP `correlation_id` property.
+/// Blank values are ignored.
+pub fn correlation_id(properties: &BasicProperties) -> Option<String> {
+    from_headers(properties).or_else(|| {
+        properties
+            .correlation_id()
+            .as_ref()
+            .map(|id| id.to_string())
+            .filter(|id| !id.trim().is_empty())
+    })
+}
+
+/// Returns the message's correlation id, generating one when it has none.
+/// An id that is not already in a header is written to `correlation_id`, so
+/// retries and the DLQ, which republish the headers, keep the same id.
+pub fn ensure_correlation_id(properties: BasicProperties) -> (BasicProperties, String) {
+    if let Some(id) = from_headers(&properties) {
+        return (properties, id);
+    }
+    let id = correlation_id(&properties).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let mut headers = properties.headers().clone().unwrap_or_default();
+    headers.insert(
+        CORRELATION_ID_HEADER.into(),
+        AMQPValue::LongString(id.as_str().into()),
+    );
+    (properties.with_headers(headers), id)
+}
+
+fn from_headers(properties: &BasicProperties) -> Option<String> {
+    let headers = properties.headers().as_ref()?;
+    [CORRELATION_ID_HEADER, X_CORRELATION_ID_HEADER]
+        .into_iter()
+        .filter_map(|name| header_string(headers, name))
+        .find(|id| !id.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::types::FieldTable;
+
+    fn with_header(name: &str, value: &str) -> BasicProperties {
+        let mut headers = FieldTable::default();
+        headers.insert(name.into(), AMQPValue::LongString(value.into()));
+        BasicProperties::default().with_headers(headers)
+    }
+
+    #[test]
+    fn test_correlation_id_header_wins_over_alternatives() {
+        let properties = with_header(X_CORRELATION_ID_HEADER, "from-x-header")
+            .with_correlation_id("from-property".into());
+        assert_eq!(
+            correlation_id(&properties).as_deref(),
+            Some("from-x-header")
+        );
+
+        let mut headers = properties.headers().clone().unwrap();
+        headers.insert(
+            CORRELATION_ID_HEADER.into(),
+            AMQPValue::LongString("from-header".into()),
+        );
+        let properties = properties.with_headers(headers);
+        assert_eq!(correlation_id(&properties).as_deref(), Some("from-header"));
+
+        let properties = BasicProperties::default().with_correlation_id("from-property".into());
+        assert_eq!(
+            correlation_id(&properties).as_deref(),
+            Some("from-property")
+        );
+        assert_eq!(
+            correlation_id(&with_header(CORRELATION_ID_HEADER, " ")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_missing_correlation_id_is_generated_into_the_headers() {
+        let (properties, id) = ensure_correlation_id(BasicProperties::default());
+
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+        let headers = properties.headers().as_ref().unwrap();
+        assert_eq!(
+            header_string(headers, CORRELATION_ID_HEADER),
+            Some(id.clone())
+        );
+        assert_eq!(ensure_correlation_id(properties).1, id);
+    }
+
+    #[test]
+    fn test_existing_header_is_left_as_it_is() {
+        let original = with_header(X_CORRELATION_ID_HEADER, "req-42");
+
+        let (properties, id) = ensure_correlation_id(original.clone());
+
+        assert_eq!(id, "req-42");
+        assert_eq!(properties, original);
+    }
+}
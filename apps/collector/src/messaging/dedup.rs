@@ -0,0 +1,119 @@
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Recently processed `message_id`s, for skipping duplicate deliveries.
+///
+/// An id is remembered only once its message has been fully processed, so a
+/// message that failed and comes back from the retry queue with the same id
+/// is still handled. Ids are forgotten after `ttl`, or sooner when more than
+/// `capacity` newer ones push them out.
+pub struct DedupCache {
+    seen: Mutex<LruCache<String, Instant>>,
+    ttl: Duration,
+}
+
+impl DedupCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        Self {
+            seen: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }
+    }
+
+    /// Whether `message_id` was processed within the last `ttl`.
+    pub fn is_duplicate(&self, message_id: &str) -> bool {
+        self.is_duplicate_at(message_id, Instant::now())
+    }
+
+    pub fn remember(&self, message_id: &str) {
+        self.remember_at(message_id, Instant::now());
+    }
+
+    fn is_duplicate_at(&self, message_id: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        match seen.peek(message_id) {
+            Some(processed_at) if now.duration_since(*processed_at) < self.ttl => true,
+            Some(_) => {
+                seen.pop(message_id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn remember_at(&self, message_id: &str, now: Instant) {
+        self.seen.lock().unwrap().put(message_id.to_string(), now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::handler::{HandlerError, HandlerOutcome, MessageHandler};
+    use crate::messaging::source::IncomingMessage;
+    use crate::messaging::test_util::delivery_with_properties;
+    use async_trait::async_trait;
+    use lapin::BasicProperties;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio_util::sync::CancellationToken;
+
+    struct CountingHandler {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl MessageHandler for CountingHandler {
+        async fn handle(
+            &self,
+            _delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    fn cache(ttl: Duration) -> DedupCache {
+        DedupCache::new(NonZeroUsize::new(16).unwrap(), ttl)
+    }
+
+    #[tokio::test]
+    async fn test_same_message_id_is_handled_once() {
+        let handler = CountingHandler {
+            calls: AtomicUsize::new(0),
+        };
+        let dedup = cache(Duration::from_secs(60));
+        let properties = BasicProperties::default().with_message_id("msg-1".into());
+
+        // What the consumer does for each delivery.
+        for tag in 1..=2 {
+            let delivery = delivery_with_properties(tag, b"{}", properties.clone());
+            let id = delivery
+                .properties
+                .message_id()
+                .as_ref()
+                .unwrap()
+                .to_string();
+            if dedup.is_duplicate(&id) {
+                continue;
+            }
+            handler.handle(delivery, CancellationToken::new()).await.unwrap();
+            dedup.remember(&id);
+        }
+
+        assert_eq!(handler.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_ids_expire_after_ttl() {
+        let dedup = cache(Duration::from_secs(60));
+        let start = Instant::now();
+        dedup.remember_at("msg-1", start);
+
+        assert!(dedup.is_duplicate_at("msg-1", start + Duration::from_secs(59)));
+        assert!(!dedup.is_duplicate_at("msg-1", start + Duration::from_secs(60)));
+        assert!(!dedup.is_duplicate_at("msg-2", start));
+    }
+}
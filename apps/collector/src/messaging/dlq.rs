@@ -0,0 +1,349 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use lapin::message::Delivery;
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::BasicProperties;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::consumer::{
+    ERROR_REASON_HEADER, ERROR_TYPE_HEADER, EVENT_VERSION_HEADER, ORIGINAL_QUEUE_HEADER,
+    RETRY_HEADER,
+};
+
+/// Written by the API's DLQ replay endpoint each time a message is replayed.
+pub const REPLAY_COUNT_HEADER: &str = "x-replay-count";
+pub const REPLAY_TIMESTAMP_HEADER: &str = "x-replay-timestamp";
+/// Written by the DLQ reanimator each time it moves a message back to its queue.
+pub const REANIMATION_COUNT_HEADER: &str = "x-reanimation-count";
+
+/// `content_type` of a DLQ message whose body is a `DlqEnvelope`.
+pub const DLQ_ENVELOPE_CONTENT_TYPE: &str = "application/vnd.collector.dlq-envelope+json";
+
+/// JSON body written to the DLQ in envelope mode, so the failed message can
+/// be inspected with its full context from the body alone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DlqEnvelope {
+    /// The original body, base64-encoded.
+    pub body: String,
+    pub routing_key: String,
+    pub content_type: Option<String>,
+    /// The original headers, as JSON values.
+    pub headers: Map<String, Value>,
+    pub error_reason: String,
+    pub error_type: String,
+    pub retry_count: u32,
+    /// Time the message was dead-lettered, in seconds since the epoch.
+    pub failed_at: u64,
+}
+
+impl DlqEnvelope {
+    /// The original body, or `None` when `body` is not valid base64.
+    pub fn original_body(&self) -> Option<Vec<u8>> {
+        BASE64.decode(&self.body).ok()
+    }
+}
+
+/// Values above this are treated as milliseconds rather than seconds since the epoch.
+/// Producers disagree on the unit (amqplib writes `Date.now()`), so both are accepted.
+const MILLIS_THRESHOLD: u64 = 100_000_000_000;
+
+/// Typed view of a message sitting in a dead letter queue.
+///
+/// Every field is parsed leniently: a missing header or one carrying an
+/// unexpected AMQP type falls back to a default instead of failing, so a
+/// single malformed message can never break DLQ inspection or replay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DlqMessage {
+    pub message_id: Option<String>,
+    pub routing_key: String,
+    pub original_queue: Option<String>,
+    pub error_reason: Option<String>,
+    pub error_type: String,
+    pub retry_count: u32,
+    pub replay_count: u32,
+    pub reanimation_count: u32,
+    pub event_version: Option<String>,
+    /// Time the message was dead-lettered, in seconds since the epoch.
+    pub failed_at: Option<u64>,
+    /// Time of the last replay, in seconds since the epoch.
+    pub replayed_at: Option<u64>,
+    pub headers: FieldTable,
+    /// The original body, unwrapped from its envelope if it has one.
+    pub body: Vec<u8>,
+    /// The original `content_type`, when an envelope recorded one.
+    pub content_type: Option<String>,
+    /// Whether the message was dead-lettered as a `DlqEnvelope`.
+    pub enveloped: bool,
+}
+
+impl DlqMessage {
+    pub fn from_delivery(delivery: &Delivery) -> Self {
+        Self::from_parts(
+            delivery.routing_key.as_str(),
+            &delivery.properties,
+            &delivery.data,
+        )
+    }
+
+    pub fn from_parts(routing_key: &str, properties: &BasicProperties, body: &[u8]) -> Self {
+        let headers = properties.headers().clone().unwrap_or_default();
+        // An envelope that fails to parse is kept whole as the body.
+        let envelope = properties
+            .content_type()
+            .as_ref()
+            .filter(|content_type| content_type.as_str() == DLQ_ENVELOPE_CONTENT_TYPE)
+            .and_then(|_| serde_json::from_slice::<DlqEnvelope>(body).ok())
+            .and_then(|envelope| Some((envelope.original_body()?, envelope.content_type)));
+        let enveloped = envelope.is_some();
+        let (body, content_type) = match envelope {
+            Some((body, content_type)) => (body, content_type),
+            None => (body.to_vec(), None),
+        };
+
+        Self {
+            message_id: properties.message_id().as_ref().map(|id| id.to_string()),
+            routing_key: routing_key.to_string(),
+            original_queue: header_string(&headers, ORIGINAL_QUEUE_HEADER),
+            error_reason: header_string(&headers, ERROR_REASON_HEADER),
+            error_type: header_string(&headers, ERROR_TYPE_HEADER)
+                .unwrap_or_else(|| "unknown".to_string()),
+            retry_count: header_u32(&headers, RETRY_HEADER).unwrap_or(0),
+            replay_count: header_u32(&headers, REPLAY_COUNT_HEADER).unwrap_or(0),
+            reanimation_count: header_u32(&headers, REANIMATION_COUNT_HEADER).unwrap_or(0),
+            event_version: header_string(&headers, EVENT_VERSION_HEADER),
+            failed_at: properties.timestamp().map(normalize_epoch_secs),
+            replayed_at: header_epoch_secs(&headers, REPLAY_TIMESTAMP_HEADER),
+            headers,
+            body,
+            content_type,
+            enveloped,
+        }
+    }
+}
+
+/// Base properties for republishing a DLQ message to a work queue. An
+/// enveloped message gets its original `content_type` back in place of the
+/// envelope's; the caller sets the headers either way.
+pub(crate) fn republish_properties(
+    message: &DlqMessage,
+    properties: &BasicProperties,
+) -> BasicProperties {
+    if !message.enveloped {
+        return properties.clone();
+    }
+    let mut republished = BasicProperties::default().with_delivery_mode(2);
+    if let Some(timestamp) = properties.timestamp() {
+        republished = republished.with_timestamp(*timestamp);
+    }
+    match &message.content_type {
+        Some(content_type) => republished.with_content_type(content_type.as_str().into()),
+        None => republished,
+    }
+}
+
+/// Headers as a JSON object, for the DLQ envelope. Strings and numbers map
+/// to their JSON counterparts; byte arrays are base64-encoded.
+pub(crate) fn headers_to_json(headers: &FieldTable) -> Map<String, Value> {
+    headers
+        .inner()
+        .iter()
+        .map(|(key, value)| (key.to_string(), amqp_to_json(value)))
+        .collect()
+}
+
+fn amqp_to_json(value: &AMQPValue) -> Value {
+    match value {
+        AMQPValue::Boolean(v) => Value::from(*v),
+        AMQPValue::ShortShortInt(v) => Value::from(*v),
+        AMQPValue::ShortShortUInt(v) => Value::from(*v),
+        AMQPValue::ShortInt(v) => Value::from(*v),
+        AMQPValue::ShortUInt(v) => Value::from(*v),
+        AMQPValue::LongInt(v) => Value::from(*v),
+        AMQPValue::LongUInt(v) => Value::from(*v),
+        AMQPValue::LongLongInt(v) => Value::from(*v),
+        AMQPValue::Float(v) => Value::from(*v),
+        AMQPValue::Double(v) => Value::from(*v),
+        AMQPValue::Timestamp(v) => Value::from(*v),
+        AMQPValue::ShortString(s) => Value::from(s.as_str()),
+        AMQPValue::LongString(s) => Value::from(String::from_utf8_lossy(s.as_bytes())),
+        AMQPValue::ByteArray(b) => Value::from(BASE64.encode(b.as_slice())),
+        AMQPValue::FieldArray(items) => {
+            Value::Array(items.as_slice().iter().map(amqp_to_json).collect())
+        }
+        AMQPValue::FieldTable(table) => Value::Object(headers_to_json(table)),
+        AMQPValue::DecimalValue(v) => Value::from(format!("{:?}", v)),
+        AMQPValue::Void => Value::Null,
+    }
+}
+
+pub(crate) fn header_string(headers: &FieldTable, key: &str) -> Option<String> {
+    match headers.inner().get(key)? {
+        AMQPValue::LongString(s) => Some(String::from_utf8_lossy(s.as_bytes()).into_owned()),
+        AMQPValue::ShortString(s) => Some(s.to_string()),
+        AMQPValue::ByteArray(b) => Some(String::from_utf8_lossy(b.as_slice()).into_owned()),
+        _ => None,
+    }
+}
+
+pub(crate) fn header_u64(headers: &FieldTable, key: &str) -> Option<u64> {
+    match headers.inner().get(key)? {
+        AMQPValue::ShortShortUInt(v) => Some(u64::from(*v)),
+        AMQPValue::ShortUInt(v) => Some(u64::from(*v)),
+        AMQPValue::LongUInt(v) => Some(u64::from(*v)),
+        AMQPValue::Timestamp(v) => Some(*v),
+        AMQPValue::ShortShortInt(v) => u64::try_from(*v).ok(),
+        AMQPValue::ShortInt(v) => u64::try_from(*v).ok(),
+        AMQPValue::LongInt(v) => u64::try_from(*v).ok(),
+        AMQPValue::LongLongInt(v) => u64::try_from(*v).ok(),
+        AMQPValue::Float(v) if *v >= 0.0 => Some(*v as u64),
+        AMQPValue::Double(v) if *v >= 0.0 => Some(*v as u64),
+        AMQPValue::LongString(s) => std::str::from_utf8(s.as_bytes()).ok()?.trim().parse().ok(),
+        AMQPValue::ShortString(s) => s.as_str().trim().parse().ok(),
+        _ => None,
+    }
+}
+
+pub(crate) fn header_u32(headers: &FieldTable, key: &str) -> Option<u32> {
+    header_u64(headers, key).and_then(|v| u32::try_from(v).ok())
+}
+
+/// Reads a timestamp header written either as seconds or milliseconds.
+pub(crate) fn header_epoch_secs(headers: &FieldTable, key: &str) -> Option<u64> {
+    header_u64(headers, key).map(normalize_epoch_secs)
+}
+
+fn normalize_epoch_secs(value: u64) -> u64 {
+    if value > MILLIS_THRESHOLD {
+        value / 1000
+    } else {
+        value
+    }
+}
+
+/// Like `normalize_epoch_secs`, but keeps millisecond precision when the producer sent it.
+pub(crate) fn normalize_epoch_millis(value: u64) -> u64 {
+    if value > MILLIS_THRESHOLD {
+        value
+    } else {
+        value.saturating_mul(1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::types::ShortString;
+
+    fn headers(entries: Vec<(&str, AMQPValue)>) -> FieldTable {
+        let mut table = FieldTable::default();
+        for (key, value) in entries {
+            table.insert(key.into(), value);
+        }
+        table
+    }
+
+    #[test]
+    fn test_complete_headers() {
+        let properties = BasicProperties::default()
+            .with_message_id(ShortString::from("msg-1"))
+            .with_timestamp(1_700_000_000)
+            .with_headers(headers(vec![
+                (
+                    ORIGINAL_QUEUE_HEADER,
+                    AMQPValue::LongString("telemetry".into()),
+                ),
+                (
+                    ERROR_REASON_HEADER,
+                    AMQPValue::LongString("Invalid JSON".into()),
+                ),
+                (ERROR_TYPE_HEADER, AMQPValue::LongString("permanent".into())),
+                (RETRY_HEADER, AMQPValue::LongUInt(3)),
+                (REPLAY_COUNT_HEADER, AMQPValue::LongUInt(1)),
+                (
+                    REPLAY_TIMESTAMP_HEADER,
+                    AMQPValue::Double(1_700_000_500_000.0),
+                ),
+                (EVENT_VERSION_HEADER, AMQPValue::LongString("v1".into())),
+            ]));
+
+        let msg = DlqMessage::from_parts("telemetry.dlq", &properties, b"{}");
+
+        assert_eq!(msg.message_id.as_deref(), Some("msg-1"));
+        assert_eq!(msg.routing_key, "telemetry.dlq");
+        assert_eq!(msg.original_queue.as_deref(), Some("telemetry"));
+        assert_eq!(msg.error_reason.as_deref(), Some("Invalid JSON"));
+        assert_eq!(msg.error_type, "permanent");
+        assert_eq!(msg.retry_count, 3);
+        assert_eq!(msg.replay_count, 1);
+        assert_eq!(msg.event_version.as_deref(), Some("v1"));
+        assert_eq!(msg.failed_at, Some(1_700_000_000));
+        assert_eq!(msg.replayed_at, Some(1_700_000_500));
+        assert_eq!(msg.body, b"{}");
+    }
+
+    #[test]
+    fn test_partial_headers_fall_back_to_defaults() {
+        let properties = BasicProperties::default().with_headers(headers(vec![(
+            ERROR_TYPE_HEADER,
+            AMQPValue::LongString("transient".into()),
+        )]));
+
+        let msg = DlqMessage::from_parts("telemetry.dlq", &properties, b"");
+
+        assert_eq!(msg.error_type, "transient");
+        assert_eq!(msg.message_id, None);
+        assert_eq!(msg.original_queue, None);
+        assert_eq!(msg.error_reason, None);
+        assert_eq!(msg.retry_count, 0);
+        assert_eq!(msg.failed_at, None);
+
+        let msg = DlqMessage::from_parts("telemetry.dlq", &BasicProperties::default(), b"");
+        assert_eq!(msg.error_type, "unknown");
+        assert!(msg.headers.inner().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_headers_are_tolerated() {
+        let properties = BasicProperties::default()
+            .with_timestamp(1_700_000_000_123)
+            .with_headers(headers(vec![
+                (ORIGINAL_QUEUE_HEADER, AMQPValue::LongUInt(42)),
+                (ERROR_TYPE_HEADER, AMQPValue::Boolean(true)),
+                (RETRY_HEADER, AMQPValue::LongString("not-a-number".into())),
+                (REPLAY_COUNT_HEADER, AMQPValue::LongLongInt(-1)),
+                (
+                    ERROR_REASON_HEADER,
+                    AMQPValue::ShortString("timeout".into()),
+                ),
+            ]));
+
+        let msg = DlqMessage::from_parts("telemetry.dlq", &properties, b"");
+
+        assert_eq!(msg.original_queue, None);
+        assert_eq!(msg.error_type, "unknown");
+        assert_eq!(msg.retry_count, 0);
+        assert_eq!(msg.replay_count, 0);
+        assert_eq!(msg.error_reason.as_deref(), Some("timeout"));
+        assert_eq!(msg.failed_at, Some(1_700_000_000));
+    }
+
+    #[test]
+    fn test_numeric_headers_accept_any_integer_encoding() {
+        let table = headers(vec![
+            ("a", AMQPValue::ShortShortUInt(2)),
+            ("b", AMQPValue::LongLongInt(7)),
+            ("c", AMQPValue::Double(4.0)),
+            ("d", AMQPValue::LongString(" 9 ".into())),
+            ("ms", AMQPValue::Double(1_700_000_000_500.0)),
+            ("secs", AMQPValue::Timestamp(1_700_000_000)),
+        ]);
+
+        assert_eq!(header_u32(&table, "a"), Some(2));
+        assert_eq!(header_u32(&table, "b"), Some(7));
+        assert_eq!(header_u32(&table, "c"), Some(4));
+        assert_eq!(header_u32(&table, "d"), Some(9));
+        assert_eq!(header_epoch_secs(&table, "ms"), Some(1_700_000_000));
+        assert_eq!(header_epoch_secs(&table, "secs"), Some(1_700_000_000));
+    }
+}
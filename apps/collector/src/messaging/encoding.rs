@@ -0,0 +1,275 @@
+//! Undoing the `content_encoding` a publisher applied to the message body,
+//! and turning non-JSON `content_type`s and CloudEvents into the JSON the
+//! handler expects.
+
+use flate2::read::MultiGzDecoder;
+use lapin::types::ShortString;
+use lapin::BasicProperties;
+use prost::Message;
+use std::io::Read;
+
+use super::cloudevents::decode_cloud_event;
+use super::handler::HandlerError;
+use super::source::{IncomingMessage, OriginalBody};
+use crate::contracts::TelemetryEvent;
+
+/// Largest body a compressed message may expand to, so a small message
+/// cannot inflate into something that exhausts memory.
+pub const MAX_DECODED_BYTES: u64 = 64 * 1024 * 1024;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// What `content_encoding` is set to once the body has been decoded.
+const IDENTITY: &str = "identity";
+
+/// Rewrites the body into the uncompressed JSON the handler expects.
+///
+/// The properties are updated to match. A converted protobuf or CloudEvent
+/// body is kept as the message's `original`, uncompressed, for signature
+/// checks and for retried and dead-lettered copies, which are converted
+/// again. Failures are permanent: the same bytes would fail the same way on
+/// every retry.
+pub(crate) fn decode_body(message: &mut IncomingMessage) -> Result<(), HandlerError> {
+    decode_content_encoding(message)?;
+    if decode_cloud_event(message, media_type(message).as_deref())? {
+        return Ok(());
+    }
+    decode_content_type(message)
+}
+
+/// Gunzips a `gzip` body. Bodies without a `content_encoding`, or with any
+/// other encoding, are left as they are.
+fn decode_content_encoding(message: &mut IncomingMessage) -> Result<(), HandlerError> {
+    let is_gzip = message
+        .properties
+        .content_encoding()
+        .as_ref()
+        .is_some_and(|encoding| encoding.as_str().eq_ignore_ascii_case("gzip"));
+    if !is_gzip {
+        return Ok(());
+    }
+
+    let mut decoded = Vec::new();
+    MultiGzDecoder::new(message.data.as_slice())
+        .take(MAX_DECODED_BYTES + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| HandlerError::Permanent(format!("Invalid gzip body: {}", e)))?;
+    if decoded.len() as u64 > MAX_DECODED_BYTES {
+        return Err(HandlerError::Permanent(format!(
+            "Gzip body expands beyond {} bytes",
+            MAX_DECODED_BYTES
+        )));
+    }
+
+    message.data = decoded;
+    message.properties = message
+        .properties
+        .clone()
+        .with_content_encoding(ShortString::from(IDENTITY));
+    Ok(())
+}
+
+/// Converts a protobuf [`TelemetryEvent`] into the JSON event it stands for.
+/// JSON bodies, and bodies without a `content_type`, pass through; any other
+/// type is rejected.
+fn decode_content_type(message: &mut IncomingMessage) -> Result<(), HandlerError> {
+    match media_type(message).as_deref() {
+        None | Some("") | Some(JSON_CONTENT_TYPE) => Ok(()),
+        Some(PROTOBUF_CONTENT_TYPE) => {
+            let event = TelemetryEvent::decode(message.data.as_slice()).map_err(|e| {
+                HandlerError::Permanent(format!("Invalid protobuf TelemetryEvent: {}", e))
+            })?;
+            let data = serde_json::to_vec(&event.to_json())
+                .map_err(|e| HandlerError::Permanent(format!("Cannot encode event: {}", e)))?;
+            let properties = message
+                .properties
+                .clone()
+                .with_content_type(ShortString::from(JSON_CONTENT_TYPE));
+            convert(message, data, properties);
+            Ok(())
+        }
+        Some(other) => Err(HandlerError::Permanent(format!(
+            "Unsupported content type `{}`; expected {} or {}",
+            other, JSON_CONTENT_TYPE, PROTOBUF_CONTENT_TYPE
+        ))),
+    }
+}
+
+/// Swaps in the converted body and properties, keeping the ones they replace
+/// as the message's `original`.
+pub(super) fn convert(message: &mut IncomingMessage, data: Vec<u8>, properties: BasicProperties) {
+    message.original = Some(OriginalBody {
+        properties: std::mem::replace(&mut message.properties, properties),
+        data: std::mem::replace(&mut message.data, data),
+    });
+}
+
+/// The lowercased `content_type` without its parameters; those, such as
+/// `; charset=utf-8`, do not change how the body is read.
+fn media_type(message: &IncomingMessage) -> Option<String> {
+    message.properties.content_type().as_ref().map(|content_type| {
+        let essence = content_type.as_str().split(';').next().unwrap_or_default();
+        essence.trim().to_ascii_lowercase()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::handler::MessageHandler;
+    use crate::messaging::test_util::delivery_with_properties;
+    use crate::metrics::Metrics;
+    use crate::processors::telemetry::TelemetryHandler;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use prost_types::value::Kind;
+    use std::io::Write;
+    use tokio_util::sync::CancellationToken;
+
+    fn gzipped(data: &[u8]) -> IncomingMessage {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        delivery_with_properties(
+            1,
+            &encoder.finish().unwrap(),
+            BasicProperties::default().with_content_encoding("gzip".into()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_gzipped_event_is_decoded_and_processed() {
+        let event = br#"{"eventType":"log","payload":{}}"#;
+        let mut message = gzipped(event);
+        assert_ne!(message.data, event);
+
+        decode_body(&mut message).unwrap();
+
+        assert_eq!(message.data, event);
+        assert_eq!(
+            message
+                .properties
+                .content_encoding()
+                .as_ref()
+                .map(|e| e.as_str()),
+            Some(IDENTITY)
+        );
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+        assert!(handler.handle(message, CancellationToken::new()).await.is_ok());
+    }
+
+    #[test]
+    fn test_invalid_gzip_is_permanent() {
+        let mut message = delivery_with_properties(
+            1,
+            b"not gzip",
+            BasicProperties::default().with_content_encoding("gzip".into()),
+        );
+
+        let result = decode_body(&mut message);
+
+        assert!(
+            matches!(result, Err(HandlerError::Permanent(reason)) if reason.starts_with("Invalid gzip body"))
+        );
+        assert_eq!(message.data, b"not gzip");
+    }
+
+    fn protobuf(event: &TelemetryEvent) -> IncomingMessage {
+        delivery_with_properties(
+            1,
+            &event.encode_to_vec(),
+            BasicProperties::default().with_content_type(PROTOBUF_CONTENT_TYPE.into()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_protobuf_event_round_trips_through_handler() {
+        let payload = prost_types::Struct {
+            fields: [
+                ("level", Kind::StringValue("info".into())),
+                ("message", Kind::StringValue("started".into())),
+                ("serviceName", Kind::StringValue("api".into())),
+            ]
+            .into_iter()
+            .map(|(key, kind)| (key.to_string(), prost_types::Value { kind: Some(kind) }))
+            .collect(),
+        };
+        let event = TelemetryEvent {
+            event_id: Some("evt-1".into()),
+            event_type: "telemetry.log.captured".into(),
+            event_version: Some(1),
+            timestamp: None,
+            correlation_id: None,
+            payload: Some(payload),
+        };
+        let mut message = protobuf(&event);
+
+        decode_body(&mut message).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&message.data).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "eventId": "evt-1",
+                "eventType": "telemetry.log.captured",
+                "eventVersion": 1,
+                "payload": {"level": "info", "message": "started", "serviceName": "api"}
+            })
+        );
+        assert_eq!(
+            message
+                .properties
+                .content_type()
+                .as_ref()
+                .map(|t| t.as_str()),
+            Some(JSON_CONTENT_TYPE)
+        );
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+        assert!(handler.handle(message, CancellationToken::new()).await.is_ok());
+    }
+
+    #[test]
+    fn test_unknown_content_type_is_permanent() {
+        let mut message = delivery_with_properties(
+            1,
+            b"<event/>",
+            BasicProperties::default().with_content_type("application/xml".into()),
+        );
+
+        let result = decode_body(&mut message);
+
+        assert!(matches!(
+            result,
+            Err(HandlerError::Permanent(reason))
+                if reason.starts_with("Unsupported content type `application/xml`")
+        ));
+    }
+
+    #[test]
+    fn test_invalid_protobuf_is_permanent() {
+        let mut message = protobuf(&TelemetryEvent::default());
+        message.data = vec![0xff, 0xff, 0xff];
+
+        assert!(matches!(
+            decode_body(&mut message),
+            Err(HandlerError::Permanent(reason)) if reason.starts_with("Invalid protobuf")
+        ));
+    }
+
+    #[test]
+    fn test_unencoded_body_is_untouched() {
+        let mut message = delivery_with_properties(1, b"{}", BasicProperties::default());
+
+        decode_body(&mut message).unwrap();
+
+        assert_eq!(message.data, b"{}");
+        assert!(message.properties.content_encoding().is_none());
+
+        let mut message = delivery_with_properties(
+            1,
+            b"{}",
+            BasicProperties::default().with_content_type("Application/JSON; charset=utf-8".into()),
+        );
+        assert!(decode_body(&mut message).is_ok());
+    }
+}
@@ -0,0 +1,485 @@
+use async_trait::async_trait;
+use lapin::types::ShortString;
+use lapin::BasicProperties;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::consumer::{event_version, UNKNOWN_VERSION};
+use super::correlation::correlation_id;
+use super::handler::{HandlerError, MessageHandler};
+use super::source::{IncomingMessage, NoopAck, Source, SourceError};
+use crate::adapters::sink::{Event, MultiSink, Sink};
+use crate::metrics::Metrics;
+
+const DONE_DIR: &str = "done";
+const FAILED_DIR: &str = "failed";
+
+/// Reads messages spooled as one-file-per-message into a local directory.
+///
+/// Files are picked up in file-name order. Hidden files (leading `.`) are
+/// skipped so producers can write to `.name.tmp` and rename atomically once
+/// the message is complete. Settled files are moved into `done/` or `failed/`
+/// next to the spool; files left in place are retried on the next pass.
+pub struct LocalFileSource {
+    dir: PathBuf,
+    routing_key: String,
+    pending: VecDeque<PathBuf>,
+    in_flight: HashMap<u64, PathBuf>,
+    next_tag: u64,
+    scanned: bool,
+}
+
+impl LocalFileSource {
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        routing_key: impl Into<String>,
+    ) -> Result<Self, SourceError> {
+        let dir = dir.into();
+        for sub in [DONE_DIR, FAILED_DIR] {
+            std::fs::create_dir_all(dir.join(sub)).map_err(|e| {
+                SourceError::Io(format!("cannot prepare {}: {}", dir.join(sub).display(), e))
+            })?;
+        }
+
+        Ok(Self {
+            dir,
+            routing_key: routing_key.into(),
+            pending: VecDeque::new(),
+            in_flight: HashMap::new(),
+            next_tag: 1,
+            scanned: false,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves the file behind `delivery_tag` into `done/`.
+    pub fn complete(&mut self, delivery_tag: u64) -> Result<(), SourceError> {
+        self.settle(delivery_tag, DONE_DIR)
+    }
+
+    /// Moves the file behind `delivery_tag` into `failed/` so it is never retried.
+    pub fn fail(&mut self, delivery_tag: u64) -> Result<(), SourceError> {
+        self.settle(delivery_tag, FAILED_DIR)
+    }
+
+    /// Leaves the file in the spool so the next pass picks it up again.
+    pub fn release(&mut self, delivery_tag: u64) {
+        self.in_flight.remove(&delivery_tag);
+    }
+
+    fn settle(&mut self, delivery_tag: u64, target: &str) -> Result<(), SourceError> {
+        let Some(path) = self.in_flight.remove(&delivery_tag) else {
+            return Err(SourceError::Io(format!(
+                "unknown delivery tag {}",
+                delivery_tag
+            )));
+        };
+        let file_name = path.file_name().unwrap_or_default();
+        let destination = self.dir.join(target).join(file_name);
+
+        std::fs::rename(&path, &destination).map_err(|e| {
+            SourceError::Io(format!(
+                "cannot move {} to {}: {}",
+                path.display(),
+                target,
+                e
+            ))
+        })
+    }
+
+    fn scan(&mut self) -> Result<(), SourceError> {
+        let entries = std::fs::read_dir(&self.dir)
+            .map_err(|e| SourceError::Io(format!("cannot read {}: {}", self.dir.display(), e)))?;
+
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .map(|entry| entry.path())
+            .collect();
+        files.sort();
+
+        self.pending = files.into();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Source for LocalFileSource {
+    fn name(&self) -> &str {
+        "local-spool"
+    }
+
+    /// Yields every file present at the start of a pass, then `None`.
+    /// The following call starts a new pass over the directory. Messages are
+    /// settled by delivery tag through `complete`, `fail` and `release`, so
+    /// their ack handle does nothing.
+    async fn next_message(&mut self) -> Option<Result<IncomingMessage, SourceError>> {
+        if !self.scanned {
+            self.scanned = true;
+            if let Err(e) = self.scan() {
+                return Some(Err(e));
+            }
+        }
+
+        let Some(path) = self.pending.pop_front() else {
+            self.scanned = false;
+            return None;
+        };
+
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) => {
+                return Some(Err(SourceError::Io(format!(
+                    "cannot read {}: {}",
+                    path.display(),
+                    e
+                ))));
+            }
+        };
+
+        let delivery_tag = self.next_tag;
+        self.next_tag += 1;
+
+        let message_id = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.in_flight.insert(delivery_tag, path);
+
+        Some(Ok(IncomingMessage {
+            delivery_tag,
+            routing_key: self.routing_key.clone(),
+            redelivered: false,
+            properties: BasicProperties::default().with_message_id(ShortString::from(message_id)),
+            data,
+            original: None,
+            acker: Arc::new(NoopAck),
+        }))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpoolPassStats {
+    pub processed: u64,
+    pub failed: u64,
+    pub deferred: u64,
+    pub discarded: u64,
+}
+
+/// Runs one pass over the spool through `handler`, writing each handled
+/// message to `sinks` like the consumer does before it acks.
+///
+/// Successes and discarded messages are moved to `done/`, permanent failures to `failed/`, and
+/// transient failures, including a sink that cannot take the event, stay in
+/// the spool for the next pass. There is no retry budget here: a file keeps
+/// being retried for as long as the fallback runs.
+pub async fn process_spool_pass(
+    source: &mut LocalFileSource,
+    handler: &dyn MessageHandler,
+    sinks: &MultiSink,
+    metrics: &Metrics,
+) -> SpoolPassStats {
+    let mut stats = SpoolPassStats::default();
+    let source_name = source.name().to_string();
+
+    while let Some(next) = source.next_message().await {
+        let delivery = match next {
+            Ok(delivery) => delivery,
+            Err(e) => {
+                error!(error = %e, spool = %source.dir().display(), "Failed to read spooled message");
+                continue;
+            }
+        };
+        let delivery_tag = delivery.delivery_tag;
+        let routing_key = delivery.routing_key.clone();
+        let version = event_version(&delivery.properties)
+            .unwrap_or_else(|| UNKNOWN_VERSION.to_string());
+        let correlation_id = correlation_id(&delivery.properties).unwrap_or_default();
+        let data = delivery.data.clone();
+
+        // A spool pass has no deadline, so its handlers are never cancelled.
+        let result = match handler.handle(delivery, CancellationToken::new()).await {
+            Ok(outcome) => {
+                let event = Event {
+                    routing_key: &routing_key,
+                    version: &version,
+                    payload: &data,
+                    delivery_tag,
+                    correlation_id: &correlation_id,
+                };
+                sinks.write(&event).await.map(|()| outcome).map_err(HandlerError::from)
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(outcome) => {
+                metrics.record_processed(&source_name, &routing_key, &version, &outcome.labels);
+                metrics.mark_processed_at(SystemTime::now());
+                if let Err(e) = source.complete(delivery_tag) {
+                    error!(error = %e, delivery_tag, "Failed to mark spooled message done");
+                }
+                stats.processed += 1;
+            }
+            Err(
+                HandlerError::Transient { reason: err, .. }
+                | HandlerError::Retry { reason: err, .. },
+            ) => {
+                metrics
+                    .messages_failed_total
+                    .with_label_values(&[&source_name, "transient", &version])
+                    .inc();
+                warn!(delivery_tag, error = %err, "Transient error, leaving spooled message for next pass");
+                source.release(delivery_tag);
+                stats.deferred += 1;
+            }
+            Err(HandlerError::Discard { reason }) => {
+                metrics.messages_discarded_total.inc();
+                info!(delivery_tag, reason = %reason, "Spooled message discarded by handler");
+                if let Err(e) = source.complete(delivery_tag) {
+                    error!(error = %e, delivery_tag, "Failed to mark spooled message done");
+                }
+                stats.discarded += 1;
+            }
+            Err(HandlerError::Permanent(err)) => {
+                metrics
+                    .messages_failed_total
+                    .with_label_values(&[&source_name, "permanent", &version])
+                    .inc();
+                error!(delivery_tag, error = %err, "Permanent error, moving spooled message to failed/");
+                if let Err(e) = source.fail(delivery_tag) {
+                    error!(error = %e, delivery_tag, "Failed to mark spooled message failed");
+                }
+                stats.failed += 1;
+            }
+        }
+    }
+
+    if stats != SpoolPassStats::default() {
+        info!(
+            spool = %source.dir().display(),
+            processed = stats.processed,
+            failed = stats.failed,
+            deferred = stats.deferred,
+            discarded = stats.discarded,
+            "Local spool pass complete"
+        );
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::sink::WriteError;
+    use crate::adapters::sqlite::SqliteSink;
+    use crate::adapters::LocalStore;
+    use crate::messaging::handler::HandlerOutcome;
+    use crate::metrics::MetricsConfig;
+
+    struct PayloadHandler;
+
+    #[async_trait]
+    impl MessageHandler for PayloadHandler {
+        async fn handle(
+            &self,
+            delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            match delivery.data.as_slice() {
+                b"ok" => Ok(HandlerOutcome::default()),
+                b"labelled" => Ok(HandlerOutcome::default()
+                    .with_label("tenant", "acme")
+                    .with_label("unlisted", "ignored")),
+                b"transient" => Err(HandlerError::transient("downstream busy")),
+                b"ping" => Err(HandlerError::Discard {
+                    reason: "health ping".to_string(),
+                }),
+                _ => Err(HandlerError::Permanent("bad payload".to_string())),
+            }
+        }
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().unwrap().is_file())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_processes_spooled_messages_through_handler() {
+        let spool = tempfile::tempdir().unwrap();
+        std::fs::write(spool.path().join("001.json"), b"ok").unwrap();
+        std::fs::write(spool.path().join("002.json"), b"transient").unwrap();
+        std::fs::write(spool.path().join("003.json"), b"garbage").unwrap();
+        std::fs::write(spool.path().join(".004.json.tmp"), b"ok").unwrap();
+
+        let metrics = Metrics::new().unwrap();
+        let mut source = LocalFileSource::open(spool.path(), "telemetry").unwrap();
+
+        let stats = process_spool_pass(&mut source, &PayloadHandler, &MultiSink::new(), &metrics).await;
+
+        assert_eq!(
+            stats,
+            SpoolPassStats {
+                processed: 1,
+                failed: 1,
+                deferred: 1,
+                discarded: 0,
+            }
+        );
+        assert_eq!(file_names(&spool.path().join(DONE_DIR)), vec!["001.json"]);
+        assert_eq!(file_names(&spool.path().join(FAILED_DIR)), vec!["003.json"]);
+        assert_eq!(file_names(spool.path()), vec![".004.json.tmp", "002.json"]);
+
+        let stats = process_spool_pass(&mut source, &PayloadHandler, &MultiSink::new(), &metrics).await;
+        assert_eq!(stats.deferred, 1);
+        assert_eq!(stats.processed, 0);
+    }
+
+    struct UnavailableSink;
+
+    #[async_trait]
+    impl Sink for UnavailableSink {
+        async fn write(&self, _event: &Event<'_>) -> Result<(), WriteError> {
+            Err(WriteError::Transient("store unavailable".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handled_messages_are_stored_before_they_are_done() {
+        let spool = tempfile::tempdir().unwrap();
+        std::fs::write(spool.path().join("001.json"), b"ok").unwrap();
+        std::fs::write(spool.path().join("002.json"), b"garbage").unwrap();
+        let store = Arc::new(SqliteSink::open(&spool.path().join("events.db")).unwrap());
+        let mut sinks = MultiSink::new();
+        sinks.push(Arc::new(LocalStore::Direct(store.clone())));
+
+        let metrics = Metrics::new().unwrap();
+        let mut source = LocalFileSource::open(spool.path(), "telemetry").unwrap();
+        process_spool_pass(&mut source, &PayloadHandler, &sinks, &metrics).await;
+
+        let stored = store.recent(10).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].routing_key, "telemetry");
+        assert_eq!(stored[0].version, "v1");
+        assert_eq!(stored[0].payload, "ok");
+        assert_eq!(file_names(&spool.path().join(DONE_DIR)), vec!["001.json"]);
+
+        // A message the sinks cannot take stays in the spool.
+        std::fs::write(spool.path().join("003.json"), b"ok").unwrap();
+        sinks.push(Arc::new(UnavailableSink));
+        let stats = process_spool_pass(&mut source, &PayloadHandler, &sinks, &metrics).await;
+
+        assert_eq!(stats.deferred, 1);
+        assert_eq!(stats.processed, 0);
+        assert!(file_names(spool.path()).contains(&"003.json".to_string()));
+        assert_eq!(file_names(&spool.path().join(DONE_DIR)), vec!["001.json"]);
+    }
+
+    #[tokio::test]
+    async fn test_discarded_messages_are_done_not_failed() {
+        let spool = tempfile::tempdir().unwrap();
+        std::fs::write(spool.path().join("001.json"), b"ping").unwrap();
+
+        let metrics = Metrics::new().unwrap();
+        let mut source = LocalFileSource::open(spool.path(), "telemetry").unwrap();
+
+        let stats = process_spool_pass(&mut source, &PayloadHandler, &MultiSink::new(), &metrics).await;
+
+        assert_eq!(stats.discarded, 1);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(file_names(&spool.path().join(DONE_DIR)), vec!["001.json"]);
+        assert!(file_names(&spool.path().join(FAILED_DIR)).is_empty());
+        assert_eq!(metrics.messages_discarded_total.get(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_processing_advances_last_processed_timestamp() {
+        let spool = tempfile::tempdir().unwrap();
+        std::fs::write(spool.path().join("001.json"), b"ok").unwrap();
+        let metrics = Metrics::new().unwrap();
+        let mut source = LocalFileSource::open(spool.path(), "telemetry").unwrap();
+
+        // Starts at the time the metrics were created.
+        let started = metrics.last_message_processed_timestamp.get();
+        assert!(started > 0.0);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+        process_spool_pass(&mut source, &PayloadHandler, &MultiSink::new(), &metrics).await;
+
+        assert!(metrics.last_message_processed_timestamp.get() > started);
+    }
+
+    #[tokio::test]
+    async fn test_handler_labels_appear_on_processed_count() {
+        let spool = tempfile::tempdir().unwrap();
+        std::fs::write(spool.path().join("001.json"), b"labelled").unwrap();
+        std::fs::write(spool.path().join("002.json"), b"ok").unwrap();
+
+        let metrics = Metrics::with_config(&MetricsConfig {
+            handler_labels: vec!["tenant".to_string()],
+            ..MetricsConfig::default()
+        })
+        .unwrap();
+        let mut source = LocalFileSource::open(spool.path(), "telemetry").unwrap();
+
+        let stats = process_spool_pass(&mut source, &PayloadHandler, &MultiSink::new(), &metrics).await;
+        assert_eq!(stats.processed, 2);
+
+        let family = metrics
+            .registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "collector_messages_processed_total")
+            .unwrap();
+        let tenants: Vec<(String, f64)> = family
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let labels = metric.get_label();
+                assert!(labels.iter().all(|label| label.get_name() != "unlisted"));
+                let tenant = labels
+                    .iter()
+                    .find(|label| label.get_name() == "tenant")
+                    .unwrap();
+                (tenant.get_value().to_string(), metric.get_counter().get_value())
+            })
+            .collect();
+        assert_eq!(tenants, vec![(String::new(), 1.0), ("acme".to_string(), 1.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_carry_routing_key_and_file_name() {
+        let spool = tempfile::tempdir().unwrap();
+        std::fs::write(spool.path().join("a.json"), br#"{"eventType":"log"}"#).unwrap();
+
+        let mut source = LocalFileSource::open(spool.path(), "telemetry").unwrap();
+        let delivery = source.next_message().await.unwrap().unwrap();
+
+        assert_eq!(delivery.routing_key, "telemetry");
+        assert_eq!(
+            delivery
+                .properties
+                .message_id()
+                .as_ref()
+                .map(|id| id.as_str()),
+            Some("a.json")
+        );
+        assert_eq!(delivery.data, br#"{"eventType":"log"}"#);
+        assert!(source.next_message().await.is_none());
+    }
+}
@@ -1,16 +1,112 @@
 use async_trait::async_trait;
-use lapin::message::Delivery;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use super::source::IncomingMessage;
+use crate::contracts::ProcessingError;
 
 #[async_trait]
 pub trait MessageHandler: Send + Sync {
-    async fn handle(&self, delivery: Delivery) -> Result<(), HandlerError>;
+    /// Handles one message. `cancel` is cancelled when the handler timeout
+    /// passes or the consumer's shutdown grace period runs out; long-running
+    /// handlers should watch it and return early, typically with a transient
+    /// error so the message is retried.
+    async fn handle(
+        &self,
+        delivery: IncomingMessage,
+        cancel: CancellationToken,
+    ) -> Result<HandlerOutcome, HandlerError>;
+}
+
+/// What a handler reports about a message it processed successfully.
+///
+/// `labels` are dimensions the handler derived from the message, such as a
+/// tenant or event subtype, recorded on `collector_messages_processed_total`.
+/// Only label names listed in `HANDLER_LABELS` are recorded; the rest are
+/// ignored, so a handler cannot grow the metric's label set on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandlerOutcome {
+    pub labels: Vec<(String, String)>,
+}
+
+impl HandlerOutcome {
+    pub fn with_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((name.into(), value.into()));
+        self
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum HandlerError {
-    #[error("Transient error (will retry): {0}")]
-    Transient(String),
+    /// Retried after the consumer's configured backoff delay. `class` picks
+    /// a per-class retry policy; without one the consumer's default applies.
+    #[error("Transient error (will retry): {reason}")]
+    Transient {
+        reason: String,
+        class: Option<String>,
+    },
+
+    /// Retried after `after` instead of the configured delay, for handlers that
+    /// know when the downstream will recover (e.g. from a `Retry-After` header).
+    #[error("Transient error (will retry in {after:?}): {reason}")]
+    Retry { after: Duration, reason: String },
 
     #[error("Permanent error (will not retry): {0}")]
     Permanent(String),
+
+    /// Acked and dropped: neither retried nor dead-lettered, so nothing of the
+    /// message is kept. Only for messages that are harmless to lose, such as
+    /// health pings routed to the wrong queue; never return it for anything
+    /// whose loss would be data loss, use `Permanent` to keep it in the DLQ.
+    #[error("Discarded: {reason}")]
+    Discard { reason: String },
+}
+
+impl HandlerError {
+    pub fn transient(reason: impl Into<String>) -> Self {
+        Self::Transient {
+            reason: reason.into(),
+            class: None,
+        }
+    }
+
+    /// Tags a transient error with the failure class its retry policy is
+    /// looked up by, e.g. `network` or `rate_limit`. Other errors are
+    /// returned unchanged.
+    pub fn with_class(self, class: impl Into<String>) -> Self {
+        match self {
+            Self::Transient { reason, .. } => Self::Transient {
+                reason,
+                class: Some(class.into()),
+            },
+            other => other,
+        }
+    }
+
+    /// The failure class of a transient error, if it was tagged with one.
+    pub fn class(&self) -> Option<&str> {
+        match self {
+            Self::Transient { class, .. } => class.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The delay the handler asked for, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Retry { after, .. } => Some(*after),
+            _ => None,
+        }
+    }
+}
+
+/// Lets handlers propagate the domain error with `?`. A transient error
+/// carries no class, so it is retried under the default policy.
+impl From<ProcessingError> for HandlerError {
+    fn from(error: ProcessingError) -> Self {
+        match error {
+            ProcessingError::Transient { reason } => Self::transient(reason),
+            ProcessingError::Permanent { reason } => Self::Permanent(reason),
+        }
+    }
 }
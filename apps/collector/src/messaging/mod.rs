@@ -1,9 +1,59 @@
+pub mod ack_window;
+pub mod broker;
 pub mod channel;
+pub mod circuit_breaker;
+pub mod cloudevents;
 pub mod connection;
 pub mod consumer;
+pub mod correlation;
+pub mod dedup;
+pub mod dlq;
+pub mod encoding;
+pub mod file_source;
 pub mod handler;
+pub mod prefetch;
+pub mod quarantine;
+pub mod rate_limit;
+pub mod reanimator;
+pub mod recovery;
+pub mod replay;
+pub mod result_cache;
+pub mod retry_policy;
+pub mod signature;
+pub mod source;
+pub mod topology;
+pub mod trace_context;
+pub mod worker_pool;
+#[cfg(test)]
+pub(crate) mod test_util;
 
-pub use channel::{ChannelError, ChannelProvider};
-pub use connection::{ConnectionError, RabbitMqConnection};
-pub use consumer::{Consumer, ConsumerError};
-pub use handler::{HandlerError, MessageHandler};
+pub use ack_window::AckWindow;
+pub use broker::ConsumerBroker;
+pub use channel::{publish_confirmed, ChannelError, ChannelProvider, PublishError};
+pub use circuit_breaker::{BreakerState, CircuitBreaker};
+pub use connection::{
+    default_connection_name, reconnect_delay, ConnectionError, RabbitMqConnection, TlsConfig,
+};
+pub use consumer::{Consumer, ConsumerBuilder, ConsumerError, DeliveryMode, RetryStrategy};
+pub use correlation::{correlation_id, ensure_correlation_id};
+pub use dedup::DedupCache;
+pub use dlq::DlqMessage;
+pub use file_source::{process_spool_pass, LocalFileSource, SpoolPassStats};
+pub use handler::{HandlerError, HandlerOutcome, MessageHandler};
+pub use prefetch::{PrefetchAdjustment, PrefetchSettings, PrefetchTuner};
+pub use quarantine::Quarantine;
+pub use rate_limit::RateLimiter;
+pub use reanimator::{DlqReanimator, ReanimatorSettings};
+pub use recovery::{declare_queues, verify_topology, ConnectionBroker, DriftPolicy};
+pub use replay::{replay_dlq, ReplayError, ReplayOptions, ReplayStats};
+pub use result_cache::CachingHandler;
+pub use retry_policy::{RetryPolicies, RetryPolicy};
+pub use signature::{SignatureFailureMode, SignatureVerifier};
+pub use source::{
+    AckHandle, Acknowledge, AmqpSource, IncomingMessage, NoopAck, Settlement, Source, SourceError,
+    VecSource,
+};
+pub use topology::{
+    ExchangeDeclaration, Overflow, QueueMode, QueueRole, QueueTopology, QueueType, TopologySpec,
+};
+pub use worker_pool::WorkerPool;
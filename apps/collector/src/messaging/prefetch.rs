@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+/// Bounds and goal of adaptive prefetch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefetchSettings {
+    /// Prefetch the channel was opened with.
+    pub base: u16,
+    pub min: u16,
+    pub max: u16,
+    /// Mean processing time the prefetch is tuned towards.
+    pub target_latency: Duration,
+    /// Time between adjustments.
+    pub interval: Duration,
+}
+
+/// A prefetch change, with the mean processing time that caused it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrefetchAdjustment {
+    pub from: u16,
+    pub to: u16,
+    pub mean_latency: Duration,
+}
+
+/// Picks a channel prefetch from how long messages took to process since
+/// the last adjustment.
+///
+/// Processing slower than the target halves the prefetch, so fewer messages
+/// sit buffered behind a slow handler. Processing faster than half the
+/// target grows it by a quarter, keeping the consumer busy. In between, and
+/// while no message was processed, it stays put. It never leaves
+/// `min..=max`.
+pub struct PrefetchTuner {
+    settings: PrefetchSettings,
+    current: u16,
+    /// Processing duration count and sum at the last observation.
+    last: Option<(u64, f64)>,
+}
+
+impl PrefetchTuner {
+    pub fn new(settings: PrefetchSettings) -> Self {
+        Self {
+            current: settings.base.clamp(settings.min, settings.max),
+            settings,
+            last: None,
+        }
+    }
+
+    pub fn current(&self) -> u16 {
+        self.current
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.settings.interval
+    }
+
+    /// Feeds the running count and sum of the processing duration histogram,
+    /// returning the change to make, if any. The first call only records
+    /// where the histogram stands.
+    pub fn observe(&mut self, count: u64, sum: f64) -> Option<PrefetchAdjustment> {
+        let (last_count, last_sum) = self.last.replace((count, sum))?;
+        let processed = count.checked_sub(last_count).filter(|&n| n > 0)?;
+        let mean_latency = Duration::from_secs_f64(((sum - last_sum) / processed as f64).max(0.0));
+
+        let PrefetchSettings {
+            min,
+            max,
+            target_latency,
+            ..
+        } = self.settings;
+        let next = if mean_latency > target_latency {
+            (self.current / 2).max(min)
+        } else if mean_latency < target_latency / 2 {
+            self.current
+                .saturating_add((self.current / 4).max(1))
+                .min(max)
+        } else {
+            self.current
+        };
+        if next == self.current {
+            return None;
+        }
+        let adjustment = PrefetchAdjustment {
+            from: self.current,
+            to: next,
+            mean_latency,
+        };
+        self.current = next;
+        Some(adjustment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuner() -> PrefetchTuner {
+        PrefetchTuner::new(PrefetchSettings {
+            base: 20,
+            min: 2,
+            max: 40,
+            target_latency: Duration::from_millis(100),
+            interval: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn test_prefetch_shrinks_when_latency_rises() {
+        let mut tuner = tuner();
+        assert_eq!(tuner.observe(100, 5.0), None);
+
+        // 10 messages at 50ms each: within the target band.
+        assert_eq!(tuner.observe(110, 5.5), None);
+        // 10 more at 300ms each.
+        let adjustment = tuner.observe(120, 8.5).unwrap();
+
+        assert_eq!((adjustment.from, adjustment.to), (20, 10));
+        assert_eq!(adjustment.mean_latency, Duration::from_millis(300));
+        assert_eq!(tuner.current(), 10);
+    }
+
+    #[test]
+    fn test_prefetch_stays_within_bounds() {
+        let mut tuner = tuner();
+        tuner.observe(0, 0.0);
+
+        let mut count = 0;
+        let mut sum = 0.0;
+        for _ in 0..10 {
+            count += 10;
+            sum += 10.0;
+            tuner.observe(count, sum);
+        }
+        assert_eq!(tuner.current(), 2);
+
+        for _ in 0..20 {
+            count += 10;
+            sum += 0.01;
+            tuner.observe(count, sum);
+        }
+        assert_eq!(tuner.current(), 40);
+    }
+
+    #[test]
+    fn test_idle_interval_leaves_prefetch_alone() {
+        let mut tuner = tuner();
+        tuner.observe(10, 10.0);
+
+        assert_eq!(tuner.observe(10, 10.0), None);
+        assert_eq!(tuner.current(), 20);
+    }
+}
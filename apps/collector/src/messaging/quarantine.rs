@@ -0,0 +1,97 @@
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// Recent failures of each message, across every queue and attempt, for
+/// dead-lettering a message that keeps failing in ways its retry count does
+/// not record.
+///
+/// Panics, handler timeouts and broker redeliveries count as failures, and a
+/// successful run forgets the message. The retry header cannot catch these
+/// loops: a broker redelivery does not bump it, and reanimation and replay
+/// reset it. Counts live in memory only, for the `capacity` most recently
+/// failed messages.
+pub struct Quarantine {
+    failures: Mutex<LruCache<String, u32>>,
+    threshold: u32,
+}
+
+impl Quarantine {
+    pub fn new(capacity: NonZeroUsize, threshold: u32) -> Self {
+        Self {
+            failures: Mutex::new(LruCache::new(capacity)),
+            threshold,
+        }
+    }
+
+    /// The key a message is counted under: its `message_id`, or the SHA-256
+    /// of its body when it has none.
+    pub fn key(message_id: Option<&str>, body: &[u8]) -> String {
+        match message_id {
+            Some(id) => format!("id:{}", id),
+            None => format!("sha256:{}", hex::encode(Sha256::digest(body))),
+        }
+    }
+
+    /// Counts a failure of `key` and returns how many it has had.
+    pub fn record_failure(&self, key: &str) -> u32 {
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.get(key).copied().unwrap_or(0) + 1;
+        failures.put(key.to_string(), count);
+        count
+    }
+
+    /// Whether `key` has failed at least `threshold` times.
+    pub fn is_quarantined(&self, key: &str) -> bool {
+        self.failures
+            .lock()
+            .unwrap()
+            .peek(key)
+            .is_some_and(|&count| count >= self.threshold)
+    }
+
+    pub fn forget(&self, key: &str) {
+        self.failures.lock().unwrap().pop(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_failures_cross_the_threshold() {
+        let quarantine = Quarantine::new(NonZeroUsize::new(10).unwrap(), 3);
+        let key = Quarantine::key(Some("msg-1"), b"{}");
+
+        for attempt in 1..=2 {
+            assert_eq!(quarantine.record_failure(&key), attempt);
+            assert!(!quarantine.is_quarantined(&key));
+        }
+        quarantine.record_failure(&key);
+        assert!(quarantine.is_quarantined(&key));
+
+        // Other messages, and the same one after a success, start clean.
+        let other = Quarantine::key(Some("msg-2"), b"{}");
+        assert!(!quarantine.is_quarantined(&other));
+        quarantine.forget(&key);
+        assert!(!quarantine.is_quarantined(&key));
+    }
+
+    #[test]
+    fn test_messages_without_id_are_keyed_by_body() {
+        assert_eq!(
+            Quarantine::key(None, b"payload"),
+            Quarantine::key(None, b"payload")
+        );
+        assert_ne!(
+            Quarantine::key(None, b"payload"),
+            Quarantine::key(None, b"other")
+        );
+        assert_ne!(
+            Quarantine::key(Some("payload"), b"payload"),
+            Quarantine::key(None, b"payload")
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A token bucket capping how many messages are handled per second, shared
+/// by every consumer it is given to.
+///
+/// The bucket holds a single token, so messages are spaced evenly `1 / rate`
+/// apart and no burst exceeds the rate, even after an idle period. A waiting
+/// message stays unacked, so prefetch holds back further deliveries.
+pub struct RateLimiter {
+    /// Tokens added per second.
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Negative while waiters have reserved tokens that are not refilled yet.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter letting through `per_second` messages a second; at least 1.
+    pub fn new(per_second: u32) -> Self {
+        Self {
+            rate: per_second.max(1) as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: 1.0,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token, waiting until one is available, and returns how long
+    /// that took. Waiters are served in the order they called.
+    pub async fn acquire(&self) -> Duration {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.rate;
+            bucket.tokens = (bucket.tokens + refill).min(1.0) - 1.0;
+            bucket.refilled = now;
+            if bucket.tokens >= 0.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64(-bucket.tokens / self.rate)
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_spaces_messages_at_the_rate() {
+        let limiter = RateLimiter::new(10);
+        let start = Instant::now();
+
+        let waits: Vec<Duration> =
+            futures::future::join_all((0..21).map(|_| limiter.acquire())).await;
+
+        // The first goes straight through, every later one 100ms after the last.
+        assert_eq!(waits[0], Duration::ZERO);
+        assert_eq!(waits[20], Duration::from_secs(2));
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_time_does_not_build_up_a_burst() {
+        let limiter = Arc::new(RateLimiter::new(2));
+        limiter.acquire().await;
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        assert_eq!(limiter.acquire().await, Duration::ZERO);
+        assert_eq!(limiter.acquire().await, Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,325 @@
+use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicGetOptions, BasicNackOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{BasicProperties, Channel};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+
+use super::channel::publish_confirmed;
+use super::consumer::{
+    ERROR_REASON_HEADER, ERROR_TYPE_HEADER, ORIGINAL_QUEUE_HEADER, RETRY_HEADER,
+};
+use super::dlq::{republish_properties, DlqMessage, REANIMATION_COUNT_HEADER};
+use crate::metrics::Metrics;
+
+/// DLQ error types that may succeed if tried again later.
+/// Permanent failures are never reanimated, whatever the configuration.
+pub const REANIMATABLE_ERROR_TYPES: &[&str] = &["transient", "throttled"];
+
+#[derive(Debug, Clone)]
+pub struct ReanimatorSettings {
+    /// Minimum time a message must have spent in the DLQ before it is moved back.
+    pub cooldown: Duration,
+    /// Time between passes over the DLQ.
+    pub interval: Duration,
+    /// Upper bound on messages moved back per second.
+    pub rate_per_sec: u32,
+    /// Reanimations allowed per message before it stays in the DLQ for good.
+    pub max_reanimations: u32,
+    /// Messages inspected per pass; they are held unacked until the pass ends.
+    pub scan_limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reanimation {
+    Reanimate,
+    /// Eligible, but still inside its cooldown.
+    Cooling,
+    /// Must stay in the DLQ.
+    Never(&'static str),
+}
+
+/// Decides whether a dead-lettered message goes back to its queue.
+///
+/// A message without a dead-letter timestamp is treated as having cooled
+/// down, since its age cannot be known and it would otherwise never move.
+pub fn decide(message: &DlqMessage, now_secs: u64, settings: &ReanimatorSettings) -> Reanimation {
+    if !REANIMATABLE_ERROR_TYPES.contains(&message.error_type.as_str()) {
+        return Reanimation::Never("error type is not reanimatable");
+    }
+    if message.reanimation_count >= settings.max_reanimations {
+        return Reanimation::Never("reanimation limit reached");
+    }
+    match message.failed_at {
+        Some(failed_at) if now_secs.saturating_sub(failed_at) < settings.cooldown.as_secs() => {
+            Reanimation::Cooling
+        }
+        _ => Reanimation::Reanimate,
+    }
+}
+
+/// Properties for a reanimated message: the retry budget starts over, the
+/// failure metadata is dropped and the reanimation count goes up by one.
+pub(crate) fn reanimated_properties(
+    message: &DlqMessage,
+    properties: &BasicProperties,
+) -> BasicProperties {
+    let mut inner = message.headers.inner().clone();
+    inner.remove(ERROR_REASON_HEADER);
+    inner.remove(ERROR_TYPE_HEADER);
+    inner.remove(ORIGINAL_QUEUE_HEADER);
+    inner.insert(RETRY_HEADER.into(), AMQPValue::LongUInt(0));
+    inner.insert(
+        REANIMATION_COUNT_HEADER.into(),
+        AMQPValue::LongUInt(message.reanimation_count + 1),
+    );
+
+    republish_properties(message, properties).with_headers(FieldTable::from(inner))
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReanimationPassStats {
+    pub reanimated: u64,
+    pub cooling: u64,
+    pub kept: u64,
+}
+
+/// Background task that moves cooled-down transient failures from a DLQ
+/// back to the queue they failed on.
+///
+/// Each pass pulls up to `scan_limit` messages with `basic_get`, publishes the
+/// eligible ones back and requeues the rest, which puts them back at their
+/// original position so the DLQ order is preserved.
+pub struct DlqReanimator {
+    channel: Channel,
+    queue_name: String,
+    settings: ReanimatorSettings,
+    metrics: Arc<Metrics>,
+    shutdown: Arc<Notify>,
+}
+
+impl DlqReanimator {
+    pub fn new(
+        channel: Channel,
+        queue_name: String,
+        settings: ReanimatorSettings,
+        metrics: Arc<Metrics>,
+        shutdown: Arc<Notify>,
+    ) -> Self {
+        Self {
+            channel,
+            queue_name,
+            settings,
+            metrics,
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) {
+        info!(
+            queue = %self.queue_name,
+            cooldown_secs = self.settings.cooldown.as_secs(),
+            rate_per_sec = self.settings.rate_per_sec,
+            max_reanimations = self.settings.max_reanimations,
+            "DLQ reanimator started"
+        );
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.notified() => {
+                    info!(queue = %self.queue_name, "DLQ reanimator stopping");
+                    return;
+                }
+                _ = tokio::time::sleep(self.settings.interval) => {
+                    if let Err(e) = self.pass().await {
+                        error!(error = %e, queue = %self.queue_name, "DLQ reanimation pass failed");
+                    }
+                }
+            }
+        }
+    }
+
+    async fn pass(&self) -> Result<ReanimationPassStats, lapin::Error> {
+        let dlq_name = format!("{}.dlq", self.queue_name);
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let throttle = Duration::from_secs(1) / self.settings.rate_per_sec.max(1);
+
+        let mut stats = ReanimationPassStats::default();
+        let mut held: Vec<Delivery> = Vec::new();
+
+        while held.len() < self.settings.scan_limit {
+            let Some(message) = self
+                .channel
+                .basic_get(&dlq_name, BasicGetOptions { no_ack: false })
+                .await?
+            else {
+                break;
+            };
+            let delivery = message.delivery;
+            let parsed = DlqMessage::from_delivery(&delivery);
+
+            match decide(&parsed, now_secs, &self.settings) {
+                Reanimation::Reanimate => {
+                    if let Err(e) = self.reanimate(&parsed, &delivery).await {
+                        warn!(error = %e, message_id = ?parsed.message_id, "Failed to reanimate message, leaving it in the DLQ");
+                        held.push(delivery);
+                        continue;
+                    }
+                    stats.reanimated += 1;
+                    tokio::time::sleep(throttle).await;
+                }
+                Reanimation::Cooling => {
+                    stats.cooling += 1;
+                    held.push(delivery);
+                }
+                Reanimation::Never(reason) => {
+                    debug!(message_id = ?parsed.message_id, reason, "Message stays in the DLQ");
+                    stats.kept += 1;
+                    held.push(delivery);
+                }
+            }
+        }
+
+        for delivery in held {
+            delivery
+                .acker
+                .nack(BasicNackOptions {
+                    multiple: false,
+                    requeue: true,
+                })
+                .await?;
+        }
+
+        if stats.reanimated > 0 {
+            info!(
+                dlq = %dlq_name,
+                reanimated = stats.reanimated,
+                cooling = stats.cooling,
+                kept = stats.kept,
+                "DLQ reanimation pass complete"
+            );
+        }
+
+        Ok(stats)
+    }
+
+    async fn reanimate(
+        &self,
+        message: &DlqMessage,
+        delivery: &Delivery,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let target = message
+            .original_queue
+            .clone()
+            .unwrap_or_else(|| self.queue_name.clone());
+
+        publish_confirmed(
+            &self.channel,
+            &target,
+            &message.body,
+            reanimated_properties(message, &delivery.properties),
+        )
+        .await?;
+        delivery.acker.ack(BasicAckOptions::default()).await?;
+
+        self.metrics.messages_reanimated_total.inc();
+        info!(
+            message_id = ?message.message_id,
+            queue = %target,
+            reanimation_count = message.reanimation_count + 1,
+            "Message reanimated from DLQ"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ReanimatorSettings {
+        ReanimatorSettings {
+            cooldown: Duration::from_secs(600),
+            interval: Duration::from_secs(60),
+            rate_per_sec: 10,
+            max_reanimations: 2,
+            scan_limit: 100,
+        }
+    }
+
+    fn dead_lettered(error_type: &str, reanimations: u32, failed_at: u64) -> DlqMessage {
+        let mut headers = FieldTable::default();
+        headers.insert(
+            ERROR_TYPE_HEADER.into(),
+            AMQPValue::LongString(error_type.into()),
+        );
+        headers.insert(
+            ERROR_REASON_HEADER.into(),
+            AMQPValue::LongString("boom".into()),
+        );
+        headers.insert(
+            ORIGINAL_QUEUE_HEADER.into(),
+            AMQPValue::LongString("telemetry".into()),
+        );
+        headers.insert(RETRY_HEADER.into(), AMQPValue::LongUInt(3));
+        headers.insert(
+            REANIMATION_COUNT_HEADER.into(),
+            AMQPValue::LongUInt(reanimations),
+        );
+
+        let properties = BasicProperties::default()
+            .with_timestamp(failed_at)
+            .with_headers(headers);
+        DlqMessage::from_parts("telemetry.dlq", &properties, b"{}")
+    }
+
+    #[test]
+    fn test_permanent_failures_are_never_reanimated() {
+        let now = 1_700_000_000;
+        let transient = dead_lettered("transient", 0, now - 3600);
+        let throttled = dead_lettered("throttled", 1, now - 3600);
+        let permanent = dead_lettered("permanent", 0, now - 3600);
+        let unknown = dead_lettered("unknown", 0, now - 3600);
+
+        assert_eq!(decide(&transient, now, &settings()), Reanimation::Reanimate);
+        assert_eq!(decide(&throttled, now, &settings()), Reanimation::Reanimate);
+        assert!(matches!(
+            decide(&permanent, now, &settings()),
+            Reanimation::Never(_)
+        ));
+        assert!(matches!(
+            decide(&unknown, now, &settings()),
+            Reanimation::Never(_)
+        ));
+
+        let fresh = dead_lettered("transient", 0, now - 10);
+        assert_eq!(decide(&fresh, now, &settings()), Reanimation::Cooling);
+
+        let exhausted = dead_lettered("transient", 2, now - 3600);
+        assert!(matches!(
+            decide(&exhausted, now, &settings()),
+            Reanimation::Never(_)
+        ));
+    }
+
+    #[test]
+    fn test_reanimated_message_starts_a_fresh_retry_budget() {
+        let message = dead_lettered("transient", 1, 1_700_000_000);
+        let original = BasicProperties::default().with_headers(message.headers.clone());
+
+        let properties = reanimated_properties(&message, &original);
+        let reanimated = DlqMessage::from_parts("telemetry", &properties, &message.body);
+
+        assert_eq!(reanimated.retry_count, 0);
+        assert_eq!(reanimated.reanimation_count, 2);
+        assert_eq!(reanimated.error_reason, None);
+        assert_eq!(reanimated.original_queue, None);
+        assert_eq!(reanimated.error_type, "unknown");
+    }
+}
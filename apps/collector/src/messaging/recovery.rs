@@ -0,0 +1,536 @@
+use async_trait::async_trait;
+use lapin::options::{
+    ExchangeBindOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+    QueueDeleteOptions,
+};
+use lapin::protocol::{AMQPErrorKind, AMQPSoftError};
+use lapin::types::FieldTable;
+use lapin::{Channel, Connection};
+use std::str::FromStr;
+use tracing::{error, info, warn};
+
+use super::consumer::ConsumerError;
+use super::topology::{QueueDeclaration, QueueTopology, TopologyOperation};
+use crate::metrics::Metrics;
+
+/// What to do when a queue on the broker no longer matches its declaration,
+/// typically because an operator changed it while the collector was
+/// disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Refuse to consume against the changed topology.
+    Strict,
+    /// Delete the drifted queue if it is empty and declare it again as expected.
+    Redeclare,
+}
+
+impl DriftPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "strict",
+            Self::Redeclare => "redeclare",
+        }
+    }
+}
+
+impl FromStr for DriftPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "redeclare" => Ok(Self::Redeclare),
+            other => Err(format!(
+                "unknown policy `{}`, expected strict or redeclare",
+                other
+            )),
+        }
+    }
+}
+
+/// The broker calls needed to check a topology.
+#[async_trait]
+pub trait TopologyBroker: Send + Sync {
+    /// Declares `queue` with its expected arguments, which succeeds only if an
+    /// existing queue of that name matches them.
+    async fn declare_queue(&self, queue: &QueueDeclaration) -> Result<(), DeclareError>;
+
+    /// Deletes `name`, failing if it still holds messages.
+    async fn delete_empty_queue(&self, name: &str) -> Result<(), DeclareError>;
+
+    /// Declares an exchange or binding, which succeeds only if an existing
+    /// exchange of that name has the same type and flags.
+    async fn apply(&self, operation: TopologyOperation<'_>) -> Result<(), DeclareError>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeclareError {
+    /// The broker has the queue or exchange with different arguments or flags.
+    #[error("differs from its declaration: {0}")]
+    Drift(String),
+
+    #[error("{0}")]
+    Broker(String),
+}
+
+/// Declares every queue in `topology`, dead-letter targets first.
+pub async fn declare_queues(
+    broker: &dyn TopologyBroker,
+    topology: &QueueTopology,
+) -> Result<(), ConsumerError> {
+    for queue in &topology.queues {
+        broker.declare_queue(queue).await.map_err(|e| {
+            ConsumerError::SetupFailed(format!("{} setup failed: {}", queue.name, e))
+        })?;
+    }
+    Ok(())
+}
+
+/// Re-declares every queue in `topology` and applies `policy` to any that
+/// drifted, then declares its exchanges and bindings again. Returns the names
+/// of the queues that were redeclared.
+///
+/// AMQP has no way to read a queue's arguments back, so drift is detected the
+/// way the broker reports it: a declaration with different arguments is
+/// refused with `PRECONDITION_FAILED`. A drifted exchange fails verification
+/// under either policy, since deleting it would also drop bindings the
+/// collector does not own.
+pub async fn verify_topology(
+    broker: &dyn TopologyBroker,
+    topology: &QueueTopology,
+    policy: DriftPolicy,
+    metrics: &Metrics,
+) -> Result<Vec<String>, ConsumerError> {
+    let mut redeclared = Vec::new();
+
+    for queue in &topology.queues {
+        let detail = match broker.declare_queue(queue).await {
+            Ok(()) => continue,
+            Err(DeclareError::Drift(detail)) => detail,
+            Err(DeclareError::Broker(e)) => {
+                return Err(ConsumerError::SetupFailed(format!(
+                    "{} verification failed: {}",
+                    queue.name, e
+                )));
+            }
+        };
+
+        metrics
+            .topology_drift_total
+            .with_label_values(&[&queue.name])
+            .inc();
+        warn!(
+            queue = %queue.name,
+            detail = %detail,
+            policy = policy.as_str(),
+            "Broker topology drifted from the declared topology"
+        );
+
+        match policy {
+            DriftPolicy::Strict => {
+                error!(
+                    queue = %queue.name,
+                    "Refusing to consume against a changed topology (TOPOLOGY_DRIFT_POLICY=strict)"
+                );
+                return Err(ConsumerError::SetupFailed(format!(
+                    "{} drifted from its declaration: {}",
+                    queue.name, detail
+                )));
+            }
+            DriftPolicy::Redeclare => {
+                redeclare(broker, queue).await?;
+                info!(queue = %queue.name, "Drifted queue redeclared");
+                redeclared.push(queue.name.clone());
+            }
+        }
+    }
+
+    for operation in topology.exchange_operations() {
+        match broker.apply(operation).await {
+            Ok(()) => {}
+            Err(DeclareError::Drift(detail)) => {
+                error!(
+                    operation = %operation,
+                    detail = %detail,
+                    "Broker exchange drifted from the declared topology"
+                );
+                return Err(ConsumerError::SetupFailed(format!(
+                    "{} drifted from its declaration: {}",
+                    operation, detail
+                )));
+            }
+            Err(DeclareError::Broker(e)) => {
+                return Err(ConsumerError::SetupFailed(format!(
+                    "{} verification failed: {}",
+                    operation, e
+                )));
+            }
+        }
+    }
+
+    Ok(redeclared)
+}
+
+async fn redeclare(
+    broker: &dyn TopologyBroker,
+    queue: &QueueDeclaration,
+) -> Result<(), ConsumerError> {
+    let failed = |e: DeclareError| {
+        ConsumerError::SetupFailed(format!("{} could not be redeclared: {}", queue.name, e))
+    };
+    broker
+        .delete_empty_queue(&queue.name)
+        .await
+        .map_err(failed)?;
+    broker.declare_queue(queue).await.map_err(failed)
+}
+
+/// Checks a topology against a live broker.
+///
+/// A refused declaration closes the channel it was made on, so every call
+/// gets a short-lived channel of its own.
+pub struct ConnectionBroker<'a> {
+    connection: &'a Connection,
+}
+
+impl<'a> ConnectionBroker<'a> {
+    pub fn new(connection: &'a Connection) -> Self {
+        Self { connection }
+    }
+}
+
+#[async_trait]
+impl TopologyBroker for ConnectionBroker<'_> {
+    async fn declare_queue(&self, queue: &QueueDeclaration) -> Result<(), DeclareError> {
+        let channel = self
+            .connection
+            .create_channel()
+            .await
+            .map_err(broker_error)?;
+        channel
+            .queue_declare(
+                &queue.name,
+                QueueDeclareOptions {
+                    durable: queue.durable,
+                    ..Default::default()
+                },
+                queue.arguments(),
+            )
+            .await
+            .map_err(declare_error)?;
+        let _ = channel.close(200, "Topology verified").await;
+        Ok(())
+    }
+
+    async fn delete_empty_queue(&self, name: &str) -> Result<(), DeclareError> {
+        let channel = self
+            .connection
+            .create_channel()
+            .await
+            .map_err(broker_error)?;
+        channel
+            .queue_delete(
+                name,
+                QueueDeleteOptions {
+                    if_empty: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(broker_error)?;
+        let _ = channel.close(200, "Queue deleted").await;
+        Ok(())
+    }
+
+    async fn apply(&self, operation: TopologyOperation<'_>) -> Result<(), DeclareError> {
+        let channel = self
+            .connection
+            .create_channel()
+            .await
+            .map_err(broker_error)?;
+        channel.apply(operation).await?;
+        let _ = channel.close(200, "Topology verified").await;
+        Ok(())
+    }
+}
+
+/// Declares on a consumer's own channel, for the initial setup where a
+/// refused declaration is fatal anyway.
+#[async_trait]
+impl TopologyBroker for Channel {
+    async fn declare_queue(&self, queue: &QueueDeclaration) -> Result<(), DeclareError> {
+        self.queue_declare(
+            &queue.name,
+            QueueDeclareOptions {
+                durable: queue.durable,
+                ..Default::default()
+            },
+            queue.arguments(),
+        )
+        .await
+        .map(|_| ())
+        .map_err(declare_error)
+    }
+
+    async fn delete_empty_queue(&self, name: &str) -> Result<(), DeclareError> {
+        self.queue_delete(
+            name,
+            QueueDeleteOptions {
+                if_empty: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .map(|_| ())
+        .map_err(broker_error)
+    }
+
+    async fn apply(&self, operation: TopologyOperation<'_>) -> Result<(), DeclareError> {
+        match operation {
+            TopologyOperation::DeclareExchange(exchange) => {
+                self.exchange_declare(
+                    &exchange.name,
+                    exchange.exchange_kind(),
+                    ExchangeDeclareOptions {
+                        durable: exchange.durable,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await
+            }
+            TopologyOperation::BindExchange(binding) => {
+                self.exchange_bind(
+                    &binding.destination,
+                    &binding.source,
+                    &binding.routing_key,
+                    ExchangeBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+            }
+            TopologyOperation::BindQueue(binding) => {
+                self.queue_bind(
+                    &binding.queue,
+                    &binding.exchange,
+                    &binding.routing_key,
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+            }
+        }
+        .map_err(declare_error)
+    }
+}
+
+fn declare_error(error: lapin::Error) -> DeclareError {
+    match &error {
+        lapin::Error::ProtocolError(amqp)
+            if matches!(
+                amqp.kind(),
+                AMQPErrorKind::Soft(AMQPSoftError::PRECONDITIONFAILED)
+            ) =>
+        {
+            DeclareError::Drift(amqp.get_message().to_string())
+        }
+        _ => broker_error(error),
+    }
+}
+
+fn broker_error(error: lapin::Error) -> DeclareError {
+    DeclareError::Broker(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::{ExchangeDeclaration, QueueRole};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// A broker holding queues and exchanges by name, refusing declarations
+    /// whose arguments or type differ the way RabbitMQ does.
+    #[derive(Default)]
+    struct FakeBroker {
+        queues: Mutex<HashMap<String, FieldTable>>,
+        deleted: Mutex<Vec<String>>,
+        exchanges: Mutex<HashMap<String, String>>,
+        bindings: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl TopologyBroker for FakeBroker {
+        async fn declare_queue(&self, queue: &QueueDeclaration) -> Result<(), DeclareError> {
+            let mut queues = self.queues.lock().unwrap();
+            let expected = queue.arguments();
+            match queues.get(&queue.name) {
+                Some(existing) if *existing != expected => Err(DeclareError::Drift(format!(
+                    "inequivalent arg for queue '{}'",
+                    queue.name
+                ))),
+                Some(_) => Ok(()),
+                None => {
+                    queues.insert(queue.name.clone(), expected);
+                    Ok(())
+                }
+            }
+        }
+
+        async fn delete_empty_queue(&self, name: &str) -> Result<(), DeclareError> {
+            self.queues.lock().unwrap().remove(name);
+            self.deleted.lock().unwrap().push(name.to_string());
+            Ok(())
+        }
+
+        async fn apply(&self, operation: TopologyOperation<'_>) -> Result<(), DeclareError> {
+            let TopologyOperation::DeclareExchange(exchange) = operation else {
+                self.bindings.lock().unwrap().push(operation.to_string());
+                return Ok(());
+            };
+            let mut exchanges = self.exchanges.lock().unwrap();
+            match exchanges.get(&exchange.name) {
+                Some(kind) if *kind != exchange.kind => Err(DeclareError::Drift(format!(
+                    "inequivalent arg 'type' for exchange '{}'",
+                    exchange.name
+                ))),
+                Some(_) => Ok(()),
+                None => {
+                    exchanges.insert(exchange.name.clone(), exchange.kind.clone());
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn topology_with_exchange() -> QueueTopology {
+        QueueTopology::for_queue("telemetry", 5000).with_exchange(
+            ExchangeDeclaration {
+                name: "telemetry.events".to_string(),
+                kind: "topic".to_string(),
+                durable: true,
+            },
+            &["log.#".to_string()],
+        )
+    }
+
+    /// The broker as left by an operator who changed the retry TTL while the
+    /// collector was disconnected.
+    fn broker_with_changed_retry_ttl(topology: &QueueTopology) -> FakeBroker {
+        let broker = FakeBroker::default();
+        for queue in &topology.queues {
+            let mut changed = queue.clone();
+            if queue.name == "telemetry.retry" {
+                changed.message_ttl_ms = Some(30_000);
+            }
+            broker
+                .queues
+                .lock()
+                .unwrap()
+                .insert(queue.name.clone(), changed.arguments());
+        }
+        broker
+    }
+
+    #[tokio::test]
+    async fn test_strict_policy_fails_on_drifted_retry_ttl() {
+        let metrics = Metrics::new().unwrap();
+        let topology = QueueTopology::for_queue("telemetry", 5000);
+        let broker = broker_with_changed_retry_ttl(&topology);
+
+        let result = verify_topology(&broker, &topology, DriftPolicy::Strict, &metrics).await;
+
+        assert!(
+            matches!(result, Err(ConsumerError::SetupFailed(reason)) if reason.contains("telemetry.retry"))
+        );
+        assert!(broker.deleted.lock().unwrap().is_empty());
+        assert_eq!(
+            metrics
+                .topology_drift_total
+                .with_label_values(&["telemetry.retry"])
+                .get(),
+            1.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redeclare_policy_restores_drifted_retry_ttl() {
+        let metrics = Metrics::new().unwrap();
+        let topology = QueueTopology::for_queue("telemetry", 5000);
+        let broker = broker_with_changed_retry_ttl(&topology);
+
+        let redeclared = verify_topology(&broker, &topology, DriftPolicy::Redeclare, &metrics)
+            .await
+            .unwrap();
+
+        assert_eq!(redeclared, vec!["telemetry.retry".to_string()]);
+        assert_eq!(*broker.deleted.lock().unwrap(), vec!["telemetry.retry"]);
+        let retry = topology.queue(QueueRole::Retry).unwrap();
+        assert_eq!(
+            broker.queues.lock().unwrap().get("telemetry.retry"),
+            Some(&retry.arguments())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_declares_exchange_and_bindings_again() {
+        let metrics = Metrics::new().unwrap();
+        let topology = topology_with_exchange();
+        let broker = FakeBroker::default();
+
+        verify_topology(&broker, &topology, DriftPolicy::Strict, &metrics)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            broker.exchanges.lock().unwrap().get("telemetry.events"),
+            Some(&"topic".to_string())
+        );
+        assert_eq!(
+            *broker.bindings.lock().unwrap(),
+            vec!["binding telemetry.events -> telemetry (log.#)"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drifted_exchange_fails_under_either_policy() {
+        let metrics = Metrics::new().unwrap();
+        let topology = topology_with_exchange();
+
+        for policy in [DriftPolicy::Strict, DriftPolicy::Redeclare] {
+            let broker = FakeBroker::default();
+            broker
+                .exchanges
+                .lock()
+                .unwrap()
+                .insert("telemetry.events".to_string(), "fanout".to_string());
+
+            let result = verify_topology(&broker, &topology, policy, &metrics).await;
+
+            assert!(
+                matches!(result, Err(ConsumerError::SetupFailed(reason)) if reason.contains("exchange telemetry.events"))
+            );
+            assert!(broker.bindings.lock().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_each_consumed_queue_gets_its_own_topology() {
+        let broker = FakeBroker::default();
+        let topologies = ["telemetry", "audit"].map(|queue| QueueTopology::for_queue(queue, 5000));
+
+        for topology in &topologies {
+            declare_queues(&broker, topology).await.unwrap();
+        }
+
+        let queues = broker.queues.lock().unwrap();
+        assert_eq!(queues.len(), 6);
+        for topology in &topologies {
+            for queue in &topology.queues {
+                assert_eq!(queues.get(&queue.name), Some(&queue.arguments()));
+            }
+        }
+        let dlq = topologies[1].queue(QueueRole::DeadLetter).unwrap();
+        assert_eq!(dlq.name, "audit.dlq");
+    }
+}
@@ -0,0 +1,181 @@
+//! One-off replay of a queue's DLQ back onto the queue, for the
+//! `collector dlq-replay` command.
+
+use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicGetOptions, BasicNackOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{BasicProperties, Channel};
+use tracing::info;
+
+use super::channel::{publish_confirmed, PublishError};
+use super::consumer::{ERROR_REASON_HEADER, ERROR_TYPE_HEADER, RETRY_HEADER};
+use super::dlq::{republish_properties, DlqMessage, REPLAY_COUNT_HEADER, REPLAY_TIMESTAMP_HEADER};
+use crate::metrics::queue_depth::QueueDepthSource;
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+    /// Stop after this many messages; `None` drains the DLQ.
+    pub max: Option<u64>,
+    /// Log what would be replayed and leave every message in the DLQ.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// Messages republished, or that would have been on a dry run.
+    pub replayed: u64,
+    /// Messages left in the DLQ afterwards.
+    pub remaining: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("Broker error: {0}")]
+    Broker(#[from] lapin::Error),
+
+    #[error("Failed to republish message: {0}")]
+    Publish(#[from] PublishError),
+
+    #[error("Cannot read depth of {queue}: {reason}")]
+    Depth { queue: String, reason: String },
+}
+
+/// Properties for a replayed message: the failure metadata and retry count
+/// are dropped so it gets a fresh retry budget, and the replay headers are
+/// written the same way the API's replay endpoint writes them. An enveloped
+/// message gets its original properties back, see `republish_properties`.
+pub(crate) fn replay_properties(
+    message: &DlqMessage,
+    properties: &BasicProperties,
+    now_millis: u64,
+) -> BasicProperties {
+    let mut inner = message.headers.inner().clone();
+    inner.remove(ERROR_REASON_HEADER);
+    inner.remove(ERROR_TYPE_HEADER);
+    inner.remove(RETRY_HEADER);
+    inner.insert(
+        REPLAY_COUNT_HEADER.into(),
+        AMQPValue::LongUInt(message.replay_count + 1),
+    );
+    inner.insert(
+        REPLAY_TIMESTAMP_HEADER.into(),
+        AMQPValue::Timestamp(now_millis),
+    );
+
+    republish_properties(message, properties).with_headers(FieldTable::from(inner))
+}
+
+/// Moves messages from `<queue_name>.dlq` back to `queue_name`, oldest first.
+///
+/// Each message is acked only once the broker has confirmed its republish,
+/// so a failure part way leaves it in the DLQ. On a dry run the messages are
+/// held unacked and requeued at the end, which keeps their DLQ order.
+pub async fn replay_dlq(
+    channel: &Channel,
+    queue_name: &str,
+    options: &ReplayOptions,
+) -> Result<ReplayStats, ReplayError> {
+    let dlq_name = format!("{}.dlq", queue_name);
+    let mut stats = ReplayStats::default();
+    let mut held: Vec<Delivery> = Vec::new();
+
+    while options.max.is_none_or(|max| stats.replayed < max) {
+        let Some(message) = channel
+            .basic_get(&dlq_name, BasicGetOptions { no_ack: false })
+            .await?
+        else {
+            break;
+        };
+        let delivery = message.delivery;
+        let parsed = DlqMessage::from_delivery(&delivery);
+
+        if options.dry_run {
+            info!(
+                message_id = ?parsed.message_id,
+                error_type = %parsed.error_type,
+                error_reason = ?parsed.error_reason,
+                "Would replay message"
+            );
+            held.push(delivery);
+        } else {
+            let properties = replay_properties(&parsed, &delivery.properties, now_millis());
+            publish_confirmed(channel, queue_name, &parsed.body, properties).await?;
+            delivery.acker.ack(BasicAckOptions::default()).await?;
+            info!(message_id = ?parsed.message_id, queue = %queue_name, "Message replayed from DLQ");
+        }
+        stats.replayed += 1;
+    }
+
+    for delivery in held {
+        delivery
+            .acker
+            .nack(BasicNackOptions {
+                multiple: false,
+                requeue: true,
+            })
+            .await?;
+    }
+
+    stats.remaining =
+        u64::from(
+            channel
+                .message_count(&dlq_name)
+                .await
+                .map_err(|reason| ReplayError::Depth {
+                    queue: dlq_name.clone(),
+                    reason,
+                })?,
+        );
+    if options.dry_run {
+        // The held messages are back in the DLQ and counted again.
+        stats.remaining = stats.remaining.saturating_sub(stats.replayed);
+    }
+
+    Ok(stats)
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::consumer::ORIGINAL_QUEUE_HEADER;
+
+    #[test]
+    fn test_replay_strips_failure_headers_and_counts_replays() {
+        let mut headers = FieldTable::default();
+        headers.insert(
+            ERROR_REASON_HEADER.into(),
+            AMQPValue::LongString("boom".into()),
+        );
+        headers.insert(
+            ERROR_TYPE_HEADER.into(),
+            AMQPValue::LongString("transient".into()),
+        );
+        headers.insert(RETRY_HEADER.into(), AMQPValue::LongUInt(3));
+        headers.insert(REPLAY_COUNT_HEADER.into(), AMQPValue::LongUInt(1));
+        headers.insert(
+            ORIGINAL_QUEUE_HEADER.into(),
+            AMQPValue::LongString("telemetry".into()),
+        );
+        let original = BasicProperties::default().with_headers(headers);
+        let message = DlqMessage::from_parts("telemetry.dlq", &original, b"{}");
+
+        let properties = replay_properties(&message, &original, 1_700_000_500_000);
+        let replayed = properties.headers().clone().unwrap_or_default();
+
+        for header in [ERROR_REASON_HEADER, ERROR_TYPE_HEADER, RETRY_HEADER] {
+            assert!(!replayed.inner().contains_key(header), "{header} kept");
+        }
+        assert!(replayed.inner().contains_key(ORIGINAL_QUEUE_HEADER));
+        let parsed = DlqMessage::from_parts("telemetry", &properties, b"{}");
+        assert_eq!(parsed.replay_count, 2);
+        assert_eq!(parsed.replayed_at, Some(1_700_000_500));
+        assert_eq!(parsed.retry_count, 0);
+    }
+}
@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use super::handler::{HandlerError, HandlerOutcome, MessageHandler};
+use super::source::IncomingMessage;
+use crate::metrics::Metrics;
+
+type PayloadHash = [u8; 32];
+
+/// Skips the wrapped handler for payloads it already processed successfully.
+///
+/// Payloads are keyed by their SHA-256, so two deliveries with byte-identical
+/// bodies are treated as the same work regardless of headers or routing key.
+/// A hit is reported as success and the message is acked without running the
+/// handler again.
+///
+/// Only use this with idempotent, deterministic handlers: any side effect the
+/// handler would have performed for the duplicate is skipped.
+pub struct CachingHandler {
+    inner: Arc<dyn MessageHandler>,
+    completed: Mutex<LruCache<PayloadHash, ()>>,
+    metrics: Arc<Metrics>,
+}
+
+impl CachingHandler {
+    pub fn new(
+        inner: Arc<dyn MessageHandler>,
+        capacity: NonZeroUsize,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            inner,
+            completed: Mutex::new(LruCache::new(capacity)),
+            metrics,
+        }
+    }
+
+    fn hash(payload: &[u8]) -> PayloadHash {
+        Sha256::digest(payload).into()
+    }
+}
+
+#[async_trait]
+impl MessageHandler for CachingHandler {
+    async fn handle(
+        &self,
+        delivery: IncomingMessage,
+        cancel: CancellationToken,
+    ) -> Result<HandlerOutcome, HandlerError> {
+        let key = Self::hash(&delivery.data);
+
+        if self.completed.lock().unwrap().get(&key).is_some() {
+            debug!(
+                delivery_tag = delivery.delivery_tag,
+                "Payload already processed, skipping handler"
+            );
+            self.metrics.cache_hits_total.inc();
+            return Ok(HandlerOutcome::default());
+        }
+
+        let outcome = self.inner.handle(delivery, cancel).await?;
+        self.completed.lock().unwrap().put(key, ());
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::test_util::delivery;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingHandler {
+        calls: AtomicUsize,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl MessageHandler for CountingHandler {
+        async fn handle(
+            &self,
+            _delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                return Err(HandlerError::transient("downstream busy"));
+            }
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    fn caching(inner: Arc<CountingHandler>, metrics: Arc<Metrics>) -> CachingHandler {
+        CachingHandler::new(inner, NonZeroUsize::new(16).unwrap(), metrics)
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_payload_hits_cache_and_skips_handler() {
+        let inner = Arc::new(CountingHandler {
+            calls: AtomicUsize::new(0),
+            fail: false,
+        });
+        let metrics = Metrics::new().unwrap();
+        let handler = caching(inner.clone(), metrics.clone());
+
+        handler.handle(delivery(1, b"{\"a\":1}"), CancellationToken::new()).await.unwrap();
+        handler.handle(delivery(2, b"{\"a\":1}"), CancellationToken::new()).await.unwrap();
+        handler.handle(delivery(3, b"{\"a\":2}"), CancellationToken::new()).await.unwrap();
+
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(metrics.cache_hits_total.get(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_payloads_are_not_cached() {
+        let inner = Arc::new(CountingHandler {
+            calls: AtomicUsize::new(0),
+            fail: true,
+        });
+        let metrics = Metrics::new().unwrap();
+        let handler = caching(inner.clone(), metrics.clone());
+
+        assert!(handler.handle(delivery(1, b"same"), CancellationToken::new()).await.is_err());
+        assert!(handler.handle(delivery(2, b"same"), CancellationToken::new()).await.is_err());
+
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(metrics.cache_hits_total.get(), 0.0);
+    }
+}
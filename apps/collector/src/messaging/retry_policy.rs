@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::handler::HandlerError;
+
+/// How many times, and how soon, a transient failure is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries before the message is dead-lettered.
+    pub max_retries: u32,
+    /// Delay before the first retry; each further retry doubles it.
+    pub base_delay: Duration,
+}
+
+/// Retry policies keyed by the class a handler tags its transient errors with.
+pub type RetryPolicies = HashMap<String, RetryPolicy>;
+
+/// The policy `error` is retried under: the one for its class, or `default`
+/// when it has no class or its class has no policy of its own.
+pub fn policy_for(
+    error: &HandlerError,
+    policies: &RetryPolicies,
+    default: RetryPolicy,
+) -> RetryPolicy {
+    error
+        .class()
+        .and_then(|class| policies.get(class))
+        .copied()
+        .unwrap_or(default)
+}
+
+/// Parses comma-separated `class:max_retries:base_delay_ms` entries, e.g.
+/// `network:5:1000,rate_limit:10:30000`.
+pub fn parse_retry_policies(raw: &str) -> Result<RetryPolicies, String> {
+    let mut policies = RetryPolicies::new();
+    for entry in raw
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let parts: Vec<&str> = entry.split(':').map(str::trim).collect();
+        let [class, max_retries, base_delay_ms] = parts[..] else {
+            return Err(format!(
+                "`{}` is not class:max_retries:base_delay_ms",
+                entry
+            ));
+        };
+        if class.is_empty() {
+            return Err(format!("`{}` has no class", entry));
+        }
+        let max_retries = max_retries
+            .parse()
+            .map_err(|_| format!("`{}` has an invalid max_retries", entry))?;
+        let base_delay_ms = base_delay_ms
+            .parse()
+            .map_err(|_| format!("`{}` has an invalid base_delay_ms", entry))?;
+        let policy = RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(base_delay_ms),
+        };
+        if policies.insert(class.to_string(), policy).is_some() {
+            return Err(format!("class `{}` is listed more than once", class));
+        }
+    }
+    Ok(policies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::consumer::dead_letter_type;
+
+    #[test]
+    fn test_classes_use_their_own_retry_limits() {
+        let policies = parse_retry_policies("network:5:1000, rate_limit:1:30000").unwrap();
+        let default = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(5000),
+        };
+        let network = HandlerError::transient("connection reset").with_class("network");
+        let rate_limit = HandlerError::transient("429").with_class("rate_limit");
+        let unclassified = HandlerError::transient("flaky");
+
+        let dead_letter_after = |error: &HandlerError| {
+            let policy = policy_for(error, &policies, default);
+            (0..=10)
+                .find(|&retry_count| {
+                    dead_letter_type(error, retry_count, policy.max_retries).is_some()
+                })
+                .unwrap()
+        };
+
+        assert_eq!(dead_letter_after(&network), 5);
+        assert_eq!(dead_letter_after(&rate_limit), 1);
+        assert_eq!(dead_letter_after(&unclassified), 3);
+        assert_eq!(
+            policy_for(&rate_limit, &policies, default).base_delay,
+            Duration::from_secs(30)
+        );
+        let unknown = HandlerError::transient("?").with_class("disk");
+        assert_eq!(policy_for(&unknown, &policies, default), default);
+    }
+
+    #[test]
+    fn test_malformed_policies_are_rejected() {
+        assert!(parse_retry_policies("").unwrap().is_empty());
+        assert!(parse_retry_policies("network:5").is_err());
+        assert!(parse_retry_policies("network:five:1000").is_err());
+        assert!(parse_retry_policies(":5:1000").is_err());
+        assert!(parse_retry_policies("network:5:1000,network:1:10").is_err());
+    }
+}
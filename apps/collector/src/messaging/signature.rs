@@ -0,0 +1,300 @@
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use lapin::types::AMQPValue;
+use sha2::Sha256;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use super::handler::{HandlerError, HandlerOutcome, MessageHandler};
+use super::source::IncomingMessage;
+use crate::metrics::Metrics;
+
+/// Header carrying the hex-encoded HMAC-SHA256 of the message body.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// What to do with a message whose signature cannot be checked at all,
+/// because the secret is missing or could not be loaded.
+///
+/// A signature that is checked and does not match is always rejected,
+/// whatever the mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureFailureMode {
+    /// Reject the message. Nothing unverified reaches the handler.
+    FailClosed,
+    /// Process the message without verification.
+    FailOpen,
+}
+
+impl SignatureFailureMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FailClosed => "fail_closed",
+            Self::FailOpen => "fail_open",
+        }
+    }
+}
+
+impl FromStr for SignatureFailureMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail_closed" => Ok(Self::FailClosed),
+            "fail_open" => Ok(Self::FailOpen),
+            other => Err(format!(
+                "unknown mode `{}`, expected fail_closed or fail_open",
+                other
+            )),
+        }
+    }
+}
+
+/// Verifies the HMAC signature of every message before the wrapped handler sees it.
+///
+/// The key is resolved once, up front. If that failed, `key` holds the reason
+/// and every message is handled according to `failure_mode`.
+pub struct SignatureVerifier {
+    inner: Arc<dyn MessageHandler>,
+    key: Result<Vec<u8>, String>,
+    failure_mode: SignatureFailureMode,
+    metrics: Arc<Metrics>,
+}
+
+impl SignatureVerifier {
+    pub fn new(
+        inner: Arc<dyn MessageHandler>,
+        key: Result<Vec<u8>, String>,
+        failure_mode: SignatureFailureMode,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            inner,
+            key,
+            failure_mode,
+            metrics,
+        }
+    }
+
+    /// Checks the body as published, before any protobuf or CloudEvent
+    /// conversion, but after gzip is undone.
+    fn verify(&self, key: &[u8], delivery: &IncomingMessage) -> Result<(), Verification> {
+        let (properties, data) = match &delivery.original {
+            Some(original) => (&original.properties, &original.data),
+            None => (&delivery.properties, &delivery.data),
+        };
+        let signature = properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(SIGNATURE_HEADER))
+            .and_then(|value| match value {
+                AMQPValue::LongString(s) => Some(s.to_string()),
+                _ => None,
+            })
+            .ok_or_else(|| Verification::Invalid("missing signature header".to_string()))?;
+        let signature = hex::decode(signature.trim())
+            .map_err(|_| Verification::Invalid("signature is not valid hex".to_string()))?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)
+            .map_err(|e| Verification::Unverifiable(format!("cannot initialise HMAC: {}", e)))?;
+        mac.update(data);
+        mac.verify_slice(&signature)
+            .map_err(|_| Verification::Invalid("signature mismatch".to_string()))
+    }
+
+    fn unverifiable(&self, reason: &str) -> Result<(), HandlerError> {
+        self.metrics
+            .signature_unverifiable_total
+            .with_label_values(&[self.failure_mode.as_str()])
+            .inc();
+
+        match self.failure_mode {
+            SignatureFailureMode::FailClosed => {
+                error!(
+                    reason,
+                    mode = self.failure_mode.as_str(),
+                    "Cannot verify message signature, rejecting"
+                );
+                Err(HandlerError::transient(format!(
+                    "Signature cannot be verified: {}",
+                    reason
+                )))
+            }
+            SignatureFailureMode::FailOpen => {
+                error!(
+                    reason,
+                    mode = self.failure_mode.as_str(),
+                    "Cannot verify message signature, processing WITHOUT verification"
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+enum Verification {
+    /// The signature was checked and is wrong or absent.
+    Invalid(String),
+    /// The signature could not be checked.
+    Unverifiable(String),
+}
+
+#[async_trait]
+impl MessageHandler for SignatureVerifier {
+    async fn handle(
+        &self,
+        delivery: IncomingMessage,
+        cancel: CancellationToken,
+    ) -> Result<HandlerOutcome, HandlerError> {
+        let outcome = match &self.key {
+            Ok(key) => self.verify(key, &delivery),
+            Err(reason) => Err(Verification::Unverifiable(reason.clone())),
+        };
+
+        match outcome {
+            Ok(()) => {}
+            Err(Verification::Invalid(reason)) => {
+                self.metrics.signature_rejected_total.inc();
+                return Err(HandlerError::Permanent(format!(
+                    "Signature verification failed: {}",
+                    reason
+                )));
+            }
+            Err(Verification::Unverifiable(reason)) => self.unverifiable(&reason)?,
+        }
+
+        self.inner.handle(delivery, cancel).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::test_util::delivery_with_properties;
+    use lapin::types::FieldTable;
+    use lapin::BasicProperties;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingHandler(AtomicUsize);
+
+    #[async_trait]
+    impl MessageHandler for CountingHandler {
+        async fn handle(
+            &self,
+            _delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    fn signed(data: &[u8], key: &[u8]) -> IncomingMessage {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(data);
+        let mut headers = FieldTable::default();
+        headers.insert(
+            SIGNATURE_HEADER.into(),
+            AMQPValue::LongString(hex::encode(mac.finalize().into_bytes()).into()),
+        );
+        delivery_with_properties(1, data, BasicProperties::default().with_headers(headers))
+    }
+
+    fn verifier(
+        key: Result<Vec<u8>, String>,
+        mode: SignatureFailureMode,
+    ) -> (SignatureVerifier, Arc<CountingHandler>, Arc<Metrics>) {
+        let metrics = Metrics::new().unwrap();
+        let inner = Arc::new(CountingHandler(AtomicUsize::new(0)));
+        let verifier = SignatureVerifier::new(inner.clone(), key, mode, metrics.clone());
+        (verifier, inner, metrics)
+    }
+
+    #[tokio::test]
+    async fn test_mismatch_is_rejected_in_either_mode() {
+        for mode in [
+            SignatureFailureMode::FailClosed,
+            SignatureFailureMode::FailOpen,
+        ] {
+            let (verifier, inner, metrics) = verifier(Ok(b"secret".to_vec()), mode);
+
+            verifier
+                .handle(signed(b"payload", b"secret"), CancellationToken::new())
+                .await
+                .unwrap();
+            let result = verifier
+                .handle(signed(b"payload", b"other"), CancellationToken::new())
+                .await;
+
+            assert!(matches!(result, Err(HandlerError::Permanent(_))));
+            assert_eq!(inner.0.load(Ordering::SeqCst), 1);
+            assert_eq!(metrics.signature_rejected_total.get(), 1.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fail_closed_rejects_when_key_is_unavailable() {
+        let (verifier, inner, metrics) = verifier(
+            Err("secret file unreadable".to_string()),
+            SignatureFailureMode::FailClosed,
+        );
+
+        let result = verifier
+            .handle(signed(b"payload", b"secret"), CancellationToken::new())
+            .await;
+
+        assert!(matches!(result, Err(HandlerError::Transient { .. })));
+        assert_eq!(inner.0.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            metrics
+                .signature_unverifiable_total
+                .with_label_values(&["fail_closed"])
+                .get(),
+            1.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fail_open_processes_when_key_is_unavailable() {
+        let (verifier, inner, metrics) = verifier(
+            Err("secret file unreadable".to_string()),
+            SignatureFailureMode::FailOpen,
+        );
+
+        verifier
+            .handle(signed(b"payload", b"secret"), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(inner.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            metrics
+                .signature_unverifiable_total
+                .with_label_values(&["fail_open"])
+                .get(),
+            1.0
+        );
+        assert_eq!(metrics.signature_rejected_total.get(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_cloud_event_is_verified_against_the_published_body() {
+        use crate::messaging::cloudevents::CLOUDEVENTS_CONTENT_TYPE;
+        use crate::messaging::encoding::decode_body;
+
+        let body = br#"{"specversion":"1.0","id":"evt-1","type":"log","source":"/api","data":{}}"#;
+        let mut message = signed(body, b"secret");
+        message.properties = message
+            .properties
+            .clone()
+            .with_content_type(CLOUDEVENTS_CONTENT_TYPE.into());
+        decode_body(&mut message).unwrap();
+        assert_ne!(message.data, body);
+
+        let (verifier, inner, _) = verifier(Ok(b"secret".to_vec()), SignatureFailureMode::FailClosed);
+        verifier.handle(message, CancellationToken::new()).await.unwrap();
+
+        assert_eq!(inner.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,321 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use lapin::acker::Acker;
+use lapin::options::{BasicAckOptions, BasicNackOptions};
+use lapin::{options::BasicConsumeOptions, types::FieldTable, BasicProperties, Channel};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Anything the collector can pull messages from.
+///
+/// Messages are surfaced as [`IncomingMessage`] so every source feeds the same
+/// `MessageHandler` path regardless of the transport the bytes came from.
+#[async_trait]
+pub trait Source: Send {
+    /// Short identifier used in logs and metric labels.
+    fn name(&self) -> &str;
+
+    /// Waits for the next message. `None` means the source has nothing more to give.
+    async fn next_message(&mut self) -> Option<Result<IncomingMessage, SourceError>>;
+}
+
+/// A message as handed to the handler, independent of its transport.
+///
+/// Properties keep the AMQP shape (headers, message id, timestamp) since
+/// retries and dead-lettering republish them to RabbitMQ unchanged.
+#[derive(Clone)]
+pub struct IncomingMessage {
+    /// Identifies the message to its source; unique per source, not globally.
+    pub delivery_tag: u64,
+    pub routing_key: String,
+    /// Set when the source handed this message out before without it being settled.
+    pub redelivered: bool,
+    pub properties: BasicProperties,
+    pub data: Vec<u8>,
+    /// What decoding converted into JSON, once any gzip was undone; `None`
+    /// if the body was not converted. Signatures cover this body.
+    pub original: Option<OriginalBody>,
+    /// Settles the message with the source it came from.
+    pub acker: AckHandle,
+}
+
+/// A body and the properties that went with it, as the publisher sent them.
+#[derive(Debug, Clone)]
+pub struct OriginalBody {
+    pub properties: BasicProperties,
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Debug for IncomingMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IncomingMessage")
+            .field("delivery_tag", &self.delivery_tag)
+            .field("routing_key", &self.routing_key)
+            .field("redelivered", &self.redelivered)
+            .field("properties", &self.properties)
+            .field("data", &self.data)
+            .field("original", &self.original)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Settles a single message with its source.
+#[async_trait]
+pub trait Acknowledge: Send + Sync {
+    async fn ack(&self) -> Result<(), SourceError>;
+
+    /// Gives the message back; with `requeue` the source hands it out again.
+    async fn reject(&self, requeue: bool) -> Result<(), SourceError>;
+}
+
+pub type AckHandle = Arc<dyn Acknowledge>;
+
+/// For sources whose messages are settled out of band, e.g. by delivery tag.
+pub struct NoopAck;
+
+#[async_trait]
+impl Acknowledge for NoopAck {
+    async fn ack(&self) -> Result<(), SourceError> {
+        Ok(())
+    }
+
+    async fn reject(&self, _requeue: bool) -> Result<(), SourceError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Acknowledge for Acker {
+    async fn ack(&self) -> Result<(), SourceError> {
+        Acker::ack(self, BasicAckOptions::default())
+            .await
+            .map_err(|e| SourceError::Broker(e.to_string()))
+    }
+
+    async fn reject(&self, requeue: bool) -> Result<(), SourceError> {
+        self.nack(BasicNackOptions {
+            requeue,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| SourceError::Broker(e.to_string()))
+    }
+}
+
+impl From<lapin::message::Delivery> for IncomingMessage {
+    fn from(delivery: lapin::message::Delivery) -> Self {
+        Self {
+            delivery_tag: delivery.delivery_tag,
+            routing_key: delivery.routing_key.to_string(),
+            redelivered: delivery.redelivered,
+            properties: delivery.properties,
+            data: delivery.data,
+            original: None,
+            acker: Arc::new(delivery.acker),
+        }
+    }
+}
+
+/// Deliveries from a `basic_consume` subscription on a RabbitMQ queue.
+pub struct AmqpSource {
+    queue_name: String,
+    consumer: lapin::Consumer,
+}
+
+impl AmqpSource {
+    pub async fn subscribe(
+        channel: &Channel,
+        queue_name: &str,
+        consumer_tag: &str,
+    ) -> Result<Self, SourceError> {
+        let consumer = channel
+            .basic_consume(
+                queue_name,
+                consumer_tag,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| SourceError::Broker(e.to_string()))?;
+
+        Ok(Self {
+            queue_name: queue_name.to_string(),
+            consumer,
+        })
+    }
+}
+
+#[async_trait]
+impl Source for AmqpSource {
+    fn name(&self) -> &str {
+        &self.queue_name
+    }
+
+    async fn next_message(&mut self) -> Option<Result<IncomingMessage, SourceError>> {
+        self.consumer.next().await.map(|result| {
+            result
+                .map(IncomingMessage::from)
+                .map_err(|e| SourceError::Broker(e.to_string()))
+        })
+    }
+}
+
+/// How a message from a [`VecSource`] was settled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Settlement {
+    Acked,
+    Rejected { requeue: bool },
+}
+
+/// An in-memory source that yields fixed payloads in order and records how
+/// each was settled, for driving handlers in tests without a broker.
+pub struct VecSource {
+    routing_key: String,
+    pending: VecDeque<Vec<u8>>,
+    next_tag: u64,
+    settlements: Arc<Mutex<Vec<(u64, Settlement)>>>,
+}
+
+impl VecSource {
+    pub fn new<P: Into<Vec<u8>>>(
+        routing_key: impl Into<String>,
+        payloads: impl IntoIterator<Item = P>,
+    ) -> Self {
+        Self {
+            routing_key: routing_key.into(),
+            pending: payloads.into_iter().map(Into::into).collect(),
+            next_tag: 1,
+            settlements: Arc::default(),
+        }
+    }
+
+    /// Delivery tags and outcomes, in the order the messages were settled.
+    pub fn settlements(&self) -> Vec<(u64, Settlement)> {
+        self.settlements.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Source for VecSource {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    async fn next_message(&mut self) -> Option<Result<IncomingMessage, SourceError>> {
+        let data = self.pending.pop_front()?;
+        let delivery_tag = self.next_tag;
+        self.next_tag += 1;
+
+        Some(Ok(IncomingMessage {
+            delivery_tag,
+            routing_key: self.routing_key.clone(),
+            redelivered: false,
+            properties: BasicProperties::default(),
+            data,
+            original: None,
+            acker: Arc::new(RecordingAck {
+                delivery_tag,
+                settlements: self.settlements.clone(),
+            }),
+        }))
+    }
+}
+
+struct RecordingAck {
+    delivery_tag: u64,
+    settlements: Arc<Mutex<Vec<(u64, Settlement)>>>,
+}
+
+#[async_trait]
+impl Acknowledge for RecordingAck {
+    async fn ack(&self) -> Result<(), SourceError> {
+        self.record(Settlement::Acked)
+    }
+
+    async fn reject(&self, requeue: bool) -> Result<(), SourceError> {
+        self.record(Settlement::Rejected { requeue })
+    }
+}
+
+impl RecordingAck {
+    fn record(&self, settlement: Settlement) -> Result<(), SourceError> {
+        let mut settlements = self.settlements.lock().unwrap();
+        if settlements.iter().any(|(tag, _)| *tag == self.delivery_tag) {
+            return Err(SourceError::AlreadySettled(self.delivery_tag));
+        }
+        settlements.push((self.delivery_tag, settlement));
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[error("Broker error: {0}")]
+    Broker(String),
+
+    #[error("Local spool error: {0}")]
+    Io(String),
+
+    #[error("Message {0} was already settled")]
+    AlreadySettled(u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::handler::{HandlerError, HandlerOutcome, MessageHandler};
+    use tokio_util::sync::CancellationToken;
+
+    struct PayloadHandler;
+
+    #[async_trait]
+    impl MessageHandler for PayloadHandler {
+        async fn handle(
+            &self,
+            message: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            match message.data.as_slice() {
+                b"ok" => Ok(HandlerOutcome::default()),
+                _ => Err(HandlerError::Permanent("bad payload".to_string())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vec_source_feeds_handler_and_records_settlements() {
+        let mut source = VecSource::new("telemetry", ["ok", "garbage", "ok"]);
+
+        while let Some(message) = source.next_message().await {
+            let message = message.unwrap();
+            assert_eq!(message.routing_key, "telemetry");
+            let acker = message.acker.clone();
+            match PayloadHandler.handle(message, CancellationToken::new()).await {
+                Ok(_) => acker.ack().await.unwrap(),
+                Err(_) => acker.reject(false).await.unwrap(),
+            }
+        }
+
+        assert_eq!(
+            source.settlements(),
+            vec![
+                (1, Settlement::Acked),
+                (2, Settlement::Rejected { requeue: false }),
+                (3, Settlement::Acked),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vec_source_rejects_double_settlement() {
+        let mut source = VecSource::new("telemetry", ["ok"]);
+        let message = source.next_message().await.unwrap().unwrap();
+
+        message.acker.ack().await.unwrap();
+        assert!(matches!(
+            message.acker.reject(true).await,
+            Err(SourceError::AlreadySettled(1))
+        ));
+        assert!(source.next_message().await.is_none());
+    }
+}
@@ -0,0 +1,183 @@
+use async_trait::async_trait;
+use lapin::BasicProperties;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use super::broker::ConsumerBroker;
+use super::channel::PublishError;
+use super::recovery::{DeclareError, TopologyBroker};
+use super::source::{IncomingMessage, NoopAck, Settlement, Source, SourceError};
+use super::topology::{QueueDeclaration, TopologyOperation};
+use crate::metrics::queue_depth::QueueDepthSource;
+
+/// Builds a delivery as it would arrive from the `telemetry` queue.
+pub(crate) fn delivery(delivery_tag: u64, data: &[u8]) -> IncomingMessage {
+    delivery_with_properties(delivery_tag, data, BasicProperties::default())
+}
+
+pub(crate) fn delivery_with_properties(
+    delivery_tag: u64,
+    data: &[u8],
+    properties: BasicProperties,
+) -> IncomingMessage {
+    IncomingMessage {
+        delivery_tag,
+        routing_key: "telemetry".into(),
+        redelivered: false,
+        properties,
+        data: data.to_vec(),
+        original: None,
+        acker: Arc::new(NoopAck),
+    }
+}
+
+/// A message a `MockBroker` was asked to publish.
+#[derive(Debug, Clone)]
+pub(crate) struct Published {
+    pub queue: String,
+    pub data: Vec<u8>,
+    pub properties: BasicProperties,
+}
+
+/// An in-memory broker for running a `Consumer` end to end.
+///
+/// A subscription hands out every message queued with `deliver` and then
+/// ends, so `Consumer::start` returns once they are all settled. Declared
+/// queues, acks, nacks and publishes are recorded for the test to inspect.
+#[derive(Default)]
+pub(crate) struct MockBroker {
+    pending: Mutex<VecDeque<IncomingMessage>>,
+    declared: Mutex<Vec<String>>,
+    settlements: Mutex<Vec<(u64, Settlement)>>,
+    published: Mutex<Vec<Published>>,
+    prefetches: Mutex<Vec<(u16, bool)>>,
+    subscriptions: Mutex<u32>,
+}
+
+impl MockBroker {
+    /// Queues a message for the next subscription, returning its delivery tag.
+    pub(crate) fn deliver(&self, data: &[u8], properties: BasicProperties) -> u64 {
+        let mut pending = self.pending.lock().unwrap();
+        let delivery_tag = pending.len() as u64 + 1;
+        pending.push_back(delivery_with_properties(delivery_tag, data, properties));
+        delivery_tag
+    }
+
+    pub(crate) fn declared(&self) -> Vec<String> {
+        self.declared.lock().unwrap().clone()
+    }
+
+    /// Delivery tags and how they were settled, in order. A multiple ack is
+    /// recorded once, under the tag it was sent with.
+    pub(crate) fn settlements(&self) -> Vec<(u64, Settlement)> {
+        self.settlements.lock().unwrap().clone()
+    }
+
+    pub(crate) fn published(&self) -> Vec<Published> {
+        self.published.lock().unwrap().clone()
+    }
+
+    /// Times a consumer subscribed.
+    pub(crate) fn subscriptions(&self) -> u32 {
+        *self.subscriptions.lock().unwrap()
+    }
+
+    /// Prefetch limits set, with whether each was global, in order.
+    pub(crate) fn prefetches(&self) -> Vec<(u16, bool)> {
+        self.prefetches.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl TopologyBroker for MockBroker {
+    async fn declare_queue(&self, queue: &QueueDeclaration) -> Result<(), DeclareError> {
+        self.declared.lock().unwrap().push(queue.name.clone());
+        Ok(())
+    }
+
+    async fn delete_empty_queue(&self, _name: &str) -> Result<(), DeclareError> {
+        Ok(())
+    }
+
+    async fn apply(&self, _operation: TopologyOperation<'_>) -> Result<(), DeclareError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueueDepthSource for MockBroker {
+    async fn message_count(&self, _queue: &str) -> Result<u32, String> {
+        Ok(self.pending.lock().unwrap().len() as u32)
+    }
+}
+
+#[async_trait]
+impl ConsumerBroker for MockBroker {
+    async fn subscribe(
+        &self,
+        queue: &str,
+        _consumer_tag: &str,
+    ) -> Result<Box<dyn Source>, SourceError> {
+        *self.subscriptions.lock().unwrap() += 1;
+        let messages = std::mem::take(&mut *self.pending.lock().unwrap());
+        Ok(Box::new(MockSource {
+            queue: queue.to_string(),
+            messages,
+        }))
+    }
+
+    async fn cancel(&self, _consumer_tag: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn ack(&self, delivery_tag: u64, _multiple: bool) -> Result<(), lapin::Error> {
+        self.settlements
+            .lock()
+            .unwrap()
+            .push((delivery_tag, Settlement::Acked));
+        Ok(())
+    }
+
+    async fn nack(&self, delivery_tag: u64, requeue: bool) -> Result<(), lapin::Error> {
+        self.settlements
+            .lock()
+            .unwrap()
+            .push((delivery_tag, Settlement::Rejected { requeue }));
+        Ok(())
+    }
+
+    async fn publish(
+        &self,
+        queue: &str,
+        data: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), PublishError> {
+        self.published.lock().unwrap().push(Published {
+            queue: queue.to_string(),
+            data: data.to_vec(),
+            properties,
+        });
+        Ok(())
+    }
+
+    async fn set_prefetch(&self, prefetch_count: u16, global: bool) -> Result<(), lapin::Error> {
+        self.prefetches.lock().unwrap().push((prefetch_count, global));
+        Ok(())
+    }
+}
+
+struct MockSource {
+    queue: String,
+    messages: VecDeque<IncomingMessage>,
+}
+
+#[async_trait]
+impl Source for MockSource {
+    fn name(&self) -> &str {
+        &self.queue
+    }
+
+    async fn next_message(&mut self) -> Option<Result<IncomingMessage, SourceError>> {
+        self.messages.pop_front().map(Ok)
+    }
+}
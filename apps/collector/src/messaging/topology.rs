@@ -0,0 +1,654 @@
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::ExchangeKind;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Everything the collector declares on the broker for one consumed queue.
+///
+/// `setup_queues` declares exactly what this describes, so serializing it is
+/// an accurate picture of the runtime topology rather than a reconstruction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueTopology {
+    pub queue: String,
+    pub exchanges: Vec<ExchangeDeclaration>,
+    pub bindings: Vec<BindingDeclaration>,
+    /// Bindings of the main queue to the exchange it consumes from.
+    pub queue_bindings: Vec<QueueBindingDeclaration>,
+    /// Queues in declaration order; dead-letter targets come before their sources.
+    pub queues: Vec<QueueDeclaration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueRole {
+    Main,
+    Retry,
+    DeadLetter,
+}
+
+/// `x-queue-type` of a declared queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueType {
+    Classic,
+    /// Replicated across the cluster; always durable, and without a queue mode.
+    Quorum,
+}
+
+impl QueueType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Classic => "classic",
+            Self::Quorum => "quorum",
+        }
+    }
+}
+
+impl FromStr for QueueType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(Self::Classic),
+            "quorum" => Ok(Self::Quorum),
+            other => Err(format!(
+                "unknown queue type `{}`, expected classic or quorum",
+                other
+            )),
+        }
+    }
+}
+
+/// `x-queue-mode` of a classic queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueMode {
+    Default,
+    /// Keeps messages on disk rather than in memory, for queues that can grow long.
+    Lazy,
+}
+
+impl QueueMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Lazy => "lazy",
+        }
+    }
+}
+
+impl FromStr for QueueMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "lazy" => Ok(Self::Lazy),
+            other => Err(format!(
+                "unknown queue mode `{}`, expected default or lazy",
+                other
+            )),
+        }
+    }
+}
+
+/// `x-overflow` of a queue with a `max_length`: what the broker does with a
+/// message that arrives once the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Drops the oldest message to make room; the broker's default.
+    DropHead,
+    /// Refuses the new message; a confirmed publish is nacked.
+    RejectPublish,
+}
+
+impl Overflow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DropHead => "drop-head",
+            Self::RejectPublish => "reject-publish",
+        }
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-head" => Ok(Self::DropHead),
+            "reject-publish" => Ok(Self::RejectPublish),
+            other => Err(format!(
+                "unknown overflow `{}`, expected drop-head or reject-publish",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueDeclaration {
+    pub name: String,
+    pub role: QueueRole,
+    pub durable: bool,
+    pub queue_type: QueueType,
+    pub queue_mode: QueueMode,
+    pub message_ttl_ms: Option<u32>,
+    pub dead_letter_exchange: Option<String>,
+    pub dead_letter_routing_key: Option<String>,
+    pub max_length: Option<u32>,
+    /// Set along with `max_length`.
+    pub overflow: Option<Overflow>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExchangeDeclaration {
+    pub name: String,
+    /// `direct`, `fanout`, `topic`, `headers`, or a plugin-provided type.
+    pub kind: String,
+    #[serde(default = "default_durable")]
+    pub durable: bool,
+}
+
+/// An exchange-to-exchange binding: messages published to `source` that
+/// match `routing_key` are also routed through `destination`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BindingDeclaration {
+    pub source: String,
+    pub destination: String,
+    #[serde(default)]
+    pub routing_key: String,
+}
+
+/// A queue binding: messages published to `exchange` that match
+/// `routing_key` are routed to `queue`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueBindingDeclaration {
+    pub exchange: String,
+    pub queue: String,
+    pub routing_key: String,
+}
+
+fn default_durable() -> bool {
+    true
+}
+
+/// Exchanges and exchange-to-exchange bindings declared before the queues,
+/// read from the JSON file named by `TOPOLOGY_SPEC_PATH`:
+///
+/// ```json
+/// {
+///   "exchanges": [{ "name": "all-telemetry", "kind": "fanout" }],
+///   "bindings": [{ "source": "all-telemetry", "destination": "logs", "routing_key": "" }]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TopologySpec {
+    #[serde(default)]
+    pub exchanges: Vec<ExchangeDeclaration>,
+    #[serde(default)]
+    pub bindings: Vec<BindingDeclaration>,
+}
+
+/// One broker call made by `Consumer::declare_topology` or
+/// `Consumer::setup_exchange_and_bindings`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TopologyOperation<'a> {
+    DeclareExchange(&'a ExchangeDeclaration),
+    BindExchange(&'a BindingDeclaration),
+    BindQueue(&'a QueueBindingDeclaration),
+}
+
+impl fmt::Display for TopologyOperation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeclareExchange(exchange) => write!(f, "exchange {}", exchange.name),
+            Self::BindExchange(binding) => {
+                write!(f, "binding {} -> {}", binding.source, binding.destination)
+            }
+            Self::BindQueue(binding) => write!(
+                f,
+                "binding {} -> {} ({})",
+                binding.exchange, binding.queue, binding.routing_key
+            ),
+        }
+    }
+}
+
+impl TopologySpec {
+    pub fn from_json(raw: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(raw)
+    }
+
+    /// The calls needed to apply the spec, in order: every exchange is
+    /// declared before any binding so a binding never references a missing
+    /// exchange from the same spec. Declaring and binding are idempotent on
+    /// the broker, so the plan can be applied on every startup.
+    pub fn operations(&self) -> Vec<TopologyOperation<'_>> {
+        self.exchanges
+            .iter()
+            .map(TopologyOperation::DeclareExchange)
+            .chain(self.bindings.iter().map(TopologyOperation::BindExchange))
+            .collect()
+    }
+}
+
+impl ExchangeDeclaration {
+    pub fn exchange_kind(&self) -> ExchangeKind {
+        match self.kind.as_str() {
+            "direct" => ExchangeKind::Direct,
+            "fanout" => ExchangeKind::Fanout,
+            "headers" => ExchangeKind::Headers,
+            "topic" => ExchangeKind::Topic,
+            other => ExchangeKind::Custom(other.to_string()),
+        }
+    }
+}
+
+impl QueueTopology {
+    /// The main/retry/DLQ triple used by `Consumer::setup_queues`.
+    ///
+    /// Failed messages are republished by the consumer, but the broker-level
+    /// dead-letter routing is kept so a `basic_reject` still lands in the DLQ
+    /// and expired retry messages flow back to the main queue.
+    pub fn for_queue(queue_name: &str, retry_delay_ms: u32) -> Self {
+        let dlq_name = format!("{}.dlq", queue_name);
+        let retry_name = format!("{}.retry", queue_name);
+
+        let dlq = QueueDeclaration::durable(dlq_name.clone(), QueueRole::DeadLetter);
+        let retry = QueueDeclaration {
+            message_ttl_ms: Some(retry_delay_ms),
+            dead_letter_exchange: Some(String::new()),
+            dead_letter_routing_key: Some(queue_name.to_string()),
+            ..QueueDeclaration::durable(retry_name, QueueRole::Retry)
+        };
+        let main = QueueDeclaration {
+            dead_letter_exchange: Some(String::new()),
+            dead_letter_routing_key: Some(dlq_name),
+            ..QueueDeclaration::durable(queue_name.to_string(), QueueRole::Main)
+        };
+
+        Self {
+            queue: queue_name.to_string(),
+            exchanges: Vec::new(),
+            bindings: Vec::new(),
+            queue_bindings: Vec::new(),
+            queues: vec![dlq, retry, main],
+        }
+    }
+
+    /// Consumes from `exchange` as well as the default exchange: the main
+    /// queue is bound to it once per routing key.
+    pub fn with_exchange(mut self, exchange: ExchangeDeclaration, routing_keys: &[String]) -> Self {
+        self.queue_bindings
+            .extend(routing_keys.iter().map(|routing_key| QueueBindingDeclaration {
+                exchange: exchange.name.clone(),
+                queue: self.queue.clone(),
+                routing_key: routing_key.clone(),
+            }));
+        self.exchanges.push(exchange);
+        self
+    }
+
+    /// The calls declaring the consumed exchange and binding the queue to
+    /// it, exchanges first, then any exchange-to-exchange bindings. Empty
+    /// without an exchange, leaving the queue on the default exchange only.
+    pub fn exchange_operations(&self) -> Vec<TopologyOperation<'_>> {
+        self.exchanges
+            .iter()
+            .map(TopologyOperation::DeclareExchange)
+            .chain(self.bindings.iter().map(TopologyOperation::BindExchange))
+            .chain(self.queue_bindings.iter().map(TopologyOperation::BindQueue))
+            .collect()
+    }
+
+    /// Replaces the retry queue with one queue per TTL in `ttls_ms`,
+    /// `<queue>.retry.1` onwards. Each holds messages for its fixed TTL
+    /// before dead-lettering them back to the main queue, so every message
+    /// in a tier waits the same time and none expires late behind another.
+    pub fn with_retry_tiers(mut self, ttls_ms: &[u32]) -> Self {
+        let Some(position) = self.queues.iter().position(|q| q.role == QueueRole::Retry) else {
+            return self;
+        };
+        let tiers = ttls_ms.iter().enumerate().map(|(index, &ttl)| QueueDeclaration {
+            message_ttl_ms: Some(ttl),
+            dead_letter_exchange: Some(String::new()),
+            dead_letter_routing_key: Some(self.queue.clone()),
+            ..QueueDeclaration::durable(retry_tier_queue(&self.queue, index + 1), QueueRole::Retry)
+        });
+        self.queues.splice(position..=position, tiers.collect::<Vec<_>>());
+        self
+    }
+
+    /// Declares the main queue as `queue_type`, and every classic queue in
+    /// `queue_mode`. The retry queue and DLQ stay classic, so a quorum main
+    /// queue can still have lazy retry and dead-letter queues.
+    pub fn with_queue_options(mut self, queue_type: QueueType, queue_mode: QueueMode) -> Self {
+        for queue in &mut self.queues {
+            if queue.role == QueueRole::Main {
+                queue.queue_type = queue_type;
+            }
+            if queue.queue_type == QueueType::Classic {
+                queue.queue_mode = queue_mode;
+            }
+        }
+        self
+    }
+
+    /// Caps the queue of `role` at `max_length` messages, with `overflow`
+    /// deciding what happens to messages beyond it.
+    pub fn with_max_length(mut self, role: QueueRole, max_length: u32, overflow: Overflow) -> Self {
+        for queue in self.queues.iter_mut().filter(|queue| queue.role == role) {
+            queue.max_length = Some(max_length);
+            queue.overflow = Some(overflow);
+        }
+        self
+    }
+
+    pub fn queue(&self, role: QueueRole) -> Option<&QueueDeclaration> {
+        self.queues.iter().find(|q| q.role == role)
+    }
+}
+
+/// Name of retry tier `tier` (1 for the first) of `queue`.
+pub fn retry_tier_queue(queue: &str, tier: usize) -> String {
+    format!("{}.retry.{}", queue, tier)
+}
+
+impl QueueDeclaration {
+    fn durable(name: String, role: QueueRole) -> Self {
+        Self {
+            name,
+            role,
+            durable: true,
+            queue_type: QueueType::Classic,
+            queue_mode: QueueMode::Default,
+            message_ttl_ms: None,
+            dead_letter_exchange: None,
+            dead_letter_routing_key: None,
+            max_length: None,
+            overflow: None,
+        }
+    }
+
+    /// The `x-*` arguments passed to `queue_declare`.
+    ///
+    /// A classic queue in the default mode sets neither `x-queue-type` nor
+    /// `x-queue-mode`, so it matches queues declared before they existed.
+    pub fn arguments(&self) -> FieldTable {
+        let mut args = FieldTable::default();
+
+        if self.queue_type != QueueType::Classic {
+            args.insert(
+                "x-queue-type".into(),
+                AMQPValue::LongString(self.queue_type.as_str().into()),
+            );
+        }
+        if self.queue_mode != QueueMode::Default {
+            args.insert(
+                "x-queue-mode".into(),
+                AMQPValue::LongString(self.queue_mode.as_str().into()),
+            );
+        }
+
+        if let Some(ttl) = self.message_ttl_ms {
+            args.insert("x-message-ttl".into(), AMQPValue::LongInt(ttl as i32));
+        }
+        if let Some(exchange) = &self.dead_letter_exchange {
+            args.insert(
+                "x-dead-letter-exchange".into(),
+                AMQPValue::LongString(exchange.clone().into()),
+            );
+        }
+        if let Some(routing_key) = &self.dead_letter_routing_key {
+            args.insert(
+                "x-dead-letter-routing-key".into(),
+                AMQPValue::LongString(routing_key.clone().into()),
+            );
+        }
+        if let Some(max_length) = self.max_length {
+            args.insert("x-max-length".into(), AMQPValue::LongInt(max_length as i32));
+        }
+        if let Some(overflow) = self.overflow {
+            args.insert(
+                "x-overflow".into(),
+                AMQPValue::LongString(overflow.as_str().into()),
+            );
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_topology_json_matches_configuration() {
+        let topology = QueueTopology::for_queue("telemetry", 5000);
+
+        assert_eq!(
+            serde_json::to_value(&topology).unwrap(),
+            json!({
+                "queue": "telemetry",
+                "exchanges": [],
+                "bindings": [],
+                "queue_bindings": [],
+                "queues": [
+                    {
+                        "name": "telemetry.dlq",
+                        "role": "dead_letter",
+                        "durable": true,
+                        "queue_type": "classic",
+                        "queue_mode": "default",
+                        "message_ttl_ms": null,
+                        "dead_letter_exchange": null,
+                        "dead_letter_routing_key": null,
+                        "max_length": null,
+                        "overflow": null
+                    },
+                    {
+                        "name": "telemetry.retry",
+                        "role": "retry",
+                        "durable": true,
+                        "queue_type": "classic",
+                        "queue_mode": "default",
+                        "message_ttl_ms": 5000,
+                        "dead_letter_exchange": "",
+                        "dead_letter_routing_key": "telemetry",
+                        "max_length": null,
+                        "overflow": null
+                    },
+                    {
+                        "name": "telemetry",
+                        "role": "main",
+                        "durable": true,
+                        "queue_type": "classic",
+                        "queue_mode": "default",
+                        "message_ttl_ms": null,
+                        "dead_letter_exchange": "",
+                        "dead_letter_routing_key": "telemetry.dlq",
+                        "max_length": null,
+                        "overflow": null
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_arguments_mirror_declaration() {
+        let topology = QueueTopology::for_queue("logs", 2500);
+
+        let retry = topology.queue(QueueRole::Retry).unwrap().arguments();
+        assert_eq!(
+            retry.inner().get("x-message-ttl"),
+            Some(&AMQPValue::LongInt(2500))
+        );
+        assert_eq!(
+            retry.inner().get("x-dead-letter-routing-key"),
+            Some(&AMQPValue::LongString("logs".into()))
+        );
+
+        let dlq = topology.queue(QueueRole::DeadLetter).unwrap().arguments();
+        assert!(dlq.inner().is_empty());
+    }
+
+    #[test]
+    fn test_max_length_limits_only_the_configured_queue() {
+        let topology = QueueTopology::for_queue("telemetry", 5000)
+            .with_max_length(QueueRole::DeadLetter, 100_000, Overflow::RejectPublish);
+
+        let dlq = topology.queue(QueueRole::DeadLetter).unwrap().arguments();
+        assert_eq!(
+            dlq.inner().get("x-max-length"),
+            Some(&AMQPValue::LongInt(100_000))
+        );
+        assert_eq!(
+            dlq.inner().get("x-overflow"),
+            Some(&AMQPValue::LongString("reject-publish".into()))
+        );
+
+        for role in [QueueRole::Main, QueueRole::Retry] {
+            let arguments = topology.queue(role).unwrap().arguments();
+            assert_eq!(arguments.inner().get("x-max-length"), None);
+            assert_eq!(arguments.inner().get("x-overflow"), None);
+        }
+    }
+
+    #[test]
+    fn test_retry_tiers_replace_the_retry_queue() {
+        let topology = QueueTopology::for_queue("telemetry", 5000)
+            .with_retry_tiers(&[5_000, 30_000, 120_000]);
+
+        let names: Vec<&str> = topology.queues.iter().map(|q| q.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "telemetry.dlq",
+                "telemetry.retry.1",
+                "telemetry.retry.2",
+                "telemetry.retry.3",
+                "telemetry"
+            ]
+        );
+        for (tier, ttl) in topology.queues[1..4].iter().zip([5_000, 30_000, 120_000]) {
+            assert_eq!(tier.role, QueueRole::Retry);
+            let arguments = tier.arguments();
+            assert_eq!(
+                arguments.inner().get("x-message-ttl"),
+                Some(&AMQPValue::LongInt(ttl))
+            );
+            assert_eq!(
+                arguments.inner().get("x-dead-letter-routing-key"),
+                Some(&AMQPValue::LongString("telemetry".into()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_quorum_main_queue_with_lazy_retry_and_dlq() {
+        let topology = QueueTopology::for_queue("telemetry", 5000)
+            .with_queue_options(QueueType::Quorum, QueueMode::Lazy);
+        let arguments = |role| topology.queue(role).unwrap().arguments();
+
+        let main = arguments(QueueRole::Main);
+        assert_eq!(
+            main.inner().get("x-queue-type"),
+            Some(&AMQPValue::LongString("quorum".into()))
+        );
+        assert_eq!(main.inner().get("x-queue-mode"), None);
+        assert_eq!(
+            main.inner().get("x-dead-letter-routing-key"),
+            Some(&AMQPValue::LongString("telemetry.dlq".into()))
+        );
+
+        for role in [QueueRole::Retry, QueueRole::DeadLetter] {
+            let arguments = arguments(role);
+            assert_eq!(arguments.inner().get("x-queue-type"), None);
+            assert_eq!(
+                arguments.inner().get("x-queue-mode"),
+                Some(&AMQPValue::LongString("lazy".into()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_topic_exchange_is_declared_then_bound_per_routing_key() {
+        let exchange = ExchangeDeclaration {
+            name: "telemetry.events".to_string(),
+            kind: "topic".to_string(),
+            durable: true,
+        };
+        let topology = QueueTopology::for_queue("telemetry", 5000)
+            .with_exchange(exchange.clone(), &["log.*".to_string(), "metric.*".to_string()]);
+
+        let binding = |routing_key: &str| QueueBindingDeclaration {
+            exchange: "telemetry.events".to_string(),
+            queue: "telemetry".to_string(),
+            routing_key: routing_key.to_string(),
+        };
+        let (logs, metrics) = (binding("log.*"), binding("metric.*"));
+        assert_eq!(
+            topology.exchange_operations(),
+            vec![
+                TopologyOperation::DeclareExchange(&exchange),
+                TopologyOperation::BindQueue(&logs),
+                TopologyOperation::BindQueue(&metrics),
+            ]
+        );
+        assert_eq!(topology.exchanges[0].exchange_kind(), ExchangeKind::Topic);
+
+        // Without an exchange the queue stays on the default exchange.
+        assert!(QueueTopology::for_queue("telemetry", 5000)
+            .exchange_operations()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_spec_operations_match_declared_exchanges_and_bindings() {
+        let spec = TopologySpec::from_json(
+            r#"{
+                "exchanges": [
+                    { "name": "all-telemetry", "kind": "fanout" },
+                    { "name": "logs", "kind": "topic", "durable": false }
+                ],
+                "bindings": [
+                    { "source": "all-telemetry", "destination": "logs", "routing_key": "log.#" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let fanout = ExchangeDeclaration {
+            name: "all-telemetry".to_string(),
+            kind: "fanout".to_string(),
+            durable: true,
+        };
+        let logs = ExchangeDeclaration {
+            name: "logs".to_string(),
+            kind: "topic".to_string(),
+            durable: false,
+        };
+        let binding = BindingDeclaration {
+            source: "all-telemetry".to_string(),
+            destination: "logs".to_string(),
+            routing_key: "log.#".to_string(),
+        };
+
+        assert_eq!(
+            spec.operations(),
+            vec![
+                TopologyOperation::DeclareExchange(&fanout),
+                TopologyOperation::DeclareExchange(&logs),
+                TopologyOperation::BindExchange(&binding),
+            ]
+        );
+        assert_eq!(spec.exchanges[0].exchange_kind(), ExchangeKind::Fanout);
+        assert_eq!(spec.exchanges[1].exchange_kind(), ExchangeKind::Topic);
+    }
+}
@@ -0,0 +1,156 @@
+//! W3C Trace Context (`traceparent` / `tracestate`) carried in AMQP headers,
+//! so the processing span joins the producer's trace.
+
+use lapin::types::FieldTable;
+use tracing::Span;
+
+use super::dlq::header_string;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// A parsed `traceparent`, with the `tracestate` that came with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub flags: u8,
+    pub tracestate: Option<String>,
+}
+
+impl TraceParent {
+    /// Parses `version-traceid-parentid-flags`. Returns `None` for anything
+    /// the spec says to ignore, including all-zero ids and version `ff`.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        // Later versions may append fields; version 00 has exactly four.
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+
+        let mut parent = Self {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            flags: 0,
+            tracestate: tracestate
+                .map(str::trim)
+                .filter(|state| !state.is_empty())
+                .map(str::to_string),
+        };
+        hex::decode_to_slice(trace_id, &mut parent.trace_id).ok()?;
+        hex::decode_to_slice(span_id, &mut parent.span_id).ok()?;
+        let mut flag = [0; 1];
+        hex::decode_to_slice(flags, &mut flag).ok()?;
+        parent.flags = flag[0];
+
+        if parent.trace_id == [0; 16] || parent.span_id == [0; 8] {
+            return None;
+        }
+        Some(parent)
+    }
+
+    pub fn from_headers(headers: &FieldTable) -> Option<Self> {
+        let traceparent = header_string(headers, TRACEPARENT_HEADER)?;
+        let tracestate = header_string(headers, TRACESTATE_HEADER);
+        Self::parse(&traceparent, tracestate.as_deref())
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// The producer's span as a remote OpenTelemetry span context.
+    #[cfg(feature = "otlp")]
+    pub fn span_context(&self) -> opentelemetry::trace::SpanContext {
+        use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+        use std::str::FromStr;
+
+        let state = self
+            .tracestate
+            .as_deref()
+            .and_then(|state| TraceState::from_str(state).ok())
+            .unwrap_or_default();
+        SpanContext::new(
+            TraceId::from_bytes(self.trace_id),
+            SpanId::from_bytes(self.span_id),
+            TraceFlags::new(self.flags),
+            true,
+            state,
+        )
+    }
+}
+
+/// Makes `parent` the parent of `span`, which must not have been entered yet.
+#[cfg(feature = "otlp")]
+pub fn set_parent(span: &Span, parent: &TraceParent) {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let cx = opentelemetry::Context::new().with_remote_span_context(parent.span_context());
+    // Fails harmlessly when spans are not being exported.
+    if let Err(e) = span.set_parent(cx) {
+        tracing::debug!(error = %e, "Could not link processing span to producer trace");
+    }
+}
+
+/// Without the `otlp` feature there is no trace to join.
+#[cfg(not(feature = "otlp"))]
+pub fn set_parent(_span: &Span, _parent: &TraceParent) {}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parses_known_traceparent() {
+        let parent = TraceParent::parse(TRACEPARENT, Some("congo=t61rcWkgMzE")).unwrap();
+
+        assert_eq!(
+            hex::encode(parent.trace_id),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(hex::encode(parent.span_id), "00f067aa0ba902b7");
+        assert!(parent.sampled());
+        assert_eq!(parent.tracestate.as_deref(), Some("congo=t61rcWkgMzE"));
+
+        #[cfg(feature = "otlp")]
+        {
+            let context = parent.span_context();
+            assert!(context.is_valid());
+            assert!(context.is_remote());
+            assert!(context.is_sampled());
+            assert_eq!(context.trace_state().get("congo"), Some("t61rcWkgMzE"));
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_traceparent() {
+        for invalid in [
+            "",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(TraceParent::parse(invalid, None), None, "{}", invalid);
+        }
+        assert!(TraceParent::parse(&format!("01{}-extra", &TRACEPARENT[2..]), None).is_some());
+    }
+}
@@ -0,0 +1,150 @@
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::error;
+
+/// Runs deliveries as separate tasks, at most `concurrency` at a time.
+///
+/// A pool of one still runs each delivery in its own task but never two at
+/// once, which keeps the consumer's original one-at-a-time behavior.
+pub struct WorkerPool {
+    permits: Arc<Semaphore>,
+    tasks: JoinSet<()>,
+}
+
+impl WorkerPool {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Waits for a free worker, then starts `task` on it.
+    pub async fn spawn<F>(&mut self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.reap();
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("worker pool semaphore is never closed");
+        self.tasks.spawn(async move {
+            task.await;
+            drop(permit);
+        });
+    }
+
+    /// Tasks started and not yet finished.
+    pub fn in_flight(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Waits for every task still running and returns how many there were.
+    pub async fn drain(&mut self) -> usize {
+        self.reap();
+        let mut drained = 0;
+        while let Some(result) = self.tasks.join_next().await {
+            log_panic(result);
+            drained += 1;
+        }
+        drained
+    }
+
+    /// Drops the results of finished tasks so the set does not grow unbounded.
+    fn reap(&mut self) {
+        while let Some(result) = self.tasks.try_join_next() {
+            log_panic(result);
+        }
+    }
+}
+
+fn log_panic(result: Result<(), tokio::task::JoinError>) {
+    if let Err(e) = result {
+        error!(error = %e, "Message processing task panicked");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::handler::{HandlerError, HandlerOutcome, MessageHandler};
+    use crate::messaging::source::{IncomingMessage, Settlement, Source, VecSource};
+    use crate::messaging::test_util::delivery;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+    use tokio_util::sync::CancellationToken;
+
+    struct SlowHandler {
+        delay: Duration,
+        handled: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl MessageHandler for SlowHandler {
+        async fn handle(
+            &self,
+            _delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            tokio::time::sleep(self.delay).await;
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    async fn run(concurrency: usize, messages: u64, delay: Duration) -> Duration {
+        let handler = Arc::new(SlowHandler {
+            delay,
+            handled: AtomicUsize::new(0),
+        });
+        let mut pool = WorkerPool::new(concurrency);
+        let start = Instant::now();
+        for tag in 1..=messages {
+            let handler = handler.clone();
+            pool.spawn(async move {
+                handler.handle(delivery(tag, b"{}"), CancellationToken::new()).await.unwrap();
+            })
+            .await;
+        }
+        pool.drain().await;
+        assert_eq!(handler.handled.load(Ordering::SeqCst), messages as usize);
+        start.elapsed()
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_in_flight_ack() {
+        let mut pool = WorkerPool::new(4);
+        let mut source = VecSource::new("telemetry", ["{}"]);
+        let message = source.next_message().await.unwrap().unwrap();
+        pool.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            message.acker.ack().await.unwrap();
+        })
+        .await;
+
+        // Shutting down mid-processing still lets the message be acked.
+        assert!(source.settlements().is_empty());
+        assert_eq!(pool.drain().await, 1);
+        assert_eq!(source.settlements(), vec![(1, Settlement::Acked)]);
+        assert_eq!(pool.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slow_messages_overlap_up_to_concurrency() {
+        let delay = Duration::from_millis(200);
+
+        // Eight messages taking T each finish in about T / 8 per message.
+        let elapsed = run(8, 8, delay).await;
+        assert!(elapsed < delay * 2, "took {:?}", elapsed);
+
+        // One worker runs them back to back.
+        let elapsed = run(1, 4, delay).await;
+        assert!(elapsed >= delay * 4, "took {:?}", elapsed);
+    }
+}
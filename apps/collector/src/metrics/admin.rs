@@ -0,0 +1,153 @@
+use axum::extract::{Query, State};
+use axum::Json;
+use serde::Deserialize;
+
+use super::recent::RecentEvent;
+use super::server::ServerState;
+use crate::messaging::QueueTopology;
+
+const DEFAULT_RECENT_LIMIT: usize = 50;
+
+/// `GET /admin/topology`: the queues, exchanges and bindings each consumer declared.
+pub async fn topology_handler(State(state): State<ServerState>) -> Json<Vec<QueueTopology>> {
+    Json(state.topology.read().unwrap().clone())
+}
+
+/// `GET /admin/consumers`: the queues currently being consumed, including any
+/// old queue still draining during a migration.
+pub async fn consumers_handler(State(state): State<ServerState>) -> Json<Vec<String>> {
+    let mut queues: Vec<String> = state
+        .all_metrics()
+        .iter()
+        .flat_map(|metrics| metrics.consumed_queues())
+        .collect();
+    queues.sort();
+    queues.dedup();
+    Json(queues)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecentQuery {
+    limit: Option<usize>,
+}
+
+/// `GET /admin/recent?limit=M`: the last handled messages, most recent first.
+pub async fn recent_handler(
+    State(state): State<ServerState>,
+    Query(query): Query<RecentQuery>,
+) -> Json<Vec<RecentEvent>> {
+    Json(
+        state
+            .recent
+            .latest(query.limit.unwrap_or(DEFAULT_RECENT_LIMIT)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::recent::{Outcome, RecentEvents};
+    use crate::metrics::server::router;
+    use std::sync::Arc;
+    use crate::metrics::Metrics;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_topology_endpoint_returns_declared_topology() {
+        let state = ServerState::new(Metrics::new().unwrap());
+        let topology = QueueTopology::for_queue("telemetry", 5000);
+        state.topology.write().unwrap().push(topology.clone());
+
+        let response = router(state)
+            .oneshot(Request::get("/admin/topology").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json, serde_json::json!([topology]));
+    }
+
+    #[tokio::test]
+    async fn test_consumers_endpoint_lists_only_active_queues() {
+        let metrics = Metrics::new().unwrap();
+        metrics
+            .queue_consuming
+            .with_label_values(&["telemetry"])
+            .set(1.0);
+        metrics
+            .queue_consuming
+            .with_label_values(&["events"])
+            .set(1.0);
+        metrics
+            .queue_consuming
+            .with_label_values(&["events-old"])
+            .set(0.0);
+
+        let response = router(ServerState::new(metrics))
+            .oneshot(Request::get("/admin/consumers").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json, serde_json::json!(["events", "telemetry"]));
+    }
+
+    #[tokio::test]
+    async fn test_recent_endpoint_returns_newest_first() {
+        let recent = Arc::new(RecentEvents::new(10));
+        let outcomes = [
+            (Outcome::Processed, None),
+            (Outcome::Retried, Some("downstream timeout")),
+            (Outcome::DeadLettered, Some("Missing required field: eventType")),
+        ];
+        for (n, (outcome, reason)) in outcomes.into_iter().enumerate() {
+            recent.record(RecentEvent {
+                queue: "telemetry".to_string(),
+                correlation_id: Some(format!("corr-{}", n)),
+                routing_key: "telemetry".to_string(),
+                outcome,
+                error_reason: reason.map(str::to_string),
+                timestamp_ms: 1_700_000_000_000 + n as u64,
+                duration_ms: 1.5,
+            });
+        }
+        let state = ServerState::new(Metrics::new().unwrap()).with_recent_events(recent);
+
+        let response = router(state)
+            .oneshot(Request::get("/admin/recent?limit=2").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "queue": "telemetry",
+                    "correlation_id": "corr-2",
+                    "routing_key": "telemetry",
+                    "outcome": "dead_lettered",
+                    "error_reason": "Missing required field: eventType",
+                    "timestamp_ms": 1_700_000_000_002u64,
+                    "duration_ms": 1.5
+                },
+                {
+                    "queue": "telemetry",
+                    "correlation_id": "corr-1",
+                    "routing_key": "telemetry",
+                    "outcome": "retried",
+                    "error_reason": "downstream timeout",
+                    "timestamp_ms": 1_700_000_000_001u64,
+                    "duration_ms": 1.5
+                }
+            ])
+        );
+    }
+}
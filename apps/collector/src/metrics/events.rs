@@ -0,0 +1,258 @@
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::server::{unauthorized, ServerState};
+use crate::adapters::sqlite::{EventQuery, StoredEvent};
+
+/// Events returned by `GET /events` without a `limit`.
+pub const DEFAULT_EVENTS_LIMIT: usize = 100;
+/// Most events one `GET /events` returns, whatever the `limit`.
+pub const MAX_EVENTS_LIMIT: usize = 1000;
+
+/// An event from the local store, as served on `/events`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventRecord {
+    pub id: i64,
+    pub routing_key: String,
+    pub version: String,
+    pub event_type: Option<String>,
+    /// The stored payload, parsed when it is JSON and as a string otherwise.
+    pub payload: Value,
+    pub timestamp_ms: u64,
+    pub delivery_tag: u64,
+}
+
+impl EventRecord {
+    fn new(id: i64, event: StoredEvent) -> Self {
+        Self {
+            id,
+            event_type: event.event_type(),
+            payload: serde_json::from_str(&event.payload)
+                .unwrap_or_else(|_| Value::String(event.payload.clone())),
+            routing_key: event.routing_key,
+            version: event.version,
+            timestamp_ms: event.timestamp_ms,
+            delivery_tag: event.delivery_tag,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    since: Option<u64>,
+    event_type: Option<String>,
+    limit: Option<usize>,
+}
+
+/// `GET /events?since=T&event_type=X&limit=N`: the newest stored events,
+/// newest first, optionally only those stored at or after `T` (milliseconds
+/// since the epoch) and of type `X`. `limit` defaults to
+/// `DEFAULT_EVENTS_LIMIT` and is capped at `MAX_EVENTS_LIMIT`.
+///
+/// Requires the metrics bearer token when one is configured.
+pub async fn events_handler(
+    State(state): State<ServerState>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if !state.authorized(&headers) {
+        return unauthorized();
+    }
+    let Some(store) = &state.event_store else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let query = EventQuery {
+        since_ms: query.since,
+        event_type: query.event_type,
+        limit: query
+            .limit
+            .unwrap_or(DEFAULT_EVENTS_LIMIT)
+            .clamp(1, MAX_EVENTS_LIMIT),
+    };
+    match store.query(&query) {
+        Ok(rows) => Json(
+            rows.into_iter()
+                .map(|(id, event)| EventRecord::new(id, event))
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => store_error(e),
+    }
+}
+
+/// `GET /events/:id`: one stored event, or 404.
+pub async fn event_handler(
+    State(state): State<ServerState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    if !state.authorized(&headers) {
+        return unauthorized();
+    }
+    let Some(store) = &state.event_store else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match store.get(id) {
+        Ok(Some(event)) => Json(EventRecord::new(id, event)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, format!("No event with id {}", id)).into_response(),
+        Err(e) => store_error(e),
+    }
+}
+
+fn store_error(error: rusqlite::Error) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Failed to read the local store: {}", error),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::sqlite::SqliteSink;
+    use crate::metrics::server::router;
+    use crate::metrics::Metrics;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{header, Request};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    const T0: u64 = 1_700_000_000_000;
+
+    /// A store holding a log, a metric, another log and a non-JSON payload,
+    /// stored a second apart from `T0`.
+    fn state(dir: &tempfile::TempDir) -> ServerState {
+        let store = SqliteSink::open(&dir.path().join("events.db")).unwrap();
+        let payloads: [&[u8]; 4] = [
+            br#"{"eventType":"log","payload":{"n":0}}"#,
+            br#"{"type":"metric","payload":{"n":1}}"#,
+            br#"{"eventType":"log","payload":{"n":2}}"#,
+            b"not json",
+        ];
+        for (n, payload) in payloads.into_iter().enumerate() {
+            let n = n as u64;
+            store
+                .insert_at("telemetry", "v1", payload, n, T0 + n * 1000)
+                .unwrap();
+        }
+        ServerState::new(Metrics::new().unwrap()).with_event_store(Arc::new(store))
+    }
+
+    async fn get(state: ServerState, uri: &str) -> (StatusCode, Value) {
+        let response = router(state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn ids(json: &Value) -> Vec<i64> {
+        json.as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["id"].as_i64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_events_filtered_by_type_and_time_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(&dir);
+
+        let (status, all) = get(state.clone(), "/events").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ids(&all), vec![4, 3, 2, 1]);
+        assert_eq!(all[3]["event_type"], "log");
+        assert_eq!(all[3]["payload"]["payload"]["n"], 0);
+        assert_eq!(all[0]["event_type"], Value::Null);
+        assert_eq!(all[0]["payload"], "not json");
+
+        let (_, logs) = get(state.clone(), "/events?event_type=log").await;
+        assert_eq!(ids(&logs), vec![3, 1]);
+        let (_, metrics) = get(state.clone(), "/events?event_type=metric").await;
+        assert_eq!(ids(&metrics), vec![2]);
+
+        let since = format!("/events?since={}", T0 + 1000);
+        let (_, recent) = get(state.clone(), &since).await;
+        assert_eq!(ids(&recent), vec![4, 3, 2]);
+        let (_, recent_logs) = get(state.clone(), &format!("{}&event_type=log", since)).await;
+        assert_eq!(ids(&recent_logs), vec![3]);
+        let (_, limited) = get(state.clone(), &format!("{}&limit=2", since)).await;
+        assert_eq!(ids(&limited), vec![4, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_limit_is_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(&dir);
+        let store = state.event_store.clone().unwrap();
+        for n in 0..MAX_EVENTS_LIMIT as u64 {
+            store.insert("telemetry", "v1", b"{}", n).unwrap();
+        }
+
+        let (_, capped) = get(state.clone(), "/events?limit=5000").await;
+        assert_eq!(capped.as_array().unwrap().len(), MAX_EVENTS_LIMIT);
+        let (_, default) = get(state.clone(), "/events").await;
+        assert_eq!(default.as_array().unwrap().len(), DEFAULT_EVENTS_LIMIT);
+        let (status, _) = get(state, "/events?limit=many").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_event_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(&dir);
+
+        let (status, event) = get(state.clone(), "/events/2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(event["id"], 2);
+        assert_eq!(event["event_type"], "metric");
+        assert_eq!(event["timestamp_ms"], T0 + 1000);
+        assert_eq!(event["delivery_tag"], 1);
+
+        let (status, _) = get(state, "/events/99").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_token_is_required_when_configured() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(&dir).with_metrics_auth_token("s3cret");
+        let status = |uri: &str, authorization: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(value) = authorization {
+                request = request.header(header::AUTHORIZATION, value);
+            }
+            let router = router(state.clone());
+            async move {
+                router
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        for uri in ["/events", "/events/1"] {
+            assert_eq!(status(uri, None).await, StatusCode::UNAUTHORIZED);
+            assert_eq!(status(uri, Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+            assert_eq!(status(uri, Some("Bearer s3cret")).await, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_are_not_found_without_a_store() {
+        let state = ServerState::new(Metrics::new().unwrap());
+
+        let (status, _) = get(state, "/events").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
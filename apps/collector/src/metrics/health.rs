@@ -0,0 +1,230 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use lapin::ConnectionStatus;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use super::server::ServerState;
+use super::Metrics;
+
+/// Whether the collector can take work, as reported on `/readyz`: the broker
+/// connection is up and every consumer is subscribed to its queue.
+#[derive(Default)]
+pub struct Readiness {
+    /// Whether each consumer, by tag, is subscribed.
+    consumers: Mutex<HashMap<String, bool>>,
+    /// Replaced after every reconnect; `None` until the first connect.
+    connected: RwLock<Option<ConnectionCheck>>,
+}
+
+type ConnectionCheck = Box<dyn Fn() -> bool + Send + Sync>;
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_connection(&self, status: ConnectionStatus) {
+        self.set_connection_check(move || status.connected());
+    }
+
+    fn set_connection_check(&self, connected: impl Fn() -> bool + Send + Sync + 'static) {
+        *self.connected.write().unwrap() = Some(Box::new(connected));
+    }
+
+    pub fn set_consuming(&self, consumer_tag: &str, consuming: bool) {
+        self.consumers
+            .lock()
+            .unwrap()
+            .insert(consumer_tag.to_string(), consuming);
+    }
+
+    /// Stops counting a consumer that finished its work, such as one that
+    /// drained the queue it was migrating from.
+    pub fn forget_consumer(&self, consumer_tag: &str) {
+        self.consumers.lock().unwrap().remove(consumer_tag);
+    }
+
+    /// Whether the latest broker connection is up; false before the first connect.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|connected| connected())
+    }
+
+    /// `Err` carries the reason the collector is not ready.
+    pub fn check(&self) -> Result<(), &'static str> {
+        if !self.is_connected() {
+            return Err("broker connection is down");
+        }
+        let consumers = self.consumers.lock().unwrap();
+        if consumers.is_empty() || consumers.values().any(|consuming| !consuming) {
+            return Err("consumer is not running");
+        }
+        Ok(())
+    }
+}
+
+/// Keeps `collector_rabbitmq_connected` in line with the broker connection
+/// between the updates made when it is lost and re-established, so a drop
+/// shows up within one interval even while the consumer is idle.
+pub struct ConnectionMonitor {
+    readiness: Arc<Readiness>,
+    metrics: Arc<Metrics>,
+    interval: Duration,
+    shutdown: Arc<Notify>,
+}
+
+impl ConnectionMonitor {
+    pub fn new(
+        readiness: Arc<Readiness>,
+        metrics: Arc<Metrics>,
+        interval: Duration,
+        shutdown: Arc<Notify>,
+    ) -> Self {
+        Self {
+            readiness,
+            metrics,
+            interval,
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            self.refresh();
+            tokio::select! {
+                _ = self.shutdown.notified() => return,
+                _ = tokio::time::sleep(self.interval) => {}
+            }
+        }
+    }
+
+    fn refresh(&self) {
+        set_connected(&self.metrics, self.readiness.is_connected());
+    }
+}
+
+/// Sets `collector_rabbitmq_connected` to 1 or 0.
+pub fn set_connected(metrics: &Metrics, connected: bool) {
+    metrics.rabbitmq_connected.set(if connected { 1.0 } else { 0.0 });
+}
+
+/// `GET /healthz`: 200 for as long as the process serves requests.
+pub async fn healthz_handler() -> axum::response::Response {
+    Json(json!({ "status": "ok" })).into_response()
+}
+
+/// `GET /readyz`: 200 once consuming, 503 with the reason otherwise.
+pub async fn readyz_handler(State(state): State<ServerState>) -> axum::response::Response {
+    match state.readiness.check() {
+        Ok(()) => Json(json!({ "status": "ready" })).into_response(),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "not_ready", "reason": reason })),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::server::router;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use prometheus::Encoder;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tower::ServiceExt;
+
+    async fn probe(state: ServerState, path: &str) -> (StatusCode, serde_json::Value) {
+        let response = router(state)
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_readyz_requires_connection_and_consumer() {
+        let readiness = Arc::new(Readiness::new());
+        let state = ServerState::new(Metrics::new().unwrap()).with_readiness(readiness.clone());
+
+        let (status, _) = probe(state.clone(), "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = probe(state.clone(), "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["reason"], "broker connection is down");
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let flag = connected.clone();
+        readiness.set_connection_check(move || flag.load(Ordering::SeqCst));
+        let (status, body) = probe(state.clone(), "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["reason"], "consumer is not running");
+
+        readiness.set_consuming("main", true);
+        let (status, body) = probe(state.clone(), "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+
+        readiness.set_consuming("migration", false);
+        let (status, _) = probe(state.clone(), "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        readiness.forget_consumer("migration");
+        let (status, _) = probe(state.clone(), "/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+
+        connected.store(false, Ordering::SeqCst);
+        let (status, _) = probe(state, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    fn gathered_connected(metrics: &Metrics) -> String {
+        let mut buffer = Vec::new();
+        prometheus::TextEncoder::new()
+            .encode(&metrics.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer)
+            .unwrap()
+            .lines()
+            .find(|line| line.starts_with("collector_rabbitmq_connected "))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_connection_gauge_follows_connection() {
+        let metrics = Metrics::new().unwrap();
+        let readiness = Arc::new(Readiness::new());
+        let monitor = ConnectionMonitor::new(
+            readiness.clone(),
+            metrics.clone(),
+            Duration::from_secs(5),
+            Arc::new(Notify::new()),
+        );
+
+        monitor.refresh();
+        assert_eq!(gathered_connected(&metrics), "collector_rabbitmq_connected 0");
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let flag = connected.clone();
+        readiness.set_connection_check(move || flag.load(Ordering::SeqCst));
+        monitor.refresh();
+        assert_eq!(gathered_connected(&metrics), "collector_rabbitmq_connected 1");
+
+        connected.store(false, Ordering::SeqCst);
+        monitor.refresh();
+        assert_eq!(gathered_connected(&metrics), "collector_rabbitmq_connected 0");
+    }
+}
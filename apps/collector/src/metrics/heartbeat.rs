@@ -0,0 +1,137 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::info;
+
+use super::server::ServerState;
+
+/// What a single liveness line reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pulse {
+    pub connected: bool,
+    pub active_consumers: i64,
+    pub processed_since_last: u64,
+}
+
+/// Periodic "still alive" log line for deployments that can go quiet for hours.
+///
+/// Each beat waits the interval plus up to 10% random jitter, so replicas
+/// started together drift apart instead of logging in lockstep.
+pub struct Heartbeat {
+    interval: Duration,
+    max_jitter: Duration,
+    state: ServerState,
+    shutdown: Arc<Notify>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, state: ServerState, shutdown: Arc<Notify>) -> Self {
+        Self {
+            interval,
+            max_jitter: interval / 10,
+            state,
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) {
+        self.run_with(|pulse| {
+            info!(
+                connected = pulse.connected,
+                active_consumers = pulse.active_consumers,
+                processed_since_last = pulse.processed_since_last,
+                "Collector heartbeat"
+            );
+        })
+        .await
+    }
+
+    async fn run_with(self, mut emit: impl FnMut(Pulse)) {
+        let mut last_processed = self.processed_total();
+
+        loop {
+            let delay = self.interval + jitter(self.max_jitter);
+            tokio::select! {
+                _ = self.shutdown.notified() => return,
+                _ = tokio::time::sleep(delay) => {
+                    let processed = self.processed_total();
+                    emit(Pulse {
+                        connected: self.state.readiness.is_connected(),
+                        active_consumers: self.active_consumers(),
+                        processed_since_last: processed.saturating_sub(last_processed),
+                    });
+                    last_processed = processed;
+                }
+            }
+        }
+    }
+
+    /// Summed over the shared and any per-queue metrics.
+    fn processed_total(&self) -> u64 {
+        self.state
+            .all_metrics()
+            .iter()
+            .map(|metrics| metrics.processed_total())
+            .sum()
+    }
+
+    fn active_consumers(&self) -> i64 {
+        self.state
+            .all_metrics()
+            .iter()
+            .map(|metrics| metrics.active_consumers.get() as i64)
+            .sum()
+    }
+}
+
+fn jitter(max: Duration) -> Duration {
+    let max_ms = max.as_millis() as u64;
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(fastrand::u64(0..=max_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use std::sync::Mutex;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_emits_at_configured_interval_until_shutdown() {
+        let metrics = Metrics::new().unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let pulses = Arc::new(Mutex::new(Vec::new()));
+
+        let heartbeat = Heartbeat::new(
+            Duration::from_secs(10),
+            ServerState::new(metrics.clone()),
+            shutdown.clone(),
+        );
+        let sink = pulses.clone();
+        let handle =
+            tokio::spawn(heartbeat.run_with(move |pulse| sink.lock().unwrap().push(pulse)));
+
+        tokio::time::sleep(Duration::from_millis(9_900)).await;
+        assert!(pulses.lock().unwrap().is_empty());
+
+        metrics
+            .messages_processed_total
+            .with_label_values(&["telemetry", "telemetry", "v1"])
+            .inc_by(3.0);
+        tokio::time::sleep(Duration::from_millis(1_200)).await;
+        assert_eq!(pulses.lock().unwrap().len(), 1);
+        assert_eq!(pulses.lock().unwrap()[0].processed_since_last, 3);
+        assert!(!pulses.lock().unwrap()[0].connected);
+
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert_eq!(pulses.lock().unwrap().len(), 2);
+        assert_eq!(pulses.lock().unwrap()[1].processed_since_last, 0);
+
+        shutdown.notify_one();
+        handle.await.unwrap();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(pulses.lock().unwrap().len(), 2);
+    }
+}
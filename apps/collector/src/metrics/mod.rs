@@ -1,30 +1,254 @@
+use prometheus::core::Collector;
 use prometheus::{
-    Counter, CounterVec, Gauge, Histogram, HistogramOpts, HistogramVec, Opts, Registry,
+    Counter, CounterVec, Gauge, GaugeVec, HistogramOpts, HistogramVec, Opts, Registry,
 };
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod admin;
+pub mod events;
+pub mod health;
+pub mod heartbeat;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+#[cfg(feature = "otlp")]
+pub mod spans;
+pub mod queue_depth;
+pub mod recent;
+pub mod resource;
 pub mod server;
+pub mod statsd;
+
+/// Receives every histogram observation as it is recorded.
+///
+/// Counters and gauges can be read back out of a registry at any time, but
+/// individual histogram observations cannot, so exporters other than the
+/// Prometheus endpoint mirror them through this hook.
+pub trait HistogramMirror: Send + Sync {
+    fn observe(&self, histogram: &HistogramVec, label_values: &[&str], value: f64);
+}
 
 pub struct Metrics {
     pub messages_processed_total: CounterVec,
     pub messages_failed_total: CounterVec,
     pub messages_retried_total: Counter,
-    pub messages_dlq_total: Counter,
+    pub messages_dlq_total: CounterVec,
+    pub messages_panicked_total: Counter,
+    /// Deliveries the broker flagged as redelivered, e.g. after a consumer crash.
+    pub messages_redelivered_total: Counter,
+    pub messages_timed_out_total: Counter,
+    pub messages_deduplicated_total: Counter,
+    /// Messages dead-lettered as poison after failing too often, see `Quarantine`.
+    pub messages_quarantined_total: Counter,
+    /// Messages a handler discarded, acked without a retry or DLQ copy.
+    pub messages_discarded_total: Counter,
+    /// Failed `basic_ack` attempts, including ones that succeeded on retry.
+    pub ack_failures_total: Counter,
     pub message_processing_duration_seconds: HistogramVec,
+    pub queue_wait_seconds: HistogramVec,
+    /// Time since the producer stamped a message, including any retries.
+    pub message_age_seconds: HistogramVec,
+    /// Retries a message had used up when its transient failure was dead-lettered.
+    pub dlq_retry_count: HistogramVec,
+    /// Time a message waited for `MAX_MESSAGES_PER_SEC` before its handler ran.
+    pub rate_limit_wait_seconds: HistogramVec,
+    /// Unix time of the last successfully processed message, starting at
+    /// the time the metrics were created.
+    pub last_message_processed_timestamp: Gauge,
+    /// State of the handler circuit breaker: 0 closed, 1 open, 2 half-open.
+    pub circuit_breaker_state: Gauge,
     pub active_consumers: Gauge,
+    /// 1 for every queue a consumer is currently subscribed to.
+    pub queue_consuming: GaugeVec,
+    /// Ready messages per queue, as last reported by the broker.
+    pub queue_depth: GaugeVec,
+    pub cache_hits_total: Counter,
+    pub messages_reanimated_total: Counter,
+    pub lenient_field_missing_total: CounterVec,
+    pub success_sampled_total: Counter,
+    pub clock_skew_detected_total: Counter,
+    pub signature_rejected_total: Counter,
+    pub signature_unverifiable_total: CounterVec,
+    pub connection_recoveries_total: Counter,
+    pub reconnect_attempts_total: Counter,
+    /// Times a consumer subscribed again after its delivery stream ended.
+    pub consumer_restarts_total: Counter,
+    /// 1 while the broker connection is up, 0 while it is down.
+    pub rabbitmq_connected: Gauge,
+    pub topology_drift_total: CounterVec,
+    /// Events in a WAL not yet forwarded to its target, by target.
+    pub wal_pending_entries: GaugeVec,
+    /// Events waiting to be sent to `DOWNSTREAM_URL`.
+    pub downstream_buffered_events: Gauge,
+    pub downstream_events_forwarded_total: Counter,
+    pub downstream_events_rejected_total: Counter,
+    /// Always 1, labeled with the version, git commit and compiler of this build.
+    pub build_info: GaugeVec,
     pub registry: Registry,
+    histogram_mirror: OnceLock<Arc<dyn HistogramMirror>>,
+    /// Handler label names appended to `messages_processed_total`'s labels.
+    handler_labels: Vec<String>,
+    handler_label_max_values: usize,
+    /// Values recorded so far for each handler label.
+    handler_label_values: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+/// Const label distinguishing per-queue registries in the aggregate `/metrics`.
+pub const REGISTRY_LABEL: &str = "registry";
+
+pub const DEFAULT_PROCESSING_DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+/// Shared by the queue wait and message age histograms.
+pub const DEFAULT_LATENCY_BUCKETS: &[f64] = &[
+    0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 3600.0,
+];
+
+/// Retry counts at DLQ time; `MAX_RETRIES` is usually in the single digits.
+pub const RETRY_COUNT_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 3.0, 5.0, 10.0, 20.0];
+
+/// Distinct values recorded per handler label before the rest become
+/// [`OTHER_LABEL_VALUE`].
+pub const DEFAULT_HANDLER_LABEL_MAX_VALUES: usize = 100;
+/// Recorded in place of a handler label value past the label's cap.
+pub const OTHER_LABEL_VALUE: &str = "other";
+
+/// Histogram bucket boundaries in seconds, and the labels handlers may add
+/// to `collector_messages_processed_total`; `None` keeps the default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsConfig {
+    pub processing_duration_buckets: Option<Vec<f64>>,
+    pub queue_wait_buckets: Option<Vec<f64>>,
+    pub message_age_buckets: Option<Vec<f64>>,
+    /// Names of the `HandlerOutcome` labels recorded on the processed count.
+    pub handler_labels: Vec<String>,
+    /// Distinct values recorded per handler label, per registry.
+    pub handler_label_max_values: Option<usize>,
+    /// Labels added to every series, such as the collector's resource
+    /// attributes.
+    pub const_labels: Vec<(String, String)>,
+}
+
+impl MetricsConfig {
+    /// A registry adding `const_labels` to every series, and for a per-queue
+    /// registry `registry="<queue>"` as well.
+    fn registry(&self, queue_name: Option<&str>) -> Result<Registry, prometheus::Error> {
+        let mut labels: HashMap<String, String> = self.const_labels.iter().cloned().collect();
+        if let Some(queue_name) = queue_name {
+            labels.insert(REGISTRY_LABEL.to_string(), queue_name.to_string());
+        }
+        if labels.is_empty() {
+            return Ok(Registry::new());
+        }
+        Registry::new_custom(None, Some(labels))
+    }
+
+    fn processing_duration_buckets(&self) -> Result<Vec<f64>, BucketError> {
+        buckets_or_default(
+            &self.processing_duration_buckets,
+            DEFAULT_PROCESSING_DURATION_BUCKETS,
+        )
+    }
+
+    fn queue_wait_buckets(&self) -> Result<Vec<f64>, BucketError> {
+        buckets_or_default(&self.queue_wait_buckets, DEFAULT_LATENCY_BUCKETS)
+    }
+
+    fn message_age_buckets(&self) -> Result<Vec<f64>, BucketError> {
+        buckets_or_default(&self.message_age_buckets, DEFAULT_LATENCY_BUCKETS)
+    }
+}
+
+fn buckets_or_default(
+    buckets: &Option<Vec<f64>>,
+    default: &[f64],
+) -> Result<Vec<f64>, BucketError> {
+    match buckets {
+        Some(buckets) => {
+            validate_buckets(buckets)?;
+            Ok(buckets.clone())
+        }
+        None => Ok(default.to_vec()),
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum BucketError {
+    #[error("at least one bucket boundary is required")]
+    Empty,
+
+    #[error("bucket boundary {0} is not a finite number")]
+    NotFinite(f64),
+
+    #[error("bucket boundaries must be strictly increasing, but {next} follows {previous}")]
+    NotIncreasing { previous: f64, next: f64 },
+}
+
+/// Checks that `buckets` are finite and strictly increasing.
+pub fn validate_buckets(buckets: &[f64]) -> Result<(), BucketError> {
+    if buckets.is_empty() {
+        return Err(BucketError::Empty);
+    }
+    if let Some(bound) = buckets.iter().find(|bound| !bound.is_finite()) {
+        return Err(BucketError::NotFinite(*bound));
+    }
+    match buckets.windows(2).find(|pair| pair[1] <= pair[0]) {
+        Some(pair) => Err(BucketError::NotIncreasing {
+            previous: pair[0],
+            next: pair[1],
+        }),
+        None => Ok(()),
+    }
 }
 
 impl Metrics {
     pub fn new() -> Result<Arc<Self>, Box<dyn std::error::Error>> {
-        let registry = Registry::new();
+        Self::with_config(&MetricsConfig::default())
+    }
+
+    /// Like `new`, with histogram buckets and constant labels from `config`.
+    /// Buckets that are not strictly increasing are rejected with a
+    /// `BucketError`.
+    pub fn with_config(config: &MetricsConfig) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        Self::with_registry_and_config(config.registry(None)?, config)
+    }
+
+    /// A separate set of metrics for one queue, served on `/metrics/<queue>`.
+    ///
+    /// Every series gathered from it carries `registry="<queue>"`, which keeps
+    /// it distinct from the same series in other registries once they are
+    /// merged into the aggregate scrape.
+    pub fn for_queue(
+        queue_name: &str,
+        config: &MetricsConfig,
+    ) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        Self::with_registry_and_config(config.registry(Some(queue_name))?, config)
+    }
+
+    /// Creates every collector metric and registers it with `registry`, which
+    /// may already hold the application's own metrics so one scrape serves both.
+    ///
+    /// Fails with the registry's `AlreadyReg` error if any collector metric is
+    /// already registered there, e.g. by a second `Metrics` on the same
+    /// registry, leaving the registry as it was.
+    pub fn with_registry(registry: Registry) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        Self::with_registry_and_config(registry, &MetricsConfig::default())
+    }
 
+    pub fn with_registry_and_config(
+        registry: Registry,
+        config: &MetricsConfig,
+    ) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
         let messages_processed_total = CounterVec::new(
             Opts::new(
                 "collector_messages_processed_total",
                 "Total number of messages successfully processed",
             ),
-            &["queue", "routing_key"],
+            &["queue", "routing_key", "version"]
+                .into_iter()
+                .chain(config.handler_labels.iter().map(String::as_str))
+                .collect::<Vec<_>>(),
         )?;
 
         let messages_failed_total = CounterVec::new(
@@ -32,7 +256,7 @@ impl Metrics {
                 "collector_messages_failed_total",
                 "Total number of messages that failed processing",
             ),
-            &["queue", "error_type"],
+            &["queue", "error_type", "version"],
         )?;
 
         let messages_retried_total = Counter::new(
@@ -40,9 +264,47 @@ impl Metrics {
             "Total number of messages sent to retry queue",
         )?;
 
-        let messages_dlq_total = Counter::new(
-            "collector_messages_dlq_total",
-            "Total number of messages sent to dead letter queue",
+        let messages_dlq_total = CounterVec::new(
+            Opts::new(
+                "collector_messages_dlq_total",
+                "Total number of messages sent to dead letter queue",
+            ),
+            &["error_type", "routing_key"],
+        )?;
+
+        let messages_panicked_total = Counter::new(
+            "collector_messages_panicked_total",
+            "Total number of messages whose handler panicked",
+        )?;
+
+        let messages_redelivered_total = Counter::new(
+            "collector_messages_redelivered_total",
+            "Total number of deliveries redelivered by the broker",
+        )?;
+
+        let messages_timed_out_total = Counter::new(
+            "collector_messages_timed_out_total",
+            "Total number of messages whose handler exceeded the handler timeout",
+        )?;
+
+        let messages_deduplicated_total = Counter::new(
+            "collector_messages_deduplicated_total",
+            "Total number of duplicate messages acked without being handled",
+        )?;
+
+        let messages_quarantined_total = Counter::new(
+            "collector_messages_quarantined_total",
+            "Total number of messages dead-lettered as poison after repeated failures",
+        )?;
+
+        let messages_discarded_total = Counter::new(
+            "collector_messages_discarded_total",
+            "Total number of messages discarded by the handler without a retry or DLQ copy",
+        )?;
+
+        let ack_failures_total = Counter::new(
+            "collector_ack_failures_total",
+            "Total number of failed attempts to acknowledge deliveries",
         )?;
 
         let message_processing_duration_seconds = HistogramVec::new(
@@ -50,30 +312,643 @@ impl Metrics {
                 "collector_message_processing_duration_seconds",
                 "Time taken to process a message",
             )
-            .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+            .buckets(config.processing_duration_buckets()?),
             &["queue", "status"],
         )?;
 
+        let queue_wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "collector_queue_wait_seconds",
+                "Time a message waited in the main queue before delivery, first attempts only",
+            )
+            .buckets(config.queue_wait_buckets()?),
+            &["queue"],
+        )?;
+
+        let message_age_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "collector_message_age_seconds",
+                "Time between the producer's timestamp and the start of processing",
+            )
+            .buckets(config.message_age_buckets()?),
+            &["queue"],
+        )?;
+
+        let dlq_retry_count = HistogramVec::new(
+            HistogramOpts::new(
+                "collector_dlq_retry_count",
+                "Retries a message had used up when it was dead-lettered after transient failures",
+            )
+            .buckets(RETRY_COUNT_BUCKETS.to_vec()),
+            &["queue"],
+        )?;
+
+        let rate_limit_wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "collector_rate_limit_wait_seconds",
+                "Time a message waited on the rate limiter before its handler ran",
+            )
+            .buckets(DEFAULT_PROCESSING_DURATION_BUCKETS.to_vec()),
+            &["queue"],
+        )?;
+
+        let last_message_processed_timestamp = Gauge::new(
+            "collector_last_message_processed_timestamp_seconds",
+            "Unix time of the last successfully processed message, or of startup before any",
+        )?;
+        last_message_processed_timestamp.set(unix_seconds(SystemTime::now()));
+
+        let circuit_breaker_state = Gauge::new(
+            "collector_circuit_breaker_state",
+            "Handler circuit breaker state: 0 closed, 1 open, 2 half-open",
+        )?;
+
         let active_consumers = Gauge::new(
             "collector_active_consumers",
             "Number of active consumer loops",
         )?;
 
-        registry.register(Box::new(messages_processed_total.clone()))?;
-        registry.register(Box::new(messages_failed_total.clone()))?;
-        registry.register(Box::new(messages_retried_total.clone()))?;
-        registry.register(Box::new(messages_dlq_total.clone()))?;
-        registry.register(Box::new(message_processing_duration_seconds.clone()))?;
-        registry.register(Box::new(active_consumers.clone()))?;
+        let queue_consuming = GaugeVec::new(
+            Opts::new(
+                "collector_queue_consuming",
+                "Whether a consumer is currently subscribed to the queue",
+            ),
+            &["queue"],
+        )?;
+
+        let cache_hits_total = Counter::new(
+            "collector_cache_hits_total",
+            "Total number of messages acked from the handler result cache",
+        )?;
+
+        let messages_reanimated_total = Counter::new(
+            "collector_messages_reanimated_total",
+            "Total number of messages moved from a dead letter queue back to their queue",
+        )?;
+
+        let lenient_field_missing_total = CounterVec::new(
+            Opts::new(
+                "collector_lenient_field_missing_total",
+                "Total number of messages processed despite a missing lenient required field",
+            ),
+            &["field"],
+        )?;
+
+        let success_sampled_total = Counter::new(
+            "collector_success_sampled_total",
+            "Total number of successfully processed events written to the sample store",
+        )?;
+
+        let clock_skew_detected_total = Counter::new(
+            "collector_clock_skew_detected_total",
+            "Total number of producer timestamps too far in the future to measure latency from",
+        )?;
+
+        let signature_rejected_total = Counter::new(
+            "collector_signature_rejected_total",
+            "Total number of messages rejected for a missing or mismatched signature",
+        )?;
+
+        let signature_unverifiable_total = CounterVec::new(
+            Opts::new(
+                "collector_signature_unverifiable_total",
+                "Total number of messages whose signature could not be checked, by failure mode",
+            ),
+            &["mode"],
+        )?;
+
+        let connection_recoveries_total = Counter::new(
+            "collector_connection_recoveries_total",
+            "Total number of times the consumer reconnected after losing the broker connection",
+        )?;
+
+        let reconnect_attempts_total = Counter::new(
+            "collector_reconnect_attempts_total",
+            "Total number of attempts to reconnect to the broker, successful or not",
+        )?;
+
+        let consumer_restarts_total = Counter::new(
+            "collector_consumer_restarts_total",
+            "Total number of attempts to resubscribe after a consumer's delivery stream ended",
+        )?;
+
+        let rabbitmq_connected = Gauge::new(
+            "collector_rabbitmq_connected",
+            "Whether the broker connection is up (1) or down (0)",
+        )?;
+
+        let queue_depth = GaugeVec::new(
+            Opts::new(
+                "collector_queue_depth",
+                "Number of messages ready in the queue, polled from the broker",
+            ),
+            &["queue"],
+        )?;
+
+        let topology_drift_total = CounterVec::new(
+            Opts::new(
+                "collector_topology_drift_total",
+                "Total number of queues found to differ from their declaration after a reconnect",
+            ),
+            &["queue"],
+        )?;
+
+        let wal_pending_entries = GaugeVec::new(
+            Opts::new(
+                "collector_wal_pending_entries",
+                "Number of events in a write-ahead buffer not yet forwarded to its target",
+            ),
+            &["target"],
+        )?;
+
+        let downstream_buffered_events = Gauge::new(
+            "collector_downstream_buffered_events",
+            "Number of events buffered for the HTTP downstream",
+        )?;
+        let downstream_events_forwarded_total = Counter::new(
+            "collector_downstream_events_forwarded_total",
+            "Total number of events delivered to the HTTP downstream",
+        )?;
+        let downstream_events_rejected_total = Counter::new(
+            "collector_downstream_events_rejected_total",
+            "Total number of events dropped because the HTTP downstream rejected their batch",
+        )?;
+
+        let build_info = GaugeVec::new(
+            Opts::new(
+                "collector_build_info",
+                "Build of the running collector, always 1",
+            ),
+            &["version", "git_sha", "rust_version"],
+        )?;
+        build_info
+            .with_label_values(&[
+                env!("CARGO_PKG_VERSION"),
+                env!("COLLECTOR_GIT_SHA"),
+                env!("COLLECTOR_RUST_VERSION"),
+            ])
+            .set(1.0);
+
+        // Built twice so a failed registration can take back the ones before it.
+        let collectors = || -> Vec<Box<dyn Collector>> {
+            vec![
+                Box::new(messages_processed_total.clone()),
+                Box::new(messages_failed_total.clone()),
+                Box::new(messages_retried_total.clone()),
+                Box::new(messages_dlq_total.clone()),
+                Box::new(messages_panicked_total.clone()),
+                Box::new(messages_redelivered_total.clone()),
+                Box::new(messages_timed_out_total.clone()),
+                Box::new(messages_deduplicated_total.clone()),
+                Box::new(messages_quarantined_total.clone()),
+                Box::new(messages_discarded_total.clone()),
+                Box::new(ack_failures_total.clone()),
+                Box::new(message_processing_duration_seconds.clone()),
+                Box::new(queue_wait_seconds.clone()),
+                Box::new(message_age_seconds.clone()),
+                Box::new(dlq_retry_count.clone()),
+                Box::new(rate_limit_wait_seconds.clone()),
+                Box::new(last_message_processed_timestamp.clone()),
+                Box::new(circuit_breaker_state.clone()),
+                Box::new(active_consumers.clone()),
+                Box::new(queue_consuming.clone()),
+                Box::new(queue_depth.clone()),
+                Box::new(cache_hits_total.clone()),
+                Box::new(messages_reanimated_total.clone()),
+                Box::new(lenient_field_missing_total.clone()),
+                Box::new(success_sampled_total.clone()),
+                Box::new(clock_skew_detected_total.clone()),
+                Box::new(signature_rejected_total.clone()),
+                Box::new(signature_unverifiable_total.clone()),
+                Box::new(connection_recoveries_total.clone()),
+                Box::new(reconnect_attempts_total.clone()),
+                Box::new(consumer_restarts_total.clone()),
+                Box::new(rabbitmq_connected.clone()),
+                Box::new(topology_drift_total.clone()),
+                Box::new(wal_pending_entries.clone()),
+                Box::new(downstream_buffered_events.clone()),
+                Box::new(downstream_events_forwarded_total.clone()),
+                Box::new(downstream_events_rejected_total.clone()),
+                Box::new(build_info.clone()),
+            ]
+        };
+        check_const_labels(&config.const_labels, &collectors())?;
+        register_all(&registry, collectors)?;
 
         Ok(Arc::new(Self {
             messages_processed_total,
             messages_failed_total,
             messages_retried_total,
             messages_dlq_total,
+            messages_panicked_total,
+            messages_redelivered_total,
+            messages_timed_out_total,
+            messages_deduplicated_total,
+            messages_quarantined_total,
+            messages_discarded_total,
+            ack_failures_total,
             message_processing_duration_seconds,
+            queue_wait_seconds,
+            message_age_seconds,
+            dlq_retry_count,
+            rate_limit_wait_seconds,
+            last_message_processed_timestamp,
+            circuit_breaker_state,
             active_consumers,
+            queue_consuming,
+            queue_depth,
+            cache_hits_total,
+            messages_reanimated_total,
+            lenient_field_missing_total,
+            success_sampled_total,
+            clock_skew_detected_total,
+            signature_rejected_total,
+            signature_unverifiable_total,
+            connection_recoveries_total,
+            reconnect_attempts_total,
+            consumer_restarts_total,
+            rabbitmq_connected,
+            topology_drift_total,
+            wal_pending_entries,
+            downstream_buffered_events,
+            downstream_events_forwarded_total,
+            downstream_events_rejected_total,
+            build_info,
             registry,
+            histogram_mirror: OnceLock::new(),
+            handler_labels: config.handler_labels.clone(),
+            handler_label_max_values: config
+                .handler_label_max_values
+                .unwrap_or(DEFAULT_HANDLER_LABEL_MAX_VALUES),
+            handler_label_values: Mutex::new(HashMap::new()),
         }))
     }
+
+    /// Counts a successfully processed message, with the value the handler
+    /// gave each configured handler label, or an empty one where it gave none.
+    /// Labels that are not configured are ignored.
+    pub fn record_processed(
+        &self,
+        queue: &str,
+        routing_key: &str,
+        version: &str,
+        labels: &[(String, String)],
+    ) {
+        let handler_values: Vec<String> = self
+            .handler_labels
+            .iter()
+            .map(|name| {
+                let value = labels
+                    .iter()
+                    .find(|(label, _)| label == name)
+                    .map_or("", |(_, value)| value.as_str());
+                self.bounded_label_value(name, value)
+            })
+            .collect();
+        let values: Vec<&str> = [queue, routing_key, version]
+            .into_iter()
+            .chain(handler_values.iter().map(String::as_str))
+            .collect();
+        self.messages_processed_total.with_label_values(&values).inc();
+    }
+
+    /// Marks `at` as the time the last message was processed.
+    pub fn mark_processed_at(&self, at: SystemTime) {
+        self.last_message_processed_timestamp.set(unix_seconds(at));
+    }
+
+    /// `value`, or `OTHER_LABEL_VALUE` once `name` already has
+    /// `handler_label_max_values` other values, keeping the series count bounded.
+    fn bounded_label_value(&self, name: &str, value: &str) -> String {
+        if value.is_empty() {
+            return String::new();
+        }
+        let mut recorded = self.handler_label_values.lock().unwrap();
+        let values = recorded.entry(name.to_string()).or_default();
+        if values.contains(value) || values.len() < self.handler_label_max_values {
+            values.insert(value.to_string());
+            value.to_string()
+        } else {
+            OTHER_LABEL_VALUE.to_string()
+        }
+    }
+
+    /// Records `value` in `histogram` and forwards it to the mirror, if any.
+    pub fn observe(&self, histogram: &HistogramVec, label_values: &[&str], value: f64) {
+        histogram.with_label_values(label_values).observe(value);
+        if let Some(mirror) = self.histogram_mirror.get() {
+            mirror.observe(histogram, label_values, value);
+        }
+    }
+
+    /// Attaches a mirror for histogram observations. Only the first one sticks.
+    pub fn set_histogram_mirror(&self, mirror: Arc<dyn HistogramMirror>) {
+        let _ = self.histogram_mirror.set(mirror);
+    }
+
+    pub fn histogram_mirror(&self) -> Option<Arc<dyn HistogramMirror>> {
+        self.histogram_mirror.get().cloned()
+    }
+
+    /// Queues with a live consumer, read back from `collector_queue_consuming`.
+    pub fn consumed_queues(&self) -> Vec<String> {
+        let mut queues: Vec<String> = self
+            .queue_consuming
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| metric.get_gauge().get_value() > 0.0)
+            .flat_map(|metric| metric.get_label())
+            .filter(|label| label.get_name() == "queue")
+            .map(|label| label.get_value().to_string())
+            .collect();
+        queues.sort();
+        queues
+    }
+
+    /// Messages processed so far, summed over every queue and routing key.
+    pub fn processed_total(&self) -> u64 {
+        self.messages_processed_total
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| metric.get_counter().get_value())
+            .sum::<f64>() as u64
+    }
+
+    /// Observations of `message_processing_duration_seconds` for `queue`, and
+    /// their sum in seconds, over every status.
+    pub fn processing_totals(&self, queue: &str) -> (u64, f64) {
+        self.message_processing_duration_seconds
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "queue" && label.get_value() == queue)
+            })
+            .map(|metric| metric.get_histogram())
+            .fold((0, 0.0), |(count, sum), histogram| {
+                (count + histogram.get_sample_count(), sum + histogram.get_sample_sum())
+            })
+    }
+}
+
+/// `at` as fractional seconds since the Unix epoch, or 0 before it.
+fn unix_seconds(at: SystemTime) -> f64 {
+    at.duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+/// Fails if a constant label reuses a variable label of any of `collectors`,
+/// or the histograms' `le`. The registry adds constant labels when gathering
+/// without checking them, so a clash would otherwise only show up as series
+/// carrying the same label twice.
+fn check_const_labels(
+    const_labels: &[(String, String)],
+    collectors: &[Box<dyn Collector>],
+) -> prometheus::Result<()> {
+    for (name, _) in const_labels {
+        let clashes = name == "le"
+            || collectors
+                .iter()
+                .flat_map(|collector| collector.desc())
+                .any(|desc| desc.variable_labels.contains(name));
+        if clashes {
+            return Err(prometheus::Error::Msg(format!(
+                "`{}` is already a label of a collector metric",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Registers every collector from `collectors`, or none of them.
+fn register_all(
+    registry: &Registry,
+    collectors: impl Fn() -> Vec<Box<dyn Collector>>,
+) -> prometheus::Result<()> {
+    for (registered, collector) in collectors().into_iter().enumerate() {
+        if let Err(e) = registry.register(collector) {
+            for collector in collectors().into_iter().take(registered) {
+                let _ = registry.unregister(collector);
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::IntCounter;
+
+    fn metric_names(registry: &Registry) -> Vec<String> {
+        registry
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_shares_registry_with_application_metrics() {
+        let registry = Registry::new();
+        let requests = IntCounter::new("app_requests_total", "Application requests").unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+
+        let metrics = Metrics::with_registry(registry.clone()).unwrap();
+        requests.inc();
+        metrics.messages_retried_total.inc();
+
+        let names = metric_names(&registry);
+        assert!(names.contains(&"app_requests_total".to_string()));
+        assert!(names.contains(&"collector_messages_retried_total".to_string()));
+    }
+
+    #[test]
+    fn test_build_info_is_one_with_build_labels() {
+        let metrics = Metrics::new().unwrap();
+
+        let families = metrics.registry.gather();
+        let family = families
+            .iter()
+            .find(|family| family.get_name() == "collector_build_info")
+            .unwrap();
+        let [metric] = family.get_metric() else {
+            panic!("expected one build_info series");
+        };
+        let labels: Vec<(&str, &str)> = metric
+            .get_label()
+            .iter()
+            .map(|label| (label.get_name(), label.get_value()))
+            .collect();
+
+        assert_eq!(metric.get_gauge().get_value(), 1.0);
+        assert_eq!(
+            labels.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            vec!["git_sha", "rust_version", "version"]
+        );
+        assert!(labels.contains(&("version", env!("CARGO_PKG_VERSION"))));
+        assert!(labels.iter().all(|(_, value)| !value.is_empty()));
+    }
+
+    #[test]
+    fn test_double_registration_is_an_error() {
+        let registry = Registry::new();
+        let _metrics = Metrics::with_registry(registry.clone()).unwrap();
+        let before = metric_names(&registry);
+
+        let error = Metrics::with_registry(registry.clone()).err().unwrap();
+
+        assert!(matches!(
+            error.downcast_ref::<prometheus::Error>(),
+            Some(prometheus::Error::AlreadyReg)
+        ));
+        assert_eq!(metric_names(&registry), before);
+    }
+
+    #[test]
+    fn test_failed_registration_leaves_registry_unchanged() {
+        let registry = Registry::new();
+        // Clashes with a collector metric registered late in `with_registry`.
+        let clash = Counter::new(
+            "collector_downstream_events_rejected_total",
+            "Total number of events dropped because the HTTP downstream rejected their batch",
+        )
+        .unwrap();
+        registry.register(Box::new(clash.clone())).unwrap();
+
+        assert!(Metrics::with_registry(registry.clone()).is_err());
+
+        // Nothing registered before the clash was left behind.
+        registry.unregister(Box::new(clash)).unwrap();
+        assert!(Metrics::with_registry(registry).is_ok());
+    }
+
+    fn bucket_bounds(metrics: &Metrics) -> Vec<f64> {
+        metrics.observe(
+            &metrics.message_processing_duration_seconds,
+            &["telemetry", "success"],
+            0.0002,
+        );
+        let families = metrics.message_processing_duration_seconds.collect();
+        families[0].get_metric()[0]
+            .get_histogram()
+            .get_bucket()
+            .iter()
+            .map(|bucket| bucket.get_upper_bound())
+            .collect()
+    }
+
+    #[test]
+    fn test_handler_label_values_are_capped() {
+        let metrics = Metrics::with_config(&MetricsConfig {
+            handler_labels: vec!["tenant".to_string()],
+            handler_label_max_values: Some(2),
+            ..MetricsConfig::default()
+        })
+        .unwrap();
+        let tenant = |value: &str| vec![("tenant".to_string(), value.to_string())];
+
+        for value in ["a", "b", "c", "a", "d"] {
+            metrics.record_processed("telemetry", "telemetry", "v1", &tenant(value));
+        }
+
+        let count = |value: &str| {
+            metrics
+                .messages_processed_total
+                .with_label_values(&["telemetry", "telemetry", "v1", value])
+                .get()
+        };
+        assert_eq!(count("a"), 2.0);
+        assert_eq!(count("b"), 1.0);
+        assert_eq!(count(OTHER_LABEL_VALUE), 2.0);
+        assert_eq!(metrics.processed_total(), 5);
+    }
+
+    #[test]
+    fn test_custom_buckets_are_used() {
+        let buckets = vec![0.0001, 0.0005, 0.001, 60.0, 600.0];
+        let config = MetricsConfig {
+            processing_duration_buckets: Some(buckets.clone()),
+            ..MetricsConfig::default()
+        };
+
+        let metrics = Metrics::with_config(&config).unwrap();
+
+        assert_eq!(bucket_bounds(&metrics), buckets);
+        assert_eq!(
+            bucket_bounds(&Metrics::new().unwrap()),
+            DEFAULT_PROCESSING_DURATION_BUCKETS
+        );
+    }
+
+    #[test]
+    fn test_const_labels_are_on_every_gathered_series() {
+        let config = MetricsConfig {
+            const_labels: vec![
+                ("service_name".to_string(), "edge-collector".to_string()),
+                ("host_name".to_string(), "node-1".to_string()),
+            ],
+            ..MetricsConfig::default()
+        };
+        let labels = |metrics: &Metrics| -> Vec<HashMap<String, String>> {
+            metrics
+                .registry
+                .gather()
+                .iter()
+                .flat_map(|family| family.get_metric().iter())
+                .map(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+                        .collect()
+                })
+                .collect()
+        };
+
+        let metrics = Metrics::with_config(&config).unwrap();
+        metrics.reconnect_attempts_total.inc();
+        let gathered = labels(&metrics);
+        assert!(!gathered.is_empty());
+        for series in &gathered {
+            assert_eq!(series["service_name"], "edge-collector");
+            assert_eq!(series["host_name"], "node-1");
+        }
+
+        let per_queue = Metrics::for_queue("logs", &config).unwrap();
+        for series in labels(&per_queue) {
+            assert_eq!(series["service_name"], "edge-collector");
+            assert_eq!(series[REGISTRY_LABEL], "logs");
+        }
+    }
+
+    #[test]
+    fn test_buckets_must_strictly_increase() {
+        let config = MetricsConfig {
+            queue_wait_buckets: Some(vec![1.0, 5.0, 5.0]),
+            ..MetricsConfig::default()
+        };
+
+        let error = Metrics::with_config(&config).err().unwrap();
+
+        assert_eq!(
+            error.downcast_ref::<BucketError>(),
+            Some(&BucketError::NotIncreasing {
+                previous: 5.0,
+                next: 5.0
+            })
+        );
+        assert_eq!(validate_buckets(&[]), Err(BucketError::Empty));
+        assert!(matches!(
+            validate_buckets(&[1.0, f64::NAN]),
+            Err(BucketError::NotFinite(_))
+        ));
+    }
 }
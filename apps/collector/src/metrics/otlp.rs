@@ -0,0 +1,393 @@
+//! Export of the collector metrics over OTLP, alongside or instead of the
+//! Prometheus endpoint.
+//!
+//! The Prometheus registries stay the single source of truth. Counters and
+//! gauges become OpenTelemetry observable instruments whose callbacks read the
+//! registries at export time, so both exports report the same values.
+//! Histograms are mirrored observation by observation through
+//! [`HistogramMirror`], using the same bucket boundaries.
+
+use opentelemetry::metrics::{Histogram, Meter, MeterProvider};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use prometheus::core::Collector;
+use prometheus::proto::{Metric, MetricType};
+use prometheus::HistogramVec;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use super::resource::ResourceAttributes;
+use super::server::ServerState;
+use super::HistogramMirror;
+
+const METER_NAME: &str = "observability-collector";
+
+/// Mirrors every registry in a `ServerState` into OpenTelemetry instruments.
+pub struct OtlpBridge {
+    meter: Meter,
+    state: ServerState,
+    /// Counter and gauge families that already have an observable instrument.
+    observed: Mutex<HashSet<String>>,
+    histograms: Mutex<HashMap<String, Histogram<f64>>>,
+}
+
+impl OtlpBridge {
+    pub fn new(meter: Meter, state: ServerState) -> Arc<Self> {
+        Arc::new(Self {
+            meter,
+            state,
+            observed: Mutex::new(HashSet::new()),
+            histograms: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Registers an observable instrument for every counter and gauge family
+    /// not mirrored yet.
+    ///
+    /// Labelled families only show up in a registry once they have a child,
+    /// so this runs again before every export.
+    pub fn sync_instruments(&self) {
+        let mut observed = self.observed.lock().unwrap();
+        for metrics in self.state.all_metrics() {
+            for family in metrics.registry.gather() {
+                let name = family.get_name();
+                let kind = family.get_field_type();
+                if !matches!(kind, MetricType::COUNTER | MetricType::GAUGE)
+                    || observed.contains(name)
+                {
+                    continue;
+                }
+
+                self.register_observable(name.to_string(), family.get_help().to_string(), kind);
+                observed.insert(name.to_string());
+            }
+        }
+    }
+
+    fn register_observable(&self, name: String, help: String, kind: MetricType) {
+        debug!(metric = %name, "Mirroring metric to OTLP");
+        let state = self.state.clone();
+        let family_name = name.clone();
+        let read = move || observations(&state, &family_name, kind);
+
+        if kind == MetricType::COUNTER {
+            self.meter
+                .f64_observable_counter(name)
+                .with_description(help)
+                .with_callback(move |observer| {
+                    for (value, attributes) in read() {
+                        observer.observe(value, &attributes);
+                    }
+                })
+                .build();
+        } else {
+            self.meter
+                .f64_observable_gauge(name)
+                .with_description(help)
+                .with_callback(move |observer| {
+                    for (value, attributes) in read() {
+                        observer.observe(value, &attributes);
+                    }
+                })
+                .build();
+        }
+    }
+
+    /// Keeps the instrument set up to date until `shutdown` is notified.
+    pub async fn run(self: Arc<Self>, interval: Duration, shutdown: Arc<Notify>) {
+        loop {
+            self.sync_instruments();
+            tokio::select! {
+                _ = shutdown.notified() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+
+    fn histogram(&self, histogram: &HistogramVec) -> Option<Histogram<f64>> {
+        let desc = histogram.desc().into_iter().next()?;
+        let mut histograms = self.histograms.lock().unwrap();
+        if let Some(existing) = histograms.get(&desc.fq_name) {
+            return Some(existing.clone());
+        }
+
+        // The Prometheus side has just recorded, so the family has a child
+        // to read the bucket boundaries from.
+        let boundaries: Vec<f64> = histogram
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric().first())
+            .flat_map(|metric| metric.get_histogram().get_bucket())
+            .map(|bucket| bucket.get_upper_bound())
+            .collect();
+        let instrument = self
+            .meter
+            .f64_histogram(desc.fq_name.clone())
+            .with_description(desc.help.clone())
+            .with_boundaries(boundaries)
+            .build();
+        histograms.insert(desc.fq_name.clone(), instrument.clone());
+        Some(instrument)
+    }
+}
+
+impl HistogramMirror for OtlpBridge {
+    fn observe(&self, histogram: &HistogramVec, label_values: &[&str], value: f64) {
+        let Some(instrument) = self.histogram(histogram) else {
+            return;
+        };
+        let Some(desc) = histogram.desc().into_iter().next() else {
+            return;
+        };
+
+        let attributes: Vec<KeyValue> = desc
+            .variable_labels
+            .iter()
+            .zip(label_values)
+            .map(|(name, value)| KeyValue::new(name.clone(), value.to_string()))
+            .chain(desc.const_label_pairs.iter().map(|pair| {
+                KeyValue::new(pair.get_name().to_string(), pair.get_value().to_string())
+            }))
+            .collect();
+        instrument.record(value, &attributes);
+    }
+}
+
+/// Current value and labels of every series of `name`, across all registries.
+fn observations(state: &ServerState, name: &str, kind: MetricType) -> Vec<(f64, Vec<KeyValue>)> {
+    state
+        .all_metrics()
+        .iter()
+        .flat_map(|metrics| metrics.registry.gather())
+        .filter(|family| family.get_name() == name)
+        .flat_map(|mut family| family.take_metric().into_iter())
+        .map(|metric| (value(&metric, kind), attributes(&metric)))
+        .collect()
+}
+
+fn value(metric: &Metric, kind: MetricType) -> f64 {
+    match kind {
+        MetricType::COUNTER => metric.get_counter().get_value(),
+        _ => metric.get_gauge().get_value(),
+    }
+}
+
+fn attributes(metric: &Metric) -> Vec<KeyValue> {
+    metric
+        .get_label()
+        .iter()
+        .map(|label| KeyValue::new(label.get_name().to_string(), label.get_value().to_string()))
+        .collect()
+}
+
+/// Starts exporting every registry in `state` to an OTLP/HTTP `endpoint`,
+/// with `resource` as the resource of every export.
+///
+/// Returns the provider, which must be shut down to flush the last export,
+/// and the bridge, whose `run` loop must be spawned.
+pub fn install(
+    endpoint: &str,
+    interval: Duration,
+    resource: &ResourceAttributes,
+    state: ServerState,
+) -> Result<(SdkMeterProvider, Arc<OtlpBridge>), Box<dyn std::error::Error>> {
+    let exporter = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = meter_provider(
+        PeriodicReader::builder(exporter)
+            .with_interval(interval)
+            .build(),
+        resource,
+    );
+
+    let bridge = OtlpBridge::new(provider.meter(METER_NAME), state.clone());
+    for metrics in state.all_metrics() {
+        metrics.set_histogram_mirror(bridge.clone());
+    }
+
+    info!(
+        endpoint,
+        interval_secs = interval.as_secs(),
+        "Exporting metrics over OTLP"
+    );
+    Ok((provider, bridge))
+}
+
+fn meter_provider<E: PushMetricExporter>(
+    reader: PeriodicReader<E>,
+    resource: &ResourceAttributes,
+) -> SdkMeterProvider {
+    SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource.otel_resource())
+        .build()
+}
+
+/// Flushes the final export. Errors are logged, since this only runs at shutdown.
+pub fn shutdown(provider: &SdkMeterProvider) {
+    if let Err(e) = provider.shutdown() {
+        warn!(error = %e, "Failed to flush OTLP metrics on shutdown");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData, ResourceMetrics};
+    use opentelemetry_sdk::metrics::Temporality;
+
+    /// Keeps the value of every exported series, keyed by name and labels,
+    /// and the resource attributes of the last export.
+    #[derive(Clone, Default)]
+    struct Capture(
+        Arc<Mutex<HashMap<String, f64>>>,
+        Arc<Mutex<HashMap<String, String>>>,
+    );
+
+    impl Capture {
+        fn get(&self, key: &str) -> Option<f64> {
+            self.0.lock().unwrap().get(key).copied()
+        }
+
+        fn resource(&self, key: &str) -> Option<String> {
+            self.1.lock().unwrap().get(key).cloned()
+        }
+    }
+
+    fn series_key<'a>(name: &str, attributes: impl Iterator<Item = &'a KeyValue>) -> String {
+        let mut labels: Vec<String> = attributes
+            .map(|kv| format!("{}={}", kv.key, kv.value))
+            .collect();
+        labels.sort();
+        format!("{}{{{}}}", name, labels.join(","))
+    }
+
+    impl PushMetricExporter for Capture {
+        async fn export(&self, metrics: &ResourceMetrics) -> OTelSdkResult {
+            *self.1.lock().unwrap() = metrics
+                .resource()
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            let mut captured = self.0.lock().unwrap();
+            for metric in metrics.scope_metrics().flat_map(|scope| scope.metrics()) {
+                let AggregatedMetrics::F64(data) = metric.data() else {
+                    continue;
+                };
+                match data {
+                    MetricData::Sum(sum) => {
+                        for point in sum.data_points() {
+                            captured.insert(
+                                series_key(metric.name(), point.attributes()),
+                                point.value(),
+                            );
+                        }
+                    }
+                    MetricData::Histogram(histogram) => {
+                        for point in histogram.data_points() {
+                            let key = series_key(metric.name(), point.attributes());
+                            captured.insert(format!("{}_count", key), point.count() as f64);
+                            captured.insert(format!("{}_sum", key), point.sum());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn temporality(&self) -> Temporality {
+            Temporality::Cumulative
+        }
+    }
+
+    #[test]
+    fn test_exports_carry_the_resource_attributes() {
+        let metrics = Metrics::new().unwrap();
+        let capture = Capture::default();
+        let configured = vec![("deployment.environment".to_string(), "prod".to_string())];
+        let resource = ResourceAttributes::new("edge-collector", &configured);
+        let provider = meter_provider(PeriodicReader::builder(capture.clone()).build(), &resource);
+        let bridge = OtlpBridge::new(provider.meter(METER_NAME), ServerState::new(metrics.clone()));
+
+        metrics.reconnect_attempts_total.inc();
+        bridge.sync_instruments();
+        provider.force_flush().unwrap();
+
+        assert_eq!(capture.resource("service.name").as_deref(), Some("edge-collector"));
+        assert_eq!(
+            capture.resource("service.version").as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(capture.resource("host.name"), resource.get("host.name").map(String::from));
+        assert_eq!(capture.resource("deployment.environment").as_deref(), Some("prod"));
+    }
+
+    #[test]
+    fn test_otlp_counter_matches_prometheus_after_processing() {
+        let metrics = Metrics::new().unwrap();
+        let state = ServerState::new(metrics.clone());
+        let capture = Capture::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(capture.clone()).build())
+            .build();
+        let bridge = OtlpBridge::new(provider.meter(METER_NAME), state);
+        metrics.set_histogram_mirror(bridge.clone());
+
+        // What the consumer records for three successfully processed messages.
+        for duration in [0.002, 0.02, 0.2] {
+            metrics
+                .messages_processed_total
+                .with_label_values(&["telemetry", "telemetry", "v1"])
+                .inc();
+            metrics.observe(
+                &metrics.message_processing_duration_seconds,
+                &["telemetry", "success"],
+                duration,
+            );
+        }
+        bridge.sync_instruments();
+        provider.force_flush().unwrap();
+
+        let prometheus = metrics
+            .messages_processed_total
+            .with_label_values(&["telemetry", "telemetry", "v1"])
+            .get();
+        assert_eq!(prometheus, 3.0);
+        assert_eq!(
+            capture
+                .get("collector_messages_processed_total{queue=telemetry,routing_key=telemetry,version=v1}"),
+            Some(prometheus)
+        );
+
+        let histogram = metrics
+            .message_processing_duration_seconds
+            .with_label_values(&["telemetry", "success"]);
+        let key = "collector_message_processing_duration_seconds{queue=telemetry,status=success}";
+        assert_eq!(
+            capture.get(&format!("{}_count", key)),
+            Some(histogram.get_sample_count() as f64)
+        );
+        assert!(
+            (capture.get(&format!("{}_sum", key)).unwrap() - histogram.get_sample_sum()).abs()
+                < 1e-9
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use async_trait::async_trait;
+use lapin::options::QueueDeclareOptions;
+use lapin::types::FieldTable;
+use lapin::Channel;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+use tracing::{info, warn};
+
+use super::Metrics;
+use crate::messaging::RabbitMqConnection;
+
+/// Reads how many messages a queue holds.
+#[async_trait]
+pub trait QueueDepthSource: Send + Sync {
+    async fn message_count(&self, queue: &str) -> Result<u32, String>;
+}
+
+/// A passive declare, which reports the queue's ready messages without
+/// creating or changing it.
+#[async_trait]
+impl QueueDepthSource for Channel {
+    async fn message_count(&self, queue: &str) -> Result<u32, String> {
+        self.queue_declare(
+            queue,
+            QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+        .map(|queue| queue.message_count())
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// Polls on a channel of `connection`, opening a new one whenever the last
+/// was closed, as a poll of a missing queue does.
+pub struct ConnectionDepthSource {
+    connection: Arc<RabbitMqConnection>,
+    channel: Mutex<Option<Channel>>,
+}
+
+impl ConnectionDepthSource {
+    pub fn new(connection: Arc<RabbitMqConnection>) -> Self {
+        Self {
+            connection,
+            channel: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl QueueDepthSource for ConnectionDepthSource {
+    async fn message_count(&self, queue: &str) -> Result<u32, String> {
+        let mut current = self.channel.lock().await;
+        let channel = match current.take() {
+            Some(channel) if channel.status().connected() => channel,
+            _ => self
+                .connection
+                .get_connection()
+                .create_channel()
+                .await
+                .map_err(|e| e.to_string())?,
+        };
+        let count = channel.message_count(queue).await;
+        *current = Some(channel);
+        count
+    }
+}
+
+/// Sets `collector_queue_depth` for each queue every `interval`, so backlog
+/// can be alerted on. A failed poll is logged and the queue's gauge removed
+/// until a poll succeeds again, rather than left at a stale value.
+pub struct QueueDepthPoller<S> {
+    source: S,
+    queues: Vec<String>,
+    interval: Duration,
+    metrics: Arc<Metrics>,
+    shutdown: Arc<Notify>,
+}
+
+impl<S: QueueDepthSource> QueueDepthPoller<S> {
+    pub fn new(
+        source: S,
+        queues: Vec<String>,
+        interval: Duration,
+        metrics: Arc<Metrics>,
+        shutdown: Arc<Notify>,
+    ) -> Self {
+        Self {
+            source,
+            queues,
+            interval,
+            metrics,
+            shutdown,
+        }
+    }
+
+    pub async fn run(self) {
+        let mut ticks = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = self.shutdown.notified() => break,
+                _ = ticks.tick() => self.poll().await,
+            }
+        }
+        info!("Queue depth poller stopped");
+    }
+
+    async fn poll(&self) {
+        for queue in &self.queues {
+            match self.source.message_count(queue).await {
+                Ok(count) => self
+                    .metrics
+                    .queue_depth
+                    .with_label_values(&[queue])
+                    .set(count as f64),
+                Err(e) => {
+                    warn!(error = %e, queue = %queue, "Cannot poll queue depth");
+                    let _ = self.metrics.queue_depth.remove_label_values(&[queue]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    struct FakeBroker {
+        depths: Arc<Mutex<HashMap<String, u32>>>,
+    }
+
+    #[async_trait]
+    impl QueueDepthSource for FakeBroker {
+        async fn message_count(&self, queue: &str) -> Result<u32, String> {
+            self.depths
+                .lock()
+                .unwrap()
+                .get(queue)
+                .copied()
+                .ok_or_else(|| format!("NOT_FOUND - no queue '{}'", queue))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_polls_each_queue_until_shutdown() {
+        let metrics = Metrics::new().unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let depths = Arc::new(Mutex::new(HashMap::from([
+            ("telemetry".to_string(), 7),
+            ("telemetry.dlq".to_string(), 2),
+        ])));
+        let broker = FakeBroker {
+            depths: depths.clone(),
+        };
+        let poller = QueueDepthPoller::new(
+            broker,
+            ["telemetry", "telemetry.retry", "telemetry.dlq"]
+                .map(String::from)
+                .to_vec(),
+            Duration::from_secs(5),
+            metrics.clone(),
+            shutdown.clone(),
+        );
+        let handle = tokio::spawn(poller.run());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let depth = |queue| metrics.queue_depth.with_label_values(&[queue]).get();
+        assert_eq!(depth("telemetry"), 7.0);
+        assert_eq!(depth("telemetry.dlq"), 2.0);
+        // The failing queue is only logged.
+        assert!(metrics
+            .queue_depth
+            .remove_label_values(&["telemetry.retry"])
+            .is_err());
+
+        depths.lock().unwrap().insert("telemetry".to_string(), 3);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(depth("telemetry"), 3.0);
+
+        shutdown.notify_one();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_poll_drops_the_stale_depth() {
+        let metrics = Metrics::new().unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let depths = Arc::new(Mutex::new(HashMap::from([("telemetry".to_string(), 7)])));
+        let broker = FakeBroker {
+            depths: depths.clone(),
+        };
+        let poller = QueueDepthPoller::new(
+            broker,
+            vec!["telemetry".to_string()],
+            Duration::from_secs(5),
+            metrics.clone(),
+            shutdown.clone(),
+        );
+        let handle = tokio::spawn(poller.run());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(metrics.queue_depth.with_label_values(&["telemetry"]).get(), 7.0);
+
+        depths.lock().unwrap().clear();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(metrics.queue_depth.remove_label_values(&["telemetry"]).is_err());
+
+        shutdown.notify_one();
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// What the consumer did with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Processed,
+    Retried,
+    DeadLettered,
+    Discarded,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Processed => "processed",
+            Self::Retried => "retried",
+            Self::DeadLettered => "dead_lettered",
+            Self::Discarded => "discarded",
+        }
+    }
+}
+
+/// Metadata of one handled message, as served on `/admin/recent`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecentEvent {
+    pub queue: String,
+    pub correlation_id: Option<String>,
+    pub routing_key: String,
+    pub outcome: Outcome,
+    pub error_reason: Option<String>,
+    /// When handling finished, in milliseconds since the epoch.
+    pub timestamp_ms: u64,
+    pub duration_ms: f64,
+}
+
+/// The last `capacity` handled messages, oldest first, for a quick look at
+/// what just happened without external tooling.
+///
+/// A single mutex guards the ring; each record is one push and at most one
+/// pop, so contention stays negligible next to message handling.
+pub struct RecentEvents {
+    capacity: usize,
+    events: Mutex<VecDeque<RecentEvent>>,
+}
+
+impl RecentEvents {
+    /// A capacity of 0 records nothing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn record(&self, event: RecentEvent) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Up to `limit` events, most recent first.
+    pub fn latest(&self, limit: usize) -> Vec<RecentEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: u64, outcome: Outcome) -> RecentEvent {
+        RecentEvent {
+            queue: "telemetry".to_string(),
+            correlation_id: Some(format!("corr-{}", n)),
+            routing_key: "telemetry".to_string(),
+            outcome,
+            error_reason: (outcome != Outcome::Processed).then(|| format!("failure {}", n)),
+            timestamp_ms: 1_700_000_000_000 + n,
+            duration_ms: n as f64,
+        }
+    }
+
+    #[test]
+    fn test_ring_keeps_only_the_newest_events() {
+        let recent = RecentEvents::new(3);
+        for n in 1..=5 {
+            recent.record(event(n, Outcome::Processed));
+        }
+
+        let ids: Vec<u64> = recent
+            .latest(10)
+            .iter()
+            .map(|event| event.duration_ms as u64)
+            .collect();
+        assert_eq!(ids, vec![5, 4, 3]);
+
+        let disabled = RecentEvents::new(0);
+        disabled.record(event(1, Outcome::Processed));
+        assert!(disabled.latest(10).is_empty());
+    }
+}
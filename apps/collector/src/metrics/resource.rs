@@ -0,0 +1,170 @@
+//! Resource attributes describing this collector instance, so several
+//! collectors reporting to one backend can be told apart.
+//!
+//! They are the OTLP resource of the exported spans and metrics, and constant
+//! labels on every Prometheus series.
+
+use percent_encoding::percent_decode_str;
+
+pub const SERVICE_NAME: &str = "service.name";
+pub const SERVICE_VERSION: &str = "service.version";
+pub const HOST_NAME: &str = "host.name";
+
+/// Resource attributes as `key=value` pairs, in order and with unique keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceAttributes(Vec<(String, String)>);
+
+impl ResourceAttributes {
+    /// `service.name`, `service.version` and `host.name` of this process,
+    /// overridden and extended by `configured`, as from
+    /// `OTEL_RESOURCE_ATTRIBUTES`.
+    pub fn new(service_name: &str, configured: &[(String, String)]) -> Self {
+        let mut attributes = Self(vec![
+            (SERVICE_NAME.to_string(), service_name.to_string()),
+            (
+                SERVICE_VERSION.to_string(),
+                env!("CARGO_PKG_VERSION").to_string(),
+            ),
+            (HOST_NAME.to_string(), hostname()),
+        ]);
+        for (key, value) in configured {
+            attributes.set(key, value);
+        }
+        attributes
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match self.0.iter_mut().find(|(existing, _)| existing == key) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.0.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The attributes as Prometheus labels, named by `label_name`:
+    /// `service.name` becomes `service_name`.
+    pub fn metric_labels(&self) -> Vec<(String, String)> {
+        self.iter()
+            .map(|(key, value)| (label_name(key), value.to_string()))
+            .collect()
+    }
+
+    /// The attributes as an OpenTelemetry resource.
+    #[cfg(feature = "otlp")]
+    pub fn otel_resource(&self) -> opentelemetry_sdk::Resource {
+        opentelemetry_sdk::Resource::builder()
+            .with_attributes(self.iter().map(|(key, value)| {
+                opentelemetry::KeyValue::new(key.to_string(), value.to_string())
+            }))
+            .build()
+    }
+}
+
+/// Parses `OTEL_RESOURCE_ATTRIBUTES`: comma-separated `key=value` pairs with
+/// percent-encoded values, e.g. `deployment.environment=prod,team=obs`.
+pub fn parse_attributes(raw: &str) -> Result<Vec<(String, String)>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("`{}` is not a key=value pair", pair))?;
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(format!("`{}` has an empty key", pair));
+            }
+            let value = percent_decode_str(value.trim())
+                .decode_utf8()
+                .map_err(|_| format!("`{}` does not decode to UTF-8", pair))?;
+            Ok((key.to_string(), value.into_owned()))
+        })
+        .collect()
+}
+
+/// This host's name, from `HOSTNAME` or `/etc/hostname`, else `unknown`.
+pub fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// `key` as a Prometheus label name, every character not allowed in one
+/// replaced by `_`.
+pub fn label_name(key: &str) -> String {
+    key.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if c.is_ascii_alphabetic() || c == '_' || (i > 0 && c.is_ascii_digit()) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured_attributes_override_and_extend_the_derived_ones() {
+        let configured =
+            parse_attributes("service.name=edge-collector, deployment.environment=prod%2Ceu")
+                .unwrap();
+
+        let attributes = ResourceAttributes::new("collector", &configured);
+
+        assert_eq!(attributes.get(SERVICE_NAME), Some("edge-collector"));
+        assert_eq!(
+            attributes.get(SERVICE_VERSION),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(attributes.get(HOST_NAME), Some(hostname().as_str()));
+        assert_eq!(attributes.get("deployment.environment"), Some("prod,eu"));
+        assert_eq!(
+            ResourceAttributes::new("collector", &[]).get(SERVICE_NAME),
+            Some("collector")
+        );
+    }
+
+    #[test]
+    fn test_malformed_attributes_are_rejected() {
+        assert!(parse_attributes("team").is_err());
+        assert!(parse_attributes("=obs").is_err());
+        assert!(parse_attributes("team=%FF").is_err());
+        assert_eq!(parse_attributes(" , ").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_metric_labels_are_valid_label_names() {
+        let attributes = ResourceAttributes::new("collector", &[("1st.tier".into(), "a".into())]);
+
+        let names: Vec<String> = attributes
+            .metric_labels()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+
+        assert_eq!(
+            names,
+            vec!["service_name", "service_version", "host_name", "_st_tier"]
+        );
+    }
+}
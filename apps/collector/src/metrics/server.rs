@@ -1,37 +1,417 @@
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::{response::IntoResponse, routing::get, Router};
+use prometheus::proto::MetricFamily;
 use prometheus::{Encoder, TextEncoder};
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
 use tracing::info;
 
+use crate::adapters::sqlite::SqliteSink;
+use crate::adapters::{Downstream, LocalStore};
+use crate::messaging::CircuitBreaker;
+use crate::messaging::DedupCache;
+use crate::messaging::Quarantine;
+use crate::messaging::QueueTopology;
+use crate::messaging::RateLimiter;
+use crate::metrics::admin;
+use crate::metrics::events;
+use crate::metrics::health::{self, Readiness};
+use crate::metrics::recent::RecentEvents;
 use crate::metrics::Metrics;
 
+/// Shared state behind the metrics and admin endpoints.
+#[derive(Clone)]
+pub struct ServerState {
+    pub metrics: Arc<Metrics>,
+    /// Filled in once the consumers have declared their queues.
+    pub topology: Arc<RwLock<Vec<QueueTopology>>>,
+    /// Per-queue registries served on `/metrics/<queue>`, when enabled.
+    pub queue_metrics: Arc<RwLock<BTreeMap<String, Arc<Metrics>>>>,
+    /// Whether `/metrics` is routed at all.
+    pub prometheus_enabled: bool,
+    /// Route of the Prometheus scrape, `/metrics` unless configured.
+    pub metrics_path: String,
+    /// Bearer token the Prometheus and `/events` endpoints require, when set.
+    pub metrics_auth_token: Option<String>,
+    /// Served on `/admin/recent`; empty unless a consumer records into it.
+    pub recent: Arc<RecentEvents>,
+    /// Reported on `/readyz`; never ready unless the main consumer updates it.
+    pub readiness: Arc<Readiness>,
+    /// Local event store the consumers write to, when `LOCAL_STORE_PATH` is set.
+    pub local_store: Option<LocalStore>,
+    /// The SQLite side of `local_store`, queried on `/events`.
+    pub event_store: Option<Arc<SqliteSink>>,
+    /// HTTP downstream the consumers forward to, when `DOWNSTREAM_URL` is set.
+    pub downstream: Option<Downstream>,
+    /// Shared by every consumer, so a duplicate is caught whichever queue it arrives on.
+    pub dedup: Option<Arc<DedupCache>>,
+    /// Shared by every consumer, so failures add up across queues and retries.
+    pub quarantine: Option<Arc<Quarantine>>,
+    /// Shared by every consumer, so `MAX_MESSAGES_PER_SEC` caps them together.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Shared by every consumer, since they all call the same handler and sinks.
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl ServerState {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics,
+            topology: Arc::new(RwLock::new(Vec::new())),
+            queue_metrics: Arc::new(RwLock::new(BTreeMap::new())),
+            prometheus_enabled: true,
+            metrics_path: "/metrics".to_string(),
+            metrics_auth_token: None,
+            recent: Arc::new(RecentEvents::new(0)),
+            readiness: Arc::new(Readiness::new()),
+            local_store: None,
+            event_store: None,
+            downstream: None,
+            dedup: None,
+            quarantine: None,
+            rate_limiter: None,
+            circuit_breaker: None,
+        }
+    }
+
+    pub fn with_readiness(mut self, readiness: Arc<Readiness>) -> Self {
+        self.readiness = readiness;
+        self
+    }
+
+    pub fn with_recent_events(mut self, recent: Arc<RecentEvents>) -> Self {
+        self.recent = recent;
+        self
+    }
+
+    pub fn with_local_store(mut self, store: LocalStore) -> Self {
+        self.local_store = Some(store);
+        self
+    }
+
+    pub fn with_event_store(mut self, store: Arc<SqliteSink>) -> Self {
+        self.event_store = Some(store);
+        self
+    }
+
+    pub fn with_downstream(mut self, downstream: Downstream) -> Self {
+        self.downstream = Some(downstream);
+        self
+    }
+
+    pub fn with_dedup(mut self, cache: Arc<DedupCache>) -> Self {
+        self.dedup = Some(cache);
+        self
+    }
+
+    pub fn with_quarantine(mut self, quarantine: Arc<Quarantine>) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Drops the Prometheus endpoints, leaving only the admin ones.
+    pub fn with_prometheus_endpoint(mut self, enabled: bool) -> Self {
+        self.prometheus_enabled = enabled;
+        self
+    }
+
+    /// Serves the scrape on `path` and the per-queue registries on `<path>/<queue>`.
+    pub fn with_metrics_path(mut self, path: impl Into<String>) -> Self {
+        self.metrics_path = path.into();
+        self
+    }
+
+    /// Requires `Authorization: Bearer <token>` on the Prometheus endpoints
+    /// and on `/events`, which serves stored payloads. The health and admin
+    /// endpoints stay open.
+    pub fn with_metrics_auth_token(mut self, token: impl Into<String>) -> Self {
+        self.metrics_auth_token = Some(token.into());
+        self
+    }
+
+    /// Whether `headers` carry the configured bearer token, compared in
+    /// constant time. Always true without a token.
+    pub(crate) fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(expected) = &self.metrics_auth_token else {
+            return true;
+        };
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| bool::from(token.as_bytes().ct_eq(expected.as_bytes())))
+    }
+
+    pub fn register_queue_metrics(&self, queue_name: &str, metrics: Arc<Metrics>) {
+        self.queue_metrics
+            .write()
+            .unwrap()
+            .insert(queue_name.to_string(), metrics);
+    }
+
+    /// The shared metrics followed by every per-queue set.
+    pub fn all_metrics(&self) -> Vec<Arc<Metrics>> {
+        std::iter::once(self.metrics.clone())
+            .chain(self.queue_metrics.read().unwrap().values().cloned())
+            .collect()
+    }
+}
+
+pub fn router(state: ServerState) -> Router {
+    let mut router = Router::new();
+    if state.prometheus_enabled {
+        let queue_path = format!("{}/:queue", state.metrics_path);
+        router = router
+            .route(&state.metrics_path, get(metrics_handler))
+            .route(&queue_path, get(queue_metrics_handler));
+    }
+    router
+        .route("/healthz", get(health::healthz_handler))
+        .route("/readyz", get(health::readyz_handler))
+        .route("/admin/topology", get(admin::topology_handler))
+        .route("/admin/consumers", get(admin::consumers_handler))
+        .route("/admin/recent", get(admin::recent_handler))
+        .route("/events", get(events::events_handler))
+        .route("/events/:id", get(events::event_handler))
+        .with_state(state)
+}
+
 pub async fn start_metrics_server(
-    metrics: Arc<Metrics>,
-    port: u16,
+    state: ServerState,
+    addr: SocketAddr,
+    shutdown: Arc<Notify>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let app = Router::new().route("/metrics", get(metrics_handler));
-
-    let app = app.with_state(metrics);
+    let listener = TcpListener::bind(addr).await?;
+    serve_metrics(listener, state, shutdown).await
+}
 
-    let addr = format!("0.0.0.0:{}", port);
-    info!(addr = %addr, "Starting metrics server");
+/// Serves the metrics and admin endpoints on an already bound `listener`.
+///
+/// Once `shutdown` is notified the listener is closed and the call returns
+/// after in-flight requests finish, so a scrape is never cut off midway.
+pub async fn serve_metrics(
+    listener: TcpListener,
+    state: ServerState,
+    shutdown: Arc<Notify>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let app = router(state);
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    info!(addr = %listener.local_addr()?, "Starting metrics server");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { shutdown.notified().await })
+        .await?;
+    info!("Metrics server stopped");
 
     Ok(())
 }
 
+/// Aggregate scrape: the shared registry merged with every per-queue registry.
 async fn metrics_handler(
-    axum::extract::State(metrics): axum::extract::State<Arc<Metrics>>,
-) -> impl IntoResponse {
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    if !state.authorized(&headers) {
+        return unauthorized();
+    }
+    let families = merge_families(
+        state
+            .all_metrics()
+            .iter()
+            .flat_map(|metrics| metrics.registry.gather())
+            .collect(),
+    );
+    encode(&families)
+}
+
+async fn queue_metrics_handler(
+    State(state): State<ServerState>,
+    Path(queue): Path<String>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    if !state.authorized(&headers) {
+        return unauthorized();
+    }
+    let Some(metrics) = state.queue_metrics.read().unwrap().get(&queue).cloned() else {
+        return (StatusCode::NOT_FOUND, format!("no metrics registry for queue {}", queue))
+            .into_response();
+    };
+    encode(&metrics.registry.gather())
+}
+
+pub(crate) fn unauthorized() -> axum::response::Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        "missing or invalid bearer token",
+    )
+        .into_response()
+}
+
+fn encode(families: &[MetricFamily]) -> axum::response::Response {
     let encoder = TextEncoder::new();
-    let metric_families = metrics.registry.gather();
     let mut buffer = vec![];
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+    encoder.encode(families, &mut buffer).unwrap();
 
     (
         [("content-type", "text/plain; version=0.0.4")],
         buffer,
     )
+        .into_response()
+}
+
+/// Combines families of the same name gathered from different registries,
+/// since the text format allows each metric name only once per scrape.
+pub(crate) fn merge_families(families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+    let mut merged: BTreeMap<String, MetricFamily> = BTreeMap::new();
+    for mut family in families {
+        match merged.get_mut(family.get_name()) {
+            Some(existing) => existing.mut_metric().extend(family.take_metric()),
+            None => {
+                merged.insert(family.get_name().to_string(), family);
+            }
+        }
+    }
+    merged.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricsConfig;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn scrape(state: ServerState, path: &str) -> (StatusCode, String) {
+        let response = router(state)
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_serves_configured_path_on_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = ServerState::new(Metrics::new().unwrap()).with_metrics_path("/prometheus");
+        tokio::spawn(async move {
+            let _ = serve_metrics(listener, state, Arc::new(Notify::new())).await;
+        });
+
+        let get = |path: &str| reqwest::get(format!("http://{}{}", addr, path));
+        let scrape = get("/prometheus").await.unwrap();
+        assert_eq!(scrape.status(), StatusCode::OK);
+        assert!(scrape.text().await.unwrap().contains("collector_build_info"));
+        assert_eq!(get("/metrics").await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_the_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let state = ServerState::new(Metrics::new().unwrap());
+        let server_shutdown = shutdown.clone();
+        let server = tokio::spawn(async move {
+            serve_metrics(listener, state, server_shutdown).await.is_ok()
+        });
+        let scrape = reqwest::get(format!("http://{}/metrics", addr)).await.unwrap();
+        assert_eq!(scrape.status(), StatusCode::OK);
+
+        shutdown.notify_one();
+
+        let stopped_cleanly = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server stops after shutdown")
+            .unwrap();
+        assert!(stopped_cleanly);
+        assert!(reqwest::get(format!("http://{}/metrics", addr)).await.is_err());
+    }
+
+    async fn scrape_with(state: ServerState, authorization: Option<&str>) -> StatusCode {
+        let mut request = Request::get("/metrics");
+        if let Some(value) = authorization {
+            request = request.header(header::AUTHORIZATION, value);
+        }
+        router(state)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_token_is_required_when_configured() {
+        let state = ServerState::new(Metrics::new().unwrap()).with_metrics_auth_token("s3cret");
+
+        assert_eq!(scrape_with(state.clone(), Some("Bearer s3cret")).await, StatusCode::OK);
+        assert_eq!(scrape_with(state.clone(), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            scrape_with(state.clone(), Some("Bearer s3cre")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(scrape_with(state.clone(), Some("s3cret")).await, StatusCode::UNAUTHORIZED);
+        // Health checks stay open for probes that carry no token.
+        let (status, _) = scrape(state, "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_no_token_configured_leaves_metrics_open() {
+        let state = ServerState::new(Metrics::new().unwrap());
+
+        assert_eq!(scrape_with(state.clone(), None).await, StatusCode::OK);
+        assert_eq!(scrape_with(state, Some("Bearer anything")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_per_queue_registries_contain_only_their_queue() {
+        let state = ServerState::new(Metrics::new().unwrap());
+        for queue in ["logs", "traces"] {
+            let metrics = Metrics::for_queue(queue, &MetricsConfig::default()).unwrap();
+            metrics
+                .messages_processed_total
+                .with_label_values(&[queue, queue, "v1"])
+                .inc();
+            state.register_queue_metrics(queue, metrics);
+        }
+
+        let (status, logs) = scrape(state.clone(), "/metrics/logs").await;
+        assert_eq!(status, StatusCode::OK);
+        let series =
+            r#"collector_messages_processed_total{queue="logs",routing_key="logs",version="v1",registry="logs"} 1"#;
+        assert!(logs.contains(series));
+        assert!(!logs.contains("traces"));
+
+        let (_, aggregate) = scrape(state.clone(), "/metrics").await;
+        assert_eq!(
+            aggregate
+                .matches("# TYPE collector_messages_processed_total counter")
+                .count(),
+            1
+        );
+        assert!(aggregate.contains(r#"registry="logs""#));
+        assert!(aggregate.contains(r#"registry="traces""#));
+
+        let (status, _) = scrape(state, "/metrics/unknown").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
 }
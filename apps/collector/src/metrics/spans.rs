@@ -0,0 +1,61 @@
+//! Export of the per-message processing spans over OTLP.
+//!
+//! Spans are ordinary `tracing` spans; [`install`] returns the layer that
+//! turns them into OpenTelemetry spans, to be added to the subscriber.
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use tracing::warn;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::Registry;
+
+use super::resource::ResourceAttributes;
+
+const TRACER_NAME: &str = "observability-collector";
+
+/// Starts a batching OTLP/HTTP span exporter.
+///
+/// `endpoint` is the base URL, as in `OTEL_EXPORTER_OTLP_ENDPOINT`; spans go
+/// to its `/v1/traces` path. Spans carry `resource` as their resource.
+pub fn install(
+    endpoint: &str,
+    resource: &ResourceAttributes,
+) -> Result<(SdkTracerProvider, OpenTelemetryLayer<Registry, SdkTracer>), Box<dyn std::error::Error>>
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_endpoint(endpoint))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.otel_resource())
+        .build();
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(TRACER_NAME));
+    Ok((provider, layer))
+}
+
+/// Exports the spans still buffered.
+pub fn shutdown(provider: &SdkTracerProvider) {
+    if let Err(e) = provider.shutdown() {
+        warn!(error = %e, "Failed to flush OTLP spans on shutdown");
+    }
+}
+
+fn traces_endpoint(endpoint: &str) -> String {
+    format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traces_endpoint_appends_signal_path() {
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/"),
+            "http://localhost:4318/v1/traces"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Push of the collector metrics to StatsD over UDP, for environments that
+//! cannot scrape the Prometheus endpoint.
+//!
+//! Like the OTLP export, this reads the Prometheus registries, so both report
+//! the same series. Each flush translates them to StatsD types:
+//!
+//! - counters are sent as `|c` with the increase since the previous flush;
+//! - gauges are sent as `|g` with their current value;
+//! - histograms are sent as two counters, `<name>.count` and `<name>.sum`,
+//!   since the individual observations are not kept.
+//!
+//! Labels become DogStatsD tags (`|#queue:telemetry`).
+
+use prometheus::proto::{Metric, MetricType};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use super::server::ServerState;
+
+/// Largest datagram sent, so packets fit a 1500-byte MTU without fragmenting.
+const MAX_DATAGRAM: usize = 1432;
+
+/// Periodically sends every registry in a `ServerState` to a StatsD server.
+pub struct StatsdPusher {
+    socket: UdpSocket,
+    state: ServerState,
+    /// Last value sent for each counter series, keyed by its line without the value.
+    sent: HashMap<String, f64>,
+}
+
+impl StatsdPusher {
+    /// Resolves `addr` (`host:port`) and connects a UDP socket to it.
+    pub async fn connect(addr: &str, state: ServerState) -> io::Result<Self> {
+        let target = tokio::net::lookup_host(addr).await?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", addr))
+        })?;
+        let local: SocketAddr = if target.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(target).await?;
+
+        Ok(Self {
+            socket,
+            state,
+            sent: HashMap::new(),
+        })
+    }
+
+    /// The StatsD lines for the current state of the registries. Counters
+    /// that have not moved since the previous call are left out.
+    pub fn lines(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for metrics in self.state.all_metrics() {
+            for mut family in metrics.registry.gather() {
+                let name = family.get_name().to_string();
+                let kind = family.get_field_type();
+                for metric in family.take_metric().iter() {
+                    let tags = tags(metric);
+                    match kind {
+                        MetricType::COUNTER => {
+                            let value = metric.get_counter().get_value();
+                            self.counter(&mut lines, &name, &tags, value);
+                        }
+                        MetricType::GAUGE => {
+                            let value = metric.get_gauge().get_value();
+                            lines.push(format!("{}:{}|g{}", name, value, tags));
+                        }
+                        MetricType::HISTOGRAM => {
+                            let histogram = metric.get_histogram();
+                            let count = histogram.get_sample_count() as f64;
+                            self.counter(&mut lines, &format!("{}.count", name), &tags, count);
+                            let sum = histogram.get_sample_sum();
+                            self.counter(&mut lines, &format!("{}.sum", name), &tags, sum);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    fn counter(&mut self, lines: &mut Vec<String>, name: &str, tags: &str, value: f64) {
+        let key = format!("{}|c{}", name, tags);
+        let previous = self.sent.insert(key, value).unwrap_or(0.0);
+        // A counter below its last value was reset; it has grown by all of it since.
+        let delta = if value >= previous {
+            value - previous
+        } else {
+            value
+        };
+        if delta > 0.0 {
+            lines.push(format!("{}:{}|c{}", name, delta, tags));
+        }
+    }
+
+    /// Sends the current lines, packed into as few datagrams as fit.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        let lines = self.lines();
+        for datagram in pack(&lines) {
+            self.socket.send(datagram.as_bytes()).await?;
+        }
+        debug!(lines = lines.len(), "Pushed metrics to StatsD");
+        Ok(())
+    }
+
+    /// Flushes every `interval` until `shutdown` is notified, then once more.
+    pub async fn run(mut self, interval: Duration, shutdown: Arc<Notify>) {
+        loop {
+            tokio::select! {
+                _ = shutdown.notified() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+            if let Err(e) = self.flush().await {
+                warn!(error = %e, "Failed to push metrics to StatsD");
+            }
+        }
+        if let Err(e) = self.flush().await {
+            warn!(error = %e, "Failed to push final metrics to StatsD");
+        }
+    }
+}
+
+/// DogStatsD tags for the labels of `metric`, or an empty string if it has none.
+fn tags(metric: &Metric) -> String {
+    let tags: Vec<String> = metric
+        .get_label()
+        .iter()
+        .map(|label| format!("{}:{}", label.get_name(), sanitize(label.get_value())))
+        .collect();
+    if tags.is_empty() {
+        String::new()
+    } else {
+        format!("|#{}", tags.join(","))
+    }
+}
+
+/// Replaces the characters that delimit StatsD lines and tags.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ',' | '|' | '#' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Joins `lines` with newlines into datagrams of at most `MAX_DATAGRAM` bytes.
+/// A longer line is sent on its own.
+fn pack(lines: &[String]) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+
+    async fn received(socket: &UdpSocket) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut buf = [0u8; 65536];
+        while let Ok(Ok(len)) =
+            tokio::time::timeout(Duration::from_millis(200), socket.recv(&mut buf)).await
+        {
+            let datagram = String::from_utf8(buf[..len].to_vec()).unwrap();
+            lines.extend(datagram.lines().map(str::to_string));
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_flush_sends_statsd_lines() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let metrics = Metrics::new().unwrap();
+        let state = ServerState::new(metrics.clone());
+        let mut pusher = StatsdPusher::connect(&server.local_addr().unwrap().to_string(), state)
+            .await
+            .unwrap();
+
+        metrics.cache_hits_total.inc_by(2.0);
+        metrics.active_consumers.set(3.0);
+        metrics
+            .queue_wait_seconds
+            .with_label_values(&["telemetry"])
+            .observe(0.5);
+        pusher.flush().await.unwrap();
+
+        let lines = received(&server).await;
+        for expected in [
+            "collector_cache_hits_total:2|c",
+            "collector_active_consumers:3|g",
+            "collector_queue_wait_seconds.count:1|c|#queue:telemetry",
+            "collector_queue_wait_seconds.sum:0.5|c|#queue:telemetry",
+        ] {
+            assert!(
+                lines.iter().any(|line| line == expected),
+                "{} missing from {:?}",
+                expected,
+                lines
+            );
+        }
+
+        // Counters are sent as the increase since the last flush, and not at
+        // all once they stop moving; gauges are sent every time.
+        metrics.cache_hits_total.inc();
+        pusher.flush().await.unwrap();
+
+        let lines = received(&server).await;
+        assert!(lines.contains(&"collector_cache_hits_total:1|c".to_string()));
+        assert!(lines.contains(&"collector_active_consumers:3|g".to_string()));
+        assert!(!lines
+            .iter()
+            .any(|line| line.starts_with("collector_queue_wait_seconds")));
+    }
+
+    #[test]
+    fn test_lines_are_packed_into_bounded_datagrams() {
+        let lines: Vec<String> = (0..200).map(|i| format!("metric_{}:1|c", i)).collect();
+
+        let datagrams = pack(&lines);
+
+        assert!(datagrams.len() > 1);
+        assert!(datagrams
+            .iter()
+            .all(|datagram| datagram.len() <= MAX_DATAGRAM));
+        assert_eq!(datagrams.join("\n").lines().count(), 200);
+    }
+}
@@ -0,0 +1,20 @@
+use serde_json::Value;
+use tracing::info;
+
+use crate::messaging::HandlerError;
+
+/// `eventType` of log lines captured by the API.
+pub const LOG_CAPTURED: &str = "telemetry.log.captured";
+
+/// Records a captured log line. The payload shape is validated by the
+/// producer, so missing fields are logged as absent rather than rejected.
+pub fn process_log_captured(event: &Value) -> Result<(), HandlerError> {
+    let payload = &event["payload"];
+    info!(
+        level = payload["level"].as_str().unwrap_or_default(),
+        service = payload["serviceName"].as_str().unwrap_or_default(),
+        message = payload["message"].as_str().unwrap_or_default(),
+        "Log captured"
+    );
+    Ok(())
+}
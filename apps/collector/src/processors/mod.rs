@@ -1,3 +1,10 @@
 
 pub mod traits;
 pub mod log_processor;
+pub mod redact;
+pub mod registry;
+pub mod sampler;
+pub mod schema;
+pub mod telemetry;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,129 @@
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// What a redacted field's value is replaced with.
+pub const MASK: &str = "***";
+
+/// Characters of the payload included in the preview unless configured.
+pub const DEFAULT_PREVIEW_LEN: usize = 100;
+
+/// Builds the payload preview logged for each message, with the values of
+/// sensitive fields masked.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    /// Lowercased field names, matched case-insensitively at any depth.
+    fields: HashSet<String>,
+    /// Characters previewed; 0 turns the preview off.
+    preview_len: usize,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self {
+            fields: HashSet::new(),
+            preview_len: DEFAULT_PREVIEW_LEN,
+        }
+    }
+}
+
+impl Redactor {
+    pub fn new(fields: impl IntoIterator<Item = String>) -> Self {
+        Self::default().with_fields(fields)
+    }
+
+    pub fn with_fields(mut self, fields: impl IntoIterator<Item = String>) -> Self {
+        self.fields = fields.into_iter().map(|f| f.to_lowercase()).collect();
+        self
+    }
+
+    pub fn with_preview_len(mut self, preview_len: usize) -> Self {
+        self.preview_len = preview_len;
+        self
+    }
+
+    /// The first `preview_len` characters of `payload` for logging, cut on a
+    /// character boundary.
+    ///
+    /// A body that is not UTF-8 is summarized by its length rather than shown
+    /// mangled. With no fields to redact the raw text is previewed. Otherwise
+    /// the body is parsed and re-serialized with every matching field masked,
+    /// and a body that is not JSON gets no preview, since it cannot be
+    /// redacted.
+    pub fn preview(&self, payload: &[u8]) -> Option<String> {
+        if self.preview_len == 0 {
+            return None;
+        }
+        let Ok(text) = std::str::from_utf8(payload) else {
+            return Some(format!("<{} bytes, not UTF-8>", payload.len()));
+        };
+        if self.fields.is_empty() {
+            return Some(text.chars().take(self.preview_len).collect());
+        }
+
+        let mut json: Value = serde_json::from_str(text).ok()?;
+        self.redact(&mut json);
+        Some(json.to_string().chars().take(self.preview_len).collect())
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.fields.contains(&key.to_lowercase()) {
+                        *value = Value::String(MASK.to_string());
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_fields_are_masked_in_preview() {
+        let redactor = Redactor::new(["password".to_string(), "Email".to_string()]);
+        let payload = r#"{"eventType":"login","payload":{"user":"ann","Password":"hunter2","contacts":[{"email":"ann@example.com"}]}}"#;
+
+        let preview = redactor.preview(payload.as_bytes()).unwrap();
+
+        assert!(!preview.contains("hunter2"), "{preview}");
+        assert!(!preview.contains("ann@example.com"), "{preview}");
+        assert!(preview.contains(r#""Password":"***""#), "{preview}");
+        assert!(preview.contains(r#""user":"ann""#), "{preview}");
+
+        assert_eq!(redactor.preview(b"password=hunter2"), None);
+        assert_eq!(
+            Redactor::default().preview(b"password=hunter2").as_deref(),
+            Some("password=hunter2")
+        );
+    }
+
+    #[test]
+    fn test_zero_preview_len_disables_preview() {
+        let redactor = Redactor::default().with_preview_len(0);
+
+        assert_eq!(redactor.preview(b"{}"), None);
+        assert_eq!(redactor.preview(&[0xff, 0xfe]), None);
+    }
+
+    #[test]
+    fn test_preview_respects_char_boundaries() {
+        let redactor = Redactor::default().with_preview_len(3);
+
+        assert_eq!(
+            redactor.preview("日本語テキスト".as_bytes()).as_deref(),
+            Some("日本語")
+        );
+        assert_eq!(
+            redactor.preview(&[b'{', 0xff, 0xfe]).as_deref(),
+            Some("<3 bytes, not UTF-8>")
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::traits::EventProcessor;
+use crate::messaging::HandlerError;
+
+/// Routes validated events to the processor registered for their `eventType`,
+/// so a new event type is a `register` call rather than another match arm.
+#[derive(Clone, Default)]
+pub struct HandlerRegistry {
+    processors: HashMap<String, Arc<dyn EventProcessor>>,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `processor` for `event_type`, replacing any earlier one.
+    pub fn register(
+        mut self,
+        event_type: impl Into<String>,
+        processor: impl EventProcessor + 'static,
+    ) -> Self {
+        self.processors
+            .insert(event_type.into(), Arc::new(processor));
+        self
+    }
+
+    /// Registered event types, in no particular order.
+    pub fn event_types(&self) -> impl Iterator<Item = &str> {
+        self.processors.keys().map(String::as_str)
+    }
+
+    /// Hands `event` to the processor for `event_type`. Unknown types are
+    /// permanent errors: retrying cannot make a processor appear.
+    pub async fn dispatch(&self, event_type: &str, event: &Value) -> Result<(), HandlerError> {
+        match self.processors.get(event_type) {
+            Some(processor) => processor.process(event).await,
+            None => Err(HandlerError::Permanent(format!(
+                "Unknown event type: {}",
+                event_type
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counting(count: Arc<AtomicUsize>) -> impl EventProcessor {
+        move |_: &Value| {
+            count.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_routes_by_event_type() {
+        let logs = Arc::new(AtomicUsize::new(0));
+        let metrics = Arc::new(AtomicUsize::new(0));
+        let registry = HandlerRegistry::new()
+            .register("log", counting(logs.clone()))
+            .register("metric", counting(metrics.clone()));
+
+        registry.dispatch("log", &json!({})).await.unwrap();
+        registry.dispatch("log", &json!({})).await.unwrap();
+        registry.dispatch("metric", &json!({})).await.unwrap();
+        assert_eq!(logs.load(Ordering::SeqCst), 2);
+        assert_eq!(metrics.load(Ordering::SeqCst), 1);
+
+        let result = registry.dispatch("trace", &json!({})).await;
+        assert!(matches!(result, Err(HandlerError::Permanent(reason)) if reason.contains("trace")));
+    }
+}
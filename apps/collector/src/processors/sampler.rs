@@ -0,0 +1,152 @@
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+/// One sampled event, written as a line of JSON alongside the sampling time.
+#[derive(Debug, Serialize)]
+pub struct SuccessSample<'a> {
+    pub routing_key: &'a str,
+    pub message_id: Option<&'a str>,
+    pub schema_version: &'a str,
+    /// The event as parsed and validated, not the raw bytes.
+    pub event: &'a serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct SampleLine<'a> {
+    /// Milliseconds since the epoch.
+    sampled_at: u64,
+    #[serde(flatten)]
+    sample: SuccessSample<'a>,
+}
+
+/// Writes a random fraction of successfully processed events to a JSON Lines
+/// file for downstream analytics.
+///
+/// Only events that passed validation are offered, so the file is a sample
+/// of good traffic rather than of everything that arrived. Write failures are
+/// logged and never fail the message.
+pub struct SuccessSampler {
+    rate: f64,
+    rng: Mutex<fastrand::Rng>,
+    sink: Mutex<Box<dyn Write + Send>>,
+    metrics: Arc<Metrics>,
+}
+
+impl SuccessSampler {
+    /// Appends samples to `path`, creating it if needed.
+    pub fn to_file(path: &Path, rate: f64, metrics: Arc<Metrics>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(
+            rate,
+            Box::new(LineWriter::<File>::new(file)),
+            fastrand::Rng::new(),
+            metrics,
+        ))
+    }
+
+    fn new(
+        rate: f64,
+        sink: Box<dyn Write + Send>,
+        rng: fastrand::Rng,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            rate: rate.clamp(0.0, 1.0),
+            rng: Mutex::new(rng),
+            sink: Mutex::new(sink),
+            metrics,
+        }
+    }
+
+    /// Records `sample` with probability `rate`.
+    pub fn offer(&self, sample: SuccessSample<'_>) {
+        if self.rng.lock().unwrap().f64() >= self.rate {
+            return;
+        }
+
+        let sampled_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut line = match serde_json::to_vec(&SampleLine { sampled_at, sample }) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!(error = %e, "Failed to serialize success sample");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(e) = self.sink.lock().unwrap().write_all(&line) {
+            warn!(error = %e, "Failed to write success sample");
+            return;
+        }
+        self.metrics.success_sampled_total.inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_samples_roughly_the_configured_fraction() {
+        let metrics = Metrics::new().unwrap();
+        let buffer = SharedBuffer::default();
+        let sampler = SuccessSampler::new(
+            0.1,
+            Box::new(buffer.clone()),
+            fastrand::Rng::with_seed(7),
+            metrics.clone(),
+        );
+        let event = json!({"eventType": "log", "payload": {"level": "info"}});
+
+        for _ in 0..10_000 {
+            sampler.offer(SuccessSample {
+                routing_key: "telemetry",
+                message_id: Some("msg-1"),
+                schema_version: "v1",
+                event: &event,
+            });
+        }
+
+        let sampled = metrics.success_sampled_total.get() as usize;
+        assert!(
+            (800..=1200).contains(&sampled),
+            "sampled {} of 10000",
+            sampled
+        );
+
+        let written = buffer.0.lock().unwrap().clone();
+        let lines: Vec<serde_json::Value> = written
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), sampled);
+        assert_eq!(lines[0]["schema_version"], "v1");
+        assert_eq!(lines[0]["event"], event);
+        assert!(lines[0]["sampled_at"].as_u64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,81 @@
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::Validator;
+use serde_json::Value;
+use std::path::Path;
+
+/// The v1 event schema built into the collector, used unless
+/// `V1_SCHEMA_PATH` points at another one.
+const EMBEDDED_V1_SCHEMA: &str = include_str!("../../schemas/event.v1.json");
+
+/// A JSON Schema compiled once and checked against every event.
+pub struct EventSchema {
+    validator: Validator,
+}
+
+/// One way an event does not match its schema.
+#[derive(Debug)]
+pub struct SchemaViolation {
+    /// JSON pointer to the offending value; empty for the event itself.
+    pub path: String,
+    pub message: String,
+    /// Set when the violation is a missing property, so lenient fields can
+    /// be let through.
+    pub missing_property: Option<String>,
+}
+
+impl std::fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "Schema violation at {}: {}", path, self.message)
+    }
+}
+
+impl EventSchema {
+    pub fn embedded_v1() -> Self {
+        Self::from_json(EMBEDDED_V1_SCHEMA).expect("embedded v1 schema is valid")
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, SchemaError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_json(raw: &str) -> Result<Self, SchemaError> {
+        let schema: Value = serde_json::from_str(raw)?;
+        let validator =
+            jsonschema::validator_for(&schema).map_err(|e| SchemaError::Invalid(e.to_string()))?;
+        Ok(Self { validator })
+    }
+
+    /// Every violation in `event`, in schema order.
+    pub fn violations(&self, event: &Value) -> Vec<SchemaViolation> {
+        self.validator
+            .iter_errors(event)
+            .map(|error| SchemaViolation {
+                path: error.instance_path().to_string(),
+                missing_property: match error.kind() {
+                    ValidationErrorKind::Required { property } => {
+                        property.as_str().map(str::to_string)
+                    }
+                    _ => None,
+                },
+                message: error.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("Failed to read schema: {0}")]
+    Read(#[from] std::io::Error),
+
+    #[error("Schema is not JSON: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("Invalid schema: {0}")]
+    Invalid(String),
+}
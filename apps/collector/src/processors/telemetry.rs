@@ -0,0 +1,454 @@
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::contracts::V1Event;
+use crate::messaging::consumer::event_version;
+use crate::messaging::source::IncomingMessage;
+use crate::messaging::{HandlerError, HandlerOutcome, MessageHandler};
+use crate::metrics::Metrics;
+use crate::processors::redact::Redactor;
+use crate::processors::registry::HandlerRegistry;
+use crate::processors::sampler::{SuccessSample, SuccessSampler};
+use crate::processors::schema::EventSchema;
+
+/// Top-level fields every v2 event must carry. v2 renames `eventType` to
+/// `type` and adds when the event happened.
+pub const V2_REQUIRED_FIELDS: &[&str] = &["type", "timestamp", "payload"];
+
+/// How long a simulated rate-limited downstream asks to be left alone.
+const SIMULATED_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Handles telemetry events published to the main queue.
+pub struct TelemetryHandler {
+    /// Required fields whose absence is logged instead of rejected, for
+    /// producers that have not yet caught up with a schema change.
+    lenient_fields: HashSet<String>,
+    v1_schema: EventSchema,
+    /// Processors for validated events; `None` accepts every event type.
+    registry: Option<HandlerRegistry>,
+    sampler: Option<SuccessSampler>,
+    redactor: Redactor,
+    metrics: Arc<Metrics>,
+}
+
+impl TelemetryHandler {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            lenient_fields: HashSet::new(),
+            v1_schema: EventSchema::embedded_v1(),
+            registry: None,
+            sampler: None,
+            redactor: Redactor::default(),
+            metrics,
+        }
+    }
+
+    pub fn with_lenient_fields(mut self, fields: impl IntoIterator<Item = String>) -> Self {
+        self.lenient_fields = fields.into_iter().collect();
+        self
+    }
+
+    /// Validates v1 events against `schema` instead of the embedded one.
+    pub fn with_v1_schema(mut self, schema: EventSchema) -> Self {
+        self.v1_schema = schema;
+        self
+    }
+
+    /// Dispatches every validated event to the processor registered for its
+    /// `eventType`; events of unregistered types become permanent errors.
+    pub fn with_registry(mut self, registry: HandlerRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Offers every successfully validated event to `sampler`.
+    pub fn with_success_sampler(mut self, sampler: SuccessSampler) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    /// Masks the values of these JSON fields in the logged payload preview.
+    pub fn with_redacted_fields(mut self, fields: impl IntoIterator<Item = String>) -> Self {
+        self.redactor = self.redactor.with_fields(fields);
+        self
+    }
+
+    /// Characters of each payload logged as `payload_preview`; 0 logs none.
+    pub fn with_preview_len(mut self, preview_len: usize) -> Self {
+        self.redactor = self.redactor.with_preview_len(preview_len);
+        self
+    }
+
+    /// Validates a v1 event and returns it parsed, along with its typed form.
+    /// That is `None` only when lenient validation let a missing `eventType`
+    /// through.
+    fn handle_v1(
+        &self,
+        payload: &str,
+    ) -> Result<(serde_json::Value, Option<V1Event>), HandlerError> {
+        // Test error simulation
+        if payload.contains("\"fail\":\"transient\"") {
+            return Err(HandlerError::transient("Simulated transient failure"));
+        }
+
+        if payload.contains("\"fail\":\"rate_limited\"") {
+            return Err(HandlerError::Retry {
+                after: SIMULATED_RETRY_AFTER,
+                reason: "Simulated rate limit".to_string(),
+            });
+        }
+
+        if payload.contains("\"fail\":\"permanent\"") {
+            return Err(HandlerError::Permanent(
+                "Simulated permanent failure".to_string(),
+            ));
+        }
+
+        // Parse and validate v1 schema
+        let json = parse_event(payload)?;
+        let mut tolerated = false;
+        for violation in self.v1_schema.violations(&json) {
+            let lenient = violation.path.is_empty()
+                && violation
+                    .missing_property
+                    .as_deref()
+                    .is_some_and(|field| self.allow_missing(field));
+            if !lenient {
+                return Err(HandlerError::Permanent(violation.to_string()));
+            }
+            tolerated = true;
+        }
+
+        let event = match V1Event::from_json(&json) {
+            Ok(event) => Some(event),
+            Err(_) if tolerated => None,
+            Err(e) => return Err(HandlerError::Permanent(format!("Invalid v1 event: {}", e))),
+        };
+
+        info!(
+            event_type = event.as_ref().map(|event| event.event_type.as_str()),
+            source = event.as_ref().and_then(|event| event.source.as_deref()),
+            "Successfully processed v1 event"
+        );
+        Ok((json, event))
+    }
+
+    /// Validates a v2 event and returns it parsed.
+    fn handle_v2(&self, payload: &str) -> Result<serde_json::Value, HandlerError> {
+        let json = parse_event(payload)?;
+        self.check_required_fields(&json, V2_REQUIRED_FIELDS)?;
+
+        info!("Successfully processed v2 event");
+        Ok(json)
+    }
+
+    fn check_required_fields(
+        &self,
+        json: &serde_json::Value,
+        required: &[&str],
+    ) -> Result<(), HandlerError> {
+        for field in required {
+            if json.get(field).is_none() && !self.allow_missing(field) {
+                return Err(HandlerError::Permanent(format!(
+                    "Missing required field: {}",
+                    field
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a missing required top-level `field` is tolerated, counting it
+    /// if so.
+    fn allow_missing(&self, field: &str) -> bool {
+        if !self.lenient_fields.contains(field) {
+            return false;
+        }
+
+        self.metrics
+            .lenient_field_missing_total
+            .with_label_values(&[field])
+            .inc();
+        warn!(field, "Required field missing, processing anyway (lenient)");
+        true
+    }
+}
+
+fn parse_event(payload: &str) -> Result<serde_json::Value, HandlerError> {
+    serde_json::from_str(payload)
+        .map_err(|e| HandlerError::Permanent(format!("Invalid JSON payload: {}", e)))
+}
+
+#[async_trait]
+impl MessageHandler for TelemetryHandler {
+    async fn handle(
+        &self,
+        delivery: IncomingMessage,
+        _cancel: CancellationToken,
+    ) -> Result<HandlerOutcome, HandlerError> {
+        let payload = String::from_utf8_lossy(&delivery.data);
+
+        // Extract version from headers; one that is not a string is treated as v1
+        let version = event_version(&delivery.properties).unwrap_or_else(|| "v1".to_string());
+
+        info!(
+            routing_key = delivery.routing_key.as_str(),
+            version = %version,
+            payload_preview = self.redactor.preview(&delivery.data).as_deref(),
+            "Handling telemetry message"
+        );
+
+        // Version-based routing; `type_field` names the field holding the event type
+        let (event, typed, type_field) = match version.as_str() {
+            "v1" => {
+                let (event, typed) = self.handle_v1(&payload)?;
+                (event, typed, "eventType")
+            }
+            "v2" => (self.handle_v2(&payload)?, None, "type"),
+            _ => {
+                return Err(HandlerError::Permanent(format!(
+                    "Unsupported event version: {}. Supported versions: v1, v2.",
+                    version
+                )));
+            }
+        };
+
+        if let Some(registry) = &self.registry {
+            let event_type = match (&typed, event.get(type_field)) {
+                (Some(typed), _) => Some(typed.event_type.as_str()),
+                (None, Some(serde_json::Value::String(event_type))) => Some(event_type.as_str()),
+                (None, Some(_)) => {
+                    return Err(HandlerError::Permanent(format!(
+                        "{} must be a string",
+                        type_field
+                    )));
+                }
+                // Only reachable when the type field is lenient.
+                (None, None) => None,
+            };
+            match event_type {
+                Some(event_type) => registry.dispatch(event_type, &event).await?,
+                None => debug!(type_field, "Event has no type, skipping dispatch"),
+            }
+        }
+
+        if let Some(sampler) = &self.sampler {
+            sampler.offer(SuccessSample {
+                routing_key: delivery.routing_key.as_str(),
+                message_id: delivery
+                    .properties
+                    .message_id()
+                    .as_ref()
+                    .map(|id| id.as_str()),
+                schema_version: &version,
+                event: &event,
+            });
+        }
+
+        Ok(HandlerOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::consumer::EVENT_VERSION_HEADER;
+    use crate::messaging::test_util::{delivery, delivery_with_properties};
+    use lapin::types::{AMQPValue, FieldTable};
+    use lapin::BasicProperties;
+
+    fn versioned(version: &str, data: &[u8]) -> IncomingMessage {
+        let mut headers = FieldTable::default();
+        headers.insert(
+            EVENT_VERSION_HEADER.into(),
+            AMQPValue::LongString(version.into()),
+        );
+        delivery_with_properties(1, data, BasicProperties::default().with_headers(headers))
+    }
+
+    #[tokio::test]
+    async fn test_valid_v2_event_is_dispatched_on_type() {
+        let registry = HandlerRegistry::new().register("log", |_: &serde_json::Value| Ok(()));
+        let handler = TelemetryHandler::new(Metrics::new().unwrap()).with_registry(registry);
+
+        let result = handler
+            .handle(
+                versioned(
+                    "v2",
+                    br#"{"type":"log","timestamp":"2024-01-01T00:00:00Z","payload":{}}"#,
+                ),
+                CancellationToken::new(),
+            )
+            .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_v2_event_missing_timestamp_is_permanent() {
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+
+        let result = handler
+            .handle(versioned("v2", br#"{"type":"log","payload":{}}"#), CancellationToken::new())
+            .await;
+
+        assert!(
+            matches!(result, Err(HandlerError::Permanent(reason)) if reason.contains("timestamp"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_v1_event_still_accepted_and_unknown_version_rejected() {
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+
+        let v1 = handler
+            .handle(
+                versioned("v1", br#"{"eventType":"log","payload":{}}"#),
+                CancellationToken::new(),
+            )
+            .await;
+        assert!(v1.is_ok());
+
+        let v3 = handler
+            .handle(versioned("v3", br#"{"type":"log","payload":{}}"#), CancellationToken::new())
+            .await;
+        assert!(matches!(v3, Err(HandlerError::Permanent(reason)) if reason.contains("v3")));
+    }
+
+    #[tokio::test]
+    async fn test_v1_schema_rejects_wrong_type_event_type() {
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+
+        let result = handler
+            .handle(delivery(1, br#"{"eventType":42,"payload":{}}"#), CancellationToken::new())
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(HandlerError::Permanent(reason))
+                if reason.starts_with("Schema violation at /eventType:")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_v1_schema_rejects_missing_nested_field() {
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+
+        let result = handler
+            .handle(
+                delivery(
+                    1,
+                    br#"{"eventType":"telemetry.log.captured","payload":{"level":"info","serviceName":"api"}}"#,
+                ),
+                CancellationToken::new(),
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(HandlerError::Permanent(reason))
+                if reason.starts_with("Schema violation at /payload:") && reason.contains("message")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_registry_rejects_unregistered_event_type() {
+        let registry = HandlerRegistry::new().register("log", |_: &serde_json::Value| Ok(()));
+        let handler = TelemetryHandler::new(Metrics::new().unwrap()).with_registry(registry);
+
+        let known = handler
+            .handle(delivery(1, br#"{"eventType":"log","payload":{}}"#), CancellationToken::new())
+            .await;
+        assert!(known.is_ok());
+
+        let unknown = handler
+            .handle(
+                delivery(2, br#"{"eventType":"metric","payload":{}}"#),
+                CancellationToken::new(),
+            )
+            .await;
+        assert!(
+            matches!(unknown, Err(HandlerError::Permanent(reason)) if reason.contains("metric"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_asks_for_retry_after_hint() {
+        let handler = TelemetryHandler::new(Metrics::new().unwrap());
+
+        let result = handler
+            .handle(
+                delivery(1, br#"{"eventType":"log","fail":"rate_limited"}"#),
+                CancellationToken::new(),
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(HandlerError::Retry { after, .. }) if after == Duration::from_secs(30)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_field_outside_lenient_set_is_permanent() {
+        let metrics = Metrics::new().unwrap();
+        let handler =
+            TelemetryHandler::new(metrics.clone()).with_lenient_fields(vec!["payload".to_string()]);
+
+        let result = handler
+            .handle(delivery(1, br#"{"payload":{"level":"info"}}"#), CancellationToken::new())
+            .await;
+
+        assert!(
+            matches!(result, Err(HandlerError::Permanent(reason)) if reason.contains("eventType"))
+        );
+        assert_eq!(
+            metrics
+                .lenient_field_missing_total
+                .with_label_values(&["eventType"])
+                .get(),
+            0.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lenient_missing_event_type_skips_dispatch() {
+        let registry = HandlerRegistry::new().register("log", |_: &serde_json::Value| {
+            Err(HandlerError::Permanent("dispatched".to_string()))
+        });
+        let handler = TelemetryHandler::new(Metrics::new().unwrap())
+            .with_registry(registry)
+            .with_lenient_fields(vec!["eventType".to_string()]);
+
+        let result = handler
+            .handle(delivery(1, br#"{"payload":{}}"#), CancellationToken::new())
+            .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_missing_lenient_field_is_processed_and_counted() {
+        let metrics = Metrics::new().unwrap();
+        let handler =
+            TelemetryHandler::new(metrics.clone()).with_lenient_fields(vec!["payload".to_string()]);
+
+        let result = handler.handle(
+            delivery(1, br#"{"eventType":"log"}"#),
+            CancellationToken::new(),
+        ).await;
+
+        assert!(result.is_ok());
+        assert_eq!(
+            metrics
+                .lenient_field_missing_total
+                .with_label_values(&["payload"])
+                .get(),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::messaging::HandlerError;
+
+/// Processes one validated event of the type it is registered for in a
+/// [`HandlerRegistry`](super::registry::HandlerRegistry).
+#[async_trait]
+pub trait EventProcessor: Send + Sync {
+    async fn process(&self, event: &Value) -> Result<(), HandlerError>;
+}
+
+#[async_trait]
+impl<F> EventProcessor for F
+where
+    F: Fn(&Value) -> Result<(), HandlerError> + Send + Sync,
+{
+    async fn process(&self, event: &Value) -> Result<(), HandlerError> {
+        self(event)
+    }
+}
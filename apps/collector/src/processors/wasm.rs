@@ -0,0 +1,361 @@
+//! Payload transforms implemented as user-provided WebAssembly modules.
+//!
+//! A transform module must export:
+//!
+//! - `memory`: its linear memory.
+//! - `alloc(len: i32) -> i32`: returns a pointer to `len` writable bytes. The
+//!   payload is copied there before `transform` is called.
+//! - `transform(ptr: i32, len: i32) -> i64`: transforms the payload at
+//!   `ptr..ptr + len`. The result packs a pointer in the high 32 bits and a
+//!   length in the low 32 bits. With the sign bit clear they locate the
+//!   transformed payload; with it set they locate a UTF-8 rejection reason.
+//!
+//! Every message gets a fresh instance, so a module cannot carry state from
+//! one message to the next.
+
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use wasmtime::{
+    Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+};
+
+use crate::messaging::source::IncomingMessage;
+use crate::messaging::{HandlerError, HandlerOutcome, MessageHandler};
+
+/// Granularity of the execution time limit.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+const ERROR_FLAG: u64 = 1 << 63;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    /// Wall-clock budget for one `transform` call, including `alloc`.
+    pub timeout: Duration,
+    /// Largest linear memory the module may grow to.
+    pub max_memory_bytes: usize,
+}
+
+/// A compiled transform module, cheap to clone and share between tasks.
+#[derive(Clone)]
+pub struct WasmTransform {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    engine: Engine,
+    module: Module,
+    linker: Linker<StoreLimits>,
+    limits: WasmLimits,
+    ticker_stop: Arc<AtomicBool>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.ticker_stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl WasmTransform {
+    pub fn load(path: &Path, limits: WasmLimits) -> Result<Self, TransformError> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| TransformError::Load(format!("cannot read {}: {}", path.display(), e)))?;
+        Self::from_bytes(&bytes, limits)
+    }
+
+    /// Compiles a module from its binary or text (`.wat`) form.
+    pub fn from_bytes(bytes: &[u8], limits: WasmLimits) -> Result<Self, TransformError> {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).map_err(|e| TransformError::Load(format!("{:#}", e)))?;
+        let module =
+            Module::new(&engine, bytes).map_err(|e| TransformError::Load(format!("{:#}", e)))?;
+
+        for export in ["memory", "alloc", "transform"] {
+            if module.get_export(export).is_none() {
+                return Err(TransformError::Load(format!(
+                    "module does not export `{}`",
+                    export
+                )));
+            }
+        }
+
+        let ticker_stop = Arc::new(AtomicBool::new(false));
+        spawn_epoch_ticker(engine.clone(), ticker_stop.clone());
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                linker: Linker::new(&engine),
+                engine,
+                module,
+                limits,
+                ticker_stop,
+            }),
+        })
+    }
+
+    /// Runs the module over `payload`. Blocks for up to the configured timeout.
+    pub fn apply(&self, payload: &[u8]) -> Result<Vec<u8>, TransformError> {
+        let inner = &self.inner;
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(inner.limits.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&inner.engine, limits);
+        store.limiter(|limits| limits);
+        let ticks = inner
+            .limits
+            .timeout
+            .as_millis()
+            .div_ceil(EPOCH_TICK.as_millis()) as u64;
+        store.set_epoch_deadline(ticks.max(1));
+
+        let instance = inner
+            .linker
+            .instantiate(&mut store, &inner.module)
+            .map_err(trapped)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| TransformError::Abi("`memory` is not a memory".to_string()))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(|e| TransformError::Abi(format!("{:#}", e)))?;
+        let transform = instance
+            .get_typed_func::<(i32, i32), i64>(&mut store, "transform")
+            .map_err(|e| TransformError::Abi(format!("{:#}", e)))?;
+
+        let len = i32::try_from(payload.len())
+            .map_err(|_| TransformError::Abi("payload larger than 2 GiB".to_string()))?;
+        let ptr = alloc.call(&mut store, len).map_err(trapped)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, payload)
+            .map_err(|_| {
+                TransformError::Abi("`alloc` returned out-of-bounds memory".to_string())
+            })?;
+
+        let packed = transform.call(&mut store, (ptr, len)).map_err(trapped)? as u64;
+        let output = read_packed(&memory, &store, packed & !ERROR_FLAG)?;
+
+        if packed & ERROR_FLAG != 0 {
+            return Err(TransformError::Rejected(
+                String::from_utf8_lossy(&output).into_owned(),
+            ));
+        }
+        Ok(output)
+    }
+}
+
+fn read_packed(
+    memory: &Memory,
+    store: &Store<StoreLimits>,
+    packed: u64,
+) -> Result<Vec<u8>, TransformError> {
+    let ptr = (packed >> 32) as usize;
+    let len = (packed & 0xffff_ffff) as usize;
+    memory
+        .data(store)
+        .get(ptr..ptr.saturating_add(len))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| TransformError::Abi("`transform` returned out-of-bounds memory".to_string()))
+}
+
+fn trapped(error: wasmtime::Error) -> TransformError {
+    match error.downcast_ref::<Trap>() {
+        Some(Trap::Interrupt) => TransformError::TimedOut,
+        _ => TransformError::Trapped(format!("{:#}", error)),
+    }
+}
+
+/// Advances the engine epoch so running instances hit their deadline.
+/// Stops once the owning `WasmTransform` is dropped.
+fn spawn_epoch_ticker(engine: Engine, stop: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(EPOCH_TICK);
+            engine.increment_epoch();
+        }
+    });
+}
+
+/// Runs the payload through a `WasmTransform` before handing it to the inner handler.
+///
+/// Any transform failure, including a timeout or exceeding the memory limit,
+/// is a permanent error: the same payload would fail the same way again.
+pub struct TransformingHandler {
+    inner: Arc<dyn MessageHandler>,
+    transform: WasmTransform,
+}
+
+impl TransformingHandler {
+    pub fn new(inner: Arc<dyn MessageHandler>, transform: WasmTransform) -> Self {
+        Self { inner, transform }
+    }
+}
+
+#[async_trait]
+impl MessageHandler for TransformingHandler {
+    async fn handle(
+        &self,
+        mut delivery: IncomingMessage,
+        cancel: CancellationToken,
+    ) -> Result<HandlerOutcome, HandlerError> {
+        let transform = self.transform.clone();
+        let payload = std::mem::take(&mut delivery.data);
+
+        let output = tokio::task::spawn_blocking(move || transform.apply(&payload))
+            .await
+            .map_err(|e| HandlerError::transient(format!("WASM transform task failed: {}", e)))?;
+
+        match output {
+            Ok(data) => {
+                delivery.data = data;
+                self.inner.handle(delivery, cancel).await
+            }
+            Err(e) => Err(HandlerError::Permanent(format!(
+                "WASM transform failed: {}",
+                e
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransformError {
+    #[error("cannot load module: {0}")]
+    Load(String),
+
+    #[error("module does not follow the transform ABI: {0}")]
+    Abi(String),
+
+    #[error("payload rejected: {0}")]
+    Rejected(String),
+
+    #[error("module trapped: {0}")]
+    Trapped(String),
+
+    #[error("execution time limit exceeded")]
+    TimedOut,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::test_util::delivery;
+    use std::sync::Mutex;
+
+    /// Bump allocator plus a `transform` that returns its input unchanged.
+    const IDENTITY: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "transform") (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len)))))
+    "#;
+
+    /// Rejects every payload with the reason stored at offset 0.
+    const REJECT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "unsupported")
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "transform") (param i32 i32) (result i64)
+            (i64.or (i64.const 0x8000000000000000) (i64.const 11))))
+    "#;
+
+    const SPIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "transform") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0)))
+    "#;
+
+    /// Tries to grow memory to 64 MiB before doing anything.
+    const GREEDY: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32)
+            (if (i32.lt_s (memory.grow (i32.const 1023)) (i32.const 0))
+              (then unreachable))
+            (i32.const 1024))
+          (func (export "transform") (param i32 i32) (result i64) (i64.const 0)))
+    "#;
+
+    fn limits() -> WasmLimits {
+        WasmLimits {
+            timeout: Duration::from_millis(100),
+            max_memory_bytes: 16 * 1024 * 1024,
+        }
+    }
+
+    struct Recording(Mutex<Vec<Vec<u8>>>);
+
+    #[async_trait]
+    impl MessageHandler for Recording {
+        async fn handle(
+            &self,
+            delivery: IncomingMessage,
+            _cancel: CancellationToken,
+        ) -> Result<HandlerOutcome, HandlerError> {
+            self.0.lock().unwrap().push(delivery.data);
+            Ok(HandlerOutcome::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identity_module_passes_payload_through() {
+        let transform = WasmTransform::from_bytes(IDENTITY.as_bytes(), limits()).unwrap();
+        assert_eq!(
+            transform.apply(br#"{"eventType":"log"}"#).unwrap(),
+            br#"{"eventType":"log"}"#
+        );
+
+        let recording = Arc::new(Recording(Mutex::new(Vec::new())));
+        let handler = TransformingHandler::new(recording.clone(), transform);
+        handler.handle(delivery(1, b"payload"), CancellationToken::new()).await.unwrap();
+
+        assert_eq!(*recording.0.lock().unwrap(), vec![b"payload".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_rejection_is_a_permanent_error() {
+        let transform = WasmTransform::from_bytes(REJECT.as_bytes(), limits()).unwrap();
+        let handler =
+            TransformingHandler::new(Arc::new(Recording(Mutex::new(Vec::new()))), transform);
+
+        let result = handler.handle(delivery(1, b"payload"), CancellationToken::new()).await;
+
+        assert!(
+            matches!(result, Err(HandlerError::Permanent(reason)) if reason.contains("unsupported"))
+        );
+    }
+
+    #[test]
+    fn test_execution_and_memory_limits_are_enforced() {
+        let spin = WasmTransform::from_bytes(SPIN.as_bytes(), limits()).unwrap();
+        assert!(matches!(spin.apply(b"x"), Err(TransformError::TimedOut)));
+
+        let greedy = WasmTransform::from_bytes(GREEDY.as_bytes(), limits()).unwrap();
+        assert!(matches!(
+            greedy.apply(b"x"),
+            Err(TransformError::Trapped(_))
+        ));
+    }
+
+    #[test]
+    fn test_module_without_transform_export_is_rejected_at_load() {
+        let result =
+            WasmTransform::from_bytes(br#"(module (memory (export "memory") 1))"#, limits());
+        assert!(matches!(result, Err(TransformError::Load(_))));
+    }
+}